@@ -0,0 +1,216 @@
+//! Execution of commands dispatched by the hub.
+
+use podpilot_common::protocol::Readiness;
+use podpilot_common::rpc::{Command, CommandResponse};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::jobs::JobRunner;
+use crate::models::{DeleteError, ModelStore, SpaceError};
+
+/// Executes hub commands against local agent resources
+#[derive(Clone)]
+pub struct CommandHandler {
+    models: Arc<ModelStore>,
+    jobs: Option<JobRunner>,
+    /// Workload commands are rejected with `not_ready` until this reports ready
+    readiness: Option<watch::Receiver<Readiness>>,
+}
+
+impl CommandHandler {
+    /// Create a new command handler
+    pub fn new(models: Arc<ModelStore>) -> Self {
+        Self {
+            models,
+            jobs: None,
+            readiness: None,
+        }
+    }
+
+    /// Accept `RunJob` commands, running them with `runner`
+    pub fn with_jobs(mut self, runner: JobRunner) -> Self {
+        self.jobs = Some(runner);
+        self
+    }
+
+    /// Reject workload commands while `readiness` reports the agent as starting
+    pub fn with_readiness(mut self, readiness: watch::Receiver<Readiness>) -> Self {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    /// Execute a command, converting any failure into `CommandResponse::Failed`
+    pub async fn handle(&self, command: Command) -> CommandResponse {
+        if command.waits_for_ready()
+            && let Some(readiness) = &self.readiness
+            && *readiness.borrow() == Readiness::Starting
+        {
+            info!(?command, "rejecting command until ready");
+            return CommandResponse::not_ready();
+        }
+
+        match command {
+            Command::Ping { nonce } => CommandResponse::Success {
+                message: None,
+                data: Some(serde_json::json!({ "nonce": nonce })),
+            },
+            Command::DownloadModel {
+                model_id,
+                r2_key,
+                filename,
+                sha256_hash,
+                file_size,
+                auto_evict,
+            } => {
+                let mut evicted = match file_size {
+                    Some(file_size) => match self
+                        .models
+                        .ensure_space(model_id, &filename, file_size, auto_evict)
+                        .await
+                    {
+                        Ok(evicted) => evicted,
+                        Err(e) => {
+                            if let SpaceError::Insufficient { .. } = e {
+                                warn!(%model_id, error = %e, "refusing model download");
+                            }
+                            return CommandResponse::Failed {
+                                error: format!("{:#}", e),
+                                details: Some(serde_json::json!({ "model_id": model_id })),
+                            };
+                        }
+                    },
+                    None => Vec::new(),
+                };
+
+                match self
+                    .models
+                    .download(model_id, &r2_key, &filename, &sha256_hash)
+                    .await
+                {
+                    Ok(outcome) => {
+                        info!(%model_id, bytes = outcome.bytes, "model downloaded");
+                        evicted.extend(outcome.evicted);
+                        CommandResponse::Success {
+                            message: Some(format!("Downloaded {}", filename)),
+                            data: Some(serde_json::json!({
+                                "model_id": model_id,
+                                "path": outcome.path,
+                                "bytes": outcome.bytes,
+                                "resumed_from": outcome.resumed_from,
+                                "duration_ms": outcome.elapsed.as_millis() as u64,
+                                "evicted": evicted,
+                            })),
+                        }
+                    }
+                    Err(e) => {
+                        warn!(%model_id, error = format!("{:#}", e), "model download failed");
+                        CommandResponse::Failed {
+                            error: format!("{:#}", e),
+                            details: Some(serde_json::json!({
+                                "model_id": model_id,
+                                "evicted": evicted,
+                            })),
+                        }
+                    }
+                }
+            }
+            Command::DeleteModel { model_id } => match self.models.delete(model_id).await {
+                Ok(freed_bytes) => CommandResponse::Success {
+                    message: Some(format!("Deleted model {}", model_id)),
+                    data: Some(serde_json::json!({
+                        "model_id": model_id,
+                        "freed_bytes": freed_bytes,
+                    })),
+                },
+                Err(e) => {
+                    if matches!(e, DeleteError::OutsideModelsDir(_)) {
+                        warn!(%model_id, error = %e, "rejected unsafe model deletion");
+                    }
+                    CommandResponse::Failed {
+                        error: e.to_string(),
+                        details: Some(serde_json::json!({ "model_id": model_id })),
+                    }
+                }
+            },
+            Command::SyncModels { desired } => {
+                let outcome = self.models.sync(&desired).await;
+                info!(
+                    downloaded = outcome.downloaded.len(),
+                    deleted = outcome.deleted.len(),
+                    unchanged = outcome.unchanged.len(),
+                    failed = outcome.failed.len(),
+                    "model sync finished"
+                );
+                let summary = format!(
+                    "{} downloaded, {} deleted, {} unchanged, {} failed",
+                    outcome.downloaded.len(),
+                    outcome.deleted.len(),
+                    outcome.unchanged.len(),
+                    outcome.failed.len()
+                );
+                let data = serde_json::to_value(&outcome).ok();
+                if outcome.failed.is_empty() {
+                    CommandResponse::Success {
+                        message: Some(summary),
+                        data,
+                    }
+                } else {
+                    warn!(failures = ?outcome.failed, "model sync incomplete");
+                    CommandResponse::Failed {
+                        error: format!("Model sync incomplete: {}", summary),
+                        details: data,
+                    }
+                }
+            }
+            Command::RunJob {
+                job_id,
+                model,
+                params,
+            } => {
+                let Some(runner) = &self.jobs else {
+                    return CommandResponse::Failed {
+                        error: "No job command is configured on this agent".to_string(),
+                        details: Some(serde_json::json!({ "job_id": job_id })),
+                    };
+                };
+
+                let (path, evicted) = match self.models.ensure_model(&model).await {
+                    Ok(prepared) => prepared,
+                    Err(e) => {
+                        warn!(%job_id, model_id = %model.model_id, error = format!("{:#}", e), "failed to prepare job model");
+                        return CommandResponse::Failed {
+                            error: format!("Failed to prepare model: {:#}", e),
+                            details: Some(serde_json::json!({ "job_id": job_id })),
+                        };
+                    }
+                };
+
+                match runner.run(job_id, model.model_id, &path, &params).await {
+                    Ok(result) => CommandResponse::Success {
+                        message: None,
+                        data: Some(serde_json::json!({
+                            "job_id": job_id,
+                            "result": result,
+                            "evicted": evicted,
+                        })),
+                    },
+                    Err(e) => {
+                        warn!(%job_id, error = format!("{:#}", e), "job failed");
+                        CommandResponse::Failed {
+                            error: format!("{:#}", e),
+                            details: Some(serde_json::json!({
+                                "job_id": job_id,
+                                "evicted": evicted,
+                            })),
+                        }
+                    }
+                }
+            }
+            other => CommandResponse::Failed {
+                error: format!("Command not supported by this agent: {:?}", other),
+                details: None,
+            },
+        }
+    }
+}
@@ -1,20 +1,113 @@
 use figment::{Figment, providers::Env};
+use podpilot_common::protocol::WireCodec;
+use podpilot_common::redact::{REDACTED, redact_url};
 use podpilot_common::types::ProviderType;
+use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use uuid::Uuid;
 
-/// Agent configuration loaded from environment variables
+/// R2 (S3-compatible) read credentials for fetching model files
+///
+/// All fields must be provided together or all omitted.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct R2Config {
+    /// S3 API endpoint (e.g., "https://<account_id>.r2.cloudflarestorage.com")
+    #[serde(rename = "r2_endpoint", skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// Bucket containing model files
+    #[serde(rename = "r2_bucket", skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// Read-only access key ID
+    #[serde(rename = "r2_access_key_id", skip_serializing)]
+    pub access_key_id: Option<SecretString>,
+    /// Read-only secret access key
+    #[serde(rename = "r2_secret_access_key", skip_serializing)]
+    pub secret_access_key: Option<SecretString>,
+}
+
+impl R2Config {
+    /// Whether any R2 setting has been provided
+    pub fn is_configured(&self) -> bool {
+        self.endpoint.is_some()
+            || self.bucket.is_some()
+            || self.access_key_id.is_some()
+            || self.secret_access_key.is_some()
+    }
+
+    /// Validate that either all R2 settings are present or none are
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.is_configured() {
+            return Ok(());
+        }
+
+        let missing: Vec<&str> = [
+            ("R2_ENDPOINT", self.endpoint.is_none()),
+            ("R2_BUCKET", self.bucket.is_none()),
+            ("R2_ACCESS_KEY_ID", self.access_key_id.is_none()),
+            ("R2_SECRET_ACCESS_KEY", self.secret_access_key.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(name, missing)| missing.then_some(name))
+        .collect();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Incomplete R2 configuration, missing: {}",
+                missing.join(", ")
+            ))
+        }
+    }
+}
+
+/// Shape of the status API's response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusFormat {
+    /// Connection, shutdown, and (with `?full=true`) resource details
+    #[default]
+    Detailed,
+    /// Just `{"status": "UP"|"DOWN", "uptime_secs": ...}`, for actuator-style health checks
+    Simple,
+}
+
+/// Agent configuration loaded from environment variables
+///
+/// `Debug` output redacts credentials embedded in hub URLs and hides hook, WebUI, and
+/// job commands, which commonly carry tokens inline.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
-    /// WebSocket URL for Hub connection
-    #[serde(default = "default_hub_url")]
-    pub hub_url: String,
+    /// WebSocket URLs for Hub connection (comma-separated)
+    ///
+    /// With several URLs, a failed connection moves on to the next one before
+    /// backing off, and reconnects prefer whichever URL last succeeded.
+    #[serde(
+        default = "default_hub_urls",
+        deserialize_with = "podpilot_common::config::deserialize_comma_separated"
+    )]
+    pub hub_urls: Vec<String>,
 
     /// Port for agent HTTP status API (default: 80, use 8080 for local dev)
     #[serde(default = "default_status_port")]
     pub status_port: u16,
 
+    /// Origins allowed to call the status API from a browser (comma-separated)
+    /// Default: none, so no CORS headers are sent
+    #[serde(
+        default,
+        deserialize_with = "podpilot_common::config::deserialize_comma_separated"
+    )]
+    pub status_cors_origins: Vec<String>,
+
+    /// Response format of the status API (detailed, simple)
+    /// Default: detailed
+    #[serde(default)]
+    pub status_format: StatusFormat,
+
     /// Provider type (local, vastai, runpod)
     /// Default: local
     #[serde(default = "default_provider")]
@@ -28,6 +121,10 @@ pub struct Config {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hostname: Option<String>,
 
+    /// Friendly name shown on the hub instead of the hostname (e.g., "training-box-1")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
     /// Tailscale IP address
     /// Default: 0.0.0.0 (should be set in production)
     #[serde(default = "default_tailscale_ip")]
@@ -37,10 +134,189 @@ pub struct Config {
     /// Default: info
     #[serde(default = "default_log_level")]
     pub log_level: String,
+
+    /// Working directory for agent state (e.g. the persisted agent ID)
+    /// Default: /workspace
+    #[serde(default = "default_workdir")]
+    pub workdir: PathBuf,
+
+    /// Directory where model files are stored
+    /// Default: /workspace/models
+    #[serde(default = "default_models_dir")]
+    pub models_dir: PathBuf,
+
+    /// Total size of downloaded models to stay under, in bytes
+    /// Default: unlimited. Least-recently-used models are evicted to stay below it.
+    #[serde(default)]
+    pub max_models_bytes: Option<u64>,
+
+    /// Preferred WebSocket wire codec (json, msgpack)
+    /// Default: json. The hub falls back to JSON if it can't honor the preference.
+    #[serde(default)]
+    pub wire_codec: WireCodec,
+
+    /// Consecutive failed connection attempts before the agent exits nonzero
+    /// Default: unlimited. Permanent rejections (auth, protocol version) exit immediately.
+    #[serde(default)]
+    pub max_reconnect_attempts: Option<u32>,
+
+    /// Largest inbound WebSocket message accepted from the hub, in bytes
+    /// Default: 16 MiB
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+
+    /// How long a message send to the hub may stall before the connection is dropped
+    /// Default: 10s
+    #[serde(
+        default = "default_send_timeout",
+        deserialize_with = "podpilot_common::config::deserialize_duration"
+    )]
+    pub send_timeout: Duration,
+
+    /// Time without a heartbeat from the hub before the connection is probed
+    /// Default: 30s. Keep it a few multiples of the hub's HEARTBEAT_INTERVAL.
+    #[serde(
+        default = "default_heartbeat_timeout",
+        deserialize_with = "podpilot_common::config::deserialize_duration"
+    )]
+    pub heartbeat_timeout: Duration,
+
+    /// How long to wait for a heartbeat after probing the hub before reconnecting;
+    /// a pong answering the probe extends this once
+    /// Default: 5s. 0 reconnects as soon as the heartbeat timeout passes.
+    #[serde(
+        default = "default_heartbeat_grace",
+        deserialize_with = "podpilot_common::config::deserialize_duration"
+    )]
+    pub heartbeat_grace: Duration,
+
+    /// Reconnect after a session has lasted this long, even if it's healthy
+    /// Default: unset (sessions last indefinitely). Each session ends up to 10%
+    /// earlier, at random, so a fleet doesn't reconnect in lockstep.
+    #[serde(
+        default,
+        deserialize_with = "podpilot_common::config::deserialize_optional_duration"
+    )]
+    pub max_session_duration: Option<Duration>,
+
+    /// Poll the hub's HTTP `/status` briefly before the first WebSocket connection
+    /// Default: true. Skipped automatically if the hub has no status endpoint.
+    #[serde(default = "default_hub_preflight")]
+    pub hub_preflight: bool,
+
+    /// Log every hub message's payload, with secrets redacted
+    /// Default: false. Logged at trace level, so LOG_LEVEL=trace is needed as well.
+    #[serde(default)]
+    pub log_ws_payloads: bool,
+
+    /// Longest gap between metrics sent with heartbeats; changed metrics are sent sooner
+    /// Default: 60s
+    #[serde(
+        default = "default_metrics_max_interval",
+        deserialize_with = "podpilot_common::config::deserialize_duration"
+    )]
+    pub metrics_max_interval: Duration,
+
+    /// Shell command run (via `sh -c`) after each successful registration with the hub
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_connect_command: Option<String>,
+
+    /// Shell command run (via `sh -c`) after each hub connection closes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_disconnect_command: Option<String>,
+
+    /// How long a connection hook may run before it is killed
+    /// Default: 60s
+    #[serde(
+        default = "default_hook_timeout",
+        deserialize_with = "podpilot_common::config::deserialize_duration"
+    )]
+    pub hook_timeout: Duration,
+
+    /// Shell command (via `sh -c`) that runs the WebUI workload, supervised by the agent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webui_command: Option<String>,
+
+    /// How long the WebUI gets to finish in-flight work after SIGTERM before SIGKILL
+    /// Default: 30s
+    #[serde(
+        default = "default_webui_shutdown_timeout",
+        deserialize_with = "podpilot_common::config::deserialize_duration"
+    )]
+    pub webui_shutdown_timeout: Duration,
+
+    /// URL that answers with a 2xx once the WebUI can serve requests
+    ///
+    /// Until it does, the agent reports itself as starting and the hub holds
+    /// workload commands. Without it, the agent is ready as soon as it connects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webui_ready_url: Option<String>,
+
+    /// Shell command (via `sh -c`) that runs scheduled jobs
+    ///
+    /// Receives the job's params as JSON on stdin and `PODPILOT_JOB_ID`,
+    /// `PODPILOT_MODEL_ID`, and `PODPILOT_MODEL_PATH` in its environment; its stdout
+    /// is the job's result. Without it, the agent doesn't accept jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_command: Option<String>,
+
+    /// How long a job may run before it is killed
+    /// Default: 1h
+    #[serde(
+        default = "default_job_timeout",
+        deserialize_with = "podpilot_common::config::deserialize_duration"
+    )]
+    pub job_timeout: Duration,
+
+    /// R2 credentials for model downloads (optional)
+    ///
+    /// Without these, `DownloadModel` commands fail.
+    #[serde(flatten)]
+    pub r2: R2Config,
 }
 
-fn default_hub_url() -> String {
-    "ws://localhost:80/ws/agent".to_string()
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hook = |command: &Option<String>| command.as_ref().map(|_| REDACTED);
+        f.debug_struct("Config")
+            .field("hub_urls", &self.redacted_hub_urls())
+            .field("status_port", &self.status_port)
+            .field("status_cors_origins", &self.status_cors_origins)
+            .field("status_format", &self.status_format)
+            .field("provider", &self.provider)
+            .field("provider_instance_id", &self.provider_instance_id)
+            .field("hostname", &self.hostname)
+            .field("display_name", &self.display_name)
+            .field("tailscale_ip", &self.tailscale_ip)
+            .field("log_level", &self.log_level)
+            .field("workdir", &self.workdir)
+            .field("models_dir", &self.models_dir)
+            .field("max_models_bytes", &self.max_models_bytes)
+            .field("wire_codec", &self.wire_codec)
+            .field("max_reconnect_attempts", &self.max_reconnect_attempts)
+            .field("max_message_bytes", &self.max_message_bytes)
+            .field("send_timeout", &self.send_timeout)
+            .field("heartbeat_timeout", &self.heartbeat_timeout)
+            .field("heartbeat_grace", &self.heartbeat_grace)
+            .field("max_session_duration", &self.max_session_duration)
+            .field("hub_preflight", &self.hub_preflight)
+            .field("log_ws_payloads", &self.log_ws_payloads)
+            .field("metrics_max_interval", &self.metrics_max_interval)
+            .field("on_connect_command", &hook(&self.on_connect_command))
+            .field("on_disconnect_command", &hook(&self.on_disconnect_command))
+            .field("hook_timeout", &self.hook_timeout)
+            .field("webui_command", &hook(&self.webui_command))
+            .field("webui_shutdown_timeout", &self.webui_shutdown_timeout)
+            .field("webui_ready_url", &self.webui_ready_url)
+            .field("job_command", &hook(&self.job_command))
+            .field("job_timeout", &self.job_timeout)
+            .field("r2", &self.r2)
+            .finish()
+    }
+}
+
+fn default_hub_urls() -> Vec<String> {
+    vec!["ws://localhost:80/ws/agent".to_string()]
 }
 
 fn default_status_port() -> u16 {
@@ -59,6 +335,50 @@ fn default_log_level() -> String {
     "info".to_string()
 }
 
+fn default_workdir() -> PathBuf {
+    PathBuf::from("/workspace")
+}
+
+fn default_models_dir() -> PathBuf {
+    PathBuf::from("/workspace/models")
+}
+
+fn default_max_message_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_send_timeout() -> Duration {
+    crate::ws::DEFAULT_SEND_TIMEOUT
+}
+
+fn default_heartbeat_timeout() -> Duration {
+    crate::ws::DEFAULT_HEARTBEAT_TIMEOUT
+}
+
+fn default_heartbeat_grace() -> Duration {
+    crate::ws::DEFAULT_HEARTBEAT_GRACE
+}
+
+fn default_metrics_max_interval() -> Duration {
+    crate::metrics::DEFAULT_METRICS_MAX_INTERVAL
+}
+
+fn default_hub_preflight() -> bool {
+    true
+}
+
+fn default_hook_timeout() -> Duration {
+    crate::hooks::DEFAULT_HOOK_TIMEOUT
+}
+
+fn default_webui_shutdown_timeout() -> Duration {
+    crate::webui::DEFAULT_WEBUI_SHUTDOWN_TIMEOUT
+}
+
+fn default_job_timeout() -> Duration {
+    crate::jobs::DEFAULT_JOB_TIMEOUT
+}
+
 impl Config {
     /// Load configuration from environment variables
     pub fn load() -> Result<Self, Box<figment::Error>> {
@@ -66,13 +386,41 @@ impl Config {
             .merge(Env::raw().map(|k| {
                 // Map environment variable names to struct field names
                 match k.as_str() {
-                    "HUB_WEBSOCKET_URL" => "hub_url".into(),
+                    "HUB_WEBSOCKET_URL" => "hub_urls".into(),
                     "STATUS_PORT" => "status_port".into(),
+                    "STATUS_CORS_ORIGINS" => "status_cors_origins".into(),
+                    "STATUS_FORMAT" => "status_format".into(),
                     "PROVIDER_TYPE" => "provider".into(),
                     "PROVIDER_INSTANCE_ID" => "provider_instance_id".into(),
                     "HOSTNAME" => "hostname".into(),
+                    "DISPLAY_NAME" => "display_name".into(),
                     "TAILSCALE_IP" => "tailscale_ip".into(),
                     "LOG_LEVEL" => "log_level".into(),
+                    "WORKDIR" => "workdir".into(),
+                    "MODELS_DIR" => "models_dir".into(),
+                    "MAX_MODELS_BYTES" => "max_models_bytes".into(),
+                    "WIRE_CODEC" => "wire_codec".into(),
+                    "MAX_MESSAGE_BYTES" => "max_message_bytes".into(),
+                    "SEND_TIMEOUT" => "send_timeout".into(),
+                    "HEARTBEAT_TIMEOUT" => "heartbeat_timeout".into(),
+                    "HEARTBEAT_GRACE" => "heartbeat_grace".into(),
+                    "MAX_SESSION_DURATION" => "max_session_duration".into(),
+                    "MAX_RECONNECT_ATTEMPTS" => "max_reconnect_attempts".into(),
+                    "HUB_PREFLIGHT" => "hub_preflight".into(),
+                    "LOG_WS_PAYLOADS" => "log_ws_payloads".into(),
+                    "METRICS_MAX_INTERVAL" => "metrics_max_interval".into(),
+                    "ON_CONNECT_COMMAND" => "on_connect_command".into(),
+                    "ON_DISCONNECT_COMMAND" => "on_disconnect_command".into(),
+                    "HOOK_TIMEOUT" => "hook_timeout".into(),
+                    "WEBUI_COMMAND" => "webui_command".into(),
+                    "WEBUI_SHUTDOWN_TIMEOUT" => "webui_shutdown_timeout".into(),
+                    "WEBUI_READY_URL" => "webui_ready_url".into(),
+                    "JOB_COMMAND" => "job_command".into(),
+                    "JOB_TIMEOUT" => "job_timeout".into(),
+                    "R2_ENDPOINT" => "r2_endpoint".into(),
+                    "R2_BUCKET" => "r2_bucket".into(),
+                    "R2_ACCESS_KEY_ID" => "r2_access_key_id".into(),
+                    "R2_SECRET_ACCESS_KEY" => "r2_secret_access_key".into(),
                     _ => k.into(),
                 }
             }))
@@ -80,6 +428,11 @@ impl Config {
             .map_err(Box::new)
     }
 
+    /// Hub URLs with any embedded credentials redacted, for logging
+    pub fn redacted_hub_urls(&self) -> Vec<String> {
+        self.hub_urls.iter().map(|url| redact_url(url)).collect()
+    }
+
     /// Get the hostname, using configured value or auto-detecting
     pub fn get_hostname(&self) -> String {
         self.hostname.clone().unwrap_or_else(|| {
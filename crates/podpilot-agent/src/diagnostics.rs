@@ -0,0 +1,56 @@
+//! Boot diagnostics reported to the hub at registration.
+//!
+//! These describe what the machine looked like when the agent started, so an
+//! agent that unexpectedly shows as GPU-less can be debugged from the hub.
+
+use podpilot_common::types::{GpuInfo, GpuVendor, ProviderType};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::gpu::GpuProbe;
+
+/// Snapshot of agent startup conditions
+#[derive(Debug, Clone, Serialize)]
+pub struct BootDiagnostics {
+    /// Outcome of querying nvidia-smi
+    pub gpu_probe: GpuProbe,
+    /// Where `cuda_version` came from ("nvidia-smi" or "unavailable")
+    pub cuda_version_source: &'static str,
+    /// Configured agent workdir
+    pub workdir: PathBuf,
+    pub models_dir: PathBuf,
+    pub provider: ProviderType,
+    /// Whether the provider was set explicitly rather than defaulted
+    pub provider_configured: bool,
+    /// Time from process start until diagnostics were collected
+    pub startup_ms: u64,
+}
+
+impl BootDiagnostics {
+    /// Collect diagnostics after GPU detection and configuration have completed
+    pub fn collect(
+        config: &Config,
+        gpu_info: &GpuInfo,
+        gpu_probe: GpuProbe,
+        startup: Duration,
+    ) -> Self {
+        let cuda_version_source =
+            if gpu_info.vendor == GpuVendor::Nvidia && gpu_info.cuda_version != "unknown" {
+                "nvidia-smi"
+            } else {
+                "unavailable"
+            };
+
+        Self {
+            gpu_probe,
+            cuda_version_source,
+            workdir: config.workdir.clone(),
+            models_dir: config.models_dir.clone(),
+            provider: config.provider,
+            provider_configured: std::env::var_os("PROVIDER_TYPE").is_some(),
+            startup_ms: startup.as_millis() as u64,
+        }
+    }
+}
@@ -1,38 +1,79 @@
-use podpilot_common::types::GpuInfo;
-use std::process::Command;
+use podpilot_common::types::{GpuInfo, GpuVendor};
+use serde::Serialize;
+use std::process::{Command, Output};
 use tracing::{debug, warn};
 
+/// Outcome of GPU detection, reported in boot diagnostics
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+pub enum GpuProbe {
+    Detected,
+    NotInstalled,
+    Failed(String),
+}
+
+/// Why GPU detection did not produce a result
+#[derive(Debug, thiserror::Error)]
+enum DetectError {
+    /// nvidia-smi is not installed, which is expected on GPU-less machines
+    #[error("nvidia-smi not found")]
+    NotInstalled,
+    /// nvidia-smi exists but a query failed
+    #[error("{0}")]
+    Failed(String),
+}
+
 /// Detect GPU information using nvidia-smi
-pub fn detect_gpu() -> GpuInfo {
+pub fn detect_gpu() -> (GpuInfo, GpuProbe) {
     match detect_nvidia_gpu() {
         Ok(gpu_info) => {
             debug!("Detected GPU: {}", gpu_info.name);
-            gpu_info
+            (gpu_info, GpuProbe::Detected)
+        }
+        Err(DetectError::NotInstalled) => {
+            debug!("nvidia-smi not found, assuming no GPU");
+            (GpuInfo::none(), GpuProbe::NotInstalled)
         }
         Err(e) => {
             warn!("Failed to detect GPU, using placeholder: {}", e);
-            GpuInfo {
-                name: "Unknown GPU".to_string(),
-                memory_gb: 0.0,
-                cuda_version: "unknown".to_string(),
-                compute_capability: None,
-            }
+            (GpuInfo::unknown(), GpuProbe::Failed(e.to_string()))
         }
     }
 }
 
-/// Try to detect NVIDIA GPU using nvidia-smi
-fn detect_nvidia_gpu() -> anyhow::Result<GpuInfo> {
-    // Query GPU name
-    let name_output = Command::new("nvidia-smi")
-        .args(["--query-gpu=name", "--format=csv,noheader"])
-        .output()?;
+/// Run nvidia-smi, distinguishing a missing binary from a failing query
+fn nvidia_smi(args: &[&str]) -> Result<Output, DetectError> {
+    let output = Command::new("nvidia-smi")
+        .args(args)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => DetectError::NotInstalled,
+            _ => DetectError::Failed(format!("failed to run nvidia-smi: {}", e)),
+        })?;
 
-    if !name_output.status.success() {
-        anyhow::bail!("nvidia-smi failed to query GPU name");
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DetectError::Failed(format!(
+            "nvidia-smi {} exited with {}: {}",
+            args.join(" "),
+            output.status,
+            stderr.trim()
+        )));
     }
 
-    let name = String::from_utf8(name_output.stdout)?
+    Ok(output)
+}
+
+/// Decode nvidia-smi stdout
+fn stdout_text(output: Output) -> Result<String, DetectError> {
+    String::from_utf8(output.stdout)
+        .map_err(|e| DetectError::Failed(format!("nvidia-smi output was not UTF-8: {}", e)))
+}
+
+/// Try to detect NVIDIA GPU using nvidia-smi
+fn detect_nvidia_gpu() -> Result<GpuInfo, DetectError> {
+    // Query GPU name
+    let name = stdout_text(nvidia_smi(&["--query-gpu=name", "--format=csv,noheader"])?)?
         .trim()
         .lines()
         .next()
@@ -40,26 +81,25 @@ fn detect_nvidia_gpu() -> anyhow::Result<GpuInfo> {
         .to_string();
 
     // Query memory in MB, convert to GB
-    let memory_output = Command::new("nvidia-smi")
-        .args(["--query-gpu=memory.total", "--format=csv,noheader,nounits"])
-        .output()?;
-
-    let memory_mb: f32 = String::from_utf8(memory_output.stdout)?
-        .trim()
-        .lines()
-        .next()
-        .unwrap_or("0")
-        .parse()
-        .unwrap_or(0.0);
+    let memory_mb: f32 = stdout_text(nvidia_smi(&[
+        "--query-gpu=memory.total",
+        "--format=csv,noheader,nounits",
+    ])?)?
+    .trim()
+    .lines()
+    .next()
+    .unwrap_or("0")
+    .parse()
+    .unwrap_or(0.0);
 
     let memory_gb = (memory_mb / 1024.0 * 100.0).round() / 100.0; // Round to 2 decimals
 
     // Query CUDA version from nvidia-smi
     // nvidia-smi reports the maximum supported CUDA version in its header output
-    let cuda_output = Command::new("nvidia-smi").output()?;
+    let cuda_output = stdout_text(nvidia_smi(&[])?)?;
 
     // Parse CUDA version from nvidia-smi output header (e.g., "CUDA Version: 13.0")
-    let cuda_version = String::from_utf8(cuda_output.stdout)?
+    let cuda_version = cuda_output
         .lines()
         .find(|line| line.contains("CUDA Version"))
         .and_then(|line| {
@@ -69,23 +109,50 @@ fn detect_nvidia_gpu() -> anyhow::Result<GpuInfo> {
         })
         .unwrap_or_else(|| "unknown".to_string());
 
-    // Query compute capability
-    let capability_output = Command::new("nvidia-smi")
-        .args(["--query-gpu=compute_cap", "--format=csv,noheader"])
-        .output();
-
-    let compute_capability = if let Ok(output) = capability_output {
-        String::from_utf8(output.stdout)
-            .ok()
-            .and_then(|s| s.trim().lines().next().map(|l| l.to_string()))
-    } else {
-        None
-    };
+    // Query compute capability (optional; older drivers don't support this field)
+    let compute_capability = nvidia_smi(&["--query-gpu=compute_cap", "--format=csv,noheader"])
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|s| s.trim().lines().next().map(|l| l.to_string()));
 
     Ok(GpuInfo {
+        vendor: GpuVendor::Nvidia,
         name,
         memory_gb,
         cuda_version,
         compute_capability,
     })
 }
+
+/// Point-in-time GPU usage
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GpuSample {
+    /// Memory in use, in bytes
+    pub memory_used: u64,
+    /// Total memory, in bytes
+    pub memory_total: u64,
+    /// Utilization percentage (0-100)
+    pub utilization: u8,
+    /// Temperature in Celsius, if reported
+    pub temperature: Option<u8>,
+}
+
+/// Sample usage of the first GPU, or `None` without a working nvidia-smi
+pub fn sample_gpu() -> Option<GpuSample> {
+    let output = nvidia_smi(&[
+        "--query-gpu=memory.used,memory.total,utilization.gpu,temperature.gpu",
+        "--format=csv,noheader,nounits",
+    ])
+    .ok()?;
+    let text = stdout_text(output).ok()?;
+    let fields: Vec<&str> = text.lines().next()?.split(',').map(str::trim).collect();
+
+    const MIB: u64 = 1024 * 1024;
+    Some(GpuSample {
+        memory_used: fields.first()?.parse::<u64>().ok()? * MIB,
+        memory_total: fields.get(1)?.parse::<u64>().ok()? * MIB,
+        utilization: fields.get(2)?.parse().unwrap_or(0),
+        // "[N/A]" on GPUs without a sensor
+        temperature: fields.get(3).and_then(|t| t.parse().ok()),
+    })
+}
@@ -0,0 +1,111 @@
+//! Operator-configured shell commands run on hub connection changes.
+//!
+//! Lets deployments trigger warmup or notification scripts when the agent joins
+//! or leaves the hub without modifying the binary. Commands run through `sh -c`
+//! with `PODPILOT_AGENT_ID` set, and are killed if they outlive the timeout.
+
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::timeout;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Default time a hook may run before it is killed
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Shell commands to run when the hub connection is established or lost
+#[derive(Debug, Clone)]
+pub struct ConnectionHooks {
+    on_connect: Option<String>,
+    on_disconnect: Option<String>,
+    timeout: Duration,
+}
+
+impl ConnectionHooks {
+    pub fn new(
+        on_connect: Option<String>,
+        on_disconnect: Option<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            on_connect,
+            on_disconnect,
+            timeout,
+        }
+    }
+
+    /// Run the connect hook in the background, so it can't delay heartbeats
+    pub fn spawn_on_connect(&self, agent_id: Uuid) {
+        if let Some(command) = self.on_connect.clone() {
+            let hook_timeout = self.timeout;
+            tokio::spawn(async move {
+                run_hook("on_connect", &command, agent_id, hook_timeout).await;
+            });
+        }
+    }
+
+    /// Run the disconnect hook and wait for it (bounded by the timeout)
+    ///
+    /// Awaited rather than spawned so it still completes when the connection
+    /// closed because the agent is shutting down.
+    pub async fn on_disconnect(&self, agent_id: Option<Uuid>) {
+        if let Some(command) = &self.on_disconnect {
+            run_hook(
+                "on_disconnect",
+                command,
+                agent_id.unwrap_or_default(),
+                self.timeout,
+            )
+            .await;
+        }
+    }
+}
+
+/// Run a hook command, logging its exit status and output
+async fn run_hook(hook: &'static str, command: &str, agent_id: Uuid, hook_timeout: Duration) {
+    // The command itself isn't logged, since tokens are often passed inline
+    info!(hook, "running connection hook");
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("PODPILOT_AGENT_ID", agent_id.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!(hook, error = %e, "failed to start connection hook");
+            return;
+        }
+    };
+
+    match timeout(hook_timeout, child.wait_with_output()).await {
+        Ok(Ok(output)) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if output.status.success() {
+                info!(hook, stdout = %stdout.trim(), stderr = %stderr.trim(), "connection hook completed");
+            } else {
+                warn!(
+                    hook,
+                    status = %output.status,
+                    stdout = %stdout.trim(),
+                    stderr = %stderr.trim(),
+                    "connection hook failed"
+                );
+            }
+        }
+        Ok(Err(e)) => warn!(hook, error = %e, "failed to wait for connection hook"),
+        // Dropping the wait future drops the child, which kills it
+        Err(_) => warn!(
+            hook,
+            timeout_secs = hook_timeout.as_secs(),
+            "connection hook timed out and was killed"
+        ),
+    }
+}
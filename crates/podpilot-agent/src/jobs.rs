@@ -0,0 +1,104 @@
+//! Running scheduled jobs through an operator-configured command.
+//!
+//! The agent doesn't know how to drive any particular workload, so jobs are handed
+//! to a shell command (via `sh -c`) that does. The job's `params` are written to its
+//! stdin as JSON, the model location is passed in the environment, and whatever it
+//! prints to stdout becomes the job's result.
+
+use anyhow::{Context, Result, anyhow};
+use podpilot_common::config::format_duration;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+use tracing::info;
+use uuid::Uuid;
+
+/// Default time a job may run before it is killed
+pub const DEFAULT_JOB_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Longest stderr excerpt included in a failed job's error
+const STDERR_EXCERPT_BYTES: usize = 2048;
+
+/// Runs jobs with the configured command
+#[derive(Debug, Clone)]
+pub struct JobRunner {
+    command: String,
+    timeout: Duration,
+}
+
+impl JobRunner {
+    pub fn new(command: String, timeout: Duration) -> Self {
+        Self { command, timeout }
+    }
+
+    /// Run a job to completion and return its result
+    ///
+    /// Stdout is parsed as JSON, falling back to a JSON string for plain output.
+    /// A nonzero exit fails the job with the tail of its stderr.
+    pub async fn run(
+        &self,
+        job_id: Uuid,
+        model_id: Uuid,
+        model_path: &Path,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        // The command itself isn't logged, since tokens are often passed inline
+        info!(%job_id, %model_id, "starting job");
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("PODPILOT_JOB_ID", job_id.to_string())
+            .env("PODPILOT_MODEL_ID", model_id.to_string())
+            .env("PODPILOT_MODEL_PATH", model_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to start job command")?;
+
+        let input = serde_json::to_vec(params).context("Failed to serialize job params")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(&input)
+                .await
+                .context("Failed to write job params")?;
+            // Dropping stdin closes it, so the command sees EOF
+        }
+
+        // Dropping the wait future drops the child, which kills it
+        let output = timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "Job did not finish within {}",
+                    format_duration(self.timeout)
+                )
+            })?
+            .context("Failed to wait for job command")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim();
+            let mut start = stderr.len().saturating_sub(STDERR_EXCERPT_BYTES);
+            while !stderr.is_char_boundary(start) {
+                start += 1;
+            }
+            return Err(anyhow!(
+                "Job exited with {}: {}",
+                output.status,
+                &stderr[start..]
+            ));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stdout = stdout.trim();
+        info!(%job_id, "job finished");
+        Ok(serde_json::from_str(stdout)
+            .unwrap_or_else(|_| serde_json::Value::String(stdout.to_string())))
+    }
+}
@@ -1,3 +1,14 @@
+pub mod commands;
 pub mod config;
+pub mod diagnostics;
 pub mod gpu;
+pub mod hooks;
+pub mod jobs;
+pub mod metrics;
+pub mod models;
+pub mod r2;
+pub mod readiness;
+pub mod shutdown;
+pub mod state;
+pub mod webui;
 pub mod ws;
@@ -1,10 +1,32 @@
+use axum::extract::{Query, State};
+use axum::http::{HeaderValue, Method};
+use axum::response::{IntoResponse, Response};
 use axum::{Json, Router, routing::get};
-use podpilot_agent::{config::Config, gpu, ws::WsClient};
+use podpilot_agent::{
+    commands::CommandHandler,
+    config::{Config, StatusFormat},
+    diagnostics::BootDiagnostics,
+    gpu,
+    hooks::ConnectionHooks,
+    jobs::JobRunner,
+    metrics::{METRICS_CACHE_TTL, MetricsCache},
+    models::{ModelStore, ModelUsage},
+    r2::R2Client,
+    readiness,
+    shutdown::{SharedShutdownReport, ShutdownReport},
+    state::StateFile,
+    webui::WebUiSupervisor,
+    ws::{ConnectionStats, WsClient},
+};
+use podpilot_common::rpc::{DiskUsage, Metrics, capability};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::process::ExitCode;
-use std::time::Instant;
-use tracing::{error, info};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
 #[derive(Serialize, Deserialize)]
@@ -12,14 +34,97 @@ struct StatusResponse {
     status: String,
     version: String,
     hub_connected: bool,
+    connection: ConnectionStats,
+    /// Present once shutdown has begun
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shutdown: Option<ShutdownReport>,
+    /// Only with `?full=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics: Option<Metrics>,
+    /// Only with `?full=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disk: Option<DiskUsage>,
+    /// Only with `?full=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    models: Option<ModelUsage>,
 }
 
-async fn get_status() -> Json<StatusResponse> {
+/// Status response for `STATUS_FORMAT=simple`, in the style of Spring's health actuator
+#[derive(Serialize)]
+struct SimpleStatusResponse {
+    /// "UP", or "DOWN" once shutdown has begun
+    status: &'static str,
+    uptime_secs: u64,
+}
+
+#[derive(Clone)]
+struct StatusState {
+    format: StatusFormat,
+    start_time: Instant,
+    shutdown: SharedShutdownReport,
+    metrics: MetricsCache,
+    ws_client: WsClient,
+    models: Arc<ModelStore>,
+}
+
+#[derive(Deserialize)]
+struct StatusQuery {
+    /// Include metrics and disk usage (sampled at most once per cache TTL) and model storage
+    #[serde(default)]
+    full: bool,
+}
+
+async fn get_status(
+    State(state): State<StatusState>,
+    Query(query): Query<StatusQuery>,
+) -> Response {
+    let shutdown = state.shutdown.read().await.clone();
+    if state.format == StatusFormat::Simple {
+        return Json(SimpleStatusResponse {
+            status: if shutdown.is_some() { "DOWN" } else { "UP" },
+            uptime_secs: state.start_time.elapsed().as_secs(),
+        })
+        .into_response();
+    }
+
+    let connection = state.ws_client.connection_stats().await;
+    let snapshot = if query.full {
+        match state.metrics.snapshot().await {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                warn!("Failed to sample metrics for status: {:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let (metrics, disk) = match snapshot {
+        Some(snapshot) => (Some(snapshot.metrics), Some(snapshot.disk)),
+        None => (None, None),
+    };
+    let models = if query.full {
+        Some(state.models.usage().await)
+    } else {
+        None
+    };
+
     Json(StatusResponse {
-        status: "ok".to_string(),
+        status: if shutdown.is_some() {
+            "shutting_down"
+        } else {
+            "ok"
+        }
+        .to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
-        hub_connected: false, // TODO: Track actual connection status
+        hub_connected: connection.connected_since.is_some(),
+        connection,
+        shutdown,
+        metrics,
+        disk,
+        models,
     })
+    .into_response()
 }
 
 #[tokio::main]
@@ -48,13 +153,18 @@ async fn main() -> ExitCode {
 
     info!(
         version = env!("CARGO_PKG_VERSION"),
-        hub_url = %config.hub_url,
+        hub_urls = ?config.redacted_hub_urls(),
         provider = ?config.provider,
         "starting podpilot-agent"
     );
 
+    if config.hub_urls.is_empty() {
+        error!("HUB_WEBSOCKET_URL must contain at least one URL");
+        return ExitCode::FAILURE;
+    }
+
     // Detect GPU information
-    let gpu_info = gpu::detect_gpu();
+    let (gpu_info, gpu_probe) = gpu::detect_gpu();
     info!(
         gpu_name = %gpu_info.name,
         memory_gb = gpu_info.memory_gb,
@@ -71,41 +181,148 @@ async fn main() -> ExitCode {
         }
     };
 
+    // Set up model storage and command execution
+    if let Err(e) = config.r2.validate() {
+        error!("Invalid R2 configuration: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let r2 = match R2Client::from_config(&config.r2) {
+        Ok(r2) => r2,
+        Err(e) => {
+            error!("Failed to configure R2 client: {:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if r2.is_none() {
+        warn!("R2 credentials not configured, model downloads are disabled");
+    }
+    let mut capabilities = vec![capability::METRICS, capability::MODEL_DELETE];
+    if r2.is_some() {
+        capabilities.push(capability::MODEL_DOWNLOAD);
+    }
+    let model_store = match ModelStore::new(config.models_dir.clone(), r2) {
+        Ok(store) => Arc::new(store.with_max_models_bytes(config.max_models_bytes)),
+        Err(e) => {
+            error!("Failed to create model store: {:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut commands = CommandHandler::new(model_store.clone());
+    if let Some(job_command) = config.job_command.clone() {
+        commands = commands.with_jobs(JobRunner::new(job_command, config.job_timeout));
+        capabilities.push(capability::JOBS);
+    }
+
+    let diagnostics = BootDiagnostics::collect(&config, &gpu_info, gpu_probe, start_time.elapsed());
+    info!(?diagnostics, "boot diagnostics collected");
+
+    let webui = match config.webui_command.as_deref().map(WebUiSupervisor::start) {
+        Some(Ok(webui)) => Some(webui),
+        Some(Err(e)) => {
+            error!("{:#}", e);
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    if webui.is_some() {
+        capabilities.push(capability::WEBUI);
+    }
+    let readiness = config.webui_ready_url.clone().map(readiness::probe_url);
+    if let Some(readiness) = &readiness {
+        commands = commands.with_readiness(readiness.clone());
+    }
+
+    // Shared by heartbeats and the status API, so neither samples more than once per TTL
+    let metrics = MetricsCache::new(config.workdir.clone(), METRICS_CACHE_TTL);
+
     // Create WebSocket client
-    let ws_client = WsClient::new(
-        config.hub_url.clone(),
+    let mut ws_client = WsClient::new(
+        config.hub_urls.clone(),
         config.provider,
         config.get_provider_instance_id(),
         config.get_hostname(),
         gpu_info.clone(),
         tailscale_ip,
-    );
+        commands,
+    )
+    .with_display_name(config.display_name.clone())
+    .with_wire_codec(config.wire_codec)
+    .with_max_message_bytes(config.max_message_bytes)
+    .with_send_timeout(config.send_timeout)
+    .with_heartbeat_timeout(config.heartbeat_timeout, config.heartbeat_grace)
+    .with_max_session_duration(config.max_session_duration)
+    .with_max_reconnect_attempts(config.max_reconnect_attempts)
+    .with_preflight(config.hub_preflight)
+    .with_log_ws_payloads(config.log_ws_payloads)
+    .with_metrics(metrics.clone(), config.metrics_max_interval)
+    .with_capabilities(capabilities.into_iter().map(String::from).collect())
+    .with_diagnostics(&diagnostics)
+    .with_state_file(StateFile::in_dir(&config.workdir))
+    .with_hooks(ConnectionHooks::new(
+        config.on_connect_command.clone(),
+        config.on_disconnect_command.clone(),
+        config.hook_timeout,
+    ));
+    if let Some(readiness) = readiness {
+        ws_client = ws_client.with_readiness(readiness);
+    }
 
-    // Spawn WebSocket client task
+    // Spawn WebSocket client task; it only returns early if it gives up on the hub
     let ws_handle = {
         let ws_client = ws_client.clone();
-        tokio::spawn(async move {
-            if let Err(e) = ws_client.run().await {
-                error!("WebSocket client error: {}", e);
-            }
-        })
+        tokio::spawn(async move { ws_client.run().await })
     };
 
     // Create and run status API server
-    let app = Router::new().route("/status", get(get_status));
+    let shutdown_report = SharedShutdownReport::default();
+    let mut app = Router::new()
+        .route("/status", get(get_status))
+        .with_state(StatusState {
+            format: config.status_format,
+            start_time,
+            shutdown: shutdown_report.clone(),
+            metrics,
+            ws_client: ws_client.clone(),
+            models: model_store,
+        });
+    match status_cors_layer(&config.status_cors_origins) {
+        Ok(Some(cors)) => {
+            info!(origins = ?config.status_cors_origins, "CORS enabled for status API");
+            app = app.layer(cors);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("Invalid STATUS_CORS_ORIGINS: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
     let addr = SocketAddr::from(([0, 0, 0, 0], config.status_port));
 
     info!(address = %addr, "starting status API server");
 
-    let result = match tokio::net::TcpListener::bind(addr).await {
+    match tokio::net::TcpListener::bind(addr).await {
         Ok(listener) => {
-            // Run server with graceful shutdown
+            // Run server with graceful shutdown; the WebSocket client is stopped before
+            // the server, so the shutdown report stays readable from /status meanwhile
             if let Err(error) = axum::serve(listener, app)
-                .with_graceful_shutdown(shutdown_signal(start_time))
+                .with_graceful_shutdown(graceful_shutdown(
+                    start_time,
+                    ws_client,
+                    ws_handle,
+                    webui.map(|webui| (webui, config.webui_shutdown_timeout)),
+                    shutdown_report.clone(),
+                ))
                 .await
             {
                 error!(error = ?error, "server error");
                 ExitCode::FAILURE
+            } else if shutdown_report
+                .read()
+                .await
+                .as_ref()
+                .is_some_and(|report| report.fatal_error.is_some())
+            {
+                ExitCode::FAILURE
             } else {
                 info!("stopped gracefully");
                 ExitCode::SUCCESS
@@ -115,27 +332,104 @@ async fn main() -> ExitCode {
             error!(error = ?error, "failed to bind TCP listener");
             ExitCode::FAILURE
         }
-    };
+    }
+}
 
-    // Shutdown WebSocket client
+/// Wait for a shutdown signal, then stop the WebSocket client and WebUI and log a
+/// [`ShutdownReport`]
+///
+/// The WebUI drains alongside the hub disconnect, so its grace period isn't spent
+/// waiting on the close handshake. If the WebSocket client gives up first, shutdown
+/// proceeds immediately and the report records the fatal error.
+async fn graceful_shutdown(
+    start_time: Instant,
+    ws_client: WsClient,
+    mut ws_handle: JoinHandle<anyhow::Result<()>>,
+    webui: Option<(WebUiSupervisor, Duration)>,
+    report: SharedShutdownReport,
+) {
+    let signal = tokio::select! {
+        signal = shutdown_signal(start_time) => signal,
+        result = &mut ws_handle => {
+            let error = match result {
+                Ok(Ok(())) => "WebSocket client stopped unexpectedly".to_string(),
+                Ok(Err(e)) => format!("{:#}", e),
+                Err(e) => format!("WebSocket client task failed: {}", e),
+            };
+            error!(error = %error, uptime_secs = start_time.elapsed().as_secs(), "fatal error, shutting down");
+            let mut fatal = ShutdownReport::fatal(error, start_time.elapsed().as_secs());
+            if let Some((webui, timeout)) = &webui {
+                fatal.webui_drain = Some(webui.drain(*timeout).await);
+            }
+            *report.write().await = Some(fatal);
+            return;
+        }
+    };
     let shutdown_start = Instant::now();
-    let ws_shutdown_start = Instant::now();
+    *report.write().await = Some(ShutdownReport::started(
+        signal,
+        start_time.elapsed().as_secs(),
+    ));
+
+    // Shutdown WebSocket client and WebUI together
     ws_client.shutdown();
-    let _ = ws_handle.await;
-    let ws_shutdown_duration = ws_shutdown_start.elapsed().as_millis() as u64;
+    let stop_ws = async {
+        let _ = ws_handle.await;
+        shutdown_start.elapsed().as_millis() as u64
+    };
+    let drain_webui = async {
+        match &webui {
+            Some((webui, timeout)) => Some(webui.drain(*timeout).await),
+            None => None,
+        }
+    };
+    let (ws_client_ms, webui_drain) = tokio::join!(stop_ws, drain_webui);
+    let ws_close = ws_client.close_stats().await;
+
+    let mut guard = report.write().await;
+    let Some(report) = guard.as_mut() else {
+        return;
+    };
+    report.ws_close = ws_close;
+    report.ws_client_ms = Some(ws_client_ms);
+    report.webui_drain = webui_drain;
+    report.total_shutdown_ms = Some(shutdown_start.elapsed().as_millis() as u64);
 
     info!(
-        total_shutdown_ms = shutdown_start.elapsed().as_millis() as u64,
-        ws_client_ms = ws_shutdown_duration,
+        signal = %report.signal,
+        uptime_secs = report.uptime_secs,
+        ws_close_send_ms = ws_close.map(|c| c.close_send_ms),
+        hub_acked_close = ws_close.map(|c| c.hub_acked),
+        ws_client_ms,
+        webui_drain = ?webui_drain,
+        total_shutdown_ms = report.total_shutdown_ms,
         graceful = true,
         "shutdown complete"
     );
+}
+
+/// Build the CORS layer for the status API, or `None` when no origins are configured
+fn status_cors_layer(origins: &[String]) -> Result<Option<CorsLayer>, String> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin).map_err(|_| format!("'{}' is not a valid origin", origin))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    result
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods([Method::GET]),
+    ))
 }
 
-/// Wait for SIGTERM or SIGINT signal for graceful shutdown
-async fn shutdown_signal(start_time: Instant) {
+/// Wait for SIGTERM or SIGINT signal for graceful shutdown, returning the signal name
+async fn shutdown_signal(start_time: Instant) -> &'static str {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -160,6 +454,7 @@ async fn shutdown_signal(start_time: Instant) {
                 uptime_secs = start_time.elapsed().as_secs(),
                 "shutdown initiated"
             );
+            "SIGINT"
         }
         _ = terminate => {
             info!(
@@ -167,6 +462,7 @@ async fn shutdown_signal(start_time: Instant) {
                 uptime_secs = start_time.elapsed().as_secs(),
                 "shutdown initiated"
             );
+            "SIGTERM"
         }
     }
 }
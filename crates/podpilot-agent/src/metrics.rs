@@ -0,0 +1,230 @@
+//! Sampling system, GPU, and disk usage.
+//!
+//! Sampling shells out to nvidia-smi and df, so snapshots are cached for a short
+//! TTL and shared by every caller within it. [`MetricsThrottle`] decides which
+//! samples are worth sending to the hub.
+
+use anyhow::{Context, anyhow};
+use chrono::Utc;
+use podpilot_common::rpc::{DiskUsage, Metrics};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::gpu;
+
+/// How long a snapshot is reused before sampling again
+pub const METRICS_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Default longest gap between metrics sent to the hub, even if nothing changed
+pub const DEFAULT_METRICS_MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Change in GPU utilization, in percentage points, worth sending early
+const UTILIZATION_DELTA: u8 = 5;
+/// Change in GPU temperature, in degrees Celsius, worth sending early
+const TEMPERATURE_DELTA: u8 = 3;
+/// Change in GPU memory, system memory, or disk usage, as a percentage of the total,
+/// worth sending early
+const USAGE_DELTA_PERCENT: u64 = 5;
+
+/// Metrics and disk usage sampled together
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub metrics: Metrics,
+    pub disk: DiskUsage,
+}
+
+/// Cached sampler for [`Snapshot`]s
+#[derive(Clone)]
+pub struct MetricsCache {
+    /// Path whose filesystem is reported as disk usage
+    disk_path: PathBuf,
+    ttl: Duration,
+    cached: Arc<Mutex<Option<(Instant, Snapshot)>>>,
+    /// Metrics from the latest sample, readable while a new one is being taken
+    latest: Arc<std::sync::Mutex<Option<Metrics>>>,
+}
+
+impl MetricsCache {
+    pub fn new(disk_path: PathBuf, ttl: Duration) -> Self {
+        Self {
+            disk_path,
+            ttl,
+            cached: Arc::new(Mutex::new(None)),
+            latest: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    /// Return the cached snapshot, sampling a new one if it has expired
+    ///
+    /// Concurrent callers wait for a single sample rather than each running one.
+    pub async fn snapshot(&self) -> anyhow::Result<Snapshot> {
+        let mut cached = self.cached.lock().await;
+        if let Some((sampled_at, snapshot)) = cached.as_ref()
+            && sampled_at.elapsed() < self.ttl
+        {
+            return Ok(snapshot.clone());
+        }
+
+        let disk_path = self.disk_path.clone();
+        let snapshot = tokio::task::spawn_blocking(move || sample(&disk_path))
+            .await
+            .context("Metrics sampling task failed")??;
+        *cached = Some((Instant::now(), snapshot.clone()));
+        *self.latest.lock().expect("latest metrics lock poisoned") = Some(snapshot.metrics.clone());
+        Ok(snapshot)
+    }
+
+    /// The most recently sampled metrics, however old
+    ///
+    /// Never samples or waits for a sample in progress.
+    pub fn latest(&self) -> Option<Metrics> {
+        self.latest
+            .lock()
+            .expect("latest metrics lock poisoned")
+            .clone()
+    }
+}
+
+/// Limits metrics sent to the hub to samples that changed meaningfully
+///
+/// A sample is sent when any tracked value moved by more than its delta since the
+/// last sent one, or once `max_interval` has passed regardless.
+#[derive(Debug)]
+pub struct MetricsThrottle {
+    max_interval: Duration,
+    last_sent: Option<(Instant, Metrics)>,
+}
+
+impl MetricsThrottle {
+    pub fn new(max_interval: Duration) -> Self {
+        Self {
+            max_interval,
+            last_sent: None,
+        }
+    }
+
+    /// Forget the last sent sample, so the next one is always sent (e.g. after reconnecting)
+    pub fn reset(&mut self) {
+        self.last_sent = None;
+    }
+
+    /// Whether `metrics` should be sent, recording it as sent if so
+    pub fn should_send(&mut self, metrics: &Metrics) -> bool {
+        let send = match &self.last_sent {
+            None => true,
+            Some((sent_at, previous)) => {
+                sent_at.elapsed() >= self.max_interval || changed_meaningfully(previous, metrics)
+            }
+        };
+        if send {
+            self.last_sent = Some((Instant::now(), metrics.clone()));
+        }
+        send
+    }
+}
+
+/// Whether any tracked value moved by more than its delta
+fn changed_meaningfully(previous: &Metrics, current: &Metrics) -> bool {
+    let usage_moved = |before: u64, after: u64, total: u64| {
+        total > 0 && before.abs_diff(after) * 100 / total >= USAGE_DELTA_PERCENT
+    };
+
+    previous.gpu_utilization.abs_diff(current.gpu_utilization) >= UTILIZATION_DELTA
+        || match (previous.gpu_temperature, current.gpu_temperature) {
+            (Some(before), Some(after)) => before.abs_diff(after) >= TEMPERATURE_DELTA,
+            (before, after) => before.is_some() != after.is_some(),
+        }
+        || usage_moved(
+            previous.gpu_memory_used,
+            current.gpu_memory_used,
+            current.gpu_memory_total,
+        )
+        || usage_moved(previous.memory_used, current.memory_used, current.memory_total)
+        || usage_moved(previous.disk_used, current.disk_used, current.disk_total)
+        // Totals only change if hardware or mounts change, which is always worth sending
+        || previous.gpu_memory_total != current.gpu_memory_total
+        || previous.memory_total != current.memory_total
+        || previous.disk_total != current.disk_total
+}
+
+/// Sample everything now; a missing GPU reports zeros
+fn sample(disk_path: &Path) -> anyhow::Result<Snapshot> {
+    let gpu = gpu::sample_gpu().unwrap_or_default();
+    let disk = disk_usage(disk_path)?;
+    let (memory_used, memory_total) = memory_usage().unwrap_or((0, 0));
+
+    Ok(Snapshot {
+        metrics: Metrics {
+            gpu_memory_used: gpu.memory_used,
+            gpu_memory_total: gpu.memory_total,
+            gpu_utilization: gpu.utilization,
+            gpu_temperature: gpu.temperature,
+            disk_used: disk.used,
+            disk_total: disk.total,
+            memory_used,
+            memory_total,
+            collected_at: Utc::now(),
+        },
+        disk,
+    })
+}
+
+/// Usage of the filesystem holding `path`, from POSIX `df`
+pub fn disk_usage(path: &Path) -> anyhow::Result<DiskUsage> {
+    let output = Command::new("df")
+        .arg("-P")
+        .arg("-k")
+        .arg(path)
+        .output()
+        .context("Failed to run df")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "df {} exited with {}: {}",
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // Filesystem 1024-blocks Used Available Capacity Mounted-on
+    let text = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = text
+        .lines()
+        .nth(1)
+        .ok_or_else(|| anyhow!("df printed no filesystem line"))?
+        .split_whitespace()
+        .collect();
+    let kib = |index: usize| -> anyhow::Result<u64> {
+        let value = fields
+            .get(index)
+            .ok_or_else(|| anyhow!("df output is missing column {}", index))?;
+        Ok(value.parse::<u64>().context("Unexpected df output")? * 1024)
+    };
+
+    let total = kib(1)?;
+    let used = kib(2)?;
+    Ok(DiskUsage {
+        total,
+        used,
+        available: kib(3)?,
+        usage_percent: (used * 100).checked_div(total).unwrap_or(0) as u8,
+        path: path.display().to_string(),
+    })
+}
+
+/// System memory `(used, total)` in bytes from `/proc/meminfo`, `None` off Linux
+fn memory_usage() -> Option<(u64, u64)> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = meminfo.lines().find(|line| line.starts_with(name))?;
+        let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kib * 1024)
+    };
+
+    let total = field("MemTotal:")?;
+    let available = field("MemAvailable:")?;
+    Some((total.saturating_sub(available), total))
+}
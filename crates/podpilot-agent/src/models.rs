@@ -0,0 +1,1133 @@
+//! Model file management within the agent's models directory.
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use podpilot_common::rpc::ModelSpec;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::metrics::disk_usage;
+use crate::r2::R2Client;
+
+/// Minimum time between progress log lines during a download
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Manifest file (inside `models_dir`) mapping model IDs to filenames
+const MANIFEST_FILENAME: &str = ".podpilot-models.json";
+
+/// Number of recent evictions kept for the status API
+const EVICTION_HISTORY: usize = 20;
+
+/// Manifest record for a downloaded model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredEntry")]
+struct ManifestEntry {
+    filename: String,
+    /// Last time the hub asked for this model (downloaded or kept by a sync)
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+/// On-disk manifest entry; older agents stored only the filename
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Filename(String),
+    Entry {
+        filename: String,
+        #[serde(default)]
+        last_used_at: Option<DateTime<Utc>>,
+    },
+}
+
+impl From<StoredEntry> for ManifestEntry {
+    fn from(stored: StoredEntry) -> Self {
+        match stored {
+            StoredEntry::Filename(filename) => Self {
+                filename,
+                last_used_at: None,
+            },
+            StoredEntry::Entry {
+                filename,
+                last_used_at,
+            } => Self {
+                filename,
+                last_used_at,
+            },
+        }
+    }
+}
+
+type Manifest = HashMap<Uuid, ManifestEntry>;
+
+/// Why a model was evicted
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// Made room for a download the disk couldn't otherwise fit
+    DiskSpace,
+    /// Brought total model size back under `max_models_bytes`
+    SizeLimit,
+}
+
+/// A model removed to free space
+#[derive(Debug, Clone, Serialize)]
+pub struct EvictionEvent {
+    pub model_id: Uuid,
+    pub filename: String,
+    pub bytes: u64,
+    pub reason: EvictionReason,
+    pub evicted_at: DateTime<Utc>,
+}
+
+/// Current model storage, for the status API
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelUsage {
+    pub models: usize,
+    pub bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// Most recent first
+    pub recent_evictions: Vec<EvictionEvent>,
+}
+
+/// Result of a completed, verified model download
+#[derive(Debug)]
+pub struct DownloadOutcome {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// Offset the download resumed from (0 for a fresh download)
+    pub resumed_from: u64,
+    pub elapsed: Duration,
+    /// Models evicted afterwards to stay under `max_models_bytes`
+    pub evicted: Vec<Uuid>,
+}
+
+/// What a sync changed, reported back to the hub
+#[derive(Debug, Default, Serialize)]
+pub struct SyncOutcome {
+    pub downloaded: Vec<Uuid>,
+    pub deleted: Vec<Uuid>,
+    /// Desired models that were already present
+    pub unchanged: Vec<Uuid>,
+    pub failed: Vec<SyncFailure>,
+    /// Models removed to stay under `max_models_bytes`, which may include desired ones
+    pub evicted: Vec<Uuid>,
+    /// Models on disk once the sync finished
+    pub present: Vec<Uuid>,
+    /// Bytes transferred by this sync, not counting resumed partial downloads
+    pub bytes_downloaded: u64,
+    /// Bytes freed by deletions
+    pub bytes_freed: u64,
+}
+
+/// A model the sync couldn't download or delete
+#[derive(Debug, Serialize)]
+pub struct SyncFailure {
+    pub model_id: Uuid,
+    pub error: String,
+}
+
+/// Raw result of streaming an object to disk
+struct FetchedFile {
+    bytes: u64,
+    resumed_from: u64,
+    sha256: String,
+}
+
+/// Error from checking for room before a download
+#[derive(Debug, thiserror::Error)]
+pub enum SpaceError {
+    /// Not enough free space, even counting what eviction could free
+    #[error(
+        "insufficient space for {filename}: needs {needed} bytes, {available} available, {evictable} evictable"
+    )]
+    Insufficient {
+        filename: String,
+        needed: u64,
+        available: u64,
+        evictable: u64,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Error from deleting a model file
+#[derive(Debug, thiserror::Error)]
+pub enum DeleteError {
+    /// The model is unknown to this agent or its file is already gone
+    #[error("model {0} not found on this agent")]
+    NotFound(Uuid),
+    /// The resolved path escapes `models_dir` (via `..` or a symlink)
+    #[error("refusing to delete '{0}': path escapes the models directory")]
+    OutsideModelsDir(PathBuf),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Manages model files stored under `models_dir`
+pub struct ModelStore {
+    models_dir: PathBuf,
+    r2: Option<R2Client>,
+    http: reqwest::Client,
+    /// Every verified download, keyed by model ID
+    manifest: Mutex<Manifest>,
+    /// Total model size to stay under by evicting least-recently-used models
+    max_models_bytes: Option<u64>,
+    evictions: Mutex<VecDeque<EvictionEvent>>,
+}
+
+impl ModelStore {
+    /// Create a new model store rooted at `models_dir`
+    pub fn new(models_dir: PathBuf, r2: Option<R2Client>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .build()
+            .context("Failed to create HTTP client")?;
+        let manifest = load_manifest(&models_dir.join(MANIFEST_FILENAME))?;
+
+        Ok(Self {
+            models_dir,
+            r2,
+            http,
+            manifest: Mutex::new(manifest),
+            max_models_bytes: None,
+            evictions: Mutex::new(VecDeque::with_capacity(EVICTION_HISTORY)),
+        })
+    }
+
+    /// Evict least-recently-used models whenever their total size exceeds `max_bytes`
+    pub fn with_max_models_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_models_bytes = max_bytes;
+        self
+    }
+
+    /// Directory where model files are stored
+    pub fn models_dir(&self) -> &Path {
+        &self.models_dir
+    }
+
+    /// Download a model from R2 into `models_dir/<filename>`
+    ///
+    /// The body is streamed to `<filename>.part` while being hashed. An existing
+    /// `.part` file from an interrupted attempt is resumed with an HTTP range
+    /// request. Once complete, the SHA256 is checked against `expected_sha256`:
+    /// on a match the file is atomically renamed into place, otherwise it is
+    /// removed and an error is returned. Transfer errors keep the `.part` file so
+    /// the next attempt can resume.
+    pub async fn download(
+        &self,
+        model_id: Uuid,
+        r2_key: &str,
+        filename: &str,
+        expected_sha256: &str,
+    ) -> Result<DownloadOutcome> {
+        let r2 = self
+            .r2
+            .as_ref()
+            .ok_or_else(|| anyhow!("R2 credentials are not configured on this agent"))?;
+
+        let path = self.model_path(filename)?;
+        let part_path = part_path(&path);
+        fs::create_dir_all(&self.models_dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.models_dir.display()))?;
+
+        let start = Instant::now();
+        let fetched = self.fetch_to_file(r2, r2_key, &part_path).await?;
+
+        if !fetched.sha256.eq_ignore_ascii_case(expected_sha256) {
+            remove_partial(&part_path).await;
+            anyhow::bail!(
+                "SHA256 mismatch for {}: expected {}, got {}",
+                filename,
+                expected_sha256,
+                fetched.sha256
+            );
+        }
+
+        fs::rename(&part_path, &path)
+            .await
+            .with_context(|| format!("Failed to move download into {}", path.display()))?;
+
+        let elapsed = start.elapsed();
+        info!(
+            path = %path.display(),
+            bytes = fetched.bytes,
+            resumed_from = fetched.resumed_from,
+            duration_ms = elapsed.as_millis() as u64,
+            "model download verified"
+        );
+
+        {
+            let mut manifest = self.manifest.lock().await;
+            manifest.insert(
+                model_id,
+                ManifestEntry {
+                    filename: filename.to_string(),
+                    last_used_at: Some(Utc::now()),
+                },
+            );
+            self.save_manifest(&manifest).await?;
+        }
+
+        let evicted = self.enforce_size_limit(model_id).await;
+
+        Ok(DownloadOutcome {
+            path,
+            bytes: fetched.bytes,
+            resumed_from: fetched.resumed_from,
+            elapsed,
+            evicted,
+        })
+    }
+
+    /// Check there is room to download `file_size` bytes as `filename`
+    ///
+    /// Bytes already in a resumable `.part` file count toward the download. With
+    /// `auto_evict`, least-recently-used models (never `model_id` itself) are deleted
+    /// until the download fits; nothing is evicted unless that would make it fit.
+    /// Returns the IDs of evicted models.
+    pub async fn ensure_space(
+        &self,
+        model_id: Uuid,
+        filename: &str,
+        file_size: u64,
+        auto_evict: bool,
+    ) -> Result<Vec<Uuid>, SpaceError> {
+        let path = self.model_path(filename)?;
+        let partial = fs::metadata(part_path(&path))
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let needed = file_size.saturating_sub(partial);
+        let mut available = self.available_space().await?;
+        if available >= needed {
+            return Ok(Vec::new());
+        }
+
+        let candidates = if auto_evict {
+            self.eviction_candidates(model_id).await
+        } else {
+            Vec::new()
+        };
+        let evictable: u64 = candidates.iter().map(|(_, bytes)| bytes).sum();
+        if !auto_evict || available + evictable < needed {
+            return Err(SpaceError::Insufficient {
+                filename: filename.to_string(),
+                needed,
+                available,
+                evictable,
+            });
+        }
+
+        let mut evicted = Vec::new();
+        for (candidate, _) in candidates {
+            if available >= needed {
+                break;
+            }
+            if let Some(freed) = self.evict(candidate, EvictionReason::DiskSpace).await {
+                available += freed;
+                evicted.push(candidate);
+            }
+        }
+
+        // Trust the filesystem over the running total, in case other writes happened
+        let available = self.available_space().await?;
+        if available < needed {
+            return Err(SpaceError::Insufficient {
+                filename: filename.to_string(),
+                needed,
+                available,
+                evictable: 0,
+            });
+        }
+        Ok(evicted)
+    }
+
+    /// Free bytes on the filesystem holding `models_dir`
+    async fn available_space(&self) -> anyhow::Result<u64> {
+        fs::create_dir_all(&self.models_dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.models_dir.display()))?;
+        let models_dir = self.models_dir.clone();
+        let usage = tokio::task::spawn_blocking(move || disk_usage(&models_dir))
+            .await
+            .context("Disk usage task panicked")??;
+        Ok(usage.available)
+    }
+
+    /// Models other than `keep` with their sizes, least recently used first
+    ///
+    /// A model counts as used when the hub last asked for it or when its file was
+    /// last read (the WebUI loading it), whichever is later. Filesystems mounted
+    /// without atime fall back to the modification time.
+    async fn eviction_candidates(&self, keep: Uuid) -> Vec<(Uuid, u64)> {
+        let entries: Vec<(Uuid, ManifestEntry)> = self
+            .manifest
+            .lock()
+            .await
+            .iter()
+            .filter(|(model_id, _)| **model_id != keep)
+            .map(|(model_id, entry)| (*model_id, entry.clone()))
+            .collect();
+
+        let mut candidates = Vec::with_capacity(entries.len());
+        for (model_id, entry) in entries {
+            let Ok(metadata) = fs::metadata(self.models_dir.join(&entry.filename)).await else {
+                continue;
+            };
+            let file_used = metadata
+                .accessed()
+                .or_else(|_| metadata.modified())
+                .ok()
+                .map(DateTime::<Utc>::from);
+            let last_used = file_used.max(entry.last_used_at);
+            candidates.push((last_used, model_id, metadata.len()));
+        }
+        candidates.sort_by_key(|(last_used, _, _)| *last_used);
+        candidates
+            .into_iter()
+            .map(|(_, model_id, bytes)| (model_id, bytes))
+            .collect()
+    }
+
+    /// Evict least-recently-used models (never `keep`) until under `max_models_bytes`
+    ///
+    /// Returns the IDs of evicted models. Does nothing without a limit.
+    async fn enforce_size_limit(&self, keep: Uuid) -> Vec<Uuid> {
+        let Some(max_bytes) = self.max_models_bytes else {
+            return Vec::new();
+        };
+
+        let candidates = self.eviction_candidates(keep).await;
+        let mut total: u64 =
+            self.model_size(keep).await + candidates.iter().map(|(_, bytes)| bytes).sum::<u64>();
+
+        let mut evicted = Vec::new();
+        for (candidate, _) in candidates {
+            if total <= max_bytes {
+                break;
+            }
+            if let Some(freed) = self.evict(candidate, EvictionReason::SizeLimit).await {
+                total = total.saturating_sub(freed);
+                evicted.push(candidate);
+            }
+        }
+
+        if total > max_bytes {
+            warn!(
+                total_bytes = total,
+                max_bytes, "models still exceed MAX_MODELS_BYTES after eviction"
+            );
+        }
+        evicted
+    }
+
+    /// Delete a model to free space and record the eviction
+    ///
+    /// Returns the bytes freed, or `None` (after logging) if it couldn't be deleted.
+    async fn evict(&self, model_id: Uuid, reason: EvictionReason) -> Option<u64> {
+        let filename = self
+            .manifest
+            .lock()
+            .await
+            .get(&model_id)
+            .map(|entry| entry.filename.clone())?;
+
+        match self.delete(model_id).await {
+            Ok(bytes) => {
+                info!(%model_id, bytes, ?reason, "evicted model");
+                let mut evictions = self.evictions.lock().await;
+                if evictions.len() == EVICTION_HISTORY {
+                    evictions.pop_back();
+                }
+                evictions.push_front(EvictionEvent {
+                    model_id,
+                    filename,
+                    bytes,
+                    reason,
+                    evicted_at: Utc::now(),
+                });
+                Some(bytes)
+            }
+            Err(e) => {
+                warn!(%model_id, error = %e, "failed to evict model");
+                None
+            }
+        }
+    }
+
+    /// Size of a downloaded model's file, or 0 if it is unknown or missing
+    async fn model_size(&self, model_id: Uuid) -> u64 {
+        let Some(filename) = self
+            .manifest
+            .lock()
+            .await
+            .get(&model_id)
+            .map(|entry| entry.filename.clone())
+        else {
+            return 0;
+        };
+        fs::metadata(self.models_dir.join(filename))
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0)
+    }
+
+    /// Current model count and size, with recent evictions
+    pub async fn usage(&self) -> ModelUsage {
+        let filenames: Vec<String> = self
+            .manifest
+            .lock()
+            .await
+            .values()
+            .map(|entry| entry.filename.clone())
+            .collect();
+
+        let mut bytes = 0;
+        for filename in &filenames {
+            if let Ok(metadata) = fs::metadata(self.models_dir.join(filename)).await {
+                bytes += metadata.len();
+            }
+        }
+
+        ModelUsage {
+            models: filenames.len(),
+            bytes,
+            max_bytes: self.max_models_bytes,
+            recent_evictions: self.evictions.lock().await.iter().cloned().collect(),
+        }
+    }
+
+    /// Mark models as used now, so they are the last to be evicted
+    async fn touch(&self, model_ids: &[Uuid]) {
+        let mut manifest = self.manifest.lock().await;
+        let now = Utc::now();
+        for model_id in model_ids {
+            if let Some(entry) = manifest.get_mut(model_id) {
+                entry.last_used_at = Some(now);
+            }
+        }
+        if let Err(e) = self.save_manifest(&manifest).await {
+            warn!(
+                error = format!("{:#}", e),
+                "failed to save model last-use times"
+            );
+        }
+    }
+
+    /// Delete a previously downloaded model, returning the number of bytes freed
+    ///
+    /// The path is canonicalized and must still lie within `models_dir`, so a
+    /// tampered manifest entry or a symlink cannot be used to delete arbitrary files.
+    pub async fn delete(&self, model_id: Uuid) -> Result<u64, DeleteError> {
+        let mut manifest = self.manifest.lock().await;
+        let filename = manifest
+            .get(&model_id)
+            .map(|entry| entry.filename.clone())
+            .ok_or(DeleteError::NotFound(model_id))?;
+
+        let root = fs::canonicalize(&self.models_dir).await?;
+        let path = match fs::canonicalize(self.models_dir.join(&filename)).await {
+            Ok(path) => path,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // File vanished out from under us; forget it so the hub can resync
+                manifest.remove(&model_id);
+                self.save_manifest(&manifest)
+                    .await
+                    .map_err(std::io::Error::other)?;
+                return Err(DeleteError::NotFound(model_id));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if path.parent() != Some(root.as_path()) {
+            return Err(DeleteError::OutsideModelsDir(path));
+        }
+
+        let metadata = fs::metadata(&path).await?;
+        if !metadata.is_file() {
+            return Err(DeleteError::OutsideModelsDir(path));
+        }
+
+        fs::remove_file(&path).await?;
+        manifest.remove(&model_id);
+        self.save_manifest(&manifest)
+            .await
+            .map_err(std::io::Error::other)?;
+
+        info!(%model_id, path = %path.display(), bytes = metadata.len(), "model deleted");
+        Ok(metadata.len())
+    }
+
+    /// Path of `spec`'s model, downloading it first if this agent doesn't have it
+    ///
+    /// Makes room by evicting least-recently-used models when needed. Returns the
+    /// path and the IDs of any evicted models.
+    pub async fn ensure_model(&self, spec: &ModelSpec) -> Result<(PathBuf, Vec<Uuid>)> {
+        let existing = self
+            .manifest
+            .lock()
+            .await
+            .get(&spec.model_id)
+            .map(|entry| entry.filename.clone());
+        if let Some(filename) = existing {
+            let path = self.models_dir.join(filename);
+            if fs::try_exists(&path).await.unwrap_or(false) {
+                self.touch(&[spec.model_id]).await;
+                return Ok((path, Vec::new()));
+            }
+        }
+
+        let mut evicted = match spec.file_size {
+            Some(file_size) => {
+                self.ensure_space(spec.model_id, &spec.filename, file_size, true)
+                    .await?
+            }
+            None => Vec::new(),
+        };
+        let download = self
+            .download(
+                spec.model_id,
+                &spec.r2_key,
+                &spec.filename,
+                &spec.sha256_hash,
+            )
+            .await?;
+        evicted.extend(download.evicted);
+        Ok((download.path, evicted))
+    }
+
+    /// Delete models not in `desired` and download the missing ones
+    ///
+    /// Deletes run first so downloads have the freed space. Failures are collected
+    /// rather than aborting, so one bad model doesn't block the rest of the set.
+    pub async fn sync(&self, desired: &[ModelSpec]) -> SyncOutcome {
+        let present: HashSet<Uuid> = self.manifest.lock().await.keys().copied().collect();
+        let wanted: HashSet<Uuid> = desired.iter().map(|spec| spec.model_id).collect();
+        let mut outcome = SyncOutcome::default();
+
+        // Kept models were just asked for, so downloads shouldn't evict them first
+        let kept: Vec<Uuid> = present.intersection(&wanted).copied().collect();
+        self.touch(&kept).await;
+
+        for &model_id in present.difference(&wanted) {
+            match self.delete(model_id).await {
+                Ok(freed) => {
+                    outcome.deleted.push(model_id);
+                    outcome.bytes_freed += freed;
+                }
+                // Already gone, which is what the sync wanted
+                Err(DeleteError::NotFound(_)) => outcome.deleted.push(model_id),
+                Err(e) => outcome.failed.push(SyncFailure {
+                    model_id,
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        for spec in desired {
+            if present.contains(&spec.model_id) {
+                outcome.unchanged.push(spec.model_id);
+                continue;
+            }
+            // Extras are already gone, so there is nothing left to evict
+            if let Some(file_size) = spec.file_size
+                && let Err(e) = self
+                    .ensure_space(spec.model_id, &spec.filename, file_size, false)
+                    .await
+            {
+                outcome.failed.push(SyncFailure {
+                    model_id: spec.model_id,
+                    error: format!("{:#}", e),
+                });
+                continue;
+            }
+            match self
+                .download(
+                    spec.model_id,
+                    &spec.r2_key,
+                    &spec.filename,
+                    &spec.sha256_hash,
+                )
+                .await
+            {
+                Ok(download) => {
+                    outcome.downloaded.push(spec.model_id);
+                    outcome.bytes_downloaded += download.bytes - download.resumed_from;
+                    outcome.evicted.extend(download.evicted);
+                }
+                Err(e) => outcome.failed.push(SyncFailure {
+                    model_id: spec.model_id,
+                    error: format!("{:#}", e),
+                }),
+            }
+        }
+
+        outcome.present = self.manifest.lock().await.keys().copied().collect();
+        outcome
+    }
+
+    /// Persist the manifest atomically (write to a temp file, then rename)
+    async fn save_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let path = self.models_dir.join(MANIFEST_FILENAME);
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec_pretty(manifest).context("Failed to serialize manifest")?;
+
+        fs::write(&tmp, json)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path)
+            .await
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Stream an object into `part_path`, resuming from any existing content
+    async fn fetch_to_file(
+        &self,
+        r2: &R2Client,
+        r2_key: &str,
+        part_path: &Path,
+    ) -> Result<FetchedFile> {
+        let mut existing = match fs::metadata(part_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to stat {}", part_path.display()));
+            }
+        };
+
+        let response = loop {
+            let mut request = self.http.get(r2.presign_get(r2_key));
+            if existing > 0 {
+                request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+            }
+            let response = request
+                .send()
+                .await
+                .context("Failed to request model from R2")?;
+
+            // A 416 means the part file is at least as large as the object, so it
+            // can't be a valid prefix; discard it and start over
+            if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && existing > 0 {
+                warn!(
+                    r2_key,
+                    part_bytes = existing,
+                    "partial download larger than object, restarting"
+                );
+                remove_partial(part_path).await;
+                existing = 0;
+                continue;
+            }
+            break response;
+        };
+
+        // Only a 206 continues the partial file; a 200 means the range was ignored
+        let resumed_from = if response.status() == StatusCode::PARTIAL_CONTENT {
+            existing
+        } else {
+            0
+        };
+        let response = response
+            .error_for_status()
+            .context("R2 rejected model request")?;
+
+        let mut hasher = Sha256::new();
+        let mut file = if resumed_from > 0 {
+            hash_existing(part_path, &mut hasher).await?;
+            fs::OpenOptions::new()
+                .append(true)
+                .open(part_path)
+                .await
+                .with_context(|| format!("Failed to open {}", part_path.display()))?
+        } else {
+            fs::File::create(part_path)
+                .await
+                .with_context(|| format!("Failed to create {}", part_path.display()))?
+        };
+
+        let total = response.content_length().map(|len| len + resumed_from);
+        info!(r2_key, resumed_from, total, "starting model download");
+
+        let mut bytes = resumed_from;
+        let mut last_progress = Instant::now();
+
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.context("Failed to read model download stream")?;
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .await
+                .context("Failed to write model file")?;
+            bytes += chunk.len() as u64;
+
+            if last_progress.elapsed() >= PROGRESS_LOG_INTERVAL {
+                debug!(r2_key, bytes, total, resumed_from, "download progress");
+                last_progress = Instant::now();
+            }
+        }
+
+        file.flush().await.context("Failed to flush model file")?;
+
+        Ok(FetchedFile {
+            bytes,
+            resumed_from,
+            sha256: hex::encode(hasher.finalize()),
+        })
+    }
+
+    /// Resolve a bare filename to a path inside `models_dir`
+    ///
+    /// Rejects anything that isn't a single path component, since filenames
+    /// arrive over the network.
+    fn model_path(&self, filename: &str) -> Result<PathBuf> {
+        let is_plain = Path::new(filename)
+            .file_name()
+            .is_some_and(|name| name == filename);
+        if !is_plain {
+            anyhow::bail!("Invalid model filename '{}'", filename);
+        }
+        Ok(self.models_dir.join(filename))
+    }
+}
+
+/// Path of the in-progress download for a final model path
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Feed the contents of an existing partial download into `hasher`
+async fn hash_existing(path: &Path, hasher: &mut Sha256) -> Result<()> {
+    let mut file = fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .await
+            .context("Failed to read partial download")?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..read]);
+    }
+}
+
+/// Load the model manifest, treating a missing file as empty
+fn load_manifest(path: &Path) -> Result<Manifest> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .with_context(|| format!("Failed to parse model manifest {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Best-effort removal of a partially written or unverified file
+async fn remove_partial(path: &Path) {
+    if let Err(e) = fs::remove_file(path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!(path = %path.display(), error = %e, "failed to remove partial download");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::R2Config;
+    use axum::Router;
+    use axum::http::HeaderMap;
+    use axum::response::IntoResponse;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// A store rooted at `<tmp>/models` whose manifest maps one model to `filename`,
+    /// next to a file outside the models directory that must never be deleted
+    struct Fixture {
+        tmp: TempDir,
+        store: ModelStore,
+        model_id: Uuid,
+    }
+
+    impl Fixture {
+        async fn new(filename: impl Into<String>) -> Self {
+            let tmp = TempDir::new().unwrap();
+            std::fs::create_dir(tmp.path().join("models")).unwrap();
+            std::fs::write(tmp.path().join("victim.txt"), b"keep me").unwrap();
+
+            let store = ModelStore::new(tmp.path().join("models"), None).unwrap();
+            let model_id = Uuid::new_v4();
+            store.manifest.lock().await.insert(
+                model_id,
+                ManifestEntry {
+                    filename: filename.into(),
+                    last_used_at: None,
+                },
+            );
+            Self {
+                tmp,
+                store,
+                model_id,
+            }
+        }
+
+        fn models_dir(&self) -> PathBuf {
+            self.tmp.path().join("models")
+        }
+
+        fn victim(&self) -> PathBuf {
+            self.tmp.path().join("victim.txt")
+        }
+
+        fn assert_victim_intact(&self) {
+            assert_eq!(std::fs::read(self.victim()).unwrap(), b"keep me");
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_removes_a_model_inside_the_models_dir() {
+        let fixture = Fixture::new("model.safetensors").await;
+        let path = fixture.models_dir().join("model.safetensors");
+        std::fs::write(&path, b"weights").unwrap();
+
+        let freed = fixture.store.delete(fixture.model_id).await.unwrap();
+
+        assert_eq!(freed, 7);
+        assert!(!path.exists());
+        assert!(
+            !fixture
+                .store
+                .manifest
+                .lock()
+                .await
+                .contains_key(&fixture.model_id)
+        );
+        fixture.assert_victim_intact();
+    }
+
+    #[tokio::test]
+    async fn delete_rejects_parent_dir_filenames() {
+        let fixture = Fixture::new("../victim.txt").await;
+
+        let result = fixture.store.delete(fixture.model_id).await;
+
+        assert!(matches!(result, Err(DeleteError::OutsideModelsDir(_))));
+        fixture.assert_victim_intact();
+    }
+
+    #[tokio::test]
+    async fn delete_rejects_absolute_paths() {
+        let tmp = TempDir::new().unwrap();
+        let outside = tmp.path().join("victim.txt");
+        std::fs::write(&outside, b"keep me").unwrap();
+        let fixture = Fixture::new(outside.to_string_lossy()).await;
+
+        let result = fixture.store.delete(fixture.model_id).await;
+
+        assert!(matches!(result, Err(DeleteError::OutsideModelsDir(_))));
+        assert_eq!(std::fs::read(&outside).unwrap(), b"keep me");
+        fixture.assert_victim_intact();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn delete_rejects_symlinks_leaving_the_models_dir() {
+        let fixture = Fixture::new("model.safetensors").await;
+        let link = fixture.models_dir().join("model.safetensors");
+        std::os::unix::fs::symlink(fixture.victim(), &link).unwrap();
+
+        let result = fixture.store.delete(fixture.model_id).await;
+
+        assert!(matches!(result, Err(DeleteError::OutsideModelsDir(_))));
+        assert!(link.symlink_metadata().is_ok());
+        fixture.assert_victim_intact();
+    }
+
+    #[tokio::test]
+    async fn delete_rejects_directories() {
+        let fixture = Fixture::new("model.safetensors").await;
+        let dir = fixture.models_dir().join("model.safetensors");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("inner.bin"), b"nested").unwrap();
+
+        let result = fixture.store.delete(fixture.model_id).await;
+
+        assert!(matches!(result, Err(DeleteError::OutsideModelsDir(_))));
+        assert_eq!(std::fs::read(dir.join("inner.bin")).unwrap(), b"nested");
+        fixture.assert_victim_intact();
+    }
+
+    #[tokio::test]
+    async fn delete_forgets_a_missing_file() {
+        let fixture = Fixture::new("model.safetensors").await;
+
+        let result = fixture.store.delete(fixture.model_id).await;
+
+        assert!(matches!(result, Err(DeleteError::NotFound(id)) if id == fixture.model_id));
+        assert!(
+            !fixture
+                .store
+                .manifest
+                .lock()
+                .await
+                .contains_key(&fixture.model_id)
+        );
+        fixture.assert_victim_intact();
+    }
+
+    #[tokio::test]
+    async fn delete_rejects_unknown_models() {
+        let fixture = Fixture::new("model.safetensors").await;
+
+        let result = fixture.store.delete(Uuid::new_v4()).await;
+
+        assert!(matches!(result, Err(DeleteError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn model_path_accepts_only_plain_filenames() {
+        let fixture = Fixture::new("model.safetensors").await;
+        let store = &fixture.store;
+
+        assert_eq!(
+            store.model_path("model.safetensors").unwrap(),
+            fixture.models_dir().join("model.safetensors")
+        );
+        for filename in [
+            "../victim.txt",
+            "..",
+            ".",
+            "",
+            "nested/model.safetensors",
+            "/etc/passwd",
+        ] {
+            assert!(
+                store.model_path(filename).is_err(),
+                "accepted {:?}",
+                filename
+            );
+        }
+    }
+
+    const OBJECT: &[u8] = b"podpilot test model weights, long enough to split into parts";
+
+    /// `Range` headers of the requests a test object server received
+    type SeenRanges = Arc<std::sync::Mutex<Vec<Option<String>>>>;
+
+    /// Serve [`OBJECT`] for any key, answering range requests with 206 (or 416 past
+    /// the end) when `honor_ranges` is set and ignoring them with a 200 otherwise
+    async fn serve_object(honor_ranges: bool) -> (R2Client, SeenRanges) {
+        let seen = SeenRanges::default();
+        let recorded = seen.clone();
+        let app = Router::new().fallback(move |headers: HeaderMap| {
+            let recorded = recorded.clone();
+            async move {
+                let range = headers
+                    .get(reqwest::header::RANGE)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+                recorded.lock().unwrap().push(range.clone());
+
+                let start = range
+                    .as_deref()
+                    .and_then(|range| range.strip_prefix("bytes="))
+                    .and_then(|range| range.strip_suffix('-'))
+                    .and_then(|start| start.parse::<usize>().ok());
+                match start {
+                    Some(start) if honor_ranges && start >= OBJECT.len() => {
+                        StatusCode::RANGE_NOT_SATISFIABLE.into_response()
+                    }
+                    Some(start) if honor_ranges => {
+                        (StatusCode::PARTIAL_CONTENT, &OBJECT[start..]).into_response()
+                    }
+                    _ => OBJECT.into_response(),
+                }
+            }
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let r2 = R2Client::from_config(&R2Config {
+            endpoint: Some(endpoint),
+            bucket: Some("models".to_string()),
+            access_key_id: Some("test".to_string().into()),
+            secret_access_key: Some("test".to_string().into()),
+        })
+        .unwrap()
+        .expect("R2 is configured");
+        (r2, seen)
+    }
+
+    /// Download [`OBJECT`] as `model.safetensors` on top of an existing `.part` file
+    async fn download_over_part(
+        honor_ranges: bool,
+        part: &[u8],
+    ) -> (TempDir, DownloadOutcome, Vec<Option<String>>) {
+        let (r2, seen) = serve_object(honor_ranges).await;
+        let tmp = TempDir::new().unwrap();
+        let store = ModelStore::new(tmp.path().to_path_buf(), Some(r2)).unwrap();
+        std::fs::write(tmp.path().join("model.safetensors.part"), part).unwrap();
+
+        let expected_sha256 = hex::encode(Sha256::digest(OBJECT));
+        let outcome = store
+            .download(
+                Uuid::new_v4(),
+                "model.safetensors",
+                "model.safetensors",
+                &expected_sha256,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.bytes, OBJECT.len() as u64);
+        assert_eq!(
+            std::fs::read(tmp.path().join("model.safetensors")).unwrap(),
+            OBJECT
+        );
+        assert!(!tmp.path().join("model.safetensors.part").exists());
+        let seen = seen.lock().unwrap().clone();
+        (tmp, outcome, seen)
+    }
+
+    #[tokio::test]
+    async fn download_appends_to_the_part_file_on_partial_content() {
+        let (_tmp, outcome, seen) = download_over_part(true, &OBJECT[..20]).await;
+
+        assert_eq!(outcome.resumed_from, 20);
+        assert_eq!(seen, [Some("bytes=20-".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn download_truncates_the_part_file_when_the_range_is_ignored() {
+        let (_tmp, outcome, seen) = download_over_part(false, b"not a prefix of it!!").await;
+
+        assert_eq!(outcome.resumed_from, 0);
+        assert_eq!(seen, [Some("bytes=20-".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn download_restarts_when_the_part_file_is_past_the_end() {
+        let oversized = [OBJECT, b" and then some"].concat();
+        let (_tmp, outcome, seen) = download_over_part(true, &oversized).await;
+
+        assert_eq!(outcome.resumed_from, 0);
+        assert_eq!(seen, [Some(format!("bytes={}-", oversized.len())), None]);
+    }
+}
@@ -0,0 +1,55 @@
+//! Read access to model files stored in Cloudflare R2 (S3-compatible).
+
+use anyhow::{Context, Result};
+use reqwest::Url;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use secrecy::ExposeSecret;
+use std::time::Duration;
+
+use crate::config::R2Config;
+
+/// Validity of presigned URLs; only needs to cover the start of the request
+const PRESIGN_EXPIRY: Duration = Duration::from_secs(60 * 10);
+
+/// R2 region name (R2 ignores regions, but SigV4 requires one)
+const R2_REGION: &str = "auto";
+
+/// Presigns R2 requests so downloads can be streamed with a plain HTTP client
+#[derive(Clone)]
+pub struct R2Client {
+    bucket: Bucket,
+    credentials: Credentials,
+}
+
+impl R2Client {
+    /// Build a client from config, returning `None` when R2 is not configured
+    pub fn from_config(config: &R2Config) -> Result<Option<Self>> {
+        let (Some(endpoint), Some(bucket), Some(key), Some(secret)) = (
+            &config.endpoint,
+            &config.bucket,
+            &config.access_key_id,
+            &config.secret_access_key,
+        ) else {
+            return Ok(None);
+        };
+
+        let endpoint: Url = endpoint
+            .parse()
+            .with_context(|| format!("Invalid R2 endpoint URL '{}'", endpoint))?;
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, bucket.clone(), R2_REGION)
+            .context("Failed to configure R2 bucket")?;
+        let credentials = Credentials::new(key.expose_secret(), secret.expose_secret());
+
+        Ok(Some(Self {
+            bucket,
+            credentials,
+        }))
+    }
+
+    /// Presigned GET URL for an object key
+    pub fn presign_get(&self, key: &str) -> Url {
+        self.bucket
+            .get_object(Some(&self.credentials), key)
+            .sign(PRESIGN_EXPIRY)
+    }
+}
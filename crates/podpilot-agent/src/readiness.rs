@@ -0,0 +1,56 @@
+//! Whether the agent can handle workload commands yet.
+//!
+//! The WebUI process starts right away but may take minutes to load before it can
+//! serve anything. With `WEBUI_READY_URL` set, the agent reports itself `starting`
+//! until that URL answers with a 2xx, rejecting workload commands with `not_ready`
+//! meanwhile; the hub holds such commands until the agent reports `ready`.
+
+use podpilot_common::protocol::Readiness;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+/// Delay between readiness requests
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Timeout for each readiness request
+const READY_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Report `Starting` until `url` answers with a success status, then `Ready`
+///
+/// Polls in the background forever; an agent whose WebUI never comes up stays
+/// `starting`, which the hub shows as `readiness` in its agent listing.
+pub fn probe_url(url: String) -> watch::Receiver<Readiness> {
+    let (tx, rx) = watch::channel(Readiness::Starting);
+
+    tokio::spawn(async move {
+        let client = match reqwest::Client::builder()
+            .timeout(READY_REQUEST_TIMEOUT)
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                warn!(error = %e, "failed to create readiness HTTP client, reporting ready");
+                let _ = tx.send(Readiness::Ready);
+                return;
+            }
+        };
+
+        let start = Instant::now();
+        loop {
+            match client.get(&url).send().await {
+                Ok(response) if response.status().is_success() => break,
+                Ok(response) => debug!(status = %response.status(), "WebUI not ready yet"),
+                Err(e) => debug!(error = %e, "WebUI not reachable yet"),
+            }
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        }
+
+        info!(
+            wait_ms = start.elapsed().as_millis() as u64,
+            "WebUI is ready"
+        );
+        let _ = tx.send(Readiness::Ready);
+    });
+
+    rx
+}
@@ -0,0 +1,57 @@
+//! Shutdown timing report.
+//!
+//! Providers kill containers that take too long to stop, so the agent records
+//! what its shutdown spent time on and logs it as a single event.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::webui::WebUiDrain;
+use crate::ws::CloseStats;
+
+/// Shared slot for the report, readable by the status API while shutdown runs
+pub type SharedShutdownReport = Arc<RwLock<Option<ShutdownReport>>>;
+
+/// What happened during the agent's shutdown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownReport {
+    /// Signal that triggered shutdown (SIGINT or SIGTERM), or `fatal_error`
+    pub signal: String,
+    pub uptime_secs: u64,
+    /// How the hub connection was closed; `None` while closing or if no connection was open
+    pub ws_close: Option<CloseStats>,
+    /// Time spent stopping the WebSocket client
+    pub ws_client_ms: Option<u64>,
+    /// How the WebUI was stopped; `None` while draining or if no WebUI is supervised
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webui_drain: Option<WebUiDrain>,
+    /// Time from the signal until shutdown finished
+    pub total_shutdown_ms: Option<u64>,
+    /// Why the agent gave up, when shutdown wasn't triggered by a signal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fatal_error: Option<String>,
+}
+
+impl ShutdownReport {
+    /// Start a report when a shutdown signal arrives
+    pub fn started(signal: &str, uptime_secs: u64) -> Self {
+        Self {
+            signal: signal.to_string(),
+            uptime_secs,
+            ws_close: None,
+            ws_client_ms: None,
+            webui_drain: None,
+            total_shutdown_ms: None,
+            fatal_error: None,
+        }
+    }
+
+    /// Report a shutdown caused by an unrecoverable error rather than a signal
+    pub fn fatal(error: String, uptime_secs: u64) -> Self {
+        Self {
+            fatal_error: Some(error),
+            ..Self::started("fatal_error", uptime_secs)
+        }
+    }
+}
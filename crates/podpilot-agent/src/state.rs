@@ -0,0 +1,67 @@
+//! Agent state persisted across restarts.
+//!
+//! The hub assigns an agent ID at registration. Keeping it in a small JSON file
+//! lets a restarted agent send it back as a hint, so its identity survives
+//! Tailscale IP or instance ID changes.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::warn;
+use uuid::Uuid;
+
+/// File name of the state file within the agent's workdir
+const STATE_FILE_NAME: &str = ".podpilot-agent.json";
+
+/// Contents of the state file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentState {
+    /// Agent ID assigned at the most recent registration
+    pub agent_id: Option<Uuid>,
+}
+
+/// Reads and writes [`AgentState`] in a directory
+#[derive(Debug, Clone)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            path: dir.into().join(STATE_FILE_NAME),
+        }
+    }
+
+    /// Load saved state, falling back to empty state if the file is missing or unreadable
+    pub fn load(&self) -> AgentState {
+        let contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return AgentState::default(),
+            Err(e) => {
+                warn!(path = %self.path.display(), error = %e, "failed to read agent state file");
+                return AgentState::default();
+            }
+        };
+
+        serde_json::from_slice(&contents).unwrap_or_else(|e| {
+            warn!(path = %self.path.display(), error = %e, "ignoring malformed agent state file");
+            AgentState::default()
+        })
+    }
+
+    /// Save state, replacing the file atomically
+    pub async fn save(&self, state: &AgentState) -> Result<()> {
+        let contents =
+            serde_json::to_vec_pretty(state).context("Failed to serialize agent state")?;
+        let tmp_path = self.path.with_extension("json.tmp");
+
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+}
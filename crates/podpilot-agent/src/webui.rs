@@ -0,0 +1,107 @@
+//! Supervision of the WebUI workload process.
+//!
+//! The WebUI runs in its own process group so shutdown can signal it along with
+//! anything it spawned (`sh -c` wrappers, Python workers). On shutdown it gets
+//! SIGTERM and a grace period to finish in-flight generations before SIGKILL.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Default time the WebUI gets to exit after SIGTERM before it is killed
+pub const DEFAULT_WEBUI_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How stopping the WebUI went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "outcome")]
+pub enum WebUiDrain {
+    /// The process had already exited before shutdown began
+    NotRunning,
+    /// The process exited on its own after SIGTERM
+    Exited { drain_ms: u64 },
+    /// The process outlived the timeout and was killed
+    Killed { drain_ms: u64 },
+}
+
+/// Handle to the running WebUI process
+#[derive(Clone)]
+pub struct WebUiSupervisor {
+    child: Arc<Mutex<Option<Child>>>,
+}
+
+impl WebUiSupervisor {
+    /// Start `command` via `sh -c` in a new process group
+    ///
+    /// Output is inherited, so it lands in the container log next to the agent's.
+    pub fn start(command: &str) -> Result<Self> {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to start WebUI")?;
+        info!(pid = child.id(), "WebUI started");
+
+        Ok(Self {
+            child: Arc::new(Mutex::new(Some(child))),
+        })
+    }
+
+    /// Send SIGTERM, wait up to `timeout` for the process to exit, then SIGKILL it
+    pub async fn drain(&self, timeout: Duration) -> WebUiDrain {
+        let Some(mut child) = self.child.lock().await.take() else {
+            return WebUiDrain::NotRunning;
+        };
+        if !matches!(child.try_wait(), Ok(None)) {
+            return WebUiDrain::NotRunning;
+        }
+        let Some(pid) = child.id() else {
+            return WebUiDrain::NotRunning;
+        };
+
+        let drain_start = Instant::now();
+        signal_group(pid, libc::SIGTERM);
+
+        match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(status) => {
+                let drain_ms = drain_start.elapsed().as_millis() as u64;
+                match status {
+                    Ok(status) => info!(%status, drain_ms, "WebUI exited"),
+                    Err(e) => warn!(error = %e, drain_ms, "failed to wait for WebUI"),
+                }
+                WebUiDrain::Exited { drain_ms }
+            }
+            Err(_) => {
+                signal_group(pid, libc::SIGKILL);
+                let _ = child.wait().await;
+                let drain_ms = drain_start.elapsed().as_millis() as u64;
+                warn!(
+                    timeout_secs = timeout.as_secs(),
+                    drain_ms, "WebUI did not exit in time and was killed"
+                );
+                WebUiDrain::Killed { drain_ms }
+            }
+        }
+    }
+}
+
+/// Signal every process in the group led by `pid`
+fn signal_group(pid: u32, signal: libc::c_int) {
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid targets the group
+    let result = unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+    if result != 0 {
+        warn!(
+            pid,
+            signal,
+            error = %std::io::Error::last_os_error(),
+            "failed to signal WebUI process group"
+        );
+    }
+}
@@ -1,70 +1,401 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use podpilot_common::protocol::{
-    AgentInfo, AgentMessage, AgentRegistration, HeartbeatAckMessage, HubMessage,
+    AgentInfo, AgentMessage, AgentRegistration, CommandMessage, CommandResponseMessage, Frame,
+    HeartbeatAckMessage, HubMessage, ProtocolError, ProtocolVersion, Readiness, ReadinessMessage,
+    ResumeMessage, WireCodec, error_code,
 };
+use podpilot_common::redact::{redact_url, redacted_payload};
+use podpilot_common::rpc::Metrics;
 use podpilot_common::types::{GpuInfo, ProviderType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use tokio::sync::{RwLock, watch};
+use tokio::net::TcpStream;
+use tokio::sync::{RwLock, mpsc, watch};
 use tokio::time::{interval, timeout};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use tracing::{debug, error, info, warn};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::http::header::{SEC_WEBSOCKET_PROTOCOL, USER_AGENT};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::protocol::frame::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async_with_config};
+use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
-const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+use super::ConnectionError;
+use crate::commands::CommandHandler;
+use crate::hooks::ConnectionHooks;
+use crate::metrics::{MetricsCache, MetricsThrottle};
+use crate::state::{AgentState, StateFile};
+
+/// Default time without a hub heartbeat before the connection is probed
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default wait for a heartbeat after probing the hub
+pub const DEFAULT_HEARTBEAT_GRACE: Duration = Duration::from_secs(5);
+/// How often heartbeat liveness is checked, at most
+const HEARTBEAT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
 const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(60);
 const RECONNECT_BACKOFF_MULTIPLIER: f64 = 2.0;
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+/// Default limit on how long one send to the hub may stall
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest fraction of `max_session_duration` a session may end early by
+const SESSION_JITTER_FRACTION: f64 = 0.1;
+/// Largest fraction of a busy hub's wait that is added on top of it
+const TRY_LATER_JITTER_FRACTION: f64 = 0.5;
+/// How long to wait for the hub to answer our close frame during shutdown
+const CLOSE_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type WsSender = SplitSink<WsStream, Message>;
+type WsReceiver = SplitStream<WsStream>;
+
+/// A send to the hub didn't complete in time, so the socket is presumed half-open
+#[derive(Debug, thiserror::Error)]
+#[error("Timed out after {0:?} sending to hub")]
+struct SendTimeout(Duration);
+
+/// How the connection to the hub was closed during shutdown
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CloseStats {
+    /// Time taken to send the close frame
+    pub close_send_ms: u64,
+    /// Whether the hub answered with its own close frame before the timeout
+    pub hub_acked: bool,
+}
+
+/// History of the hub connection, exposed on the status endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    /// Connection attempts made after the first one, whether they succeeded or not
+    pub total_reconnects: u64,
+    /// Hub URL of the current session, if connected
+    pub hub_url: Option<String>,
+    /// When the current session was registered, if connected
+    pub connected_since: Option<DateTime<Utc>>,
+    /// Length of the current session, filled in by [`WsClient::connection_stats`]
+    pub session_uptime_secs: Option<u64>,
+    /// When a connection last completed registration
+    pub last_connected_at: Option<DateTime<Utc>>,
+    /// Why the last session ended (e.g. `hub_closed`, `stream_ended`)
+    pub last_disconnect_reason: Option<String>,
+}
+
+/// A registered connection to the hub
+struct HubConnection {
+    sender: WsSender,
+    receiver: WsReceiver,
+    codec: WireCodec,
+    started_at: Instant,
+    /// Readiness reported in the handshake
+    readiness: Readiness,
+}
+
+/// Receiving end of the outbound messages, shared across connections
+struct OutboundQueue {
+    rx: mpsc::Receiver<AgentMessage>,
+    /// Message whose send failed, delivered before anything else on the next connection
+    unsent: Option<AgentMessage>,
+}
+
+impl OutboundQueue {
+    /// Next message to send; cancel safe, like [`mpsc::Receiver::recv`]
+    async fn next(&mut self) -> Option<AgentMessage> {
+        match self.unsent.take() {
+            Some(message) => Some(message),
+            None => self.rx.recv().await,
+        }
+    }
+
+    /// Put a message back at the front of the queue
+    fn put_back(&mut self, message: AgentMessage) {
+        self.unsent = Some(message);
+    }
+}
 
 /// WebSocket client for Agent-to-Hub communication
 #[derive(Clone)]
 pub struct WsClient {
-    hub_url: String,
+    /// Hub URLs to try in turn; at least one
+    hub_urls: Arc<[String]>,
+    /// Index into `hub_urls` of the last URL that connected, tried first on reconnect
+    preferred_hub: Arc<AtomicUsize>,
     provider: ProviderType,
     provider_instance_id: String,
     hostname: String,
+    /// Name shown on the hub instead of the hostname, if set
+    display_name: Option<String>,
     gpu_info: GpuInfo,
     tailscale_ip: IpAddr,
+    commands: CommandHandler,
+    /// Preferred wire codec to offer the hub at registration
+    wire_codec: WireCodec,
+    /// Largest inbound message accepted from the hub
+    max_message_bytes: usize,
+    /// Longest a single send may stall before the connection is treated as lost
+    send_timeout: Duration,
+    /// Reconnect once a session has lasted about this long (`None` never does)
+    max_session_duration: Option<Duration>,
+    /// Time without a hub heartbeat before the connection is probed
+    heartbeat_timeout: Duration,
+    /// Time the probe has to be answered before the connection is declared lost
+    heartbeat_grace: Duration,
+    /// Consecutive failed attempts before giving up (`None` retries forever)
+    max_reconnect_attempts: Option<u32>,
+    /// Boot diagnostics sent with every registration
+    diagnostics: Option<serde_json::Value>,
+    /// Optional features advertised at registration
+    capabilities: Vec<String>,
+    /// Where the assigned agent ID is persisted, if anywhere
+    state_file: Option<StateFile>,
+    /// Commands run when the hub connection is established or lost
+    hooks: Option<ConnectionHooks>,
+    /// Poll the hub's HTTP status endpoint before the first connection attempt
+    preflight: bool,
+    /// Trace-log redacted hub message payloads
+    log_ws_payloads: bool,
+    /// Source of the readiness reported to the hub (`None` is always ready)
+    readiness: Option<watch::Receiver<Readiness>>,
+    /// Source of metrics attached to heartbeat acks, if any
+    metrics: Option<MetricsCache>,
+    /// Decides which samples are worth attaching
+    metrics_throttle: Arc<std::sync::Mutex<MetricsThrottle>>,
+    /// Last agent ID assigned by the hub, sent back as a hint when registering
     agent_id: Arc<RwLock<Option<Uuid>>>,
+    /// Token from the hub's latest acknowledgment during this run, so reconnects can
+    /// resume `agent_id`
+    session_token: Arc<RwLock<Option<Uuid>>>,
+    /// Messages produced outside the receive loop (e.g. command responses)
+    ///
+    /// Outlives each connection, so a command that finishes while the agent is
+    /// reconnecting is answered on the next one.
+    outbound_tx: mpsc::Sender<AgentMessage>,
+    outbound_rx: Arc<tokio::sync::Mutex<OutboundQueue>>,
+    /// Correlation IDs of commands whose response hasn't been sent yet, reported at
+    /// each handshake so the hub keeps waiting for them
+    in_flight: Arc<std::sync::Mutex<HashSet<Uuid>>>,
     last_heartbeat: Arc<RwLock<DateTime<Utc>>>,
+    /// Set when the connection is closed for shutdown
+    close_stats: Arc<RwLock<Option<CloseStats>>>,
+    connection_stats: Arc<RwLock<ConnectionStats>>,
     shutdown_tx: Arc<watch::Sender<bool>>,
     shutdown_rx: watch::Receiver<bool>,
 }
 
 impl WsClient {
     /// Create a new WebSocket client
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hub_urls` is empty.
     pub fn new(
-        hub_url: String,
+        hub_urls: Vec<String>,
         provider: ProviderType,
         provider_instance_id: String,
         hostname: String,
         gpu_info: GpuInfo,
         tailscale_ip: IpAddr,
+        commands: CommandHandler,
     ) -> Self {
+        assert!(!hub_urls.is_empty(), "at least one hub URL is required");
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let (outbound_tx, outbound_rx) = mpsc::channel(32);
 
         Self {
-            hub_url,
+            hub_urls: hub_urls.into(),
+            preferred_hub: Arc::new(AtomicUsize::new(0)),
             provider,
             provider_instance_id,
             hostname,
+            display_name: None,
             gpu_info,
             tailscale_ip,
+            commands,
+            wire_codec: WireCodec::default(),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+            max_session_duration: None,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            heartbeat_grace: DEFAULT_HEARTBEAT_GRACE,
+            max_reconnect_attempts: None,
+            diagnostics: None,
+            capabilities: Vec::new(),
+            state_file: None,
+            hooks: None,
+            preflight: false,
+            log_ws_payloads: false,
+            readiness: None,
+            metrics: None,
+            metrics_throttle: Arc::new(std::sync::Mutex::new(MetricsThrottle::new(
+                crate::metrics::DEFAULT_METRICS_MAX_INTERVAL,
+            ))),
             agent_id: Arc::new(RwLock::new(None)),
+            session_token: Arc::new(RwLock::new(None)),
+            outbound_tx,
+            outbound_rx: Arc::new(tokio::sync::Mutex::new(OutboundQueue {
+                rx: outbound_rx,
+                unsent: None,
+            })),
+            in_flight: Arc::new(std::sync::Mutex::new(HashSet::new())),
             last_heartbeat: Arc::new(RwLock::new(Utc::now())),
+            close_stats: Arc::new(RwLock::new(None)),
+            connection_stats: Arc::new(RwLock::new(ConnectionStats::default())),
             shutdown_tx: Arc::new(shutdown_tx),
             shutdown_rx,
         }
     }
 
+    /// Offer `codec` to the hub at registration (JSON remains the fallback)
+    pub fn with_wire_codec(mut self, codec: WireCodec) -> Self {
+        self.wire_codec = codec;
+        self
+    }
+
+    /// Limit the size of inbound messages; larger ones close the connection
+    pub fn with_max_message_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_message_bytes = max_bytes;
+        self
+    }
+
+    /// Drop the connection when a send stalls for longer than `send_timeout`
+    ///
+    /// Reads alone can't detect a half-open socket whose writes hang; the heartbeat
+    /// monitor only notices once the hub stops sending.
+    pub fn with_send_timeout(mut self, send_timeout: Duration) -> Self {
+        self.send_timeout = send_timeout;
+        self
+    }
+
+    /// Probe the hub after `timeout` without a heartbeat, reconnecting if no
+    /// heartbeat follows within `grace`
+    ///
+    /// The grace window absorbs local stalls (e.g. a paused process) that delay a
+    /// heartbeat without the connection actually being lost. A pong answering the
+    /// probe extends the wait once by another `grace`. A zero grace reconnects as
+    /// soon as the timeout passes.
+    pub fn with_heartbeat_timeout(mut self, timeout: Duration, grace: Duration) -> Self {
+        self.heartbeat_timeout = timeout;
+        self.heartbeat_grace = grace;
+        self
+    }
+
+    /// Close and reconnect healthy sessions after `max_duration`, less up to 10% jitter
+    ///
+    /// For hubs behind proxies or load balancers that silently drop long-lived
+    /// WebSockets.
+    pub fn with_max_session_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_session_duration = max_duration;
+        self
+    }
+
+    /// Give up after `max_attempts` consecutive failed connections (`None` retries forever)
+    pub fn with_max_reconnect_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_reconnect_attempts = max_attempts;
+        self
+    }
+
+    /// Register under a friendly name; blank names are ignored
+    pub fn with_display_name(mut self, display_name: Option<String>) -> Self {
+        self.display_name = display_name
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        self
+    }
+
+    /// Advertise `capabilities` at registration, so the hub only sends commands we can run
+    pub fn with_capabilities(mut self, capabilities: Vec<String>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Attach boot diagnostics to registration messages
+    pub fn with_diagnostics(mut self, diagnostics: &impl serde::Serialize) -> Self {
+        match serde_json::to_value(diagnostics) {
+            Ok(value) => self.diagnostics = Some(value),
+            Err(e) => warn!(error = %e, "failed to serialize boot diagnostics"),
+        }
+        self
+    }
+
+    /// Persist the assigned agent ID in `state_file`, starting from the saved one
+    pub fn with_state_file(mut self, state_file: StateFile) -> Self {
+        let saved = state_file.load().agent_id;
+        if let Some(agent_id) = saved {
+            info!(%agent_id, "loaded persisted agent ID");
+        }
+        self.agent_id = Arc::new(RwLock::new(saved));
+        self.state_file = Some(state_file);
+        self
+    }
+
+    /// Run `hooks` on each successful registration and each closed connection
+    pub fn with_hooks(mut self, hooks: ConnectionHooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Wait for the hub's `/status` to respond before the first connection attempt
+    pub fn with_preflight(mut self, enabled: bool) -> Self {
+        self.preflight = enabled;
+        self
+    }
+
+    /// Trace-log each hub message's payload, with secrets redacted
+    pub fn with_log_ws_payloads(mut self, enabled: bool) -> Self {
+        self.log_ws_payloads = enabled;
+        self
+    }
+
+    /// Report `readiness` at registration and whenever it changes
+    pub fn with_readiness(mut self, readiness: watch::Receiver<Readiness>) -> Self {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    /// Attach metrics from `cache` to heartbeat acks when they changed meaningfully,
+    /// or at least every `max_interval`
+    pub fn with_metrics(mut self, cache: MetricsCache, max_interval: Duration) -> Self {
+        self.metrics = Some(cache);
+        self.metrics_throttle = Arc::new(std::sync::Mutex::new(MetricsThrottle::new(max_interval)));
+        self
+    }
+
     /// Run the WebSocket client with automatic reconnection
+    ///
+    /// A failed connection moves on to the next hub URL; backoff only applies once
+    /// every URL has failed in a row.
     pub async fn run(&self) -> Result<()> {
         let mut backoff = RECONNECT_INITIAL_BACKOFF;
         let mut shutdown_rx = self.shutdown_rx.clone();
         let mut reconnect_count: u32 = 0;
+        // Consecutive failures since the last backoff, to know when every URL has failed
+        let mut failed_urls: usize = 0;
+
+        if self.preflight {
+            tokio::select! {
+                _ = shutdown_rx.changed() => {
+                    debug!("shutdown initiated");
+                    return Ok(());
+                }
+                ready = super::preflight::wait_for_hub(&self.hub_urls) => {
+                    if let Some(index) = ready {
+                        self.preferred_hub.store(index, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        let mut url_index = self.preferred_hub.load(Ordering::Relaxed);
+        let mut first_attempt = true;
 
         loop {
             // Check if shutdown was already signaled to avoid deadlock
@@ -73,20 +404,81 @@ impl WsClient {
                 break;
             }
 
+            if !std::mem::take(&mut first_attempt) {
+                self.connection_stats.write().await.total_reconnects += 1;
+            }
+
             tokio::select! {
                 _ = shutdown_rx.changed() => {
                     debug!("shutdown initiated");
                     break;
                 }
-                result = self.connect_and_handle(reconnect_count) => {
+                connected = self.connect(&self.hub_urls[url_index], reconnect_count) => {
+                    if connected.is_ok() {
+                        self.preferred_hub.store(url_index, Ordering::Relaxed);
+                        let now = Utc::now();
+                        let mut stats = self.connection_stats.write().await;
+                        stats.hub_url = Some(redact_url(&self.hub_urls[url_index]));
+                        stats.connected_since = Some(now);
+                        stats.last_connected_at = Some(now);
+                    }
+                    // Handled outside the race so shutdown can finish the close handshake
+                    let result = match connected {
+                        Ok(connection) => self
+                            .handle_connection(connection)
+                            .await
+                            .map_err(ConnectionError::Transient),
+                        Err(e) => Err(e),
+                    };
                     match result {
                         Ok(_) => {
                             info!("connection closed normally");
                             backoff = RECONNECT_INITIAL_BACKOFF;
                             reconnect_count = 0;
+                            failed_urls = 0;
                         }
-                        Err(e) => {
+                        Err(ConnectionError::Fatal(e)) => {
+                            return Err(e.context("Hub rejected the connection permanently"));
+                        }
+                        // The hub is up but shedding registrations, so wait as asked
+                        // without failing over or counting toward the attempt limit
+                        Err(ConnectionError::TryLater { error, retry_after }) => {
+                            let wait = jittered_try_later_wait(retry_after.max(backoff));
+                            warn!(
+                                error = %error,
+                                retry_after_secs = wait.as_secs_f64(),
+                                "hub is busy, will retry"
+                            );
+                            tokio::time::sleep(wait).await;
+                            backoff = std::cmp::min(
+                                Duration::from_secs_f64(backoff.as_secs_f64() * RECONNECT_BACKOFF_MULTIPLIER),
+                                RECONNECT_MAX_BACKOFF,
+                            );
+                        }
+                        Err(ConnectionError::Transient(e)) => {
                             reconnect_count += 1;
+                            if let Some(max_attempts) = self.max_reconnect_attempts
+                                && reconnect_count >= max_attempts
+                            {
+                                return Err(e.context(format!(
+                                    "Giving up after {} consecutive failed connection attempts",
+                                    reconnect_count
+                                )));
+                            }
+                            // Fail over to the next hub before backing off
+                            url_index = (url_index + 1) % self.hub_urls.len();
+                            failed_urls += 1;
+                            if failed_urls < self.hub_urls.len() {
+                                warn!(
+                                    error = %e,
+                                    attempt = reconnect_count,
+                                    next_hub_url = %redact_url(&self.hub_urls[url_index]),
+                                    "connection failed, trying next hub"
+                                );
+                                continue;
+                            }
+                            failed_urls = 0;
+
                             error!(
                                 error = %e,
                                 attempt = reconnect_count,
@@ -110,110 +502,307 @@ impl WsClient {
         Ok(())
     }
 
-    /// Connect to Hub and handle messages
-    async fn connect_and_handle(&self, attempt: u32) -> Result<()> {
-        let session_start = Instant::now();
+    /// Connect to the hub at `hub_url` and complete registration
+    async fn connect(&self, hub_url: &str, attempt: u32) -> Result<HubConnection, ConnectionError> {
         let connect_start = Instant::now();
 
         info!(
-            hub_url = %self.hub_url,
+            hub_url = %redact_url(hub_url),
             attempt = if attempt == 0 { 1 } else { attempt },
             "connecting to hub"
         );
 
-        let (ws_stream, _) = connect_async(&self.hub_url).await?;
+        let ws_config = WebSocketConfig {
+            max_message_size: Some(self.max_message_bytes),
+            max_frame_size: Some(self.max_message_bytes),
+            ..Default::default()
+        };
+        let mut request = hub_url.into_client_request()?;
+        request.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(ProtocolVersion::LATEST.subprotocol()),
+        );
+        request.headers_mut().insert(
+            USER_AGENT,
+            HeaderValue::from_static(concat!("podpilot-agent/", env!("CARGO_PKG_VERSION"))),
+        );
+        let (ws_stream, response) =
+            connect_async_with_config(request, Some(ws_config), false).await?;
+
+        let protocol = response
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(ProtocolVersion::from_subprotocol);
 
         info!(
             connect_duration_ms = connect_start.elapsed().as_millis() as u64,
+            ?protocol,
             "connected, sending registration"
         );
 
         let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-        // Send registration message
-        let registration = self.create_registration_message();
-        let registration_json = serde_json::to_string(&registration)?;
-        ws_sender.send(Message::Text(registration_json)).await?;
-
-        // Wait for registration acknowledgment
-        let reg_response = timeout(Duration::from_secs(30), ws_receiver.next())
-            .await
-            .context("Timeout waiting for registration ack (30s)")?
-            .ok_or_else(|| anyhow::anyhow!("Connection closed during registration"))??;
-
-        if let Message::Text(text) = reg_response {
-            let hub_msg: HubMessage =
-                serde_json::from_str(&text).context("Failed to parse registration response")?;
-            match hub_msg {
-                HubMessage::RegisterAck(ack) => {
-                    self.handle_registration_ack(ack).await?;
-                }
-                HubMessage::Error { message, code, .. } => {
-                    anyhow::bail!("Registration rejected by hub [code: {}]: {}", code, message);
-                }
-                _ => {
-                    anyhow::bail!("Unexpected message type during registration: {:?}", hub_msg);
-                }
+        // Changes after this point are sent once the connection is registered
+        let readiness = self.readiness();
+
+        // Resume the registration from earlier in this run if there is one, since the
+        // hub can re-attach it without redoing the full upsert
+        let resume_with = match *self.session_token.read().await {
+            Some(token) => self.agent_id.read().await.map(|agent_id| (agent_id, token)),
+            None => None,
+        };
+        let mut reply = match resume_with {
+            Some((agent_id, token)) => {
+                let resume = AgentMessage::Resume(ResumeMessage {
+                    correlation_id: Uuid::new_v4(),
+                    agent_id,
+                    session_token: Some(token),
+                    supported_codecs: WireCodec::advertised(self.wire_codec),
+                    readiness,
+                    in_flight: self.in_flight_commands(),
+                });
+                send_handshake_message(&mut ws_sender, &resume, self.send_timeout).await?;
+                receive_handshake_reply(&mut ws_receiver).await?
             }
-        } else {
-            anyhow::bail!(
-                "Expected text message for registration ack, received: {:?}",
-                reg_response
-            );
+            None => {
+                let registration = self.create_registration_message(readiness).await;
+                send_handshake_message(&mut ws_sender, &registration, self.send_timeout).await?;
+                receive_handshake_reply(&mut ws_receiver).await?
+            }
+        };
+
+        if resume_with.is_some()
+            && matches!(&reply, HubMessage::Error { code, .. } if code == error_code::RESUME_REJECTED)
+        {
+            info!("hub rejected resume, registering in full");
+            *self.session_token.write().await = None;
+            let registration = self.create_registration_message(readiness).await;
+            send_handshake_message(&mut ws_sender, &registration, self.send_timeout).await?;
+            reply = receive_handshake_reply(&mut ws_receiver).await?;
         }
 
+        let codec = match reply {
+            // A codec we didn't offer means the hub and agent disagree on the protocol
+            HubMessage::RegisterAck(ack) => self
+                .handle_registration_ack(ack)
+                .await
+                .map_err(ConnectionError::Fatal)?,
+            HubMessage::Error {
+                message,
+                code,
+                retry_after_secs,
+                ..
+            } => {
+                return Err(ConnectionError::from_hub_error(
+                    &code,
+                    &message,
+                    retry_after_secs,
+                ));
+            }
+            other => {
+                return Err(ProtocolError::UnexpectedDuringHandshake {
+                    received: other.kind(),
+                }
+                .into());
+            }
+        };
+
+        Ok(HubConnection {
+            sender: ws_sender,
+            receiver: ws_receiver,
+            codec,
+            started_at: connect_start,
+            readiness,
+        })
+    }
+
+    /// Handle messages on a registered connection until it closes
+    ///
+    /// Shutdown is handled here rather than by the caller, so the close frame is
+    /// sent and the hub's reply awaited before returning.
+    async fn handle_connection(&self, connection: HubConnection) -> Result<()> {
+        let HubConnection {
+            sender: mut ws_sender,
+            receiver: mut ws_receiver,
+            codec,
+            started_at: session_start,
+            readiness: mut reported_readiness,
+        } = connection;
+        let mut readiness_rx = self.readiness.clone();
+
         // Update last heartbeat time
         *self.last_heartbeat.write().await = Utc::now();
 
-        // Spawn heartbeat timeout monitor
-        let last_heartbeat = self.last_heartbeat.clone();
-        let mut shutdown_rx = self.shutdown_rx.clone();
-        let monitor = tokio::spawn(async move {
-            let mut check_interval = interval(Duration::from_secs(5));
-            loop {
-                tokio::select! {
-                    _ = shutdown_rx.changed() => {
-                        debug!("heartbeat monitor shutdown");
-                        break;
-                    }
-                    _ = check_interval.tick() => {
-                        let last_hb = *last_heartbeat.read().await;
-                        let elapsed = Utc::now().signed_duration_since(last_hb);
+        // The hub may have lost track of our metrics, so send the first sample regardless
+        self.metrics_throttle
+            .lock()
+            .expect("metrics throttle lock poisoned")
+            .reset();
 
-                        if elapsed > chrono::Duration::from_std(HEARTBEAT_TIMEOUT).unwrap() {
-                            error!(timeout_secs = HEARTBEAT_TIMEOUT.as_secs(), "no heartbeat received, connection lost");
-                            break;
-                        }
-                    }
-                }
-            }
-        });
+        // Checked often enough to honour the grace window; a zero grace would stall
+        // the interval, so it falls back to the regular check period
+        let check_period = match self.heartbeat_grace.is_zero() {
+            true => HEARTBEAT_CHECK_INTERVAL,
+            false => HEARTBEAT_CHECK_INTERVAL.min(self.heartbeat_grace),
+        };
+        let mut heartbeat_check = interval(check_period);
+        // Deadline for the probe sent after an overdue heartbeat, if one is outstanding,
+        // and whether a pong has already pushed it back
+        let mut probe_deadline: Option<Instant> = None;
+        let mut probe_extended = false;
+
+        // Messages produced outside the receive loop, including responses to commands
+        // from earlier connections
+        let mut outbound_rx = self.outbound_rx.lock().await;
 
         // Handle incoming messages
         let mut shutdown_rx = self.shutdown_rx.clone();
 
+        let session_limit = self.max_session_duration.map(jittered_session_limit);
+        let session_expiry = async {
+            match session_limit {
+                Some(limit) => tokio::time::sleep(limit).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(session_expiry);
+
         let close_reason = loop {
             tokio::select! {
+                _ = &mut session_expiry => {
+                    info!(
+                        session_limit_secs = session_limit.unwrap_or_default().as_secs(),
+                        "session reached its maximum duration, reconnecting"
+                    );
+                    let _ = send_frame(&mut ws_sender, Message::Close(None), self.send_timeout).await;
+                    await_close_ack(&mut ws_receiver).await;
+                    break "max_session_duration";
+                }
+                _ = heartbeat_check.tick() => {
+                    let last_heartbeat = *self.last_heartbeat.read().await;
+                    let silent_for = (Utc::now() - last_heartbeat).to_std().unwrap_or_default();
+                    if silent_for <= self.heartbeat_timeout {
+                        probe_deadline = None;
+                        probe_extended = false;
+                        continue;
+                    }
+
+                    match probe_deadline {
+                        None if !self.heartbeat_grace.is_zero() => {
+                            warn!(
+                                silent_secs = silent_for.as_secs(),
+                                grace_secs = self.heartbeat_grace.as_secs_f64(),
+                                "heartbeat overdue, probing hub"
+                            );
+                            let probe = Message::Ping(Vec::new());
+                            if let Err(e) = send_frame(&mut ws_sender, probe, self.send_timeout).await {
+                                error!(error = %e, "failed to probe hub");
+                                break send_failure_reason(&e);
+                            }
+                            probe_deadline = Some(Instant::now() + self.heartbeat_grace);
+                        }
+                        Some(deadline) if Instant::now() < deadline => {}
+                        _ => {
+                            error!(
+                                timeout_secs = self.heartbeat_timeout.as_secs(),
+                                "no heartbeat received, connection lost"
+                            );
+                            break "heartbeat_timeout";
+                        }
+                    }
+                }
                 _ = shutdown_rx.changed() => {
                     debug!("closing connection due to shutdown");
                     // Send close frame to Hub
-                    let _ = ws_sender.send(Message::Close(None)).await;
+                    let close_start = Instant::now();
+                    let _ = send_frame(&mut ws_sender, Message::Close(None), self.send_timeout).await;
+                    let close_send_ms = close_start.elapsed().as_millis() as u64;
+                    let hub_acked = await_close_ack(&mut ws_receiver).await;
+                    *self.close_stats.write().await = Some(CloseStats {
+                        close_send_ms,
+                        hub_acked,
+                    });
                     break "shutdown";
                 }
-                msg_result = ws_receiver.next() => {
-                    match msg_result {
-                        Some(Ok(Message::Text(text))) => {
-                            if let Err(e) = self.handle_hub_message(&mut ws_sender, &text).await {
-                                error!(error = %e, "error handling hub message");
+                Some(readiness) = readiness_change(&mut readiness_rx, reported_readiness) => {
+                    info!(?readiness, "reporting readiness to hub");
+                    reported_readiness = readiness;
+                    let message = AgentMessage::Readiness(ReadinessMessage { readiness });
+                    let frame = match codec.encode(&message) {
+                        Ok(frame) => to_ws_message(frame),
+                        Err(e) => {
+                            error!(error = %e, "failed to encode readiness for hub");
+                            break "encode_error";
+                        }
+                    };
+                    if let Err(e) = send_frame(&mut ws_sender, frame, self.send_timeout).await {
+                        error!(error = %e, "failed to send message to hub");
+                        break send_failure_reason(&e);
+                    }
+                }
+                Some(outbound) = outbound_rx.next() => {
+                    let frame = match codec.encode(&outbound) {
+                        Ok(frame) => to_ws_message(frame),
+                        Err(e) => {
+                            // Retrying can't help, so drop it; no longer reporting a dropped
+                            // response as in flight lets the hub release its command
+                            error!(error = %e, "failed to encode message for hub, dropping it");
+                            if let AgentMessage::CommandResponse(response) = &outbound {
+                                self.in_flight
+                                    .lock()
+                                    .expect("in-flight commands lock poisoned")
+                                    .remove(&response.correlation_id);
                             }
+                            break "encode_error";
                         }
+                    };
+                    if let Err(e) = send_frame(&mut ws_sender, frame, self.send_timeout).await {
+                        error!(error = %e, "failed to send message to hub");
+                        // Deliver it on the next connection instead of losing it
+                        outbound_rx.put_back(outbound);
+                        break send_failure_reason(&e);
+                    }
+                    if let AgentMessage::CommandResponse(response) = &outbound {
+                        self.in_flight
+                            .lock()
+                            .expect("in-flight commands lock poisoned")
+                            .remove(&response.correlation_id);
+                    }
+                }
+                msg_result = ws_receiver.next() => {
+                    let decoded = match msg_result {
+                        Some(Ok(Message::Text(text))) => codec.decode_text::<HubMessage>(&text),
+                        Some(Ok(Message::Binary(bytes))) => codec.decode_binary::<HubMessage>(&bytes),
                         Some(Ok(Message::Close(_))) => {
                             break "hub_closed";
                         }
-                        Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {
-                            // WebSocket library handles these automatically
+                        Some(Ok(Message::Pong(_))) => {
+                            // The socket is alive, but only a hub heartbeat proves the hub
+                            // still tracks this session (one that dropped the agent keeps
+                            // answering pings), so a pong buys a single extra grace window
+                            if let Some(deadline) = probe_deadline.as_mut()
+                                && !probe_extended
+                            {
+                                debug!("hub answered heartbeat probe, waiting for a heartbeat");
+                                *deadline += self.heartbeat_grace;
+                                probe_extended = true;
+                            }
+                            continue;
+                        }
+                        Some(Ok(_)) => {
+                            // Pings are answered automatically by the WebSocket library
+                            continue;
+                        }
+                        Some(Err(WsError::Capacity(e))) => {
+                            error!(error = %e, max_bytes = self.max_message_bytes, "oversized message from hub");
+                            let close = Message::Close(Some(CloseFrame {
+                                code: CloseCode::Size,
+                                reason: e.to_string().into(),
+                            }));
+                            let _ = send_frame(&mut ws_sender, close, self.send_timeout).await;
+                            break "message_too_large";
                         }
-                        Some(Ok(_)) => {}
                         Some(Err(e)) => {
                             error!(error = %e, "websocket error");
                             break "error";
@@ -221,63 +810,137 @@ impl WsClient {
                         None => {
                             break "stream_ended";
                         }
+                    };
+
+                    let result = match decoded {
+                        Ok(hub_msg) => {
+                            self.handle_hub_message(&mut ws_sender, codec, hub_msg)
+                                .await
+                        }
+                        Err(e) => Err(ProtocolError::from(e).into()),
+                    };
+                    if let Err(e) = result {
+                        // A stalled heartbeat ack means the socket is gone; reconnect
+                        if e.is::<SendTimeout>() {
+                            error!(error = %e, "failed to send message to hub");
+                            break "send_timeout";
+                        }
+                        match e.downcast_ref::<ProtocolError>() {
+                            Some(violation) => {
+                                warn!(error = %violation, code = violation.code(), "protocol violation from hub");
+                            }
+                            None => error!(error = %e, "error handling hub message"),
+                        }
                     }
                 }
             }
         };
 
-        // Cancel heartbeat monitor
-        monitor.abort();
-
         info!(
             session_duration_secs = session_start.elapsed().as_secs(),
             reason = close_reason,
             "connection closed"
         );
+        {
+            let mut stats = self.connection_stats.write().await;
+            stats.hub_url = None;
+            stats.connected_since = None;
+            stats.last_disconnect_reason = Some(close_reason.to_string());
+        }
+
+        if let Some(hooks) = &self.hooks {
+            hooks.on_disconnect(*self.agent_id.read().await).await;
+        }
 
         Ok(())
     }
 
+    /// Current readiness, ready if nothing gates it
+    fn readiness(&self) -> Readiness {
+        self.readiness
+            .as_ref()
+            .map_or(Readiness::Ready, |readiness| *readiness.borrow())
+    }
+
     /// Create registration message
-    fn create_registration_message(&self) -> AgentMessage {
+    async fn create_registration_message(&self, readiness: Readiness) -> AgentMessage {
         AgentMessage::Register(AgentInfo {
             correlation_id: Uuid::new_v4(),
             provider: self.provider,
             provider_instance_id: self.provider_instance_id.clone(),
             hostname: self.hostname.clone(),
+            display_name: self.display_name.clone(),
             gpu_info: self.gpu_info.clone(),
             tailscale_ip: self.tailscale_ip,
             agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            supported_codecs: WireCodec::advertised(self.wire_codec),
+            diagnostics: self.diagnostics.clone(),
+            agent_id_hint: *self.agent_id.read().await,
+            capabilities: Some(self.capabilities.clone()),
+            readiness,
+            metrics: match &self.metrics {
+                Some(cache) => cache.latest(),
+                None => None,
+            },
+            in_flight: self.in_flight_commands(),
         })
     }
 
-    /// Handle registration acknowledgment
-    async fn handle_registration_ack(&self, ack: AgentRegistration) -> Result<()> {
+    /// Correlation IDs of commands still awaiting their response's send
+    fn in_flight_commands(&self) -> Vec<Uuid> {
+        self.in_flight
+            .lock()
+            .expect("in-flight commands lock poisoned")
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Handle registration acknowledgment, returning the codec chosen by the hub
+    async fn handle_registration_ack(&self, ack: AgentRegistration) -> Result<WireCodec> {
+        if !WireCodec::advertised(self.wire_codec).contains(&ack.codec) {
+            anyhow::bail!("Hub selected a codec that was not offered: {:?}", ack.codec);
+        }
+
         let agent_id = ack.agent_id;
-        *self.agent_id.write().await = Some(agent_id);
+        let previous = self.agent_id.write().await.replace(agent_id);
+        *self.session_token.write().await = ack.session_token;
+
+        if previous != Some(agent_id)
+            && let Some(state_file) = &self.state_file
+        {
+            let state = AgentState {
+                agent_id: Some(agent_id),
+            };
+            if let Err(e) = state_file.save(&state).await {
+                warn!(error = format!("{:#}", e), "failed to persist agent ID");
+            }
+        }
 
         info!(
             agent_id = %agent_id,
             hub_version = %ack.hub_version,
             gpu_name = %self.gpu_info.name,
             provider = ?self.provider,
+            codec = ?ack.codec,
             "connected to hub"
         );
-        Ok(())
+        if let Some(hooks) = &self.hooks {
+            hooks.spawn_on_connect(agent_id);
+        }
+        Ok(ack.codec)
     }
 
     /// Handle incoming message from Hub
     async fn handle_hub_message(
         &self,
-        ws_sender: &mut futures_util::stream::SplitSink<
-            tokio_tungstenite::WebSocketStream<
-                tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-            >,
-            Message,
-        >,
-        text: &str,
+        ws_sender: &mut WsSender,
+        codec: WireCodec,
+        hub_msg: HubMessage,
     ) -> Result<()> {
-        let hub_msg: HubMessage = serde_json::from_str(text)?;
+        if self.log_ws_payloads {
+            trace!(payload = %redacted_payload(&hub_msg), "hub message");
+        }
 
         match hub_msg {
             HubMessage::Heartbeat(hb) => {
@@ -290,15 +953,30 @@ impl WsClient {
                 let ack = AgentMessage::HeartbeatAck(HeartbeatAckMessage {
                     correlation_id: hb.correlation_id,
                     timestamp: Utc::now(),
+                    metrics: self.heartbeat_metrics(),
                 });
 
-                let ack_json = serde_json::to_string(&ack)?;
-                ws_sender.send(Message::Text(ack_json)).await?;
+                send_frame(
+                    ws_sender,
+                    to_ws_message(codec.encode(&ack)?),
+                    self.send_timeout,
+                )
+                .await?;
 
                 debug!("sent heartbeat ack");
             }
-            HubMessage::RegisterAck(_) => {
-                warn!("received unexpected register ack");
+            HubMessage::Command(cmd) => {
+                self.spawn_command(cmd);
+            }
+            HubMessage::UploadUrl(upload) => {
+                // Only sent in answer to `RequestUploadUrl`, which this agent doesn't issue yet
+                debug!(correlation_id = %upload.correlation_id, r2_key = %upload.r2_key, "ignoring unrequested upload URL");
+            }
+            ack @ HubMessage::RegisterAck(_) => {
+                return Err(ProtocolError::UnexpectedMessage {
+                    received: ack.kind(),
+                }
+                .into());
             }
             HubMessage::Error { message, code, .. } => {
                 error!(error_code = code, error_message = %message, "received error from hub");
@@ -308,9 +986,187 @@ impl WsClient {
         Ok(())
     }
 
+    /// Metrics to attach to a heartbeat ack, if they're worth sending
+    ///
+    /// Sampling shells out and can stall, so the ack carries the latest sample and a
+    /// fresh one is taken in the background for the next heartbeat.
+    fn heartbeat_metrics(&self) -> Option<Metrics> {
+        let cache = self.metrics.as_ref()?;
+        let refresh = cache.clone();
+        tokio::spawn(async move {
+            if let Err(e) = refresh.snapshot().await {
+                warn!(
+                    error = format!("{:#}", e),
+                    "failed to sample metrics for heartbeat"
+                );
+            }
+        });
+
+        let metrics = cache.latest()?;
+        let send = self
+            .metrics_throttle
+            .lock()
+            .expect("metrics throttle lock poisoned")
+            .should_send(&metrics);
+        send.then_some(metrics)
+    }
+
+    /// Execute a hub command in the background and queue its response
+    ///
+    /// Commands like model downloads can take minutes, so they must not block
+    /// the receive loop (and with it, heartbeat handling). The command keeps running
+    /// if the connection drops, and its response is sent once reconnected.
+    fn spawn_command(&self, cmd: CommandMessage) {
+        info!(correlation_id = %cmd.correlation_id, command = ?cmd.command, "received command");
+
+        self.in_flight
+            .lock()
+            .expect("in-flight commands lock poisoned")
+            .insert(cmd.correlation_id);
+        let commands = self.commands.clone();
+        let outbound_tx = self.outbound_tx.clone();
+        tokio::spawn(async move {
+            let response = commands.handle(cmd.command).await;
+            let message = AgentMessage::CommandResponse(CommandResponseMessage {
+                correlation_id: cmd.correlation_id,
+                response,
+            });
+
+            if outbound_tx.send(message).await.is_err() {
+                warn!(
+                    correlation_id = %cmd.correlation_id,
+                    "client stopped before command response could be sent"
+                );
+            }
+        });
+    }
+
     /// Shutdown the client gracefully
     pub fn shutdown(&self) {
         debug!("shutdown requested");
         let _ = self.shutdown_tx.send(true);
     }
+
+    /// Reconnect and session history, with the current session's uptime filled in
+    pub async fn connection_stats(&self) -> ConnectionStats {
+        let mut stats = self.connection_stats.read().await.clone();
+        stats.session_uptime_secs = stats
+            .connected_since
+            .map(|since| (Utc::now() - since).num_seconds().max(0) as u64);
+        stats
+    }
+
+    /// How the hub connection was closed, if it was open when shutdown began
+    pub async fn close_stats(&self) -> Option<CloseStats> {
+        *self.close_stats.read().await
+    }
+}
+
+/// Wait until readiness differs from what was last `reported`
+///
+/// Returns `None` when there's no readiness source or it stopped changing, which
+/// disables the caller's `select!` branch.
+async fn readiness_change(
+    readiness: &mut Option<watch::Receiver<Readiness>>,
+    reported: Readiness,
+) -> Option<Readiness> {
+    let readiness = readiness.as_mut()?;
+    let current = readiness
+        .wait_for(|current| *current != reported)
+        .await
+        .ok()?;
+    Some(*current)
+}
+
+/// Wait for the hub's close frame after sending ours, returning whether it arrived
+async fn await_close_ack(ws_receiver: &mut WsReceiver) -> bool {
+    let ack = async {
+        while let Some(message) = ws_receiver.next().await {
+            match message {
+                Ok(Message::Close(_)) => return true,
+                Ok(_) => continue,
+                Err(_) => return false,
+            }
+        }
+        false
+    };
+    timeout(CLOSE_ACK_TIMEOUT, ack).await.unwrap_or(false)
+}
+
+/// Send a handshake message, which is always JSON
+async fn send_handshake_message(
+    ws_sender: &mut WsSender,
+    message: &AgentMessage,
+    send_timeout: Duration,
+) -> Result<(), ConnectionError> {
+    let json = serde_json::to_string(message).context("Failed to serialize registration")?;
+    timeout(send_timeout, ws_sender.send(Message::Text(json)))
+        .await
+        .map_err(|_| anyhow::Error::from(SendTimeout(send_timeout)))??;
+    Ok(())
+}
+
+/// Send a frame, failing with [`SendTimeout`] if it stalls past `send_timeout`
+async fn send_frame(
+    ws_sender: &mut WsSender,
+    message: Message,
+    send_timeout: Duration,
+) -> Result<()> {
+    timeout(send_timeout, ws_sender.send(message))
+        .await
+        .map_err(|_| SendTimeout(send_timeout))??;
+    Ok(())
+}
+
+/// A random fraction in `[0, 1)`
+fn random_fraction() -> f64 {
+    // A v4 UUID is 122 random bits; a few of them are plenty for jitter
+    (Uuid::new_v4().as_u128() % 1_000_000) as f64 / 1_000_000.0
+}
+
+/// `max_duration` shortened by a random amount up to [`SESSION_JITTER_FRACTION`]
+fn jittered_session_limit(max_duration: Duration) -> Duration {
+    max_duration.mul_f64(1.0 - SESSION_JITTER_FRACTION * random_fraction())
+}
+
+/// `wait` lengthened by a random amount up to [`TRY_LATER_JITTER_FRACTION`]
+///
+/// Never shorter than the hub asked for, but spread out so agents turned away
+/// together don't all retry at the same moment.
+fn jittered_try_later_wait(wait: Duration) -> Duration {
+    wait.mul_f64(1.0 + TRY_LATER_JITTER_FRACTION * random_fraction())
+}
+
+/// Close reason for a failed send
+fn send_failure_reason(error: &anyhow::Error) -> &'static str {
+    if error.is::<SendTimeout>() {
+        "send_timeout"
+    } else {
+        "error"
+    }
+}
+
+/// Wait up to 30s for the hub's reply to a handshake message
+async fn receive_handshake_reply(
+    ws_receiver: &mut WsReceiver,
+) -> Result<HubMessage, ConnectionError> {
+    let response = timeout(Duration::from_secs(30), ws_receiver.next())
+        .await
+        .context("Timeout waiting for registration ack (30s)")?
+        .ok_or_else(|| anyhow::anyhow!("Connection closed during registration"))??;
+
+    match response {
+        Message::Text(text) => Ok(WireCodec::Json
+            .decode_text(&text)
+            .map_err(ProtocolError::from)?),
+        _ => Err(ProtocolError::NonTextHandshake.into()),
+    }
+}
+
+/// Wrap an encoded frame in the matching WebSocket message type
+fn to_ws_message(frame: Frame) -> Message {
+    match frame {
+        Frame::Text(text) => Message::Text(text),
+        Frame::Binary(bytes) => Message::Binary(bytes),
+    }
 }
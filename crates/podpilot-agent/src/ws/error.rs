@@ -0,0 +1,69 @@
+//! Connection errors, classified by whether retrying can help.
+
+use podpilot_common::protocol::{ProtocolError, error_code};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Error as WsError;
+
+/// Error from connecting to or talking with the hub
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectionError {
+    /// Will fail the same way on every attempt (auth, protocol version, bad config)
+    #[error("{0:#}")]
+    Fatal(anyhow::Error),
+    /// May succeed on a later attempt (network failures, hub restarts)
+    #[error("{0:#}")]
+    Transient(anyhow::Error),
+    /// The hub is busy and asked us to wait at least `retry_after` before retrying
+    #[error("{error:#}")]
+    TryLater {
+        error: anyhow::Error,
+        retry_after: Duration,
+    },
+}
+
+impl ConnectionError {
+    /// Classify a `HubMessage::Error` by its code
+    pub fn from_hub_error(code: &str, message: &str, retry_after_secs: Option<u64>) -> Self {
+        let error = anyhow::anyhow!("Hub returned error [code: {}]: {}", code, message);
+        if error_code::is_fatal(code) {
+            Self::Fatal(error)
+        } else if code == error_code::TRY_LATER {
+            Self::TryLater {
+                error,
+                retry_after: Duration::from_secs(retry_after_secs.unwrap_or(1)),
+            }
+        } else {
+            Self::Transient(error)
+        }
+    }
+}
+
+impl From<anyhow::Error> for ConnectionError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::Transient(error)
+    }
+}
+
+impl From<ProtocolError> for ConnectionError {
+    fn from(error: ProtocolError) -> Self {
+        Self::Transient(error.into())
+    }
+}
+
+impl From<WsError> for ConnectionError {
+    /// The hub refuses the upgrade with 401/403 for rejected credentials and 426 for
+    /// an unsupported protocol version, and an invalid hub URL never becomes valid;
+    /// neither changes by reconnecting.
+    fn from(error: WsError) -> Self {
+        let fatal = match &error {
+            WsError::Http(response) => matches!(response.status().as_u16(), 401 | 403 | 426),
+            WsError::Url(_) => true,
+            _ => false,
+        };
+        if fatal {
+            Self::Fatal(error.into())
+        } else {
+            Self::Transient(error.into())
+        }
+    }
+}
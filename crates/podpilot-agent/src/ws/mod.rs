@@ -1,3 +1,9 @@
 mod client;
+mod error;
+mod preflight;
 
-pub use client::WsClient;
+pub use client::{
+    CloseStats, ConnectionStats, DEFAULT_HEARTBEAT_GRACE, DEFAULT_HEARTBEAT_TIMEOUT,
+    DEFAULT_SEND_TIMEOUT, WsClient,
+};
+pub use error::ConnectionError;
@@ -0,0 +1,109 @@
+//! Pre-flight check of the hub's HTTP status endpoint before the first connection.
+//!
+//! When the agent and hub start together, the first WebSocket dial usually lands
+//! before the hub is listening and drops straight into reconnect backoff. Polling
+//! `/status` briefly first lets that first connection succeed instead.
+
+use podpilot_common::redact::redact_url;
+use reqwest::{StatusCode, Url};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Number of status requests before giving up and dialing anyway
+const PREFLIGHT_ATTEMPTS: u32 = 10;
+/// Delay between status requests
+const PREFLIGHT_INTERVAL: Duration = Duration::from_secs(1);
+/// Timeout for each status request
+const PREFLIGHT_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Derive the hub's status URL from its WebSocket URL
+///
+/// `ws://hub:80/ws/agent` becomes `http://hub:80/status`. Returns `None` for
+/// URLs that aren't `ws://` or `wss://`.
+pub(super) fn status_url(hub_url: &str) -> Option<Url> {
+    let mut url = Url::parse(hub_url).ok()?;
+    let scheme = match url.scheme() {
+        "ws" => "http",
+        "wss" => "https",
+        _ => return None,
+    };
+    url.set_scheme(scheme).ok()?;
+    url.set_path("/status");
+    // Credentials in the WebSocket URL are for the upgrade, and would end up in logs
+    url.set_username("").ok()?;
+    url.set_password(None).ok()?;
+    url.set_query(None);
+    url.set_fragment(None);
+    Some(url)
+}
+
+/// Poll the hubs' status endpoints until one answers, for a bounded number of attempts
+///
+/// Returns the index of the first hub that answered, if any. Never fails: if every
+/// hub stays unreachable or has no status endpoint, the normal connection path (and
+/// its backoff) takes over.
+pub(super) async fn wait_for_hub(hub_urls: &[String]) -> Option<usize> {
+    let urls: Vec<(usize, Url)> = hub_urls
+        .iter()
+        .enumerate()
+        .filter_map(|(index, hub_url)| {
+            let url = status_url(hub_url);
+            if url.is_none() {
+                debug!(
+                    hub_url = %redact_url(hub_url),
+                    "hub URL has no HTTP equivalent, skipping pre-flight check"
+                );
+            }
+            url.map(|url| (index, url))
+        })
+        .collect();
+    if urls.is_empty() {
+        return None;
+    }
+    let client = match reqwest::Client::builder()
+        .timeout(PREFLIGHT_REQUEST_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(error = %e, "failed to create pre-flight HTTP client, skipping");
+            return None;
+        }
+    };
+
+    for attempt in 1..=PREFLIGHT_ATTEMPTS {
+        for (index, url) in &urls {
+            match client.get(url.clone()).send().await {
+                Ok(response) if response.status().is_success() => {
+                    info!(%url, attempt, "hub is up");
+                    return Some(*index);
+                }
+                Ok(response)
+                    if matches!(
+                        response.status(),
+                        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+                    ) =>
+                {
+                    debug!(%url, status = %response.status(), "hub has no status endpoint, skipping pre-flight check");
+                    return None;
+                }
+                Ok(response) => {
+                    debug!(%url, attempt, status = %response.status(), "hub not ready yet");
+                }
+                Err(e) => {
+                    debug!(%url, attempt, error = %e, "hub not reachable yet");
+                }
+            }
+        }
+
+        if attempt < PREFLIGHT_ATTEMPTS {
+            tokio::time::sleep(PREFLIGHT_INTERVAL).await;
+        }
+    }
+
+    warn!(
+        attempts = PREFLIGHT_ATTEMPTS,
+        "hub status check did not succeed, connecting anyway"
+    );
+    None
+}
@@ -0,0 +1,41 @@
+//! Export JSON Schema for the agent <-> hub WebSocket protocol.
+//!
+//! Writes `schema/protocol.json` (or the path given as the first argument) with one
+//! schema per top-level type, for integrators writing agents in other languages.
+//! Regenerate with `just schema` after changing protocol types; diffing the output
+//! against the previous version shows exactly how the wire format changed.
+
+use podpilot_common::schema::{PROTOCOL_SCHEMA_PATH, protocol_schema_json};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let path = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(PROTOCOL_SCHEMA_PATH));
+
+    let result = protocol_schema_json()
+        .map_err(anyhow::Error::from)
+        .and_then(|json| {
+            if let Some(parent) = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, json)?;
+            Ok(())
+        });
+
+    match result {
+        Ok(()) => {
+            println!("wrote {}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to write {}: {:#}", path.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
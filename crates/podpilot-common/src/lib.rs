@@ -3,5 +3,8 @@ pub mod error;
 pub mod formatter;
 pub mod logging;
 pub mod protocol;
+pub mod redact;
+pub mod retry;
 pub mod rpc;
+pub mod schema;
 pub mod types;
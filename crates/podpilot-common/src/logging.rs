@@ -1,20 +1,38 @@
 use crate::config::Config;
 use crate::formatter::CustomJsonFormatter;
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{EnvFilter, Registry, fmt, reload};
+
+/// Handle for replacing the log filter of a running process
+#[derive(Clone)]
+pub struct LogReloadHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogReloadHandle {
+    /// Apply `log_level` the same way [`setup_logging`] does (`RUST_LOG` still wins)
+    pub fn set_level(&self, log_level: &str) -> anyhow::Result<()> {
+        self.0.reload(build_filter(log_level))?;
+        Ok(())
+    }
+}
+
+/// Build the log filter from `RUST_LOG`, or from `log_level` when it's unset
+fn build_filter(log_level: &str) -> EnvFilter {
+    EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,podpilot_hub={}", log_level)))
+}
 
 /// Configure and initialize logging for the application
-pub fn setup_logging(config: &Config) {
-    // Configure logging based on config
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-        let base_level = &config.log_level;
-        EnvFilter::new(format!("warn,podpilot_hub={}", base_level))
-    });
+///
+/// The returned handle changes the log level without a restart.
+pub fn setup_logging(config: &Config) -> LogReloadHandle {
+    let (filter, handle) = reload::Layer::new(build_filter(&config.log_level));
 
-    let subscriber = FmtSubscriber::builder()
-        .with_target(true)
-        .with_env_filter(filter)
-        .event_format(CustomJsonFormatter)
-        .finish();
+    let subscriber = tracing_subscriber::registry().with(filter).with(
+        fmt::layer()
+            .with_target(true)
+            .event_format(CustomJsonFormatter),
+    );
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    LogReloadHandle(handle)
 }
@@ -0,0 +1,92 @@
+//! Wire encoding for WebSocket protocol messages.
+//!
+//! JSON text frames are the default and are always accepted. Connections that
+//! negotiate MessagePack additionally exchange binary frames, which are smaller
+//! and cheaper to encode for high-frequency traffic like metrics.
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Encoding used for messages on a single connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WireCodec {
+    /// JSON in text frames
+    #[default]
+    Json,
+    /// MessagePack in binary frames
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+/// An encoded message, ready to be wrapped in a WebSocket frame
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Error encoding or decoding a protocol message
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("JSON codec error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("MessagePack encode error: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+    #[error("MessagePack decode error: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+    #[error("received a binary frame on a JSON connection")]
+    UnexpectedBinary,
+}
+
+impl WireCodec {
+    /// Codecs this build can speak, in order of preference
+    pub const SUPPORTED: [WireCodec; 2] = [WireCodec::MessagePack, WireCodec::Json];
+
+    /// Codecs to advertise when `preferred` is the configured choice
+    ///
+    /// JSON is always included as the fallback.
+    pub fn advertised(preferred: WireCodec) -> Vec<WireCodec> {
+        let mut codecs = vec![preferred];
+        if preferred != WireCodec::Json {
+            codecs.push(WireCodec::Json);
+        }
+        codecs
+    }
+
+    /// Pick the first codec offered by the peer that this build supports
+    ///
+    /// Falls back to JSON when nothing matches (including peers that predate
+    /// codec negotiation and offer nothing).
+    pub fn negotiate(offered: &[WireCodec]) -> WireCodec {
+        offered
+            .iter()
+            .copied()
+            .find(|codec| Self::SUPPORTED.contains(codec))
+            .unwrap_or_default()
+    }
+
+    /// Encode a message into a frame of the appropriate type
+    pub fn encode<T: Serialize>(self, message: &T) -> Result<Frame, CodecError> {
+        match self {
+            WireCodec::Json => Ok(Frame::Text(serde_json::to_string(message)?)),
+            // Named encoding keeps structs as maps, which internally tagged enums
+            // and `skip_serializing_if` fields require
+            WireCodec::MessagePack => Ok(Frame::Binary(rmp_serde::to_vec_named(message)?)),
+        }
+    }
+
+    /// Decode a text frame (always JSON, regardless of the negotiated codec)
+    pub fn decode_text<T: DeserializeOwned>(self, text: &str) -> Result<T, CodecError> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Decode a binary frame, which is only valid once MessagePack is negotiated
+    pub fn decode_binary<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            WireCodec::Json => Err(CodecError::UnexpectedBinary),
+            WireCodec::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+}
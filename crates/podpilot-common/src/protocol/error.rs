@@ -0,0 +1,37 @@
+//! Errors for messages that break the agent <-> hub protocol.
+//!
+//! Shared by both sides so violations are logged the same way and, on the hub,
+//! reported back to the agent with a matching [`error_code`](super::error_code).
+
+use super::codec::CodecError;
+use super::error_code;
+
+/// A peer sent something the protocol doesn't allow
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    /// A frame could not be decoded into a message
+    #[error("failed to decode message: {0}")]
+    Decode(#[from] CodecError),
+    /// Registration must be a JSON text frame, but something else arrived
+    #[error("expected a text frame during registration")]
+    NonTextHandshake,
+    /// A valid message arrived during registration, but not the one expected
+    #[error("unexpected {received} message during registration")]
+    UnexpectedDuringHandshake { received: &'static str },
+    /// A valid message arrived that the receiver never expects on a registered connection
+    #[error("unexpected {received} message")]
+    UnexpectedMessage { received: &'static str },
+}
+
+impl ProtocolError {
+    /// Error code to report to the peer
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Decode(_) => error_code::INVALID_MESSAGE,
+            Self::NonTextHandshake | Self::UnexpectedDuringHandshake { .. } => {
+                error_code::INVALID_REGISTRATION
+            }
+            Self::UnexpectedMessage { .. } => error_code::UNEXPECTED_MESSAGE,
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Codes sent in [`HubMessage::Error`](super::HubMessage::Error).
+//!
+//! Agents use these to decide whether reconnecting can help: fatal codes describe
+//! problems that will recur on every attempt until the agent is reconfigured.
+
+/// The agent exceeded its message rate limit
+pub const RATE_LIMITED: &str = "rate_limited";
+/// The hub is turning away registrations for now; retry after the error's
+/// `retry_after_secs`
+pub const TRY_LATER: &str = "try_later";
+/// The registration message was malformed or unexpected
+pub const INVALID_REGISTRATION: &str = "invalid_registration";
+/// The agent can't resume its previous registration and should register in full
+pub const RESUME_REJECTED: &str = "resume_rejected";
+/// The agent's credentials were rejected
+pub const UNAUTHORIZED: &str = "unauthorized";
+/// The agent's protocol version is not supported
+pub const UNSUPPORTED_VERSION: &str = "unsupported_version";
+/// A message could not be decoded
+pub const INVALID_MESSAGE: &str = "invalid_message";
+/// A message arrived that isn't valid on a registered connection
+pub const UNEXPECTED_MESSAGE: &str = "unexpected_message";
+/// The hub has no R2 credentials, so it can't issue upload URLs
+pub const UPLOADS_DISABLED: &str = "uploads_disabled";
+/// An upload URL request was malformed
+pub const INVALID_UPLOAD: &str = "invalid_upload";
+/// An asset's content type isn't one the hub accepts
+pub const UNSUPPORTED_CONTENT_TYPE: &str = "unsupported_content_type";
+/// The hub failed while handling the request
+pub const INTERNAL: &str = "internal";
+
+/// Whether an error with this code will recur if the agent reconnects unchanged
+pub fn is_fatal(code: &str) -> bool {
+    matches!(
+        code,
+        INVALID_REGISTRATION | UNAUTHORIZED | UNSUPPORTED_VERSION
+    )
+}
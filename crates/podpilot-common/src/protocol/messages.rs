@@ -1,55 +1,192 @@
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 use uuid::Uuid;
 
+use super::codec::WireCodec;
+use crate::rpc::{AssetMetadata, Command, CommandResponse, Metrics};
 use crate::types::{GpuInfo, ProviderType};
 
 /// Messages sent from Agent to Hub
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum AgentMessage {
     Register(AgentInfo),
+    /// Re-attach to an agent ID from earlier in the same process, skipping the
+    /// full registration; the hub answers with `RegisterAck` or a `resume_rejected` error
+    Resume(ResumeMessage),
     HeartbeatAck(HeartbeatAckMessage),
+    CommandResponse(CommandResponseMessage),
+    /// A generated asset was uploaded to R2
+    AssetCreated(AssetMetadata),
+    /// The agent's readiness changed since it was last reported
+    Readiness(ReadinessMessage),
+    /// Ask for a presigned URL to upload an asset to R2; the hub answers with
+    /// `UploadUrl` or an error carrying the same `correlation_id`
+    RequestUploadUrl(UploadUrlRequest),
 }
 
 /// Messages sent from Hub to Agent
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum HubMessage {
     RegisterAck(AgentRegistration),
     Heartbeat(HeartbeatMessage),
+    Command(CommandMessage),
+    UploadUrl(UploadUrlMessage),
     Error {
         message: String,
         code: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         correlation_id: Option<Uuid>,
+        /// Seconds to wait before trying again, for errors like [`TRY_LATER`](super::error_code::TRY_LATER)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry_after_secs: Option<u64>,
     },
 }
 
+impl AgentMessage {
+    /// Message type as it appears in the `type` tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Register(_) => "register",
+            Self::Resume(_) => "resume",
+            Self::HeartbeatAck(_) => "heartbeat_ack",
+            Self::CommandResponse(_) => "command_response",
+            Self::AssetCreated(_) => "asset_created",
+            Self::Readiness(_) => "readiness",
+            Self::RequestUploadUrl(_) => "request_upload_url",
+        }
+    }
+}
+
+impl HubMessage {
+    /// Message type as it appears in the `type` tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::RegisterAck(_) => "register_ack",
+            Self::Heartbeat(_) => "heartbeat",
+            Self::Command(_) => "command",
+            Self::UploadUrl(_) => "upload_url",
+            Self::Error { .. } => "error",
+        }
+    }
+}
+
 /// Agent registration information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AgentInfo {
     pub correlation_id: Uuid,
     pub provider: ProviderType,
     pub provider_instance_id: String,
     pub hostname: String,
+    /// Operator-chosen name shown in place of the hostname
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
     pub gpu_info: GpuInfo,
     pub tailscale_ip: IpAddr,
     pub agent_version: String,
+    /// Wire codecs the agent accepts after registration, in order of preference
+    ///
+    /// Empty for agents that predate codec negotiation, which implies JSON.
+    #[serde(default)]
+    pub supported_codecs: Vec<WireCodec>,
+    /// Snapshot of the agent's startup conditions, for troubleshooting provisioning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<serde_json::Value>,
+    /// Agent ID assigned at a previous registration, persisted across restarts
+    ///
+    /// The hub reuses that agent's row when it still exists, keeping the identity
+    /// stable if the Tailscale IP or instance ID changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id_hint: Option<Uuid>,
+    /// Optional features this agent supports (see [`capability`](crate::rpc::capability))
+    ///
+    /// `None` for agents that predate capability advertisement; the hub then
+    /// assumes every command is supported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<String>>,
+    /// Whether the agent can handle workload commands yet
+    ///
+    /// Defaults to ready for agents that predate readiness reporting.
+    #[serde(default)]
+    pub readiness: Readiness,
+    /// Most recent metrics sample, bridging the gap until the first heartbeat ack
+    ///
+    /// `None` for agents that haven't sampled yet or don't collect metrics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Metrics>,
+    /// Correlation IDs of commands still running from an earlier connection
+    ///
+    /// The hub keeps waiting for their responses and gives up on any other command
+    /// it sent this agent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_flight: Vec<Uuid>,
+}
+
+/// Request to resume a previous registration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ResumeMessage {
+    pub correlation_id: Uuid,
+    /// Agent ID assigned at the agent's last successful registration
+    pub agent_id: Uuid,
+    /// Token from the `RegisterAck` that started the session being resumed
+    ///
+    /// The hub rejects a resume without the current token, so an agent ID alone
+    /// can't take over another agent's registration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<Uuid>,
+    /// Wire codecs the agent accepts after resuming, in order of preference
+    #[serde(default)]
+    pub supported_codecs: Vec<WireCodec>,
+    /// Whether the agent can handle workload commands yet
+    #[serde(default)]
+    pub readiness: Readiness,
+    /// Correlation IDs of commands still running from the earlier connection
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_flight: Vec<Uuid>,
+}
+
+/// Whether an agent can handle commands that need its workload
+///
+/// Until an agent reports `Ready`, the hub holds commands that
+/// [wait for readiness](crate::rpc::Command::waits_for_ready) rather than sending them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Readiness {
+    /// Still initializing, e.g. waiting for the WebUI to come up
+    Starting,
+    #[default]
+    Ready,
+}
+
+/// Readiness update from Agent to Hub
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ReadinessMessage {
+    pub readiness: Readiness,
 }
 
 /// Agent registration response
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct AgentRegistration {
     pub correlation_id: Uuid,
     pub agent_id: Uuid,
     pub registered_at: DateTime<Utc>,
     pub hub_version: String,
+    /// Codec used for all messages after this acknowledgment
+    #[serde(default)]
+    pub codec: WireCodec,
+    /// Token to present when resuming this registration, replaced at every handshake
+    ///
+    /// `None` from hubs that predate resume tokens, or when the hub failed to issue
+    /// one; the agent then registers in full when it reconnects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<Uuid>,
 }
 
 /// Heartbeat ping from Hub to Agent
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct HeartbeatMessage {
     pub correlation_id: Uuid,
     pub timestamp: DateTime<Utc>,
@@ -57,8 +194,49 @@ pub struct HeartbeatMessage {
 }
 
 /// Heartbeat acknowledgment from Agent to Hub
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct HeartbeatAckMessage {
     pub correlation_id: Uuid,
     pub timestamp: DateTime<Utc>,
+    /// Attached only when metrics changed meaningfully or the last sample went stale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Metrics>,
+}
+
+/// Command dispatched from Hub to Agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CommandMessage {
+    pub correlation_id: Uuid,
+    pub command: Command,
+}
+
+/// Command result from Agent to Hub, matched to the command by `correlation_id`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CommandResponseMessage {
+    pub correlation_id: Uuid,
+    pub response: CommandResponse,
+}
+
+/// Upload URL request from Agent to Hub
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct UploadUrlRequest {
+    pub correlation_id: Uuid,
+    /// Name of the file being uploaded, without any directory
+    pub filename: String,
+    /// MIME type of the file (e.g., "image/png")
+    pub content_type: String,
+}
+
+/// Presigned upload URL from Hub to Agent
+///
+/// The agent uploads with a `PUT` to `url`, sending the requested `Content-Type`,
+/// then reports the asset under `r2_key` with `AssetCreated`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct UploadUrlMessage {
+    pub correlation_id: Uuid,
+    pub url: String,
+    /// Object key the upload is stored under
+    pub r2_key: String,
+    /// The URL is rejected after this
+    pub expires_at: DateTime<Utc>,
 }
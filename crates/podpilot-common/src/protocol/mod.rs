@@ -1,5 +1,16 @@
+pub mod codec;
+pub mod error;
+pub mod error_code;
 pub mod messages;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod version;
 
+pub use codec::{CodecError, Frame, WireCodec};
+pub use error::ProtocolError;
 pub use messages::{
-    AgentInfo, AgentMessage, AgentRegistration, HeartbeatAckMessage, HeartbeatMessage, HubMessage,
+    AgentInfo, AgentMessage, AgentRegistration, CommandMessage, CommandResponseMessage,
+    HeartbeatAckMessage, HeartbeatMessage, HubMessage, Readiness, ReadinessMessage, ResumeMessage,
+    UploadUrlMessage, UploadUrlRequest,
 };
+pub use version::ProtocolVersion;
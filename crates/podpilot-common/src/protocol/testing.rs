@@ -0,0 +1,535 @@
+//! In-memory WebSocket transport for exercising the protocol without TCP.
+//!
+//! [`pair`] connects an agent end and a hub end over a `tokio::io::duplex` pipe
+//! with real WebSocket framing, so messages pass through the same codec and
+//! frame handling as a live connection. Either end can be unwrapped into its
+//! [`MemorySocket`] to drive code that takes a socket directly.
+//!
+//! [`round_trip`] and [`check_golden`] guard the wire format itself: the serde
+//! tag and rename attributes on protocol enums are easy to break, and a silent
+//! rename breaks agents running an older build. Golden fixtures live in
+//! `fixtures/protocol/`.
+//!
+//! Only built with the `test-util` feature, and for this crate's own tests.
+
+use anyhow::{Context, anyhow};
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::DuplexStream;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::Role;
+
+use super::{AgentMessage, Frame, HubMessage, WireCodec};
+
+/// Bytes buffered in each direction of the pipe
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// How long [`Endpoint::recv`] waits before failing
+const RECV_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A WebSocket over an in-memory pipe
+pub type MemorySocket = WebSocketStream<DuplexStream>;
+
+/// The agent's side: sends [`AgentMessage`]s, receives [`HubMessage`]s
+pub type AgentEnd = Endpoint<AgentMessage, HubMessage>;
+
+/// The hub's side: sends [`HubMessage`]s, receives [`AgentMessage`]s
+pub type HubEnd = Endpoint<HubMessage, AgentMessage>;
+
+/// Connect an agent end (client role) to a hub end (server role)
+pub async fn pair(codec: WireCodec) -> (AgentEnd, HubEnd) {
+    let (agent, hub) = tokio::io::duplex(PIPE_CAPACITY);
+    let agent = WebSocketStream::from_raw_socket(agent, Role::Client, None).await;
+    let hub = WebSocketStream::from_raw_socket(hub, Role::Server, None).await;
+    (Endpoint::new(agent, codec), Endpoint::new(hub, codec))
+}
+
+/// One end of an in-memory connection, typed by the messages it sends and receives
+pub struct Endpoint<S, R> {
+    socket: MemorySocket,
+    codec: WireCodec,
+    _messages: PhantomData<fn(S) -> R>,
+}
+
+impl<S, R> Endpoint<S, R>
+where
+    S: Serialize,
+    R: DeserializeOwned + Debug,
+{
+    fn new(socket: MemorySocket, codec: WireCodec) -> Self {
+        Self {
+            socket,
+            codec,
+            _messages: PhantomData,
+        }
+    }
+
+    /// Encode and send a message with this end's codec
+    pub async fn send(&mut self, message: &S) -> anyhow::Result<()> {
+        let frame = match self.codec.encode(message)? {
+            Frame::Text(text) => Message::Text(text),
+            Frame::Binary(bytes) => Message::Binary(bytes),
+        };
+        self.socket
+            .send(frame)
+            .await
+            .context("Failed to send frame")
+    }
+
+    /// Receive the next message, skipping control frames
+    ///
+    /// Fails if the peer closes or nothing arrives within two seconds.
+    pub async fn recv(&mut self) -> anyhow::Result<R> {
+        loop {
+            let frame = tokio::time::timeout(RECV_TIMEOUT, self.socket.next())
+                .await
+                .context("Timed out waiting for a message")?
+                .ok_or_else(|| anyhow!("Connection closed"))?
+                .context("Failed to read frame")?;
+
+            return match frame {
+                Message::Text(text) => Ok(self.codec.decode_text(&text)?),
+                Message::Binary(bytes) => Ok(self.codec.decode_binary(&bytes)?),
+                Message::Close(frame) => Err(anyhow!("Connection closed: {:?}", frame)),
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+            };
+        }
+    }
+
+    /// Receive the next message and extract a value from it, panicking if it doesn't match
+    pub async fn expect<T>(&mut self, extract: impl FnOnce(R) -> Option<T>) -> T {
+        let message = self.recv().await.expect("expected a message");
+        let description = format!("{:?}", message);
+        extract(message).unwrap_or_else(|| panic!("unexpected message: {}", description))
+    }
+
+    /// Unwrap the underlying socket
+    pub fn into_socket(self) -> MemorySocket {
+        self.socket
+    }
+}
+
+/// Encode and decode `message` with `codec`, failing if the result differs
+pub fn round_trip<T>(message: &T, codec: WireCodec) -> anyhow::Result<()>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let decoded: T = match codec.encode(message)? {
+        Frame::Text(text) => codec.decode_text(&text)?,
+        Frame::Binary(bytes) => codec.decode_binary(&bytes)?,
+    };
+    if &decoded != message {
+        return Err(anyhow!(
+            "{:?} round trip changed the message: {:?} became {:?}",
+            codec,
+            message,
+            decoded
+        ));
+    }
+    Ok(())
+}
+
+/// Check `message` against the golden fixture `fixtures/protocol/{name}.json`
+///
+/// Both directions must match: `message` serializes to the fixture's JSON, and the
+/// fixture deserializes to `message`.
+pub fn check_golden<T>(name: &str, message: &T) -> anyhow::Result<()>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures/protocol")
+        .join(format!("{}.json", name));
+    let fixture = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read fixture {}", path.display()))?;
+    let expected: serde_json::Value = serde_json::from_str(&fixture)
+        .with_context(|| format!("Fixture {} is not valid JSON", path.display()))?;
+
+    let actual = serde_json::to_value(message)?;
+    if actual != expected {
+        return Err(anyhow!(
+            "Wire format for '{}' changed:\nexpected: {}\nactual:   {}",
+            name,
+            expected,
+            actual
+        ));
+    }
+
+    let parsed: T = serde_json::from_value(expected)
+        .with_context(|| format!("Fixture '{}' no longer deserializes", name))?;
+    if &parsed != message {
+        return Err(anyhow!(
+            "Fixture '{}' deserialized to {:?}, expected {:?}",
+            name,
+            parsed,
+            message
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        AgentInfo, AgentRegistration, CommandMessage, CommandResponseMessage, HeartbeatAckMessage,
+        HeartbeatMessage, Readiness, ReadinessMessage, ResumeMessage, UploadUrlMessage,
+        UploadUrlRequest,
+    };
+    use crate::rpc::{AssetMetadata, Command, CommandResponse, ModelSpec};
+    use crate::types::{GpuInfo, GpuVendor, ProviderType};
+    use chrono::{DateTime, Utc};
+    use uuid::{Uuid, uuid};
+
+    const AGENT_ID: Uuid = uuid!("0f6e2d1c-8b7a-4c5d-9e3f-a1b2c3d4e5f6");
+    const REGISTER_ID: Uuid = uuid!("5b1f7c2e-3a4d-4e8f-9a01-2b3c4d5e6f70");
+    const HEARTBEAT_ID: Uuid = uuid!("9d8c7b6a-5f4e-4d3c-8b2a-1f0e9d8c7b6a");
+    const COMMAND_ID: Uuid = uuid!("3c2b1a09-8f7e-4d6c-9b5a-4f3e2d1c0b9a");
+    const SESSION_TOKEN: Uuid = uuid!("c4d5e6f7-0a1b-4c2d-8e3f-4a5b6c7d8e9f");
+    const UPLOAD_ID: Uuid = uuid!("2e4f6a8c-1b3d-4f5e-9a7b-c8d9e0f1a2b3");
+    const JOB_ID: Uuid = uuid!("8e7d6c5b-4a39-4281-b706-f5e4d3c2b1a0");
+    const ASSET_KEY: &str = "assets/0f6e2d1c-8b7a-4c5d-9e3f-a1b2c3d4e5f6/6a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d/00001-1234567890.png";
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().expect("valid timestamp")
+    }
+
+    fn model_spec() -> ModelSpec {
+        ModelSpec {
+            model_id: uuid!("7a6b5c4d-3e2f-4a1b-8c9d-0e1f2a3b4c5d"),
+            r2_key: "models/sdxl-base-1.0.safetensors".to_string(),
+            filename: "sdxl-base-1.0.safetensors".to_string(),
+            sha256_hash: "31e35c80fc4829d14f90153f4c74cd59c90b779f6afe05a74cd6ffb4d9a2cb08"
+                .to_string(),
+            file_size: Some(6938078334),
+        }
+    }
+
+    fn command(command: Command) -> HubMessage {
+        HubMessage::Command(CommandMessage {
+            correlation_id: COMMAND_ID,
+            command,
+        })
+    }
+
+    /// Every agent message variant and command response variant, paired with its fixture
+    fn agent_messages() -> Vec<(&'static str, AgentMessage)> {
+        vec![
+            (
+                "agent_register",
+                AgentMessage::Register(AgentInfo {
+                    correlation_id: REGISTER_ID,
+                    provider: ProviderType::Runpod,
+                    provider_instance_id: "pod-abc123".to_string(),
+                    hostname: "gpu-worker-1".to_string(),
+                    display_name: None,
+                    gpu_info: GpuInfo {
+                        vendor: GpuVendor::Nvidia,
+                        name: "NVIDIA GeForce RTX 4090".to_string(),
+                        memory_gb: 24.0,
+                        cuda_version: "12.4".to_string(),
+                        compute_capability: Some("8.9".to_string()),
+                    },
+                    tailscale_ip: "100.64.0.12".parse().unwrap(),
+                    agent_version: "0.2.0".to_string(),
+                    supported_codecs: vec![WireCodec::MessagePack, WireCodec::Json],
+                    diagnostics: None,
+                    agent_id_hint: Some(AGENT_ID),
+                    capabilities: None,
+                    readiness: Readiness::Ready,
+                    metrics: None,
+                    in_flight: Vec::new(),
+                }),
+            ),
+            (
+                "agent_resume",
+                AgentMessage::Resume(ResumeMessage {
+                    correlation_id: uuid!("7d3e9a4f-1b2c-4d5e-8f60-718293a4b5c6"),
+                    agent_id: AGENT_ID,
+                    session_token: Some(SESSION_TOKEN),
+                    supported_codecs: vec![WireCodec::MessagePack, WireCodec::Json],
+                    readiness: Readiness::Starting,
+                    in_flight: vec![COMMAND_ID],
+                }),
+            ),
+            (
+                "agent_heartbeat_ack",
+                AgentMessage::HeartbeatAck(HeartbeatAckMessage {
+                    correlation_id: HEARTBEAT_ID,
+                    timestamp: at("2026-01-01T00:00:10Z"),
+                    metrics: None,
+                }),
+            ),
+            (
+                "agent_command_response",
+                AgentMessage::CommandResponse(CommandResponseMessage {
+                    correlation_id: COMMAND_ID,
+                    response: CommandResponse::Failed {
+                        error: "Model checksum mismatch".to_string(),
+                        details: Some(serde_json::json!({
+                            "expected": "abc123",
+                            "actual": "def456",
+                        })),
+                    },
+                }),
+            ),
+            (
+                "agent_command_response_success",
+                AgentMessage::CommandResponse(CommandResponseMessage {
+                    correlation_id: COMMAND_ID,
+                    response: CommandResponse::Success {
+                        message: Some("Model deleted".to_string()),
+                        data: Some(serde_json::json!({ "freed_bytes": 6938078334u64 })),
+                    },
+                }),
+            ),
+            (
+                "agent_asset_created",
+                AgentMessage::AssetCreated(AssetMetadata {
+                    filename: "00001-1234567890.png".to_string(),
+                    file_size: 1048576,
+                    content_type: "image/png".to_string(),
+                    r2_key: ASSET_KEY.to_string(),
+                    sha256_hash: None,
+                    prompt: Some("a lighthouse at dusk".to_string()),
+                    negative_prompt: None,
+                    model_name: Some("sdxl-base-1.0".to_string()),
+                    generation_params: Some(serde_json::json!({
+                        "steps": 30,
+                        "cfg_scale": 7.0,
+                    })),
+                    created_at: at("2026-01-01T00:00:00Z"),
+                }),
+            ),
+            (
+                "agent_readiness",
+                AgentMessage::Readiness(ReadinessMessage {
+                    readiness: Readiness::Starting,
+                }),
+            ),
+            (
+                "agent_request_upload_url",
+                AgentMessage::RequestUploadUrl(UploadUrlRequest {
+                    correlation_id: UPLOAD_ID,
+                    filename: "00001-1234567890.png".to_string(),
+                    content_type: "image/png".to_string(),
+                }),
+            ),
+        ]
+    }
+
+    /// Every hub message variant and command variant, paired with its fixture
+    fn hub_messages() -> Vec<(&'static str, HubMessage)> {
+        vec![
+            (
+                "hub_register_ack",
+                HubMessage::RegisterAck(AgentRegistration {
+                    correlation_id: REGISTER_ID,
+                    agent_id: AGENT_ID,
+                    registered_at: at("2026-01-01T00:00:00Z"),
+                    hub_version: "0.2.0".to_string(),
+                    codec: WireCodec::MessagePack,
+                    session_token: Some(SESSION_TOKEN),
+                }),
+            ),
+            (
+                "hub_heartbeat",
+                HubMessage::Heartbeat(HeartbeatMessage {
+                    correlation_id: HEARTBEAT_ID,
+                    timestamp: at("2026-01-01T00:00:10Z"),
+                    sequence: 42,
+                }),
+            ),
+            (
+                "hub_command_download_model",
+                HubMessage::Command(CommandMessage {
+                    correlation_id: COMMAND_ID,
+                    command: Command::DownloadModel {
+                        model_id: uuid!("7a6b5c4d-3e2f-4a1b-8c9d-0e1f2a3b4c5d"),
+                        r2_key: "models/sdxl-base-1.0.safetensors".to_string(),
+                        filename: "sdxl-base-1.0.safetensors".to_string(),
+                        sha256_hash:
+                            "31e35c80fc4829d14f90153f4c74cd59c90b779f6afe05a74cd6ffb4d9a2cb08"
+                                .to_string(),
+                        file_size: None,
+                        auto_evict: false,
+                    },
+                }),
+            ),
+            ("hub_command_ping", command(Command::Ping { nonce: 7 })),
+            ("hub_command_get_status", command(Command::GetStatus)),
+            ("hub_command_get_disk_usage", command(Command::GetDiskUsage)),
+            ("hub_command_restart_webui", command(Command::RestartWebui)),
+            ("hub_command_terminate", command(Command::Terminate)),
+            (
+                "hub_command_delete_model",
+                command(Command::DeleteModel {
+                    model_id: uuid!("7a6b5c4d-3e2f-4a1b-8c9d-0e1f2a3b4c5d"),
+                }),
+            ),
+            (
+                "hub_command_sync_models",
+                command(Command::SyncModels {
+                    desired: vec![model_spec()],
+                }),
+            ),
+            (
+                "hub_command_run_job",
+                command(Command::RunJob {
+                    job_id: JOB_ID,
+                    model: model_spec(),
+                    params: serde_json::json!({
+                        "prompt": "a lighthouse at dusk",
+                        "steps": 30,
+                    }),
+                }),
+            ),
+            (
+                "hub_upload_url",
+                HubMessage::UploadUrl(UploadUrlMessage {
+                    correlation_id: UPLOAD_ID,
+                    url: format!(
+                        "https://assets.example.r2.cloudflarestorage.com/{}?X-Amz-Signature=abc123",
+                        ASSET_KEY
+                    ),
+                    r2_key: ASSET_KEY.to_string(),
+                    expires_at: at("2026-01-01T00:15:00Z"),
+                }),
+            ),
+            (
+                "hub_error",
+                HubMessage::Error {
+                    message: "Exceeded 50 messages/sec for a sustained period".to_string(),
+                    code: "rate_limited".to_string(),
+                    correlation_id: None,
+                    retry_after_secs: None,
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn agent_messages_match_golden_fixtures() {
+        for (name, message) in agent_messages() {
+            check_golden(name, &message).unwrap();
+        }
+    }
+
+    #[test]
+    fn hub_messages_match_golden_fixtures() {
+        for (name, message) in hub_messages() {
+            check_golden(name, &message).unwrap();
+        }
+    }
+
+    #[test]
+    fn agent_messages_round_trip() {
+        for (_, message) in agent_messages() {
+            // The handshake is sent before a codec is negotiated, so it is always JSON
+            let codecs: &[WireCodec] = match message {
+                AgentMessage::Register(_) | AgentMessage::Resume(_) => &[WireCodec::Json],
+                _ => &WireCodec::SUPPORTED,
+            };
+            for &codec in codecs {
+                round_trip(&message, codec).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn hub_messages_round_trip() {
+        for (_, message) in hub_messages() {
+            for codec in WireCodec::SUPPORTED {
+                round_trip(&message, codec).unwrap();
+            }
+        }
+    }
+
+    /// The fixture message named `name`
+    fn fixture<T>(messages: Vec<(&'static str, T)>, name: &str) -> T {
+        messages
+            .into_iter()
+            .find_map(|(fixture, message)| (fixture == name).then_some(message))
+            .unwrap_or_else(|| panic!("no fixture named {}", name))
+    }
+
+    #[tokio::test]
+    async fn handshake_then_heartbeat_over_memory_pair() {
+        let (mut agent, mut hub) = pair(WireCodec::Json).await;
+
+        agent
+            .send(&fixture(agent_messages(), "agent_register"))
+            .await
+            .unwrap();
+        let info = hub
+            .expect(|message| match message {
+                AgentMessage::Register(info) => Some(info),
+                _ => None,
+            })
+            .await;
+        assert_eq!(info.correlation_id, REGISTER_ID);
+
+        hub.send(&fixture(hub_messages(), "hub_register_ack"))
+            .await
+            .unwrap();
+        let registration = agent
+            .expect(|message| match message {
+                HubMessage::RegisterAck(registration) => Some(registration),
+                _ => None,
+            })
+            .await;
+        assert_eq!(registration.correlation_id, info.correlation_id);
+        assert_eq!(registration.agent_id, AGENT_ID);
+
+        hub.send(&fixture(hub_messages(), "hub_heartbeat"))
+            .await
+            .unwrap();
+        let heartbeat = agent
+            .expect(|message| match message {
+                HubMessage::Heartbeat(heartbeat) => Some(heartbeat),
+                _ => None,
+            })
+            .await;
+        agent
+            .send(&AgentMessage::HeartbeatAck(HeartbeatAckMessage {
+                correlation_id: heartbeat.correlation_id,
+                timestamp: heartbeat.timestamp,
+                metrics: None,
+            }))
+            .await
+            .unwrap();
+        let ack = hub
+            .expect(|message| match message {
+                AgentMessage::HeartbeatAck(ack) => Some(ack),
+                _ => None,
+            })
+            .await;
+        assert_eq!(ack.correlation_id, HEARTBEAT_ID);
+    }
+
+    #[tokio::test]
+    async fn memory_pair_carries_binary_frames() {
+        let (mut agent, mut hub) = pair(WireCodec::MessagePack).await;
+
+        let heartbeat = fixture(hub_messages(), "hub_heartbeat");
+        hub.send(&heartbeat).await.unwrap();
+        assert_eq!(agent.recv().await.unwrap(), heartbeat);
+
+        let readiness = fixture(agent_messages(), "agent_readiness");
+        agent.send(&readiness).await.unwrap();
+        assert_eq!(hub.recv().await.unwrap(), readiness);
+    }
+
+    #[tokio::test]
+    async fn recv_reports_a_closed_peer() {
+        let (agent, mut hub) = pair(WireCodec::Json).await;
+        agent.into_socket().close(None).await.unwrap();
+        assert!(hub.recv().await.is_err());
+    }
+}
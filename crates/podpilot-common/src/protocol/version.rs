@@ -0,0 +1,42 @@
+//! WebSocket subprotocol versions.
+//!
+//! Agents request a versioned subprotocol (e.g. `podpilot.v1`) in the
+//! `Sec-WebSocket-Protocol` header, and the hub only upgrades connections for
+//! versions it knows. A new message format gets a new version, so the hub can
+//! keep serving older agents while newer ones opt in.
+
+use serde::{Deserialize, Serialize};
+
+/// A version of the agent <-> hub message protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolVersion {
+    V1,
+}
+
+impl ProtocolVersion {
+    /// Version requested by this build of the agent
+    pub const LATEST: Self = Self::V1;
+
+    /// Versions this build can speak, in order of preference
+    pub const SUPPORTED: &'static [Self] = &[Self::V1];
+
+    /// Subprotocol name sent in `Sec-WebSocket-Protocol`
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            Self::V1 => "podpilot.v1",
+        }
+    }
+
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        Self::SUPPORTED
+            .iter()
+            .copied()
+            .find(|version| version.subprotocol() == name.trim())
+    }
+
+    /// Pick the first supported version from a comma-separated `Sec-WebSocket-Protocol` value
+    pub fn negotiate(offered: &str) -> Option<Self> {
+        offered.split(',').find_map(Self::from_subprotocol)
+    }
+}
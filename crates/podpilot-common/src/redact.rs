@@ -0,0 +1,90 @@
+//! Helpers for keeping secrets out of logs and `Debug` output.
+//!
+//! Secret config values are held in [`secrecy::SecretString`], whose `Debug`
+//! output is already redacted. These cover values that are mostly safe to show
+//! but can embed credentials, like URLs.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Placeholder printed in place of a secret
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Strip credentials from a URL, keeping enough to tell where it points
+///
+/// Userinfo (`user:password@`) and the query string are replaced with
+/// [`REDACTED`], since tokens commonly travel in either.
+///
+/// # Examples
+///
+/// - `postgresql://user:pw@db:5432/app?sslmode=require` -> `postgresql://[REDACTED]@db:5432/app?[REDACTED]`
+/// - `ws://hub-prod:80/ws/agent` -> unchanged
+pub fn redact_url(url: &str) -> String {
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme), rest),
+        None => (None, url),
+    };
+
+    // Userinfo ends at the last '@' before the path starts
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let rest = match rest[..authority_end].rfind('@') {
+        Some(at) => format!("{}@{}", REDACTED, &rest[at + 1..]),
+        None => rest.to_string(),
+    };
+    let rest = match rest.split_once('?') {
+        Some((path, query)) => {
+            let fragment = query.find('#').map_or("", |index| &query[index..]);
+            format!("{}?{}{}", path, REDACTED, fragment)
+        }
+        None => rest,
+    };
+
+    match scheme {
+        Some(scheme) => format!("{}://{}", scheme, rest),
+        None => rest,
+    }
+}
+
+/// Substrings of JSON object keys whose values are always redacted
+const SECRET_KEYS: &[&str] = &[
+    "secret",
+    "token",
+    "password",
+    "credential",
+    "authorization",
+    "api_key",
+];
+
+/// Redact likely secrets in a JSON value, in place
+///
+/// Values under keys containing one of [`SECRET_KEYS`] become [`REDACTED`], and
+/// strings that look like URLs go through [`redact_url`] (presigned upload URLs
+/// carry their signature in the query string).
+pub fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if SECRET_KEYS.iter().any(|secret| key.contains(secret)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::String(text) if text.contains("://") => *text = redact_url(text),
+        _ => {}
+    }
+}
+
+/// A message serialized as JSON with [`redact_json`] applied, for logging payloads
+pub fn redacted_payload<T: Serialize>(message: &T) -> String {
+    match serde_json::to_value(message) {
+        Ok(mut value) => {
+            redact_json(&mut value);
+            value.to_string()
+        }
+        Err(e) => format!("<unserializable: {}>", e),
+    }
+}
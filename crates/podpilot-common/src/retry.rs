@@ -0,0 +1,66 @@
+//! Retry helper with exponential backoff for transient failures.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// Classification of a failed attempt
+///
+/// Only `Transient` errors are retried; `Permanent` errors (e.g. a constraint
+/// violation) would fail identically on every attempt and are returned immediately.
+#[derive(Debug)]
+pub enum RetryError<E> {
+    /// Temporary failure (connection reset, timeout, pool exhaustion)
+    Transient(E),
+    /// Failure that retrying cannot fix
+    Permanent(E),
+}
+
+impl<E> RetryError<E> {
+    /// Unwrap the underlying error
+    pub fn into_inner(self) -> E {
+        match self {
+            RetryError::Transient(e) | RetryError::Permanent(e) => e,
+        }
+    }
+}
+
+/// Run `f` until it succeeds, fails permanently, or `attempts` are exhausted
+///
+/// The delay starts at `initial` and doubles after each transient failure, capped
+/// at `max`. `attempts` is the total number of calls, so `1` disables retrying.
+pub async fn retry_with_backoff<F, Fut, T, E>(
+    attempts: u32,
+    initial: Duration,
+    max: Duration,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RetryError<E>>>,
+    E: Display,
+{
+    let attempts = attempts.max(1);
+    let mut backoff = initial;
+
+    for attempt in 1..=attempts {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(RetryError::Transient(e)) if attempt < attempts => {
+                warn!(
+                    error = %e,
+                    attempt,
+                    max_attempts = attempts,
+                    backoff_ms = backoff.as_millis() as u64,
+                    "transient failure, retrying"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, max);
+            }
+            Err(e) => return Err(e.into_inner()),
+        }
+    }
+
+    unreachable!("retry loop always returns on the final attempt")
+}
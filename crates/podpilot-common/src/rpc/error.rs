@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 /// Error type for RPC operations
-#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
 pub enum RpcError {
     /// Agent is not registered with the hub
     #[error("Agent is not registered")]
@@ -5,5 +5,6 @@ pub mod types;
 
 pub use error::RpcError;
 pub use types::{
-    AgentStatusInfo, AssetMetadata, Command, CommandResponse, DiskUsage, LogLevel, LogLine, Metrics,
+    AgentStatusInfo, AssetMetadata, Command, CommandResponse, DiskUsage, LogLevel, LogLine,
+    Metrics, ModelSpec, NOT_READY, RUN_JOB_TIMEOUT, capability,
 };
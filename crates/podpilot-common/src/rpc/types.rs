@@ -1,11 +1,13 @@
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use uuid::Uuid;
 
 use crate::types::AgentStatus;
 
 /// System and GPU metrics from the agent
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct Metrics {
     /// GPU memory usage in bytes
     pub gpu_memory_used: u64,
@@ -29,7 +31,7 @@ pub struct Metrics {
 }
 
 /// Metadata for a generated asset (image, video, etc.)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct AssetMetadata {
     /// Filename of the asset
     pub filename: String,
@@ -59,7 +61,7 @@ pub struct AssetMetadata {
 }
 
 /// Structured log line from the agent
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LogLine {
     /// Log level (trace, debug, info, warn, error)
     pub level: LogLevel,
@@ -86,10 +88,15 @@ pub enum LogLevel {
     Error,
 }
 
+/// How long the hub waits for a `RunJob` response: a possible model download plus the job
+pub const RUN_JOB_TIMEOUT: Duration = Duration::from_secs(60 * 60 * 3);
+
 /// Commands that the hub can send to agents
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Command {
+    /// Echo `nonce` back immediately, for measuring command round-trip latency
+    Ping { nonce: u64 },
     /// Get current agent status
     GetStatus,
     /// Get disk usage information
@@ -99,13 +106,122 @@ pub enum Command {
     /// Terminate the agent gracefully
     Terminate,
     /// Download a specific model
-    DownloadModel { model_id: Uuid, r2_key: String },
+    ///
+    /// The file is stored as `filename` inside the agent's models directory and
+    /// verified against `sha256_hash` before being reported as successful. When
+    /// `file_size` is known, the agent refuses to start without room for it, first
+    /// evicting least-recently-used models if `auto_evict` is set.
+    DownloadModel {
+        model_id: Uuid,
+        r2_key: String,
+        filename: String,
+        sha256_hash: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_size: Option<u64>,
+        #[serde(default)]
+        auto_evict: bool,
+    },
     /// Delete a model from agent storage
     DeleteModel { model_id: Uuid },
+    /// Make the agent's models exactly `desired`
+    ///
+    /// Models the agent has but aren't listed are deleted first (freeing space),
+    /// then missing ones are downloaded one at a time. A single response reports
+    /// what changed; one model failing doesn't stop the rest.
+    SyncModels { desired: Vec<ModelSpec> },
+    /// Run a scheduled job against `model`, downloading it first if needed
+    ///
+    /// `params` is passed through to the agent's job runner untouched; its result
+    /// comes back in the response's `data`.
+    RunJob {
+        job_id: Uuid,
+        model: ModelSpec,
+        params: serde_json::Value,
+    },
 }
 
+/// A model an agent should have, with what it needs to download it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ModelSpec {
+    pub model_id: Uuid,
+    pub r2_key: String,
+    pub filename: String,
+    pub sha256_hash: String,
+    /// Expected size in bytes, checked against free space before downloading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<u64>,
+}
+
+impl Command {
+    /// How long the hub should wait for a response to this command by default
+    ///
+    /// Status queries are expected to answer quickly, while model downloads may
+    /// transfer many gigabytes.
+    pub fn default_timeout(&self) -> Duration {
+        match self {
+            Command::Ping { .. } | Command::GetStatus | Command::GetDiskUsage => {
+                Duration::from_secs(10)
+            }
+            Command::Terminate | Command::DeleteModel { .. } => Duration::from_secs(30),
+            Command::RestartWebui => Duration::from_secs(120),
+            Command::DownloadModel { .. } => Duration::from_secs(60 * 60 * 2),
+            // Downloads run sequentially, so allow each one the full download timeout
+            Command::SyncModels { desired } => {
+                Duration::from_secs(60 * 60 * 2) * desired.len().max(1) as u32
+            }
+            Command::RunJob { .. } => RUN_JOB_TIMEOUT,
+        }
+    }
+
+    /// Whether this command needs the agent to be [`Ready`](crate::protocol::Readiness::Ready)
+    ///
+    /// Queries, pings, and `Terminate` are answered while the agent is still starting;
+    /// everything else is held by the hub until the agent reports ready.
+    pub fn waits_for_ready(&self) -> bool {
+        !matches!(
+            self,
+            Command::Ping { .. } | Command::GetStatus | Command::GetDiskUsage | Command::Terminate
+        )
+    }
+
+    /// Capability an agent must advertise to accept this command, if any
+    pub fn required_capability(&self) -> Option<&'static str> {
+        match self {
+            Command::Ping { .. }
+            | Command::GetStatus
+            | Command::GetDiskUsage
+            | Command::Terminate => None,
+            Command::RestartWebui => Some(capability::WEBUI),
+            Command::DownloadModel { .. } | Command::SyncModels { .. } => {
+                Some(capability::MODEL_DOWNLOAD)
+            }
+            Command::DeleteModel { .. } => Some(capability::MODEL_DELETE),
+            Command::RunJob { .. } => Some(capability::JOBS),
+        }
+    }
+}
+
+/// Capability names agents advertise at registration
+pub mod capability {
+    /// Supervises a WebUI process
+    pub const WEBUI: &str = "webui";
+    /// Can fetch model files from R2
+    pub const MODEL_DOWNLOAD: &str = "model_download";
+    /// Can remove model files from its models directory
+    pub const MODEL_DELETE: &str = "model_delete";
+    /// Attaches system and GPU metrics to heartbeat acks
+    pub const METRICS: &str = "metrics";
+    /// Runs scheduled jobs
+    pub const JOBS: &str = "jobs";
+}
+
+/// `CommandResponse::Failed` error from an agent that can't handle a command yet
+///
+/// The hub retries the command once the agent reports ready.
+pub const NOT_READY: &str = "not_ready";
+
 /// Response from command execution
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "status", rename_all = "snake_case")]
 pub enum CommandResponse {
     /// Command executed successfully
@@ -123,8 +239,23 @@ pub enum CommandResponse {
     },
 }
 
+impl CommandResponse {
+    /// Rejection sent for a command that arrives before the agent is ready
+    pub fn not_ready() -> Self {
+        CommandResponse::Failed {
+            error: NOT_READY.to_string(),
+            details: None,
+        }
+    }
+
+    /// Whether the agent rejected the command only because it wasn't ready yet
+    pub fn is_not_ready(&self) -> bool {
+        matches!(self, CommandResponse::Failed { error, .. } if error == NOT_READY)
+    }
+}
+
 /// Disk usage information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DiskUsage {
     /// Total disk space in bytes
     pub total: u64,
@@ -139,7 +270,7 @@ pub struct DiskUsage {
 }
 
 /// Status information for an agent
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AgentStatusInfo {
     /// Current agent status
     pub status: AgentStatus,
@@ -0,0 +1,51 @@
+//! JSON Schema for the agent <-> hub WebSocket protocol.
+//!
+//! The generated document is committed as `schema/protocol.json` for integrators
+//! writing agents in other languages. Regenerate it with `just schema` after
+//! changing protocol types; a test fails while the committed copy is stale.
+
+use schemars::schema_for;
+use serde_json::Value;
+
+use crate::protocol::{AgentMessage, HubMessage};
+use crate::rpc::{Command, CommandResponse};
+
+/// Path of the committed schema, relative to the workspace root
+pub const PROTOCOL_SCHEMA_PATH: &str = "schema/protocol.json";
+
+/// One schema per top-level protocol type
+pub fn protocol_schema() -> Value {
+    serde_json::json!({
+        "agent_message": schema_for!(AgentMessage),
+        "hub_message": schema_for!(HubMessage),
+        "command": schema_for!(Command),
+        "command_response": schema_for!(CommandResponse),
+    })
+}
+
+/// [`protocol_schema`] as pretty-printed JSON with a trailing newline
+pub fn protocol_schema_json() -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&protocol_schema()).map(|json| json + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn committed_schema_is_current() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../..")
+            .join(PROTOCOL_SCHEMA_PATH);
+        let committed = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+        let committed: Value = serde_json::from_str(&committed)
+            .unwrap_or_else(|e| panic!("failed to parse {}: {}", path.display(), e));
+
+        assert!(
+            committed == protocol_schema(),
+            "{PROTOCOL_SCHEMA_PATH} is out of date, regenerate it with `just schema`"
+        );
+    }
+}
@@ -1,11 +1,51 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+/// Source of an agent's GPU information
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuVendor {
+    /// Detected through nvidia-smi
+    Nvidia,
+    /// No GPU tooling present (e.g., a CPU-only local agent)
+    None,
+    /// Detection tooling exists but failed, or the agent predates vendor reporting
+    #[default]
+    Unknown,
+}
+
 /// GPU information reported by agent
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct GpuInfo {
+    #[serde(default)]
+    pub vendor: GpuVendor,
     pub name: String,
     pub memory_gb: f32,
     pub cuda_version: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub compute_capability: Option<String>,
 }
+
+impl GpuInfo {
+    /// Placeholder for an agent without any GPU
+    pub fn none() -> Self {
+        Self {
+            vendor: GpuVendor::None,
+            name: "No GPU".to_string(),
+            memory_gb: 0.0,
+            cuda_version: "none".to_string(),
+            compute_capability: None,
+        }
+    }
+
+    /// Placeholder for an agent whose GPU could not be queried
+    pub fn unknown() -> Self {
+        Self {
+            vendor: GpuVendor::Unknown,
+            name: "Unknown GPU".to_string(),
+            memory_gb: 0.0,
+            cuda_version: "unknown".to_string(),
+            compute_capability: None,
+        }
+    }
+}
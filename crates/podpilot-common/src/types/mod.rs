@@ -2,4 +2,4 @@ pub mod agent;
 pub mod gpu;
 
 pub use agent::{AgentStatus, ProviderType};
-pub use gpu::GpuInfo;
+pub use gpu::{GpuInfo, GpuVendor};
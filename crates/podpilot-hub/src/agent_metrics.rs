@@ -0,0 +1,208 @@
+//! Recording metrics samples reported by agents, and expiring old ones.
+//!
+//! Agents attach a sample to a heartbeat ack only when it changed meaningfully or
+//! the last one went stale, so every sample that arrives is worth a row.
+//!
+//! Samples older than `METRICS_RETENTION_DAYS` are deleted by the cleanup task.
+//! With `METRICS_DOWNSAMPLE` set, they are first rolled up into one
+//! `agent_metrics_hourly` row per agent and hour, which is kept indefinitely.
+
+use anyhow::Context;
+use chrono::{DateTime, DurationRound, Utc};
+use podpilot_common::config::Config;
+use podpilot_common::rpc::Metrics;
+use std::time::Duration;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::state::AppState;
+
+/// Insert a metrics sample for an agent
+pub async fn record_metrics(
+    state: &AppState,
+    agent_id: Uuid,
+    metrics: &Metrics,
+) -> anyhow::Result<()> {
+    let mut conn = state
+        .acquire_db("record_metrics")
+        .await
+        .context("Failed to acquire database connection")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO agent_metrics (
+            agent_id, gpu_memory_used, gpu_memory_total, gpu_utilization, gpu_temperature,
+            disk_used, disk_total, memory_used, memory_total, collected_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+        agent_id,
+        metrics.gpu_memory_used as i64,
+        metrics.gpu_memory_total as i64,
+        i16::from(metrics.gpu_utilization),
+        metrics.gpu_temperature.map(i16::from),
+        metrics.disk_used as i64,
+        metrics.disk_total as i64,
+        metrics.memory_used as i64,
+        metrics.memory_total as i64,
+        metrics.collected_at
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Failed to record metrics")?;
+
+    Ok(())
+}
+
+/// Record metrics an agent re-sent when registering, unless a sample at least as
+/// recent is already stored (e.g. the same one, from its last heartbeat ack)
+pub async fn record_reconnect_metrics(
+    state: &AppState,
+    agent_id: Uuid,
+    metrics: &Metrics,
+) -> anyhow::Result<()> {
+    let mut conn = state
+        .acquire_db("record_reconnect_metrics")
+        .await
+        .context("Failed to acquire database connection")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO agent_metrics (
+            agent_id, gpu_memory_used, gpu_memory_total, gpu_utilization, gpu_temperature,
+            disk_used, disk_total, memory_used, memory_total, collected_at
+        )
+        SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10
+        WHERE NOT EXISTS (
+            SELECT 1 FROM agent_metrics WHERE agent_id = $1 AND collected_at >= $10
+        )
+        "#,
+        agent_id,
+        metrics.gpu_memory_used as i64,
+        metrics.gpu_memory_total as i64,
+        i16::from(metrics.gpu_utilization),
+        metrics.gpu_temperature.map(i16::from),
+        metrics.disk_used as i64,
+        metrics.disk_total as i64,
+        metrics.memory_used as i64,
+        metrics.memory_total as i64,
+        metrics.collected_at
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Failed to record metrics")?;
+
+    Ok(())
+}
+
+/// How long raw metrics samples are kept, and what happens to them afterwards
+#[derive(Debug, Clone, Copy)]
+pub struct MetricsRetention {
+    /// Days samples are kept; 0 keeps them forever
+    pub days: u32,
+    /// Roll expired samples up into hourly aggregates before deleting them
+    pub downsample: bool,
+    /// How often expired samples are removed
+    pub interval: Duration,
+}
+
+impl MetricsRetention {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            days: config.metrics_retention_days,
+            downsample: config.metrics_downsample,
+            interval: config.metrics_retention_interval,
+        }
+    }
+
+    /// Samples collected before this are expired, or `None` if samples are kept forever
+    ///
+    /// Rounded down to the hour, so each hourly rollup is written in one pass.
+    fn cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.days == 0 {
+            return None;
+        }
+        let cutoff = now - chrono::Duration::days(i64::from(self.days));
+        Some(
+            cutoff
+                .duration_trunc(chrono::Duration::hours(1))
+                .unwrap_or(cutoff),
+        )
+    }
+}
+
+/// Delete samples older than the retention period, rolling them up first if enabled
+///
+/// Returns how many samples were removed. A late sample landing in an hour that was
+/// already rolled up is merged into the existing row.
+pub async fn expire_metrics(state: &AppState, retention: &MetricsRetention) -> anyhow::Result<u64> {
+    let Some(cutoff) = retention.cutoff(Utc::now()) else {
+        return Ok(0);
+    };
+
+    let mut conn = state
+        .acquire_db("expire_metrics")
+        .await
+        .context("Failed to acquire database connection")?;
+    let expired = sqlx::query!(
+        r#"
+        WITH expired AS (
+            DELETE FROM agent_metrics
+            WHERE collected_at < $1
+            RETURNING *
+        ),
+        rolled_up AS (
+            INSERT INTO agent_metrics_hourly AS hourly (
+                agent_id, hour, samples, gpu_utilization_avg, gpu_utilization_max,
+                gpu_temperature_max, gpu_memory_used_avg, gpu_memory_used_max,
+                gpu_memory_total, disk_used_max, disk_total, memory_used_avg,
+                memory_used_max, memory_total
+            )
+            SELECT agent_id, date_trunc('hour', collected_at), COUNT(*),
+                   AVG(gpu_utilization)::float8, MAX(gpu_utilization),
+                   MAX(gpu_temperature), AVG(gpu_memory_used)::bigint, MAX(gpu_memory_used),
+                   MAX(gpu_memory_total), MAX(disk_used), MAX(disk_total),
+                   AVG(memory_used)::bigint, MAX(memory_used), MAX(memory_total)
+            FROM expired
+            WHERE $2
+            GROUP BY agent_id, date_trunc('hour', collected_at)
+            ON CONFLICT (agent_id, hour) DO UPDATE SET
+                samples = hourly.samples + EXCLUDED.samples,
+                gpu_utilization_avg = (hourly.gpu_utilization_avg * hourly.samples
+                    + EXCLUDED.gpu_utilization_avg * EXCLUDED.samples)
+                    / (hourly.samples + EXCLUDED.samples),
+                gpu_utilization_max = GREATEST(hourly.gpu_utilization_max, EXCLUDED.gpu_utilization_max),
+                gpu_temperature_max = GREATEST(hourly.gpu_temperature_max, EXCLUDED.gpu_temperature_max),
+                gpu_memory_used_avg = (hourly.gpu_memory_used_avg * hourly.samples
+                    + EXCLUDED.gpu_memory_used_avg * EXCLUDED.samples)
+                    / (hourly.samples + EXCLUDED.samples),
+                gpu_memory_used_max = GREATEST(hourly.gpu_memory_used_max, EXCLUDED.gpu_memory_used_max),
+                gpu_memory_total = GREATEST(hourly.gpu_memory_total, EXCLUDED.gpu_memory_total),
+                disk_used_max = GREATEST(hourly.disk_used_max, EXCLUDED.disk_used_max),
+                disk_total = GREATEST(hourly.disk_total, EXCLUDED.disk_total),
+                memory_used_avg = (hourly.memory_used_avg * hourly.samples
+                    + EXCLUDED.memory_used_avg * EXCLUDED.samples)
+                    / (hourly.samples + EXCLUDED.samples),
+                memory_used_max = GREATEST(hourly.memory_used_max, EXCLUDED.memory_used_max),
+                memory_total = GREATEST(hourly.memory_total, EXCLUDED.memory_total)
+            RETURNING 1
+        )
+        SELECT
+            (SELECT COUNT(*) FROM expired) AS "deleted!",
+            (SELECT COUNT(*) FROM rolled_up) AS "hours!"
+        "#,
+        cutoff,
+        retention.downsample
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .context("Failed to expire metrics samples")?;
+
+    if expired.deleted > 0 {
+        info!(
+            deleted = expired.deleted,
+            hours = expired.hours,
+            retention_days = retention.days,
+            "expired agent metrics samples"
+        );
+    }
+    Ok(expired.deleted as u64)
+}
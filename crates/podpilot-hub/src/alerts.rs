@@ -0,0 +1,137 @@
+//! Threshold alerts on agent metrics.
+//!
+//! Each incoming metrics sample is checked against the configured thresholds
+//! (`ALERT_GPU_TEMPERATURE`, `ALERT_DISK_PERCENT`, ...). Crossing one fires an
+//! [`HubEvent::Alert`] and a warning log; the alert resolves only once the value
+//! falls `ALERT_HYSTERESIS` below the threshold, so a metric hovering around it
+//! doesn't flap.
+//!
+//! Which alerts are firing is kept in memory per hub instance, so a restarted hub
+//! re-fires alerts that are still breached on the next sample.
+
+use dashmap::DashSet;
+use podpilot_common::config::Config;
+use podpilot_common::rpc::Metrics;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::events::{EventBus, HubEvent};
+
+/// A metric that can be alerted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    /// Degrees Celsius
+    GpuTemperature,
+    DiskPercent,
+    MemoryPercent,
+    GpuMemoryPercent,
+}
+
+impl AlertMetric {
+    /// This metric's value in a sample, if the agent reported it
+    fn value(self, metrics: &Metrics) -> Option<f64> {
+        match self {
+            AlertMetric::GpuTemperature => metrics.gpu_temperature.map(f64::from),
+            AlertMetric::DiskPercent => percent(metrics.disk_used, metrics.disk_total),
+            AlertMetric::MemoryPercent => percent(metrics.memory_used, metrics.memory_total),
+            AlertMetric::GpuMemoryPercent => {
+                percent(metrics.gpu_memory_used, metrics.gpu_memory_total)
+            }
+        }
+    }
+}
+
+fn percent(used: u64, total: u64) -> Option<f64> {
+    (total > 0).then(|| used as f64 / total as f64 * 100.0)
+}
+
+/// Whether an alert started or ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// Configured thresholds, each firing when a metric rises above it
+#[derive(Debug, Clone, Default)]
+pub struct AlertThresholds {
+    thresholds: Vec<(AlertMetric, f64)>,
+    /// Distance below a threshold a metric must fall to resolve its alert
+    hysteresis: f64,
+}
+
+impl AlertThresholds {
+    pub fn from_config(config: &Config) -> Self {
+        let thresholds = [
+            (AlertMetric::GpuTemperature, config.alert_gpu_temperature),
+            (AlertMetric::DiskPercent, config.alert_disk_percent),
+            (AlertMetric::MemoryPercent, config.alert_memory_percent),
+            (
+                AlertMetric::GpuMemoryPercent,
+                config.alert_gpu_memory_percent,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(metric, threshold)| threshold.map(|threshold| (metric, threshold)))
+        .collect();
+
+        Self {
+            thresholds,
+            hysteresis: config.alert_hysteresis.max(0.0),
+        }
+    }
+}
+
+/// Tracks which alerts are firing for each agent
+#[derive(Clone, Default)]
+pub struct Alerts {
+    thresholds: Arc<AlertThresholds>,
+    firing: Arc<DashSet<(Uuid, AlertMetric)>>,
+}
+
+impl Alerts {
+    pub fn new(thresholds: AlertThresholds) -> Self {
+        Self {
+            thresholds: Arc::new(thresholds),
+            firing: Arc::new(DashSet::new()),
+        }
+    }
+
+    /// Check a sample against every threshold, publishing alerts that fire or resolve
+    pub fn evaluate(&self, events: &EventBus, agent_id: Uuid, metrics: &Metrics) {
+        for &(metric, threshold) in &self.thresholds.thresholds {
+            let Some(value) = metric.value(metrics) else {
+                continue;
+            };
+            let key = (agent_id, metric);
+
+            let state = if value > threshold {
+                if !self.firing.insert(key) {
+                    continue;
+                }
+                warn!(%agent_id, ?metric, value, threshold, "alert firing");
+                AlertState::Firing
+            } else if value < threshold - self.thresholds.hysteresis {
+                if self.firing.remove(&key).is_none() {
+                    continue;
+                }
+                info!(%agent_id, ?metric, value, threshold, "alert resolved");
+                AlertState::Resolved
+            } else {
+                continue;
+            };
+
+            events.publish(HubEvent::Alert {
+                agent_id,
+                metric,
+                state,
+                value,
+                threshold,
+            });
+        }
+    }
+}
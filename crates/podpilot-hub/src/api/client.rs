@@ -3,35 +3,76 @@
 use std::sync::Arc;
 
 use crate::api::{
-    errors::ApiClientError, json::parse_json_with_context, middleware::TransparentMiddleware,
+    errors::ApiClientError,
+    json::parse_json_with_context,
+    middleware::{
+        ApiStats, ApiStatsSnapshot, POOL_IDLE_TIMEOUT, RetryMiddleware, RetryPolicy,
+        TransparentMiddleware,
+    },
+    rate_limit::RateLimitInfo,
 };
 use anyhow::{Context, Result, anyhow};
-use reqwest::{Client, Request, Response};
+use reqwest::{Client, Request, Response, tls};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use serde_json;
 use tracing::{debug, error, info, trace, warn};
 
+/// Lowest TLS version the client will negotiate with provider APIs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MinTlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl From<MinTlsVersion> for tls::Version {
+    fn from(version: MinTlsVersion) -> Self {
+        match version {
+            MinTlsVersion::Tls12 => tls::Version::TLS_1_2,
+            MinTlsVersion::Tls13 => tls::Version::TLS_1_3,
+        }
+    }
+}
+
 /// Main API client.
 pub struct ApiClient {
-    #[allow(dead_code)]
     http: ClientWithMiddleware,
+    stats: Arc<ApiStats>,
 }
 
 impl ApiClient {
-    /// Creates a new API client.
-    pub fn new() -> Result<Self> {
+    /// Creates a new API client that refuses TLS versions below `min_tls_version`
+    /// and retries failed requests according to `retry`.
+    pub fn new(min_tls_version: MinTlsVersion, retry: RetryPolicy) -> Result<Self> {
+        let stats = Arc::new(ApiStats::default());
         let http = ClientBuilder::new(
             Client::builder()
+                .min_tls_version(min_tls_version.into())
                 .tcp_keepalive(Some(std::time::Duration::from_secs(60 * 5)))
+                .pool_idle_timeout(POOL_IDLE_TIMEOUT)
                 .read_timeout(std::time::Duration::from_secs(10))
                 .connect_timeout(std::time::Duration::from_secs(10))
                 .timeout(std::time::Duration::from_secs(30))
                 .build()
                 .context("Failed to create HTTP client")?,
         )
-        .with(TransparentMiddleware)
+        // Retry wraps the stats middleware so every attempt is counted
+        .with(RetryMiddleware::new(retry))
+        .with(TransparentMiddleware::new(stats.clone()))
         .build();
 
-        Ok(Self { http })
+        Ok(Self { http, stats })
+    }
+
+    /// Sends a request, returning the response with any rate-limit headers it carried.
+    pub async fn execute(&self, request: Request) -> Result<(Response, Option<RateLimitInfo>)> {
+        let response = self.http.execute(request).await?;
+        let rate_limit = RateLimitInfo::from_headers(response.headers());
+        Ok((response, rate_limit))
+    }
+
+    /// Request, connection reuse and error counts since the client was created.
+    pub fn stats(&self) -> ApiStatsSnapshot {
+        self.stats.snapshot()
     }
 }
@@ -1,9 +1,143 @@
-use http::Extensions;
+use http::{Extensions, Method, StatusCode};
 use reqwest::{Request, Response};
 use reqwest_middleware::{Middleware, Next};
-use tracing::{trace, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, trace, warn};
 
-pub struct TransparentMiddleware;
+use crate::api::rate_limit::{RateLimitInfo, retry_after};
+
+/// How long reqwest keeps idle pooled connections open
+pub const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Minimum time between periodic stats log lines
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Delay before the first retry, doubled for each one after
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Upper bound on the exponential backoff between retries
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Longest `Retry-After` worth waiting for; longer ones return the response as-is
+const RETRY_AFTER_LIMIT: Duration = Duration::from_secs(60);
+
+/// Request counters shared between the middleware and its `ApiClient`
+///
+/// reqwest doesn't report whether a request reused a pooled connection, so reuse is
+/// inferred from timing: a request to a host contacted within [`POOL_IDLE_TIMEOUT`]
+/// most likely found an idle connection waiting for it.
+pub struct ApiStats {
+    requests: AtomicU64,
+    reused_connections: AtomicU64,
+    new_connections: AtomicU64,
+    /// Requests that failed without a response (connect, TLS, timeout, ...)
+    failed: AtomicU64,
+    /// Requests answered with a non-success status
+    error_responses: AtomicU64,
+    last_request_by_host: Mutex<HashMap<String, Instant>>,
+    last_logged: Mutex<Instant>,
+}
+
+/// Point-in-time copy of [`ApiStats`]
+#[derive(Debug, Clone, Copy)]
+pub struct ApiStatsSnapshot {
+    pub requests: u64,
+    pub reused_connections: u64,
+    pub new_connections: u64,
+    pub failed: u64,
+    pub error_responses: u64,
+    /// Fraction of requests that failed or got a non-success status
+    pub error_rate: f64,
+}
+
+impl Default for ApiStats {
+    fn default() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            reused_connections: AtomicU64::new(0),
+            new_connections: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            error_responses: AtomicU64::new(0),
+            last_request_by_host: Mutex::new(HashMap::new()),
+            last_logged: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl ApiStats {
+    pub fn snapshot(&self) -> ApiStatsSnapshot {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let failed = self.failed.load(Ordering::Relaxed);
+        let error_responses = self.error_responses.load(Ordering::Relaxed);
+        let error_rate = if requests == 0 {
+            0.0
+        } else {
+            (failed + error_responses) as f64 / requests as f64
+        };
+
+        ApiStatsSnapshot {
+            requests,
+            reused_connections: self.reused_connections.load(Ordering::Relaxed),
+            new_connections: self.new_connections.load(Ordering::Relaxed),
+            failed,
+            error_responses,
+            error_rate,
+        }
+    }
+
+    fn record_request(&self, host: Option<&str>) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        let now = Instant::now();
+        let previous = host.and_then(|host| {
+            self.last_request_by_host
+                .lock()
+                .unwrap()
+                .insert(host.to_string(), now)
+        });
+        let reused = previous.is_some_and(|at| now.duration_since(at) < POOL_IDLE_TIMEOUT);
+        if reused {
+            self.reused_connections.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.new_connections.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Log a snapshot if [`STATS_LOG_INTERVAL`] has passed since the last one
+    fn maybe_log(&self) {
+        {
+            let mut last_logged = self.last_logged.lock().unwrap();
+            if last_logged.elapsed() < STATS_LOG_INTERVAL {
+                return;
+            }
+            *last_logged = Instant::now();
+        }
+
+        let stats = self.snapshot();
+        info!(
+            requests = stats.requests,
+            reused_connections = stats.reused_connections,
+            new_connections = stats.new_connections,
+            failed = stats.failed,
+            error_responses = stats.error_responses,
+            error_rate = format!("{:.3}", stats.error_rate),
+            "API client stats"
+        );
+    }
+}
+
+pub struct TransparentMiddleware {
+    stats: Arc<ApiStats>,
+}
+
+impl TransparentMiddleware {
+    pub fn new(stats: Arc<ApiStats>) -> Self {
+        Self { stats }
+    }
+}
 
 #[async_trait::async_trait]
 impl Middleware for TransparentMiddleware {
@@ -20,10 +154,22 @@ impl Middleware for TransparentMiddleware {
             method = req.method().to_string(),
             path = req.url().path(),
         );
+        self.stats.record_request(req.url().host_str());
         let response_result = next.run(req, extensions).await;
 
-        match response_result {
+        let result = match response_result {
             Ok(response) => {
+                if let Some(rate_limit) = RateLimitInfo::from_headers(response.headers()) {
+                    if rate_limit.is_exhausted() {
+                        warn!(
+                            domain = response.url().domain(),
+                            ?rate_limit,
+                            "Provider rate limit exhausted"
+                        );
+                    } else {
+                        trace!(domain = response.url().domain(), ?rate_limit, "Rate limit");
+                    }
+                }
                 if response.status().is_success() {
                     trace!(
                         "{code} {reason} {path}",
@@ -33,15 +179,121 @@ impl Middleware for TransparentMiddleware {
                     );
                     Ok(response)
                 } else {
+                    self.stats.error_responses.fetch_add(1, Ordering::Relaxed);
                     let e = response.error_for_status_ref().unwrap_err();
                     warn!(error = ?e, "Request failed (server)");
                     Ok(response)
                 }
             }
             Err(error) => {
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
                 warn!(error = ?error, "Request failed (middleware)");
                 Err(error)
             }
+        };
+
+        self.stats.maybe_log();
+        result
+    }
+}
+
+/// When `RetryMiddleware` retries a request
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts per request, including the first; 1 disables retries
+    pub max_attempts: u32,
+    /// Also retry non-idempotent methods (POST, PATCH), which may repeat side effects
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+/// Retries connection errors, timeouts, 429s and 5xx responses with exponential
+/// backoff, waiting for the server's `Retry-After` instead when it sends one
+pub struct RetryMiddleware {
+    policy: RetryPolicy,
+}
+
+impl RetryMiddleware {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RetryMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> std::result::Result<Response, reqwest_middleware::Error> {
+        if !self.policy.retry_non_idempotent && !is_idempotent(req.method()) {
+            return next.run(req, extensions).await;
+        }
+
+        let mut attempt = 1;
+        loop {
+            // The last attempt sends the original; streaming bodies can't be cloned
+            // for a replay, so those only ever get one attempt
+            let Some(attempt_req) = (attempt < self.policy.max_attempts)
+                .then(|| req.try_clone())
+                .flatten()
+            else {
+                return next.run(req, extensions).await;
+            };
+
+            let result = next.clone().run(attempt_req, extensions).await;
+            let delay = match &result {
+                Ok(response) if is_retryable_status(response.status()) => {
+                    Some(retry_after(response.headers()).unwrap_or_else(|| backoff(attempt)))
+                }
+                Err(reqwest_middleware::Error::Reqwest(error))
+                    if error.is_connect() || error.is_timeout() =>
+                {
+                    Some(backoff(attempt))
+                }
+                _ => None,
+            };
+            let Some(delay) = delay.filter(|delay| *delay <= RETRY_AFTER_LIMIT) else {
+                return result;
+            };
+
+            warn!(
+                attempt,
+                max_attempts = self.policy.max_attempts,
+                delay_ms = delay.as_millis() as u64,
+                method = %req.method(),
+                path = req.url().path(),
+                "Retrying request"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
     }
 }
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Exponential backoff before retry number `attempt` (1-based)
+fn backoff(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RETRY_MAX_BACKOFF)
+}
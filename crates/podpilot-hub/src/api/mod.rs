@@ -6,6 +6,8 @@ pub mod client;
 pub mod errors;
 pub mod json;
 pub mod middleware;
+pub mod rate_limit;
 
 pub use client::*;
 pub use errors::*;
+pub use rate_limit::RateLimitInfo;
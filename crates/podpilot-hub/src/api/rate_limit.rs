@@ -0,0 +1,55 @@
+//! Rate-limit headers sent by provider APIs.
+
+use chrono::{DateTime, Utc};
+use http::{HeaderMap, header};
+use std::time::Duration;
+
+const RATE_LIMIT_REMAINING: &str = "x-ratelimit-remaining";
+const RATE_LIMIT_LIMIT: &str = "x-ratelimit-limit";
+
+/// A provider's view of how much of our rate limit is left.
+///
+/// Callers doing bulk operations (e.g. destroying many idle instances) should slow
+/// down as `remaining` approaches zero and wait out `retry_after` when present.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    /// Requests left in the current window (`X-RateLimit-Remaining`)
+    pub remaining: Option<u64>,
+    /// Requests allowed per window (`X-RateLimit-Limit`)
+    pub limit: Option<u64>,
+    /// How long the provider asked us to wait (`Retry-After`)
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitInfo {
+    /// Parse rate-limit headers, returning `None` if the response had none
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let info = Self {
+            remaining: header_u64(headers, RATE_LIMIT_REMAINING),
+            limit: header_u64(headers, RATE_LIMIT_LIMIT),
+            retry_after: retry_after(headers),
+        };
+        (info != Self::default()).then_some(info)
+    }
+
+    /// Whether further requests should wait before being sent
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0) || self.retry_after.is_some()
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// `Retry-After` as either delay-seconds or an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    Some((at - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
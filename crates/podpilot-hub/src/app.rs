@@ -1,6 +1,13 @@
-use crate::state::AppState;
-use crate::web::create_router;
-use podpilot_common::config::Config;
+use crate::alerts::AlertThresholds;
+use crate::commands::PendingCommands;
+use crate::r2::R2Uploads;
+use crate::registry::ConnectionRegistry;
+use crate::state::{AppState, ConnectionLimits};
+use crate::web::auth::ApiKeys;
+use crate::web::{cors_layer, create_router};
+use podpilot_common::config::{Config, format_duration};
+use podpilot_common::redact::redact_url;
+use secrecy::ExposeSecret;
 use sqlx::postgres::PgPoolOptions;
 use std::net::SocketAddr;
 use std::process::ExitCode;
@@ -23,9 +30,19 @@ impl App {
             .tailscale
             .validate()
             .expect("Invalid Tailscale configuration");
+        config.r2.validate().expect("Invalid R2 configuration");
+        let r2_uploads = R2Uploads::from_config(&config.r2).expect("Failed to configure R2");
+
+        if config.stale_agent_threshold < config.heartbeat_interval * 2 {
+            tracing::warn!(
+                stale_agent_threshold = format_duration(config.stale_agent_threshold),
+                heartbeat_interval = format_duration(config.heartbeat_interval),
+                "STALE_AGENT_THRESHOLD is under two heartbeat intervals, a single lost ack will mark agents errored"
+            );
+        }
 
         // Check if the database URL is via private networking
-        let is_private = config.database_url.contains("railway.internal");
+        let is_private = config.database_host().contains("railway.internal");
         let slow_threshold = if cfg!(debug_assertions) {
             Duration::from_secs(1)
         } else if is_private {
@@ -35,19 +52,22 @@ impl App {
         };
 
         let db_pool = PgPoolOptions::new()
-            .min_connections(0)
-            .max_connections(4)
+            .min_connections(config.db_min_connections)
+            .max_connections(config.db_max_connections)
             .acquire_slow_threshold(slow_threshold)
-            .acquire_timeout(Duration::from_secs(4))
+            .acquire_timeout(config.db_acquire_timeout)
             .idle_timeout(Duration::from_secs(60 * 2))
             .max_lifetime(Duration::from_secs(60 * 30))
-            .connect(&config.database_url)
+            .connect(config.database_url.expose_secret())
             .await
             .expect("Failed to create database pool");
 
         info!(
             is_private = is_private,
-            slow_threshold = format!("{:.2?}", slow_threshold),
+            slow_threshold = format_duration(slow_threshold),
+            min_connections = config.db_min_connections,
+            max_connections = config.db_max_connections,
+            acquire_timeout = format_duration(config.db_acquire_timeout),
             "database pool established"
         );
 
@@ -63,7 +83,40 @@ impl App {
             .await
             .expect("Database schema validation failed");
 
-        let app_state = AppState::new(db_pool.clone());
+        let pending = PendingCommands::default();
+        let connections = match &config.redis_url {
+            Some(redis_url) => {
+                ConnectionRegistry::with_redis(redis_url.expose_secret(), pending.clone())
+                    .await
+                    .expect("Failed to connect connection registry to Redis")
+            }
+            None => ConnectionRegistry::local(),
+        };
+
+        let app_state = AppState::new(
+            db_pool.clone(),
+            ConnectionLimits {
+                max_message_bytes: config.ws_max_message_bytes,
+                messages_per_sec: config.ws_rate_limit_per_sec,
+            },
+            connections,
+            slow_threshold,
+        )
+        .with_pending_commands(pending)
+        .with_allowed_agent_cidrs(config.allowed_agent_cidrs.clone())
+        .with_agent_identity_key(config.agent_identity_key)
+        .with_job_max_retries(config.job_max_retries)
+        .with_allow_any_content_type(config.allow_any_content_type)
+        .with_log_ws_payloads(config.log_ws_payloads)
+        .with_registration_limit(config.registration_rate_per_sec, config.registration_burst)
+        .with_alert_thresholds(AlertThresholds::from_config(&config));
+        let app_state = match r2_uploads {
+            Some(r2) => app_state.with_r2_uploads(r2),
+            None => {
+                info!("R2 not configured, agent asset uploads are disabled");
+                app_state
+            }
+        };
 
         // Initialize Tailscale (auto-detects existing daemon or spawns own)
         crate::tailscale::initialize(&config)
@@ -79,12 +132,33 @@ impl App {
 
     /// Run the application: start Axum and handle graceful shutdown signals
     pub async fn run(self) -> ExitCode {
+        use crate::agent_metrics::MetricsRetention;
         use crate::signals::shutdown_signal;
         use crate::ws::{cleanup_task, heartbeat_sender_task};
         use std::sync::Arc;
         use std::sync::atomic::AtomicBool;
 
-        let router = create_router(self.state.clone());
+        let cors = match cors_layer(&self.config.cors_allowed_origins) {
+            Ok(cors) => cors,
+            Err(error) => {
+                tracing::error!(error = format!("{:#}", error), "invalid CORS configuration");
+                return ExitCode::FAILURE;
+            }
+        };
+        if !self.config.cors_allowed_origins.is_empty() {
+            info!(origins = ?self.config.cors_allowed_origins, "CORS enabled for configured origins");
+        }
+
+        let api_keys = ApiKeys::new(self.config.api_keys.clone());
+        if api_keys.is_empty() {
+            if cfg!(debug_assertions) {
+                tracing::warn!("API_KEYS not configured, /api routes are unauthenticated");
+            } else {
+                tracing::warn!("API_KEYS not configured, all /api requests will be rejected");
+            }
+        }
+
+        let router = create_router(self.state.clone(), cors, api_keys);
         let addr = SocketAddr::from(([0, 0, 0, 0], self.config.port));
 
         // Spawn background tasks
@@ -92,14 +166,51 @@ impl App {
 
         let heartbeat_state = self.state.clone();
         let heartbeat_shutdown = shutdown_flag.clone();
+        let heartbeat_interval = self.config.heartbeat_interval;
+        let heartbeat_concurrency = self.config.heartbeat_concurrency;
         tokio::spawn(async move {
-            heartbeat_sender_task(heartbeat_state, heartbeat_shutdown).await;
+            heartbeat_sender_task(
+                heartbeat_state,
+                heartbeat_interval,
+                heartbeat_concurrency,
+                heartbeat_shutdown,
+            )
+            .await;
         });
 
         let cleanup_state = self.state.clone();
         let cleanup_shutdown = shutdown_flag.clone();
+        let cleanup_interval = self.config.cleanup_interval;
+        let stale_threshold = self.config.stale_agent_threshold;
+        let metrics_retention = MetricsRetention::from_config(&self.config);
+        tokio::spawn(async move {
+            cleanup_task(
+                cleanup_state,
+                cleanup_interval,
+                stale_threshold,
+                metrics_retention,
+                cleanup_shutdown,
+            )
+            .await;
+        });
+
+        if let Some(retention_days) = self.config.terminated_agent_retention_days {
+            let retention_state = self.state.clone();
+            let retention_shutdown = shutdown_flag.clone();
+            tokio::spawn(async move {
+                crate::retention::retention_task(
+                    retention_state,
+                    retention_days,
+                    retention_shutdown,
+                )
+                .await;
+            });
+        }
+
+        let scheduler_state = self.state.clone();
+        let scheduler_shutdown = shutdown_flag.clone();
         tokio::spawn(async move {
-            cleanup_task(cleanup_state, cleanup_shutdown).await;
+            crate::scheduler::scheduler_task(scheduler_state, scheduler_shutdown).await;
         });
 
         // Spawn Tailscale IP updater task (always enabled)
@@ -114,16 +225,49 @@ impl App {
             .await;
         });
 
-        info!("Background tasks spawned (heartbeat sender, cleanup, tailscale updater)");
+        let watchdog_config = self.config.tailscale.clone();
+        let watchdog_interval = self.config.tailscaled_watchdog_interval;
+        let watchdog_shutdown = shutdown_flag.clone();
+        tokio::spawn(async move {
+            crate::tailscale::tailscaled_watchdog_task(
+                watchdog_config,
+                watchdog_interval,
+                watchdog_shutdown,
+            )
+            .await;
+        });
+
+        info!(
+            "Background tasks spawned (heartbeat sender, cleanup, job scheduler, tailscale updater, tailscaled watchdog)"
+        );
 
         tracing::info!(address = %addr, "starting axum web server");
 
+        let sessions = self.state.sessions.clone();
         match tokio::net::TcpListener::bind(addr).await {
             Ok(listener) => {
-                if let Err(error) = axum::serve(listener, router)
-                    .with_graceful_shutdown(shutdown_signal())
-                    .await
-                {
+                let result = axum::serve(
+                    listener,
+                    router.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown({
+                    let sessions = sessions.clone();
+                    async move {
+                        shutdown_signal().await;
+                        sessions.begin_shutdown();
+                    }
+                })
+                .await;
+
+                // Agent sockets aren't awaited by axum, so close them explicitly
+                let report = sessions.drain(self.config.shutdown_timeout).await;
+                info!(
+                    drained = report.drained,
+                    aborted = report.aborted,
+                    "agent sessions closed"
+                );
+
+                if let Err(error) = result {
                     tracing::error!(error = ?error, "axum server error");
                     ExitCode::FAILURE
                 } else {
@@ -138,6 +282,53 @@ impl App {
         }
     }
 
+    /// Log the effective configuration once at startup, without secrets
+    ///
+    /// Secret values (API keys, Tailscale OAuth) are reduced to whether they're
+    /// configured, and URLs are stripped of credentials.
+    pub fn log_startup_config(&self) {
+        let config = &self.config;
+        info!(
+            port = config.port,
+            log_level = %config.log_level,
+            database_host = config.database_host(),
+            db_min_connections = config.db_min_connections,
+            db_max_connections = config.db_max_connections,
+            db_acquire_timeout = format_duration(config.db_acquire_timeout),
+            shutdown_timeout = format_duration(config.shutdown_timeout),
+            ws_max_message_bytes = config.ws_max_message_bytes,
+            ws_rate_limit_per_sec = config.ws_rate_limit_per_sec,
+            registration_rate_per_sec = config.registration_rate_per_sec,
+            registration_burst = config.registration_burst,
+            log_ws_payloads = config.log_ws_payloads,
+            heartbeat_interval = format_duration(config.heartbeat_interval),
+            heartbeat_concurrency = config.heartbeat_concurrency,
+            stale_agent_threshold = format_duration(config.stale_agent_threshold),
+            cleanup_interval = format_duration(config.cleanup_interval),
+            tailscaled_watchdog_interval = format_duration(config.tailscaled_watchdog_interval),
+            terminated_agent_retention_days = config.terminated_agent_retention_days,
+            metrics_retention_days = config.metrics_retention_days,
+            metrics_downsample = config.metrics_downsample,
+            metrics_retention_interval = format_duration(config.metrics_retention_interval),
+            job_max_retries = config.job_max_retries,
+            alert_gpu_temperature = ?config.alert_gpu_temperature,
+            alert_disk_percent = ?config.alert_disk_percent,
+            alert_memory_percent = ?config.alert_memory_percent,
+            alert_gpu_memory_percent = ?config.alert_gpu_memory_percent,
+            allow_any_content_type = config.allow_any_content_type,
+            redis_url = ?config.redis_url.as_ref().map(|url| redact_url(url.expose_secret())),
+            cors_allowed_origins = ?config.cors_allowed_origins,
+            api_keys = config.api_keys.len(),
+            allowed_agent_cidrs = ?config.allowed_agent_cidrs,
+            agent_identity_key = ?config.agent_identity_key,
+            tailscale_oauth = config.tailscale.oauth().is_some(),
+            r2_bucket = ?config.r2.bucket,
+            tailscale_advertise_routes = ?config.tailscale.advertise_routes,
+            tailscale_accept_routes = config.tailscale.accept_routes,
+            "startup_config"
+        );
+    }
+
     /// Get a reference to the configuration
     pub fn config(&self) -> &Config {
         &self.config
@@ -149,13 +340,12 @@ impl App {
         &self.state
     }
 
-    /// Validate that critical database tables exist
+    /// Validate that expected database tables and enum types exist
     async fn validate_database_schema(pool: &sqlx::PgPool) -> Result<(), anyhow::Error> {
+        use crate::data::{EXPECTED_ENUM_TYPES, EXPECTED_TABLES};
         use anyhow::Context;
 
-        let critical_tables = ["agents", "assets", "models"];
-
-        for table in critical_tables {
+        for &table in EXPECTED_TABLES {
             let exists = sqlx::query_scalar!(
                 "SELECT EXISTS (
                     SELECT FROM information_schema.tables
@@ -177,6 +367,28 @@ impl App {
             }
         }
 
+        for &type_name in EXPECTED_ENUM_TYPES {
+            let exists = sqlx::query_scalar!(
+                "SELECT EXISTS (
+                    SELECT FROM pg_type
+                    WHERE typname = $1
+                    AND typtype = 'e'
+                )",
+                type_name
+            )
+            .fetch_one(pool)
+            .await
+            .with_context(|| format!("Failed to check if enum type '{}' exists", type_name))?
+            .unwrap_or(false);
+
+            if !exists {
+                anyhow::bail!(
+                    "Enum type '{}' does not exist in database schema",
+                    type_name
+                );
+            }
+        }
+
         info!("Database schema validation passed");
         Ok(())
     }
@@ -0,0 +1,230 @@
+//! Recording assets generated by agents.
+//!
+//! Agents upload generated files to R2 themselves, through a presigned URL issued
+//! in answer to `AgentMessage::RequestUploadUrl`, and then report them with
+//! `AgentMessage::AssetCreated`. This records the asset and announces it on the
+//! event bus, so dashboards can show new images as they're produced.
+
+use anyhow::Context;
+use chrono::Utc;
+use podpilot_common::protocol::{UploadUrlMessage, UploadUrlRequest, error_code};
+use podpilot_common::rpc::AssetMetadata;
+use std::time::Duration;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::data::models::Asset;
+use crate::events::HubEvent;
+use crate::state::AppState;
+
+/// Record an asset reported by an agent and publish it to subscribers
+///
+/// Reports are keyed on `r2_key`, so a repeated report (e.g. after a reconnect)
+/// refreshes the existing row and is not announced again.
+pub async fn record_asset(
+    state: &AppState,
+    agent_id: Uuid,
+    asset: &AssetMetadata,
+) -> anyhow::Result<Asset> {
+    let file_size = i64::try_from(asset.file_size).context("Asset file size out of range")?;
+    let metadata = serde_json::json!({
+        "sha256_hash": asset.sha256_hash,
+        "prompt": asset.prompt,
+        "negative_prompt": asset.negative_prompt,
+        "model_name": asset.model_name,
+        "generation_params": asset.generation_params,
+    });
+
+    let mut conn = state
+        .acquire_db("record_asset")
+        .await
+        .context("Failed to acquire database connection")?;
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO assets (
+            agent_id, r2_key, filename, file_size, content_type, metadata, created_at, synced_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+        ON CONFLICT (r2_key) DO UPDATE SET
+            agent_id = EXCLUDED.agent_id,
+            filename = EXCLUDED.filename,
+            file_size = EXCLUDED.file_size,
+            content_type = EXCLUDED.content_type,
+            metadata = EXCLUDED.metadata,
+            synced_at = NOW(),
+            updated_at = NOW()
+        RETURNING id, created_at, synced_at, updated_at, (xmax = 0) AS "inserted!"
+        "#,
+        agent_id,
+        &asset.r2_key,
+        &asset.filename,
+        file_size,
+        &asset.content_type,
+        metadata,
+        asset.created_at
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .context("Failed to record asset")?;
+
+    let recorded = Asset {
+        id: record.id,
+        agent_id: Some(agent_id),
+        r2_key: asset.r2_key.clone(),
+        filename: asset.filename.clone(),
+        file_size,
+        content_type: asset.content_type.clone(),
+        metadata: Some(sqlx::types::Json(metadata)),
+        created_at: record.created_at,
+        synced_at: record.synced_at,
+        updated_at: record.updated_at,
+    };
+
+    if record.inserted {
+        info!(%agent_id, asset_id = %recorded.id, r2_key = %recorded.r2_key, "asset created");
+        state.events.publish(HubEvent::AssetCreated {
+            asset: recorded.clone(),
+        });
+    } else {
+        debug!(%agent_id, asset_id = %recorded.id, "asset report refreshed existing record");
+    }
+
+    Ok(recorded)
+}
+
+/// Content types accepted for assets unless `ALLOW_ANY_CONTENT_TYPE` is set
+const ALLOWED_CONTENT_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/webp",
+    "image/gif",
+    "image/avif",
+    "video/mp4",
+    "video/webm",
+    "audio/mpeg",
+    "audio/wav",
+    "audio/ogg",
+];
+
+/// Whether an asset may be stored with `content_type`
+///
+/// Case and parameters (e.g. `; codecs=...`) are ignored when matching the
+/// allowlist. With `allow_any`, any well-formed `type/subtype` is accepted. The
+/// value ends up in a signed `Content-Type` header, so control characters are
+/// always rejected.
+pub fn content_type_allowed(content_type: &str, allow_any: bool) -> bool {
+    if content_type.chars().any(|c| c.is_control()) {
+        return false;
+    }
+
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let well_formed = essence
+        .split_once('/')
+        .is_some_and(|(kind, subtype)| is_mime_token(kind) && is_mime_token(subtype));
+
+    well_formed && (allow_any || ALLOWED_CONTENT_TYPES.contains(&essence.as_str()))
+}
+
+/// Whether `s` is a valid MIME type or subtype name (RFC 6838)
+fn is_mime_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$&^_.+-".contains(&b))
+}
+
+/// How long a presigned upload URL stays valid
+const UPLOAD_URL_EXPIRY: Duration = Duration::from_secs(60 * 15);
+
+/// Longest filename accepted for an upload
+const MAX_UPLOAD_FILENAME_BYTES: usize = 255;
+
+/// Why an upload URL couldn't be issued
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Asset uploads are not configured on this hub")]
+    Disabled,
+    #[error("Invalid upload filename '{0}'")]
+    InvalidFilename(String),
+    #[error("Unsupported asset content type '{0}'")]
+    UnsupportedContentType(String),
+}
+
+impl UploadError {
+    /// Code sent to the agent in `HubMessage::Error`
+    pub fn code(&self) -> &'static str {
+        match self {
+            UploadError::Disabled => error_code::UPLOADS_DISABLED,
+            UploadError::InvalidFilename(_) => error_code::INVALID_UPLOAD,
+            UploadError::UnsupportedContentType(_) => error_code::UNSUPPORTED_CONTENT_TYPE,
+        }
+    }
+}
+
+/// Presign an upload for an agent's asset
+///
+/// Each upload gets a fresh key under `assets/<agent_id>/`, so agents can't
+/// overwrite each other's objects or an earlier upload of the same filename.
+pub fn issue_upload_url(
+    state: &AppState,
+    agent_id: Uuid,
+    request: &UploadUrlRequest,
+) -> Result<UploadUrlMessage, UploadError> {
+    let r2 = state.r2.as_ref().ok_or(UploadError::Disabled)?;
+    if !is_valid_filename(&request.filename) {
+        return Err(UploadError::InvalidFilename(request.filename.clone()));
+    }
+    if !content_type_allowed(&request.content_type, state.allow_any_content_type) {
+        return Err(UploadError::UnsupportedContentType(
+            request.content_type.clone(),
+        ));
+    }
+
+    let r2_key = format!(
+        "{}{}/{}",
+        asset_key_prefix(agent_id),
+        Uuid::new_v4(),
+        request.filename
+    );
+    let url = r2.presign_put(&r2_key, &request.content_type, UPLOAD_URL_EXPIRY);
+    let expires_at = Utc::now()
+        + chrono::Duration::from_std(UPLOAD_URL_EXPIRY).expect("upload URL expiry in range");
+    debug!(%agent_id, %r2_key, "issued upload URL");
+
+    Ok(UploadUrlMessage {
+        correlation_id: request.correlation_id,
+        url: url.to_string(),
+        r2_key,
+        expires_at,
+    })
+}
+
+/// Prefix of every object key issued for `agent_id`'s uploads
+fn asset_key_prefix(agent_id: Uuid) -> String {
+    format!("assets/{}/", agent_id)
+}
+
+/// Whether `r2_key` lies under the prefix of `agent_id`'s uploads
+///
+/// Assets are keyed on `r2_key`, so recording a key outside its own prefix would
+/// let an agent take over another agent's asset.
+pub fn is_agent_asset_key(agent_id: Uuid, r2_key: &str) -> bool {
+    r2_key
+        .strip_prefix(&asset_key_prefix(agent_id))
+        .is_some_and(|rest| !rest.is_empty())
+}
+
+/// Whether `filename` is a plain file name that's safe to embed in an object key
+fn is_valid_filename(filename: &str) -> bool {
+    !filename.is_empty()
+        && filename.len() <= MAX_UPLOAD_FILENAME_BYTES
+        && filename != "."
+        && filename != ".."
+        && !filename
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c.is_control())
+}
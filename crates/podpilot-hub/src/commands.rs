@@ -0,0 +1,622 @@
+//! Dispatching commands to agents and recording their results.
+//!
+//! Every command is written to `command_log` before it is sent, and the row is
+//! completed when the agent's `CommandResponse` arrives, leaving an audit trail
+//! for destructive operations like `Terminate` and `DeleteModel`.
+//!
+//! Callers that need the result use [`execute`], which registers the command in
+//! [`PendingCommands`] and waits for the matching response. A response arrives on
+//! the hub instance holding the agent's connection; one to a command relayed from
+//! another instance is sent back to it (see [`crate::registry`]).
+//!
+//! Commands that change agent state go through the agent's [`CommandQueues`] entry:
+//! each is sent only after the previous one was answered or timed out, so dependent
+//! operations (a download followed by a delete of the same model) run in the order
+//! issued. Read-only commands skip the queue, so a ping isn't stuck behind a download.
+//!
+//! Commands that need the agent's workload are also held until the agent reports
+//! itself [`Ready`](Readiness::Ready), tracked per connection in [`AgentReadiness`].
+//! An agent that answers `not_ready` anyway (it raced its own readiness report) has
+//! the command re-sent by [`execute`] once it is ready.
+
+use anyhow::Context;
+use dashmap::DashMap;
+use podpilot_common::config::format_duration;
+use podpilot_common::protocol::{CommandMessage, CommandResponseMessage, HubMessage, Readiness};
+use podpilot_common::rpc::{Command, CommandResponse};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedMutexGuard, oneshot, watch};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::data::models::CommandStatus;
+use crate::scheduler;
+use crate::state::AppState;
+
+/// Error from dispatching a command and awaiting its response
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    /// The agent is not connected (or its connection closed mid-send)
+    #[error("agent unreachable: {0:#}")]
+    Unreachable(anyhow::Error),
+    /// The agent didn't advertise the capability the command needs
+    #[error("agent does not support this command (requires capability '{0}')")]
+    Unsupported(&'static str),
+    /// No response arrived within the timeout
+    #[error("no response within {}", format_duration(*.0))]
+    TimedOut(Duration),
+    /// The agent didn't become ready to take the command within the timeout
+    #[error("agent not ready within {}", format_duration(*.0))]
+    NotReady(Duration),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+/// A caller waiting for a command's response
+struct Waiter {
+    agent_id: Uuid,
+    /// Job the command runs, if it is a `RunJob`
+    job_id: Option<Uuid>,
+    sender: oneshot::Sender<CommandResponse>,
+}
+
+/// Commands awaiting a response, keyed by correlation ID
+#[derive(Clone, Default)]
+pub struct PendingCommands {
+    waiters: Arc<DashMap<Uuid, Waiter>>,
+}
+
+impl PendingCommands {
+    fn register(
+        &self,
+        correlation_id: Uuid,
+        agent_id: Uuid,
+        job_id: Option<Uuid>,
+    ) -> oneshot::Receiver<CommandResponse> {
+        let (sender, rx) = oneshot::channel();
+        self.waiters.insert(
+            correlation_id,
+            Waiter {
+                agent_id,
+                job_id,
+                sender,
+            },
+        );
+        rx
+    }
+
+    fn cancel(&self, correlation_id: &Uuid) {
+        self.waiters.remove(correlation_id);
+    }
+
+    /// Hand a response to its waiter, if anyone is still waiting
+    pub fn resolve(&self, correlation_id: &Uuid, response: CommandResponse) {
+        if let Some((_, waiter)) = self.waiters.remove(correlation_id) {
+            let _ = waiter.sender.send(response);
+        }
+    }
+
+    /// Stop waiting on every command sent to an agent that didn't come back
+    ///
+    /// The waiters see the agent as unreachable instead of sitting out the full
+    /// timeout (hours, for a job) while holding the agent's command queue.
+    pub fn abandon_agent(&self, agent_id: &Uuid) -> usize {
+        let before = self.waiters.len();
+        self.waiters
+            .retain(|_, waiter| waiter.agent_id != *agent_id);
+        before.saturating_sub(self.waiters.len())
+    }
+
+    /// Commands awaited from an agent that aren't among those it reports `in_flight`,
+    /// as correlation IDs with the job each one runs
+    pub fn lost(&self, agent_id: &Uuid, in_flight: &[Uuid]) -> Vec<(Uuid, Option<Uuid>)> {
+        self.waiters
+            .iter()
+            .filter(|entry| entry.agent_id == *agent_id && !in_flight.contains(entry.key()))
+            .map(|entry| (*entry.key(), entry.job_id))
+            .collect()
+    }
+
+    /// Stop waiting on specific commands, which their waiters see as the agent
+    /// disconnecting
+    pub fn abandon(&self, correlation_ids: &[Uuid]) -> usize {
+        correlation_ids
+            .iter()
+            .filter(|correlation_id| self.waiters.remove(correlation_id).is_some())
+            .count()
+    }
+
+    /// Number of commands currently awaiting a response
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
+
+/// Pause before re-sending a command the agent rejected as `not_ready`
+const NOT_READY_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Readiness reported by each agent connected to this instance
+#[derive(Clone, Default)]
+pub struct AgentReadiness {
+    agents: Arc<DashMap<Uuid, watch::Sender<Readiness>>>,
+}
+
+impl AgentReadiness {
+    /// Record an agent's readiness, from its handshake or a readiness update
+    pub fn set(&self, agent_id: Uuid, readiness: Readiness) {
+        self.agents
+            .entry(agent_id)
+            .or_insert_with(|| watch::channel(readiness).0)
+            .send_replace(readiness);
+    }
+
+    /// Readiness an agent connected here last reported, `None` if it isn't connected here
+    pub fn get(&self, agent_id: &Uuid) -> Option<Readiness> {
+        self.agents.get(agent_id).map(|sender| *sender.borrow())
+    }
+
+    /// Forget a disconnected agent, releasing anyone waiting on it
+    pub fn remove(&self, agent_id: &Uuid) {
+        self.agents.remove(agent_id);
+    }
+
+    /// Wait up to `timeout` for an agent to be ready, returning whether it is
+    ///
+    /// Agents connected to another instance (or not at all) aren't tracked here and
+    /// count as ready; sending to them reports any problem.
+    async fn wait_ready(&self, agent_id: Uuid, timeout: Duration) -> bool {
+        let Some(mut readiness) = self.agents.get(&agent_id).map(|sender| sender.subscribe())
+        else {
+            return true;
+        };
+        // An error means the agent disconnected meanwhile, which sending will report
+        tokio::time::timeout(
+            timeout,
+            readiness.wait_for(|readiness| *readiness == Readiness::Ready),
+        )
+        .await
+        .is_ok()
+    }
+}
+
+/// Per-agent command queues, so each agent has at most one command in flight
+#[derive(Clone, Default)]
+pub struct CommandQueues {
+    agents: Arc<DashMap<Uuid, Arc<AgentQueue>>>,
+}
+
+#[derive(Default)]
+struct AgentQueue {
+    /// Held for the lifetime of the in-flight command; tokio's mutex is FIFO
+    turn: Arc<Mutex<()>>,
+    /// Commands waiting for their turn plus the one in flight
+    depth: AtomicUsize,
+}
+
+/// An agent's turn to receive a command, released when dropped
+struct QueueSlot {
+    _turn: OwnedMutexGuard<()>,
+    _membership: QueueMembership,
+}
+
+/// Counts a command toward its agent's queue depth until dropped
+///
+/// Separate from the turn so a caller that gives up while waiting is uncounted too.
+struct QueueMembership {
+    agents: Arc<DashMap<Uuid, Arc<AgentQueue>>>,
+    agent_id: Uuid,
+    queue: Arc<AgentQueue>,
+}
+
+impl CommandQueues {
+    /// Wait until every command queued earlier for `agent_id` has completed
+    ///
+    /// Returns `None` without waiting for read-only commands.
+    async fn acquire(&self, agent_id: Uuid, command: &Command) -> Option<QueueSlot> {
+        if matches!(
+            command,
+            Command::Ping { .. } | Command::GetStatus | Command::GetDiskUsage
+        ) {
+            return None;
+        }
+
+        // Counted under the map's entry lock, so an idle queue can't be removed
+        // between looking it up and joining it
+        let queue = {
+            let entry = self.agents.entry(agent_id).or_default();
+            entry.depth.fetch_add(1, Ordering::Relaxed);
+            Arc::clone(&entry)
+        };
+        let membership = QueueMembership {
+            agents: self.agents.clone(),
+            agent_id,
+            queue,
+        };
+        let turn = membership.queue.turn.clone().lock_owned().await;
+        Some(QueueSlot {
+            _turn: turn,
+            _membership: membership,
+        })
+    }
+
+    /// Queued plus in-flight commands for each agent with any
+    pub fn depths(&self) -> HashMap<Uuid, usize> {
+        self.agents
+            .iter()
+            .map(|entry| (*entry.key(), entry.depth.load(Ordering::Relaxed)))
+            .filter(|(_, depth)| *depth > 0)
+            .collect()
+    }
+}
+
+impl Drop for QueueMembership {
+    fn drop(&mut self) {
+        if self.queue.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.agents.remove_if(&self.agent_id, |_, queue| {
+                queue.depth.load(Ordering::Relaxed) == 0
+            });
+        }
+    }
+}
+
+/// Send a command and wait up to `timeout` for the agent's response
+///
+/// The timeout starts once any commands queued ahead of it have completed, and
+/// covers waiting for the agent to be ready as well as the response itself. On
+/// timeout, the log entry is marked `timed_out` and a late response is ignored.
+///
+/// A `not_ready` rejection is retried (as a new command) until the timeout runs out.
+///
+/// Once queued, the command runs in its own task, which keeps the agent's queue
+/// until the command settles even if the caller (e.g. an HTTP request whose client
+/// went away) stops waiting.
+pub async fn execute(
+    state: &AppState,
+    agent_id: Uuid,
+    command: Command,
+    timeout: Duration,
+) -> Result<(Uuid, CommandResponse), CommandError> {
+    ensure_supported(state, agent_id, &command).await?;
+    let slot = state.command_queues.acquire(agent_id, &command).await;
+
+    let state = state.clone();
+    let task = tokio::spawn(async move {
+        let result = send_until_answered(&state, agent_id, command, timeout).await;
+        drop(slot);
+        result
+    });
+    task.await.context("Command task failed")?
+}
+
+/// Send a command, re-sending it while the agent answers `not_ready`, and wait up to
+/// `timeout` overall for its response
+async fn send_until_answered(
+    state: &AppState,
+    agent_id: Uuid,
+    command: Command,
+    timeout: Duration,
+) -> Result<(Uuid, CommandResponse), CommandError> {
+    // A timeout too large to represent never runs out, like tokio's own timers
+    let now = Instant::now();
+    let deadline = now
+        .checked_add(timeout)
+        .unwrap_or_else(|| now + Duration::from_secs(u32::MAX as u64));
+
+    loop {
+        if command.waits_for_ready()
+            && !state
+                .readiness
+                .wait_ready(agent_id, deadline.saturating_duration_since(Instant::now()))
+                .await
+        {
+            return Err(CommandError::NotReady(timeout));
+        }
+
+        let (correlation_id, waiter) = send_pending(state, agent_id, command.clone()).await?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let response = await_response(state, agent_id, correlation_id, waiter, remaining).await?;
+
+        let retry_at = Instant::now() + NOT_READY_RETRY_DELAY;
+        if !response.is_not_ready() || retry_at >= deadline {
+            return Ok((correlation_id, response));
+        }
+        info!(%agent_id, %correlation_id, "agent not ready for command, retrying");
+        tokio::time::sleep_until(retry_at).await;
+    }
+}
+
+/// Register a waiter for a new command, then log and send it
+async fn send_pending(
+    state: &AppState,
+    agent_id: Uuid,
+    command: Command,
+) -> Result<(Uuid, oneshot::Receiver<CommandResponse>), CommandError> {
+    let correlation_id = Uuid::new_v4();
+    let job_id = match &command {
+        Command::RunJob { job_id, .. } => Some(*job_id),
+        _ => None,
+    };
+
+    // Register before sending so a fast response can't arrive ahead of its waiter
+    let waiter = state.pending.register(correlation_id, agent_id, job_id);
+    if let Err(e) = send_logged(state, agent_id, correlation_id, command).await {
+        state.pending.cancel(&correlation_id);
+        return Err(e);
+    }
+    if let Some(job_id) = job_id {
+        scheduler::mark_running(state, job_id).await;
+    }
+    Ok((correlation_id, waiter))
+}
+
+/// Wait up to `timeout` for a sent command's response, marking it timed out otherwise
+async fn await_response(
+    state: &AppState,
+    agent_id: Uuid,
+    correlation_id: Uuid,
+    waiter: oneshot::Receiver<CommandResponse>,
+    timeout: Duration,
+) -> Result<CommandResponse, CommandError> {
+    match tokio::time::timeout(timeout, waiter).await {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(_)) => {
+            // Only `abandon_agent` and `abandon` drop a waiter without answering it
+            warn!(%agent_id, %correlation_id, "agent disconnected before responding");
+            let failure = CommandResponse::Failed {
+                error: "Agent disconnected before responding".to_string(),
+                details: None,
+            };
+            complete(
+                state,
+                agent_id,
+                correlation_id,
+                CommandStatus::Failed,
+                &failure,
+            )
+            .await?;
+            Err(CommandError::Unreachable(anyhow::anyhow!(
+                "agent disconnected before responding"
+            )))
+        }
+        Err(_) => {
+            state.pending.cancel(&correlation_id);
+            warn!(%agent_id, %correlation_id, timeout_ms = timeout.as_millis() as u64, "command timed out");
+            let failure = CommandResponse::Failed {
+                error: format!("No response within {}", format_duration(timeout)),
+                details: None,
+            };
+            complete(
+                state,
+                agent_id,
+                correlation_id,
+                CommandStatus::TimedOut,
+                &failure,
+            )
+            .await?;
+            Err(CommandError::TimedOut(timeout))
+        }
+    }
+}
+
+/// Reject a command the agent didn't advertise support for
+///
+/// Agents that predate capability advertisement (NULL `capabilities`) accept everything.
+async fn ensure_supported(
+    state: &AppState,
+    agent_id: Uuid,
+    command: &Command,
+) -> Result<(), CommandError> {
+    let Some(required) = command.required_capability() else {
+        return Ok(());
+    };
+
+    let mut conn = state
+        .acquire_db("check_capabilities")
+        .await
+        .context("Failed to acquire database connection")?;
+    let capabilities =
+        sqlx::query_scalar!("SELECT capabilities FROM agents WHERE id = $1", agent_id)
+            .fetch_optional(&mut *conn)
+            .await
+            .context("Failed to load agent capabilities")?
+            .flatten();
+
+    match capabilities {
+        Some(capabilities) if !capabilities.iter().any(|c| c == required) => {
+            Err(CommandError::Unsupported(required))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Write the log entry for a command, then send it
+async fn send_logged(
+    state: &AppState,
+    agent_id: Uuid,
+    correlation_id: Uuid,
+    command: Command,
+) -> Result<(), CommandError> {
+    let command_json = serde_json::to_value(&command).context("Failed to serialize command")?;
+
+    let mut conn = state
+        .acquire_db("record_command")
+        .await
+        .context("Failed to acquire database connection")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO command_log (agent_id, correlation_id, command)
+        VALUES ($1, $2, $3)
+        "#,
+        agent_id,
+        correlation_id,
+        command_json
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Failed to record command")?;
+    drop(conn);
+
+    info!(%agent_id, %correlation_id, ?command, "dispatching command");
+
+    let message = HubMessage::Command(CommandMessage {
+        correlation_id,
+        command,
+    });
+    if let Err(e) = state.send_to_agent(&agent_id, message).await {
+        let failure = CommandResponse::Failed {
+            error: format!("{:#}", e),
+            details: None,
+        };
+        complete(
+            state,
+            agent_id,
+            correlation_id,
+            CommandStatus::Failed,
+            &failure,
+        )
+        .await?;
+        return Err(CommandError::Unreachable(e));
+    }
+
+    Ok(())
+}
+
+/// Nonce for a ping, unique enough to catch mismatched responses
+pub fn ping_nonce() -> u64 {
+    let (high, low) = Uuid::new_v4().as_u64_pair();
+    high ^ low
+}
+
+/// Actively check that an agent is alive by pinging it
+///
+/// Returns whether the agent responded within `timeout`. Pings skip the agent's
+/// command queue and readiness gate, so a busy or starting agent still answers.
+pub async fn probe(state: &AppState, agent_id: Uuid, timeout: Duration) -> bool {
+    let command = Command::Ping {
+        nonce: ping_nonce(),
+    };
+    match execute(state, agent_id, command, timeout).await {
+        Ok(_) => true,
+        Err(e) => {
+            debug!(%agent_id, error = %e, "probe failed");
+            false
+        }
+    }
+}
+
+/// Record a command response received from an agent and wake its waiter
+pub async fn record_response(
+    state: &AppState,
+    agent_id: Uuid,
+    message: CommandResponseMessage,
+) -> anyhow::Result<()> {
+    let status = match message.response {
+        CommandResponse::Success { .. } => CommandStatus::Succeeded,
+        CommandResponse::Failed { .. } => CommandStatus::Failed,
+    };
+    let recorded = complete(
+        state,
+        agent_id,
+        message.correlation_id,
+        status,
+        &message.response,
+    )
+    .await;
+    if let Err(e) = forget_evicted_models(state, agent_id, &message.response).await {
+        warn!(%agent_id, error = format!("{:#}", e), "failed to record evicted models");
+    }
+
+    // Wake the waiter even if the audit write failed; the agent already acted
+    if !state.connections.relay_response(&message).await {
+        state
+            .pending
+            .resolve(&message.correlation_id, message.response);
+    }
+    recorded
+}
+
+/// Drop `agent_models` rows for models the agent reports evicting
+///
+/// Agents evict least-recently-used models to make room for downloads and list
+/// them under `evicted` in the response, whether or not the command succeeded.
+async fn forget_evicted_models(
+    state: &AppState,
+    agent_id: Uuid,
+    response: &CommandResponse,
+) -> anyhow::Result<()> {
+    let report = match response {
+        CommandResponse::Success { data, .. } => data.as_ref(),
+        CommandResponse::Failed { details, .. } => details.as_ref(),
+    };
+    let evicted: Vec<Uuid> = match report.and_then(|report| report.get("evicted")) {
+        Some(evicted) => {
+            serde_json::from_value(evicted.clone()).context("Malformed evicted model list")?
+        }
+        None => return Ok(()),
+    };
+    if evicted.is_empty() {
+        return Ok(());
+    }
+
+    let mut conn = state
+        .acquire_db("forget_evicted_models")
+        .await
+        .context("Failed to acquire database connection")?;
+    sqlx::query!(
+        "DELETE FROM agent_models WHERE agent_id = $1 AND model_id = ANY($2)",
+        agent_id,
+        &evicted
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Failed to remove evicted models")?;
+
+    info!(%agent_id, evicted = evicted.len(), "agent evicted models");
+    Ok(())
+}
+
+/// Complete a pending log entry; entries that are already complete are left untouched
+async fn complete(
+    state: &AppState,
+    agent_id: Uuid,
+    correlation_id: Uuid,
+    status: CommandStatus,
+    response: &CommandResponse,
+) -> anyhow::Result<()> {
+    let response_json = serde_json::to_value(response).context("Failed to serialize response")?;
+
+    let mut conn = state
+        .acquire_db("complete_command")
+        .await
+        .context("Failed to acquire database connection")?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE command_log
+        SET status = $3, response = $4, completed_at = NOW()
+        WHERE correlation_id = $1
+          AND agent_id = $2
+          AND status = 'pending'::command_status
+        "#,
+        correlation_id,
+        agent_id,
+        status as _,
+        response_json
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Failed to record command result")?;
+
+    if result.rows_affected() == 0 {
+        warn!(%agent_id, %correlation_id, "no pending command matched response");
+    }
+
+    Ok(())
+}
@@ -1,3 +1,29 @@
 //! Database models and schema.
 
 pub mod models;
+pub mod retry;
+
+/// Tables the hub requires, checked at startup after migrations run
+///
+/// Keep in sync with `migrations/` when adding or dropping tables.
+pub const EXPECTED_TABLES: &[&str] = &[
+    "agents",
+    "assets",
+    "models",
+    "agent_models",
+    "command_log",
+    "hub_network_events",
+    "agent_events",
+    "agent_metrics",
+    "agent_metrics_hourly",
+    "jobs",
+];
+
+/// Postgres enum types backing the `sqlx::Type` enums in [`models`]
+pub const EXPECTED_ENUM_TYPES: &[&str] = &[
+    "provider_type",
+    "agent_status",
+    "model_type",
+    "command_status",
+    "job_status",
+];
@@ -1,7 +1,9 @@
 use chrono::{DateTime, Utc};
+use podpilot_common::types::GpuInfo;
 use serde::{Deserialize, Serialize};
 use sqlx::types::Json;
 use std::net::IpAddr;
+use tracing::warn;
 use uuid::Uuid;
 
 /// Cloud provider or platform type for agent instances
@@ -27,6 +29,23 @@ pub enum AgentStatus {
     Terminated,
 }
 
+impl AgentStatus {
+    /// Whether an agent in this status may move to `next`
+    ///
+    /// Terminated is final. Any other status may fall to error or terminated, or go
+    /// back to registering when the agent re-registers; an errored agent must
+    /// re-register before becoming ready again.
+    pub fn can_transition_to(self, next: AgentStatus) -> bool {
+        use AgentStatus::*;
+        match (self, next) {
+            (Terminated, _) => false,
+            (_, Registering | Error | Terminated) => true,
+            (Registering | Ready | Running | Idle, Ready | Running | Idle) => true,
+            (Error, _) => false,
+        }
+    }
+}
+
 /// Type of model file (checkpoint, LoRA, embedding, VAE)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
 #[sqlx(type_name = "model_type", rename_all = "lowercase")]
@@ -38,6 +57,32 @@ pub enum ModelType {
     Vae,
 }
 
+/// Lifecycle of a command dispatched to an agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "command_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Pending,
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
+/// Lifecycle of a scheduled job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "job_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for a capable agent to be free
+    Queued,
+    /// Claimed for an agent, not yet sent
+    Assigned,
+    /// Sent to the agent, awaiting its result
+    Running,
+    Completed,
+    Failed,
+}
+
 /// Remote GPU agent instance
 #[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
 pub struct Agent {
@@ -45,16 +90,46 @@ pub struct Agent {
     pub provider: ProviderType,
     pub provider_instance_id: Option<String>,
     pub hostname: String,
+    /// Operator-chosen name, or the hostname when none is set
+    pub display_name: String,
     pub status: AgentStatus,
     pub tailscale_ip: Option<IpAddr>,
+    /// Source address of the latest connection as seen by the hub
+    pub remote_ip: Option<IpAddr>,
+    /// Agent binary version reported at the latest registration
+    pub agent_version: Option<String>,
     pub gpu_info: Option<Json<serde_json::Value>>,
+    /// Startup conditions reported at the latest registration
+    pub boot_diagnostics: Option<Json<serde_json::Value>>,
+    /// Features advertised at the latest registration; `None` means all are assumed
+    pub capabilities: Option<Vec<String>>,
     pub registered_at: DateTime<Utc>,
     pub last_seen_at: Option<DateTime<Utc>>,
     pub terminated_at: Option<DateTime<Utc>>,
+    /// Terminated agent with the same identity that this one replaced, if any
+    pub previous_agent_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl Agent {
+    /// Typed view of the `gpu_info` column
+    ///
+    /// The column is stored as untyped JSON so that rows written by older agents
+    /// still load; if the stored shape doesn't match `GpuInfo`, this logs and
+    /// returns `None` rather than failing.
+    pub fn gpu(&self) -> Option<GpuInfo> {
+        let Json(value) = self.gpu_info.as_ref()?;
+        match serde_json::from_value(value.clone()) {
+            Ok(gpu) => Some(gpu),
+            Err(e) => {
+                warn!(agent_id = %self.id, error = %e, "agent has malformed gpu_info");
+                None
+            }
+        }
+    }
+}
+
 /// Generated asset (image, video, etc.) stored in R2
 #[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
 pub struct Asset {
@@ -91,3 +166,52 @@ pub struct AgentModel {
     pub model_id: Uuid,
     pub downloaded_at: DateTime<Utc>,
 }
+
+/// Audit record of a command sent to an agent
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct CommandLogEntry {
+    pub id: Uuid,
+    /// NULL once the agent is deleted; the entry is kept for the audit trail
+    pub agent_id: Option<Uuid>,
+    pub correlation_id: Uuid,
+    pub command: serde_json::Value,
+    pub status: CommandStatus,
+    pub response: Option<serde_json::Value>,
+    pub sent_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Work scheduled onto an agent
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    pub model_id: Uuid,
+    pub params: serde_json::Value,
+    pub status: JobStatus,
+    pub assigned_agent_id: Option<Uuid>,
+    /// Times the job was requeued after losing its agent
+    pub retries: i32,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub result: Option<serde_json::Value>,
+}
+
+/// A recorded change in an agent's status
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct AgentEvent {
+    pub id: i64,
+    pub agent_id: Uuid,
+    pub status: AgentStatus,
+    pub reason: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// A change in the hub's Tailscale IP
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct NetworkEvent {
+    pub id: i64,
+    pub previous_ip: Option<IpAddr>,
+    pub ip: IpAddr,
+    pub observed_at: DateTime<Utc>,
+}
@@ -0,0 +1,56 @@
+//! Classification of database errors for retrying transient failures.
+
+use podpilot_common::retry::RetryError;
+use std::time::Duration;
+
+/// Total attempts for retryable database operations
+pub const DB_RETRY_ATTEMPTS: u32 = 3;
+/// Delay before the first retry (doubles on each subsequent retry)
+pub const DB_RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// Upper bound on the delay between retries
+pub const DB_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Whether a database error is likely to succeed if the operation is retried
+///
+/// Connection-level failures and pool exhaustion are transient. Database errors
+/// are transient only for connection exceptions (SQLSTATE class 08), serialization
+/// failures, deadlocks, connection limits, and admin shutdowns; everything else
+/// (notably constraint violations) is permanent.
+pub fn is_transient(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db_error) => db_error.code().is_some_and(|code| {
+            code.starts_with("08") || matches!(code.as_ref(), "40001" | "40P01" | "53300" | "57P01")
+        }),
+        _ => false,
+    }
+}
+
+/// Classify a database error for `retry_with_backoff`
+pub fn classify(error: sqlx::Error) -> RetryError<sqlx::Error> {
+    if is_transient(&error) {
+        RetryError::Transient(error)
+    } else {
+        RetryError::Permanent(error)
+    }
+}
+
+/// Classify an `anyhow` error by any `sqlx::Error` in its cause chain
+///
+/// Errors without an underlying database error are treated as permanent.
+pub fn classify_anyhow(error: anyhow::Error) -> RetryError<anyhow::Error> {
+    let transient = error.chain().any(|cause| {
+        cause
+            .downcast_ref::<sqlx::Error>()
+            .is_some_and(is_transient)
+    });
+
+    if transient {
+        RetryError::Transient(error)
+    } else {
+        RetryError::Permanent(error)
+    }
+}
@@ -0,0 +1,228 @@
+//! Hub-wide event stream for live dashboards.
+//!
+//! Events are broadcast to every subscriber; a subscriber that falls behind
+//! skips the events it missed rather than slowing down publishers.
+//!
+//! Each event gets a sequence number, and recent events are kept so a client
+//! reconnecting with the last number it saw can catch up on what it missed.
+//!
+//! Events only cover this hub instance: agents connected to other replicas are
+//! not announced here.
+
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::alerts::{AlertMetric, AlertState};
+use crate::data::models::{AgentStatus, Asset};
+
+/// Events buffered per subscriber before it starts missing them, and the number
+/// of recent events kept for replay
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened in the fleet
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HubEvent {
+    /// An agent registered and its connection is live
+    AgentConnected { agent_id: Uuid },
+    /// An agent's connection closed or was dropped as stale
+    AgentDisconnected { agent_id: Uuid },
+    /// An agent's status was updated
+    AgentStatusChanged { agent_id: Uuid, status: AgentStatus },
+    /// An agent reported a newly generated asset
+    AssetCreated { asset: Asset },
+    /// An agent's metric crossed its alert threshold, or fell back below it
+    Alert {
+        agent_id: Uuid,
+        metric: AlertMetric,
+        state: AlertState,
+        value: f64,
+        threshold: f64,
+    },
+    /// The subscriber fell behind and `missed` events were skipped
+    ///
+    /// Never published; subscribers emit it so clients know to refetch.
+    Lagged { missed: u64 },
+}
+
+impl HubEvent {
+    /// The serialized `type` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            HubEvent::AgentConnected { .. } => "agent_connected",
+            HubEvent::AgentDisconnected { .. } => "agent_disconnected",
+            HubEvent::AgentStatusChanged { .. } => "agent_status_changed",
+            HubEvent::AssetCreated { .. } => "asset_created",
+            HubEvent::Alert { .. } => "alert",
+            HubEvent::Lagged { .. } => "lagged",
+        }
+    }
+
+    /// The category used for subscription filters, `None` for stream bookkeeping
+    pub fn category(&self) -> Option<EventCategory> {
+        match self {
+            HubEvent::AgentConnected { .. }
+            | HubEvent::AgentDisconnected { .. }
+            | HubEvent::AgentStatusChanged { .. } => Some(EventCategory::Agent),
+            HubEvent::AssetCreated { .. } => Some(EventCategory::Asset),
+            HubEvent::Alert { .. } => Some(EventCategory::Alert),
+            HubEvent::Lagged { .. } => None,
+        }
+    }
+}
+
+/// Groups of events a subscriber can filter on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventCategory {
+    Agent,
+    Asset,
+    Alert,
+}
+
+impl FromStr for EventCategory {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "agent" => Ok(Self::Agent),
+            "asset" => Ok(Self::Asset),
+            "alert" => Ok(Self::Alert),
+            other => Err(format!(
+                "Unknown event type '{}', expected 'agent', 'asset', or 'alert'",
+                other
+            )),
+        }
+    }
+}
+
+/// Which event categories a subscriber wants
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// Empty means every category
+    categories: HashSet<EventCategory>,
+}
+
+impl EventFilter {
+    /// Parse a comma-separated list such as `agent,alert`; `None` or empty allows all
+    pub fn parse(types: Option<&str>) -> Result<Self, String> {
+        let categories = types
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(EventCategory::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { categories })
+    }
+
+    pub fn allows(&self, event: &HubEvent) -> bool {
+        match event.category() {
+            Some(category) => self.categories.is_empty() || self.categories.contains(&category),
+            None => true,
+        }
+    }
+}
+
+/// A published event and its sequence number
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    /// Increases by one per event, starting at 1 when the hub starts
+    pub id: u64,
+    pub event: HubEvent,
+}
+
+/// Catch-up state for a subscriber resuming after `last_id`
+pub struct Resume {
+    /// Buffered events published after `last_id`, oldest first
+    pub replay: Vec<Envelope>,
+    /// Events published after `last_id` that are no longer buffered
+    pub missed: u64,
+    /// Live events following the replayed ones
+    pub receiver: broadcast::Receiver<Envelope>,
+}
+
+/// Recently published events, with the next sequence number
+struct History {
+    next_id: u64,
+    recent: VecDeque<Envelope>,
+}
+
+/// Broadcast channel for [`HubEvent`]s
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Envelope>,
+    history: Arc<Mutex<History>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            sender,
+            history: Arc::new(Mutex::new(History {
+                next_id: 1,
+                recent: VecDeque::with_capacity(EVENT_CAPACITY),
+            })),
+        }
+    }
+
+    /// Publish an event; subscribers that aren't connected only see it on replay
+    pub fn publish(&self, event: HubEvent) {
+        // Sending under the lock keeps broadcast order identical to sequence order
+        let mut history = self.history.lock().expect("event history lock poisoned");
+        let envelope = Envelope {
+            id: history.next_id,
+            event,
+        };
+        history.next_id += 1;
+        if history.recent.len() == EVENT_CAPACITY {
+            history.recent.pop_front();
+        }
+        history.recent.push_back(envelope.clone());
+        let _ = self.sender.send(envelope);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.sender.subscribe()
+    }
+
+    /// Subscribe, replaying buffered events published after `last_id`
+    ///
+    /// A `last_id` from before a hub restart (ahead of the current sequence)
+    /// replays nothing, since the numbering started over.
+    pub fn resume(&self, last_id: u64) -> Resume {
+        let history = self.history.lock().expect("event history lock poisoned");
+        let receiver = self.sender.subscribe();
+
+        if last_id >= history.next_id {
+            return Resume {
+                replay: Vec::new(),
+                missed: 0,
+                receiver,
+            };
+        }
+
+        let replay: Vec<_> = history
+            .recent
+            .iter()
+            .filter(|envelope| envelope.id > last_id)
+            .cloned()
+            .collect();
+        let published = history.next_id - 1 - last_id;
+        Resume {
+            missed: published - replay.len() as u64,
+            replay,
+            receiver,
+        }
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,7 +1,18 @@
+pub mod agent_metrics;
+pub mod alerts;
 pub mod api;
 pub mod app;
+pub mod assets;
 pub mod cli;
+pub mod commands;
 pub mod data;
+pub mod events;
+pub mod lifecycle;
+pub mod metrics;
+pub mod r2;
+pub mod registry;
+pub mod retention;
+pub mod scheduler;
 pub mod signals;
 pub mod state;
 pub mod tailscale;
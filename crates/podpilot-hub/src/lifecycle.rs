@@ -0,0 +1,105 @@
+//! Recording agent status changes.
+//!
+//! The `agents.status` column only holds the current state, so every change is
+//! also appended to `agent_events` and announced on the event bus. Call
+//! [`record_status`] wherever the status column is written.
+
+use anyhow::Context;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::data::models::AgentStatus;
+use crate::state::AppState;
+
+/// Move an agent to `next` if [`AgentStatus::can_transition_to`] allows it,
+/// returning whether the status changed
+///
+/// Moving to [`AgentStatus::Terminated`] also stamps `terminated_at`.
+///
+/// The row is locked while checking, so a concurrent change (e.g. stale cleanup
+/// marking the agent errored) can't be overwritten with a transition that's no
+/// longer valid.
+pub async fn transition(
+    state: &AppState,
+    agent_id: Uuid,
+    next: AgentStatus,
+    reason: &str,
+) -> anyhow::Result<bool> {
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .context("Failed to start status transition")?;
+    let current = sqlx::query_scalar!(
+        r#"SELECT status AS "status: AgentStatus" FROM agents WHERE id = $1 FOR UPDATE"#,
+        agent_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to read agent status")?;
+
+    let Some(current) = current else {
+        return Ok(false);
+    };
+    if current == next {
+        return Ok(false);
+    }
+    if !current.can_transition_to(next) {
+        debug!(%agent_id, ?current, ?next, reason, "skipping invalid status transition");
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE agents
+        SET status = $2,
+            terminated_at = CASE WHEN $2 = 'terminated'::agent_status
+                THEN COALESCE(terminated_at, NOW())
+                ELSE terminated_at
+            END,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+        agent_id,
+        next as _
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to update agent status")?;
+    tx.commit()
+        .await
+        .context("Failed to commit status transition")?;
+
+    record_status(state, agent_id, next, reason).await;
+    Ok(true)
+}
+
+/// Append a status change to the agent's history and publish it
+///
+/// Failures are logged rather than returned: the status itself is already
+/// written, and losing a history row shouldn't fail the change.
+pub async fn record_status(state: &AppState, agent_id: Uuid, status: AgentStatus, reason: &str) {
+    state.publish_status(agent_id, status);
+
+    let mut conn = match state.acquire_db("record_agent_event").await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!(%agent_id, ?status, "Failed to acquire connection for agent event: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO agent_events (agent_id, status, reason)
+        VALUES ($1, $2, $3)
+        "#,
+        agent_id,
+        status as _,
+        reason
+    )
+    .execute(&mut *conn)
+    .await
+    {
+        error!(%agent_id, ?status, "Failed to record agent event: {}", e);
+    }
+}
@@ -25,7 +25,11 @@ async fn main() -> ExitCode {
         .extract()
         .expect("Failed to load config");
 
-    podpilot_common::logging::setup_logging(&config);
+    let log_handle = podpilot_common::logging::setup_logging(&config);
+    tokio::spawn(podpilot_hub::signals::reload_log_level_on_sighup(
+        log_handle,
+        config.log_level.clone(),
+    ));
 
     // Log application startup context
     info!(
@@ -42,6 +46,7 @@ async fn main() -> ExitCode {
     let app = App::new(config)
         .await
         .expect("Failed to initialize application");
+    app.log_startup_config();
 
     // Run the application (Axum server + graceful shutdown)
     app.run().await
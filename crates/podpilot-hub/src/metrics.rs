@@ -0,0 +1,81 @@
+//! Prometheus metrics exposed on `/metrics`.
+//!
+//! Fleet gauges are recomputed from the database on each scrape rather than
+//! maintained incrementally, so they stay correct across hub restarts.
+
+use anyhow::{Context, Result};
+use prometheus::{Encoder, IntGaugeVec, Opts, Registry, TextEncoder};
+use sqlx::PgPool;
+
+/// Label used for agents that registered without reporting a version
+const UNKNOWN_VERSION: &str = "unknown";
+
+/// Registry of hub metrics
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    agents_by_version: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let agents_by_version = IntGaugeVec::new(
+            Opts::new(
+                "podpilot_agents_by_version",
+                "Non-terminated agents by reported agent version",
+            ),
+            &["version"],
+        )
+        .expect("valid metric definition");
+        registry
+            .register(Box::new(agents_by_version.clone()))
+            .expect("metric registered once");
+
+        Self {
+            registry,
+            agents_by_version,
+        }
+    }
+
+    /// Refresh database-backed gauges and render all metrics in text format
+    pub async fn render(&self, db: &PgPool) -> Result<String> {
+        self.refresh_agent_versions(db).await?;
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("Failed to encode metrics")?;
+        String::from_utf8(buffer).context("Metrics output was not UTF-8")
+    }
+
+    async fn refresh_agent_versions(&self, db: &PgPool) -> Result<()> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT agent_version, COUNT(*) AS "count!"
+            FROM agents
+            WHERE terminated_at IS NULL
+            GROUP BY agent_version
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to count agents by version")?;
+
+        // Versions no longer present must disappear rather than report stale counts
+        self.agents_by_version.reset();
+        for row in rows {
+            let version = row.agent_version.as_deref().unwrap_or(UNKNOWN_VERSION);
+            self.agents_by_version
+                .with_label_values(&[version])
+                .set(row.count);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
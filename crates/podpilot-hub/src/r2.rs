@@ -0,0 +1,58 @@
+//! Presigned uploads to Cloudflare R2 (S3-compatible).
+//!
+//! Agents upload generated assets straight to R2 but never hold credentials: they
+//! ask for a presigned PUT URL over their WebSocket (`RequestUploadUrl`), upload to
+//! it, then report the asset with `AssetCreated`.
+
+use anyhow::{Context, Result};
+use podpilot_common::config::R2Config;
+use reqwest::Url;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use secrecy::ExposeSecret;
+use std::time::Duration;
+
+/// R2 region name (R2 ignores regions, but SigV4 requires one)
+const R2_REGION: &str = "auto";
+
+/// Presigns R2 uploads for agents
+#[derive(Clone)]
+pub struct R2Uploads {
+    bucket: Bucket,
+    credentials: Credentials,
+}
+
+impl R2Uploads {
+    /// Build a presigner from config, returning `None` when R2 is not configured
+    pub fn from_config(config: &R2Config) -> Result<Option<Self>> {
+        let (Some(endpoint), Some(bucket), Some(key), Some(secret)) = (
+            &config.endpoint,
+            &config.bucket,
+            &config.access_key_id,
+            &config.secret_access_key,
+        ) else {
+            return Ok(None);
+        };
+
+        let endpoint: Url = endpoint
+            .parse()
+            .with_context(|| format!("Invalid R2 endpoint URL '{}'", endpoint))?;
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, bucket.clone(), R2_REGION)
+            .context("Failed to configure R2 bucket")?;
+        let credentials = Credentials::new(key.expose_secret(), secret.expose_secret());
+
+        Ok(Some(Self {
+            bucket,
+            credentials,
+        }))
+    }
+
+    /// Presigned PUT URL for an object key, valid for `expiry`
+    ///
+    /// `Content-Type` is a signed header, so the upload must send exactly
+    /// `content_type` and the stored object is served with it.
+    pub fn presign_put(&self, key: &str, content_type: &str, expiry: Duration) -> Url {
+        let mut action = self.bucket.put_object(Some(&self.credentials), key);
+        action.headers_mut().insert("content-type", content_type);
+        action.sign(expiry)
+    }
+}
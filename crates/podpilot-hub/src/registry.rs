@@ -0,0 +1,417 @@
+//! Routing of hub messages to connected agents.
+//!
+//! Each hub instance holds the WebSocket senders for the agents connected to it.
+//! When a Redis URL is configured, messages for agents connected to another replica
+//! are published on a per-agent channel that only the owning replica subscribes to,
+//! so any replica can reach any agent. Without Redis, only the local map is used.
+//!
+//! A relayed command's response arrives on the replica holding the connection, while
+//! its waiter is on the replica that sent it. Relayed messages carry their origin
+//! replica, and the owning replica publishes the response on that replica's own
+//! response channel, where it is handed to the waiting [`PendingCommands`].
+
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use futures_util::StreamExt;
+use podpilot_common::protocol::{CommandResponseMessage, HubMessage};
+use redis::AsyncCommands;
+use redis::aio::{MultiplexedConnection, PubSubSink};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify, mpsc};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::commands::PendingCommands;
+
+/// Prefix of the per-agent Redis channel
+const CHANNEL_PREFIX: &str = "podpilot:agent:";
+
+/// Prefix of the per-replica Redis channel carrying responses to relayed commands
+const RESPONSE_CHANNEL_PREFIX: &str = "podpilot:responses:";
+
+/// How long a relayed command's origin is remembered without a response
+///
+/// Longer than any command's timeout, after which the origin stopped waiting anyway.
+const RELAY_ORIGIN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often forgotten relay origins are swept out
+const RELAY_ORIGIN_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+fn channel_name(agent_id: &Uuid) -> String {
+    format!("{}{}", CHANNEL_PREFIX, agent_id)
+}
+
+fn response_channel_name(instance_id: &Uuid) -> String {
+    format!("{}{}", RESPONSE_CHANNEL_PREFIX, instance_id)
+}
+
+/// A message published for an agent connected to another replica
+#[derive(Serialize, Deserialize)]
+struct RelayedMessage {
+    /// Replica that sent the message, which awaits the response to a command
+    origin: Uuid,
+    message: HubMessage,
+}
+
+/// A WebSocket session with an agent on this instance
+struct LocalConnection {
+    /// Distinguishes this session from later ones for the same agent
+    session_id: Uuid,
+    sender: mpsc::Sender<HubMessage>,
+    connected_at: Instant,
+    /// Tells the session to close its socket once the hub has dropped it
+    close: Arc<Notify>,
+}
+
+/// What removing a session's connection found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionRemoval {
+    /// The session was removed after being connected this long
+    Removed(Duration),
+    /// Nothing was registered for the agent, e.g. stale cleanup removed it already
+    Gone,
+    /// A newer session for the same agent replaced it and was left in place
+    Superseded,
+}
+
+type LocalConnections = Arc<DashMap<Uuid, LocalConnection>>;
+
+/// Registry of agent connections, optionally shared across hub replicas
+pub struct ConnectionRegistry {
+    local: LocalConnections,
+    relay: Option<RedisRelay>,
+}
+
+/// Redis pub/sub relay for agents connected to other replicas
+struct RedisRelay {
+    /// Identifies this replica as the origin of the messages it relays
+    instance_id: Uuid,
+    publisher: MultiplexedConnection,
+    subscriptions: Mutex<PubSubSink>,
+    /// Replica each command relayed to a local agent came from, and when, keyed by
+    /// correlation ID
+    origins: Arc<DashMap<Uuid, (Uuid, Instant)>>,
+    last_origin_sweep: std::sync::Mutex<Instant>,
+}
+
+impl ConnectionRegistry {
+    /// Registry for a single hub instance
+    pub fn local() -> Self {
+        Self {
+            local: Arc::new(DashMap::new()),
+            relay: None,
+        }
+    }
+
+    /// Registry that relays messages between replicas through Redis
+    ///
+    /// Responses to commands this replica relayed are handed to `pending`.
+    pub async fn with_redis(redis_url: &str, pending: PendingCommands) -> Result<Self> {
+        let client = redis::Client::open(redis_url).context("Invalid Redis URL")?;
+        let publisher = client
+            .get_multiplexed_async_connection()
+            .await
+            .context("Failed to connect to Redis")?;
+        let (mut subscriptions, mut stream) = client
+            .get_async_pubsub()
+            .await
+            .context("Failed to open Redis pub/sub connection")?
+            .split();
+
+        let instance_id = Uuid::new_v4();
+        let response_channel = response_channel_name(&instance_id);
+        subscriptions
+            .subscribe(&response_channel)
+            .await
+            .context("Failed to subscribe to relayed command responses")?;
+
+        let local: LocalConnections = Arc::new(DashMap::new());
+        let origins: Arc<DashMap<Uuid, (Uuid, Instant)>> = Arc::new(DashMap::new());
+
+        // Forward relayed messages to locally connected agents, and relayed responses
+        // to this replica's waiters
+        let forward_to = local.clone();
+        let record_origins = origins.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = stream.next().await {
+                if msg.get_channel_name() == response_channel {
+                    match msg
+                        .get_payload::<Vec<u8>>()
+                        .map_err(anyhow::Error::from)
+                        .and_then(|payload| {
+                            Ok(serde_json::from_slice::<CommandResponseMessage>(&payload)?)
+                        }) {
+                        Ok(response) => {
+                            pending.resolve(&response.correlation_id, response.response)
+                        }
+                        Err(e) => warn!("Discarding malformed relayed command response: {}", e),
+                    }
+                    continue;
+                }
+
+                let Some(agent_id) = msg
+                    .get_channel_name()
+                    .strip_prefix(CHANNEL_PREFIX)
+                    .and_then(|id| id.parse::<Uuid>().ok())
+                else {
+                    continue;
+                };
+
+                let relayed = match msg
+                    .get_payload::<Vec<u8>>()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|payload| Ok(serde_json::from_slice::<RelayedMessage>(&payload)?))
+                {
+                    Ok(relayed) => relayed,
+                    Err(e) => {
+                        warn!(
+                            "Discarding malformed relayed message for agent {}: {}",
+                            agent_id, e
+                        );
+                        continue;
+                    }
+                };
+                let message = relayed.message;
+                if let HubMessage::Command(command) = &message {
+                    record_origins.insert(command.correlation_id, (relayed.origin, Instant::now()));
+                }
+
+                let sender = forward_to.get(&agent_id).map(|entry| entry.sender.clone());
+                match sender {
+                    Some(sender) => {
+                        if sender.send(message).await.is_err() {
+                            debug!(
+                                "Agent {} disconnected before relayed message was delivered",
+                                agent_id
+                            );
+                        }
+                    }
+                    None => debug!("Relayed message for agent {} not connected here", agent_id),
+                }
+            }
+            error!("Redis subscription stream ended, cross-instance delivery stopped");
+        });
+
+        info!(%instance_id, "Connection registry relaying through Redis");
+
+        Ok(Self {
+            local,
+            relay: Some(RedisRelay {
+                instance_id,
+                publisher,
+                subscriptions: Mutex::new(subscriptions),
+                origins,
+                last_origin_sweep: std::sync::Mutex::new(Instant::now()),
+            }),
+        })
+    }
+
+    /// Register a connection to an agent on this instance, replacing any earlier
+    /// session for the same agent
+    ///
+    /// The returned signal fires when the connection is removed out from under the
+    /// session (see [`remove`](Self::remove)) or replaced, and the session should
+    /// close its socket.
+    pub async fn register(
+        &self,
+        agent_id: Uuid,
+        session_id: Uuid,
+        sender: mpsc::Sender<HubMessage>,
+    ) -> Arc<Notify> {
+        let close = Arc::new(Notify::new());
+        let replaced = self.local.insert(
+            agent_id,
+            LocalConnection {
+                session_id,
+                sender,
+                connected_at: Instant::now(),
+                close: close.clone(),
+            },
+        );
+        if let Some(replaced) = replaced {
+            debug!(
+                "Agent {} reconnected before its previous session ended",
+                agent_id
+            );
+            replaced.close.notify_one();
+        }
+
+        if let Some(relay) = &self.relay
+            && let Err(e) = relay
+                .subscriptions
+                .lock()
+                .await
+                .subscribe(channel_name(&agent_id))
+                .await
+        {
+            error!(
+                "Failed to subscribe to relay channel for agent {}: {}",
+                agent_id, e
+            );
+        }
+
+        close
+    }
+
+    /// Drop the connection to an agent on this instance and tell its session to
+    /// close the socket, returning how long it was connected, or `None` if it wasn't
+    pub async fn remove(&self, agent_id: &Uuid) -> Option<Duration> {
+        let (_, connection) = self.local.remove(agent_id)?;
+        connection.close.notify_one();
+        self.unsubscribe(agent_id).await;
+        Some(connection.connected_at.elapsed())
+    }
+
+    /// Remove an agent's connection only if it still belongs to `session_id`
+    ///
+    /// A session that ends after the agent already reconnected must not evict the
+    /// newer session.
+    pub async fn remove_session(&self, agent_id: &Uuid, session_id: Uuid) -> SessionRemoval {
+        let removed = self.local.remove_if(agent_id, |_, connection| {
+            connection.session_id == session_id
+        });
+        match removed {
+            Some((_, connection)) => {
+                self.unsubscribe(agent_id).await;
+                SessionRemoval::Removed(connection.connected_at.elapsed())
+            }
+            None if self.local.contains_key(agent_id) => SessionRemoval::Superseded,
+            None => SessionRemoval::Gone,
+        }
+    }
+
+    /// Stop receiving relayed messages for an agent no longer connected here
+    async fn unsubscribe(&self, agent_id: &Uuid) {
+        if let Some(relay) = &self.relay
+            && let Err(e) = relay
+                .subscriptions
+                .lock()
+                .await
+                .unsubscribe(channel_name(agent_id))
+                .await
+        {
+            warn!(
+                "Failed to unsubscribe from relay channel for agent {}: {}",
+                agent_id, e
+            );
+        }
+    }
+
+    /// Send a message to an agent connected to this or (with Redis) any other instance
+    pub async fn send(&self, agent_id: &Uuid, message: HubMessage) -> Result<()> {
+        // Clone the sender so no map guard is held across the await
+        let sender = self.local.get(agent_id).map(|entry| entry.sender.clone());
+        if let Some(sender) = sender {
+            return sender
+                .send(message)
+                .await
+                .map_err(|_| anyhow::anyhow!("Failed to send message to agent {}", agent_id));
+        }
+
+        let Some(relay) = &self.relay else {
+            anyhow::bail!("Agent {} not connected", agent_id);
+        };
+
+        let relayed = RelayedMessage {
+            origin: relay.instance_id,
+            message,
+        };
+        let payload =
+            serde_json::to_vec(&relayed).context("Failed to serialize relayed message")?;
+        let receivers: i64 = relay
+            .publisher
+            .clone()
+            .publish(channel_name(agent_id), payload)
+            .await
+            .context("Failed to publish relayed message")?;
+
+        if receivers == 0 {
+            anyhow::bail!("Agent {} not connected", agent_id);
+        }
+        Ok(())
+    }
+
+    /// Send a command response back to the replica that relayed the command here,
+    /// returning `false` if the command wasn't relayed from another replica
+    pub async fn relay_response(&self, response: &CommandResponseMessage) -> bool {
+        let Some(relay) = &self.relay else {
+            return false;
+        };
+        relay.sweep_origins();
+        let Some((_, (origin, _))) = relay.origins.remove(&response.correlation_id) else {
+            return false;
+        };
+
+        let result = async {
+            let payload = serde_json::to_vec(response)
+                .context("Failed to serialize relayed command response")?;
+            let receivers: i64 = relay
+                .publisher
+                .clone()
+                .publish(response_channel_name(&origin), payload)
+                .await
+                .context("Failed to publish relayed command response")?;
+            anyhow::Ok(receivers)
+        }
+        .await;
+
+        match result {
+            Ok(0) => debug!(
+                correlation_id = %response.correlation_id,
+                %origin,
+                "replica awaiting relayed command response is gone"
+            ),
+            Ok(_) => {}
+            Err(e) => warn!(
+                correlation_id = %response.correlation_id,
+                %origin,
+                "Failed to relay command response: {:#}", e
+            ),
+        }
+        true
+    }
+
+    /// Whether an agent is connected to this instance
+    pub fn is_local(&self, agent_id: &Uuid) -> bool {
+        self.local.contains_key(agent_id)
+    }
+
+    /// IDs of agents connected to this instance
+    pub fn local_agents(&self) -> Vec<Uuid> {
+        self.local.iter().map(|entry| *entry.key()).collect()
+    }
+
+    /// How long each agent connected to this instance has been connected
+    pub fn session_ages(&self) -> Vec<(Uuid, Duration)> {
+        self.local
+            .iter()
+            .map(|entry| (*entry.key(), entry.connected_at.elapsed()))
+            .collect()
+    }
+
+    /// Number of agents connected to this instance
+    pub fn local_count(&self) -> usize {
+        self.local.len()
+    }
+}
+
+impl RedisRelay {
+    /// Forget origins of relayed commands that never got a response, at most once per
+    /// [`RELAY_ORIGIN_SWEEP_INTERVAL`]
+    fn sweep_origins(&self) {
+        {
+            let mut last_sweep = self
+                .last_origin_sweep
+                .lock()
+                .expect("relay origin sweep lock poisoned");
+            if last_sweep.elapsed() < RELAY_ORIGIN_SWEEP_INTERVAL {
+                return;
+            }
+            *last_sweep = Instant::now();
+        }
+        self.origins
+            .retain(|_, (_, relayed_at)| relayed_at.elapsed() < RELAY_ORIGIN_TTL);
+    }
+}
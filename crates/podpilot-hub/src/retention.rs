@@ -0,0 +1,76 @@
+//! Deleting agents that have been terminated for longer than the retention period.
+//!
+//! Deleting an agent cascades to its `agent_models` rows, while its `command_log`
+//! rows and assets are kept with `agent_id` set to NULL.
+
+use chrono::Utc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::time::{Duration, interval};
+use tracing::{error, info};
+
+use crate::state::AppState;
+
+/// How often terminated agents are checked against the retention period
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically delete agents terminated more than `retention_days` ago
+pub async fn retention_task(state: AppState, retention_days: u32, shutdown: Arc<AtomicBool>) {
+    info!(retention_days, "Starting terminated agent retention task");
+
+    let mut tick_interval = interval(RETENTION_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = tick_interval.tick() => {
+                delete_expired_agents(&state, retention_days).await;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Retention task received shutdown signal");
+                shutdown.store(true, Ordering::SeqCst);
+                break;
+            }
+        }
+
+        if shutdown.load(Ordering::SeqCst) {
+            info!("Retention task shutting down");
+            break;
+        }
+    }
+
+    info!("Retention task stopped");
+}
+
+/// Delete terminated agents older than the retention period
+async fn delete_expired_agents(state: &AppState, retention_days: u32) {
+    let cutoff = Utc::now() - chrono::Duration::days(i64::from(retention_days));
+
+    let mut conn = match state.acquire_db("delete_expired_agents").await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Failed to acquire connection for agent retention: {}", e);
+            return;
+        }
+    };
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM agents
+        WHERE status = 'terminated'::agent_status
+          AND terminated_at < $1
+        "#,
+        cutoff
+    )
+    .execute(&mut *conn)
+    .await;
+
+    match result {
+        Ok(result) if result.rows_affected() > 0 => {
+            info!(
+                deleted = result.rows_affected(),
+                retention_days, "deleted long-terminated agents"
+            );
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to delete expired agents: {}", e),
+    }
+}
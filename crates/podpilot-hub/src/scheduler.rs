@@ -0,0 +1,427 @@
+//! Assigning queued jobs to free, capable agents.
+//!
+//! Jobs wait in the `jobs` table in submission order. Whenever something changes
+//! (a job is submitted or finishes, an agent connects) and on a fixed interval, the
+//! scheduler walks the queue and gives each job to a connected `ready`/`idle` agent
+//! that has reported [`Readiness::Ready`], advertises the `jobs` capability, has no
+//! other job, and either has the job's model already or can download it. Agents that
+//! already have the model are preferred. Each agent runs one job at a time; jobs no
+//! free agent can take stay queued.
+//!
+//! Only agents connected to this hub instance are considered, since the job's
+//! result is matched on the instance holding the connection (see [`crate::commands`]).
+//!
+//! Job state lives in the database, so queued jobs survive a hub restart. A job
+//! whose agent disconnects mid-run stays assigned while the agent reconnects, since
+//! the agent keeps running it; it is put back in the queue if the agent stays away
+//! past a grace period or comes back without it. One that was in flight when its hub
+//! instance died is requeued once it has been sent (or, if never sent, assigned) for
+//! longer than `RunJob`'s timeout. Either way the job's `retries` count goes up, and
+//! after `JOB_MAX_RETRIES` requeues the job fails instead.
+
+use podpilot_common::protocol::Readiness;
+use podpilot_common::rpc::{Command, CommandResponse, ModelSpec, RUN_JOB_TIMEOUT, capability};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+use tokio::time::{Duration, interval};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::commands;
+use crate::data::models::JobStatus;
+use crate::state::AppState;
+
+/// How often the queue is checked even without a wakeup
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Most queued jobs considered per pass
+const SCHEDULE_BATCH: i64 = 100;
+
+/// Requeues allowed per job unless configured otherwise
+pub const DEFAULT_JOB_MAX_RETRIES: u32 = 3;
+
+/// Wakes the scheduler when jobs or agents change
+#[derive(Clone)]
+pub struct Scheduler {
+    wake: Arc<Notify>,
+    /// Requeues allowed before a job that keeps losing its agent fails
+    max_retries: u32,
+}
+
+impl Scheduler {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            wake: Arc::new(Notify::new()),
+            max_retries,
+        }
+    }
+
+    /// Ask for a scheduling pass soon
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+}
+
+/// A queued job with what the agent needs to run it
+struct QueuedJob {
+    id: Uuid,
+    model: ModelSpec,
+    params: serde_json::Value,
+}
+
+/// Assign queued jobs whenever woken, and at least every `SCHEDULE_INTERVAL`
+pub async fn scheduler_task(state: AppState, shutdown: Arc<AtomicBool>) {
+    info!("Starting job scheduler task");
+
+    let mut tick_interval = interval(SCHEDULE_INTERVAL);
+    let wake = state.scheduler.wake.clone();
+
+    loop {
+        tokio::select! {
+            _ = tick_interval.tick() => {
+                assign_queued_jobs(&state).await;
+            }
+            _ = wake.notified() => {
+                assign_queued_jobs(&state).await;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Scheduler task received shutdown signal");
+                shutdown.store(true, Ordering::SeqCst);
+                break;
+            }
+        }
+
+        if shutdown.load(Ordering::SeqCst) {
+            info!("Scheduler task shutting down");
+            break;
+        }
+    }
+
+    info!("Scheduler task stopped");
+}
+
+/// Walk the queue oldest first, starting each job that a free agent can take
+async fn assign_queued_jobs(state: &AppState) {
+    if let Err(e) = requeue_expired_jobs(state).await {
+        error!("Failed to requeue expired jobs: {:#}", e);
+    }
+
+    // Agents still starting would only hold the job until they report ready
+    let connected: Vec<Uuid> = state
+        .connected_agents()
+        .into_iter()
+        .filter(|agent_id| state.readiness.get(agent_id) == Some(Readiness::Ready))
+        .collect();
+    if connected.is_empty() {
+        return;
+    }
+
+    let jobs = match load_queued_jobs(state).await {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Failed to load queued jobs: {:#}", e);
+            return;
+        }
+    };
+
+    for job in jobs {
+        let agent_id = match find_agent(state, &connected, job.model.model_id).await {
+            Ok(Some(agent_id)) => agent_id,
+            Ok(None) => continue,
+            Err(e) => {
+                error!(job_id = %job.id, "Failed to find an agent for job: {:#}", e);
+                return;
+            }
+        };
+
+        match claim(state, job.id, agent_id).await {
+            Ok(true) => {
+                info!(job_id = %job.id, %agent_id, "job assigned");
+                let state = state.clone();
+                tokio::spawn(async move { run_job(state, job, agent_id).await });
+            }
+            // Another hub instance took it first
+            Ok(false) => {}
+            Err(e) => error!(job_id = %job.id, "Failed to assign job: {:#}", e),
+        }
+    }
+}
+
+/// Requeue in-flight jobs that outlived `RunJob`'s timeout
+///
+/// A live hub would have failed these when the command timed out, so whoever was
+/// waiting for their result is gone (the hub restarted or crashed).
+async fn requeue_expired_jobs(state: &AppState) -> anyhow::Result<()> {
+    // Margin for the command's send and the response's delivery
+    let expiry = RUN_JOB_TIMEOUT + Duration::from_secs(60);
+    let cutoff = chrono::Utc::now() - chrono::Duration::from_std(expiry)?;
+
+    let mut conn = state.acquire_db("requeue_expired_jobs").await?;
+    let requeued = sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = CASE WHEN retries >= $2 THEN 'failed' ELSE 'queued' END::job_status,
+            retries = CASE WHEN retries >= $2 THEN retries ELSE retries + 1 END,
+            assigned_agent_id = CASE WHEN retries >= $2 THEN assigned_agent_id END,
+            assigned_at = CASE WHEN retries >= $2 THEN assigned_at END,
+            started_at = CASE WHEN retries >= $2 THEN started_at END,
+            finished_at = CASE WHEN retries >= $2 THEN NOW() END,
+            result = CASE WHEN retries >= $2
+                THEN jsonb_build_object('error', 'Result lost with its hub instance too many times')
+            END
+        WHERE status IN ('assigned', 'running')
+          AND COALESCE(started_at, assigned_at, created_at) < $1
+        RETURNING id, status AS "status: JobStatus", retries
+        "#,
+        cutoff,
+        state.scheduler.max_retries as i32
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for job in requeued {
+        warn!(job_id = %job.id, status = ?job.status, retries = job.retries, "job orphaned by a previous hub instance");
+    }
+    Ok(())
+}
+
+/// Requeue the jobs an agent lost by disconnecting, or only those in `only`
+///
+/// Jobs that already used up their retries are failed instead. Called before the
+/// agent's pending commands are abandoned, so the job's own task finds it requeued
+/// rather than recording the disconnect as the job's failure.
+pub async fn requeue_agent_jobs(state: &AppState, agent_id: Uuid, only: Option<&[Uuid]>) {
+    let result = async {
+        let mut conn = state.acquire_db("requeue_agent_jobs").await?;
+        let jobs = sqlx::query!(
+            r#"
+            UPDATE jobs
+            SET status = CASE WHEN retries >= $2 THEN 'failed' ELSE 'queued' END::job_status,
+                retries = CASE WHEN retries >= $2 THEN retries ELSE retries + 1 END,
+                assigned_agent_id = CASE WHEN retries >= $2 THEN assigned_agent_id END,
+                assigned_at = CASE WHEN retries >= $2 THEN assigned_at END,
+                started_at = CASE WHEN retries >= $2 THEN started_at END,
+                finished_at = CASE WHEN retries >= $2 THEN NOW() END,
+                result = CASE WHEN retries >= $2
+                    THEN jsonb_build_object('error', 'Agent disconnected too many times')
+                END
+            WHERE assigned_agent_id = $1
+              AND status IN ('assigned', 'running')
+              AND ($3::uuid[] IS NULL OR id = ANY($3))
+            RETURNING id, status AS "status: JobStatus", retries
+            "#,
+            agent_id,
+            state.scheduler.max_retries as i32,
+            only
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        anyhow::Ok(jobs)
+    }
+    .await;
+
+    match result {
+        Ok(jobs) if jobs.is_empty() => {}
+        Ok(jobs) => {
+            for job in jobs {
+                match job.status {
+                    JobStatus::Failed => {
+                        warn!(job_id = %job.id, %agent_id, retries = job.retries, "job failed after its agent disconnected too often")
+                    }
+                    _ => {
+                        info!(job_id = %job.id, %agent_id, retries = job.retries, "agent disconnected mid-job, requeued")
+                    }
+                }
+            }
+            state.scheduler.wake();
+        }
+        Err(e) => error!(%agent_id, "Failed to requeue agent's jobs: {:#}", e),
+    }
+}
+
+/// Oldest queued jobs, with their model's download details
+async fn load_queued_jobs(state: &AppState) -> anyhow::Result<Vec<QueuedJob>> {
+    let mut conn = state.acquire_db("load_queued_jobs").await?;
+    let rows = sqlx::query!(
+        r#"
+        SELECT j.id, j.params, m.id AS model_id, m.r2_key, m.hash, m.file_size
+        FROM jobs j
+        JOIN models m ON m.id = j.model_id
+        WHERE j.status = 'queued'::job_status
+        ORDER BY j.created_at
+        LIMIT $1
+        "#,
+        SCHEDULE_BATCH
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| QueuedJob {
+            id: row.id,
+            model: ModelSpec {
+                model_id: row.model_id,
+                // Models are stored on the agent under the last segment of their key
+                filename: row
+                    .r2_key
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                r2_key: row.r2_key,
+                sha256_hash: row.hash,
+                file_size: u64::try_from(row.file_size).ok(),
+            },
+            params: row.params,
+        })
+        .collect())
+}
+
+/// A free agent among `connected` that can run a job on `model_id`
+async fn find_agent(
+    state: &AppState,
+    connected: &[Uuid],
+    model_id: Uuid,
+) -> anyhow::Result<Option<Uuid>> {
+    let mut conn = state.acquire_db("find_job_agent").await?;
+    let agent_id = sqlx::query_scalar!(
+        r#"
+        SELECT a.id
+        FROM agents a
+        LEFT JOIN agent_models am ON am.agent_id = a.id AND am.model_id = $2
+        WHERE a.id = ANY($1)
+          AND a.status IN ('ready', 'idle')
+          AND $3 = ANY(a.capabilities)
+          AND (am.model_id IS NOT NULL OR $4 = ANY(a.capabilities))
+          AND NOT EXISTS (
+              SELECT 1 FROM jobs j
+              WHERE j.assigned_agent_id = a.id
+                AND j.status IN ('assigned', 'running')
+          )
+        ORDER BY am.model_id IS NOT NULL DESC, a.last_seen_at DESC NULLS LAST
+        LIMIT 1
+        "#,
+        connected,
+        model_id,
+        capability::JOBS,
+        capability::MODEL_DOWNLOAD
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(agent_id)
+}
+
+/// Assign a queued job to an agent, returning `false` if it is no longer queued
+async fn claim(state: &AppState, job_id: Uuid, agent_id: Uuid) -> anyhow::Result<bool> {
+    let mut conn = state.acquire_db("claim_job").await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = 'assigned'::job_status, assigned_agent_id = $2, assigned_at = NOW()
+        WHERE id = $1 AND status = 'queued'::job_status
+        "#,
+        job_id,
+        agent_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Send an assigned job to its agent and record the outcome
+///
+/// The job is marked running once the command is actually sent (see
+/// [`mark_running`]), after any commands queued ahead of it for the agent.
+async fn run_job(state: AppState, job: QueuedJob, agent_id: Uuid) {
+    let job_id = job.id;
+    let command = Command::RunJob {
+        job_id,
+        model: job.model,
+        params: job.params,
+    };
+    let timeout = command.default_timeout();
+    let (status, result) = match commands::execute(&state, agent_id, command, timeout).await {
+        Ok((_, CommandResponse::Success { data, .. })) => (
+            JobStatus::Completed,
+            data.and_then(|mut data| data.get_mut("result").map(serde_json::Value::take)),
+        ),
+        Ok((_, CommandResponse::Failed { error, .. })) => (
+            JobStatus::Failed,
+            Some(serde_json::json!({ "error": error })),
+        ),
+        Err(e) => (
+            JobStatus::Failed,
+            Some(serde_json::json!({ "error": e.to_string() })),
+        ),
+    };
+
+    match status {
+        JobStatus::Completed => info!(%job_id, %agent_id, "job completed"),
+        _ => warn!(%job_id, %agent_id, ?result, "job failed"),
+    }
+    if let Err(e) = finish(&state, job_id, agent_id, status, result).await {
+        error!(%job_id, "Failed to record job result: {:#}", e);
+    }
+
+    // The agent is free for the next job
+    state.scheduler.wake();
+}
+
+/// Move an assigned job to running once its `RunJob` command has been sent
+pub async fn mark_running(state: &AppState, job_id: Uuid) {
+    let result = async {
+        let mut conn = state.acquire_db("start_job").await?;
+        sqlx::query!(
+            r#"
+            UPDATE jobs
+            SET status = 'running'::job_status, started_at = NOW()
+            WHERE id = $1 AND status = 'assigned'::job_status
+            "#,
+            job_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        anyhow::Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        error!(%job_id, "Failed to mark job running: {:#}", e);
+    }
+}
+
+/// Record a job's final status and result
+///
+/// Only applies while the job is still running on `agent_id`, so a late outcome
+/// can't overwrite a job that has since been requeued.
+async fn finish(
+    state: &AppState,
+    job_id: Uuid,
+    agent_id: Uuid,
+    status: JobStatus,
+    result: Option<serde_json::Value>,
+) -> anyhow::Result<()> {
+    let mut conn = state.acquire_db("finish_job").await?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = $3, result = $4, finished_at = NOW()
+        WHERE id = $1
+          AND assigned_agent_id = $2
+          AND status IN ('assigned', 'running')
+        "#,
+        job_id,
+        agent_id,
+        status as _,
+        result
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        warn!(%job_id, %agent_id, "job was reassigned before its result arrived, discarding it");
+    }
+    Ok(())
+}
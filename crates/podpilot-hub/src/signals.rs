@@ -1,5 +1,6 @@
+use podpilot_common::logging::LogReloadHandle;
 use tokio::signal;
-use tracing::info;
+use tracing::{info, warn};
 
 /// Future that resolves when the process receives Ctrl+C or SIGTERM
 ///
@@ -29,3 +30,42 @@ pub async fn shutdown_signal() {
         _ = sigterm => {}
     }
 }
+
+/// Re-read `LOG_LEVEL` and apply it whenever the process receives SIGHUP
+///
+/// The `.env` file is reloaded first, so editing it and sending SIGHUP changes
+/// the level without dropping agent connections. Falls back to `default_level`
+/// when `LOG_LEVEL` is unset. Does nothing on non-unix platforms.
+pub async fn reload_log_level_on_sighup(handle: LogReloadHandle, default_level: String) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(
+                    "Failed to install SIGHUP handler, log level reload disabled: {}",
+                    e
+                );
+                return;
+            }
+        };
+
+        while sighup.recv().await.is_some() {
+            dotenvy::dotenv_override().ok();
+            let level = std::env::var("LOG_LEVEL")
+                .or_else(|_| std::env::var("log_level"))
+                .unwrap_or_else(|_| default_level.clone());
+
+            match handle.set_level(&level) {
+                Ok(()) => info!(log_level = %level, "received SIGHUP, log level reloaded"),
+                Err(e) => warn!("Failed to reload log level: {:#}", e),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (handle, default_level);
+    }
+}
@@ -1,58 +1,297 @@
-use dashmap::DashMap;
+use ipnet::IpNet;
+use podpilot_common::config::AgentIdentityKey;
 use podpilot_common::protocol::HubMessage;
-use sqlx::PgPool;
+use sqlx::pool::PoolConnection;
+use sqlx::{PgPool, Postgres};
 use std::net::IpAddr;
 use std::sync::Arc;
-use tokio::sync::{RwLock, mpsc};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock, Semaphore, SemaphorePermit, mpsc};
+use tracing::{debug, warn};
 use uuid::Uuid;
 
+use crate::alerts::{AlertThresholds, Alerts};
+use crate::commands::{AgentReadiness, CommandQueues, PendingCommands};
+use crate::data::models::AgentStatus;
+use crate::events::{EventBus, HubEvent};
+use crate::metrics::Metrics;
+use crate::r2::R2Uploads;
+use crate::registry::{ConnectionRegistry, SessionRemoval};
+use crate::scheduler::{DEFAULT_JOB_MAX_RETRIES, Scheduler};
+use crate::ws::{RegistrationLimiter, SessionTracker};
+
+/// Longest a message handler waits for a DB permit before its work is shed
+///
+/// Kept below the default pool acquire timeout so backpressure kicks in first.
+const DB_PERMIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Limits applied to every agent WebSocket connection
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionLimits {
+    /// Largest inbound message (and frame) accepted, in bytes
+    pub max_message_bytes: usize,
+    /// Inbound messages per second allowed per connection (0 disables limiting)
+    pub messages_per_sec: u32,
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
-    pub connections: Arc<DashMap<Uuid, mpsc::Sender<HubMessage>>>,
+    pub limits: ConnectionLimits,
+    pub connections: Arc<ConnectionRegistry>,
     pub tailscale_ip: Arc<RwLock<Option<IpAddr>>>,
+    /// Dispatched commands awaiting a response
+    pub pending: PendingCommands,
+    /// Orders commands to each agent, one in flight at a time
+    pub command_queues: CommandQueues,
+    /// Readiness of agents connected here, gating workload commands
+    pub readiness: AgentReadiness,
+    /// Assigns queued jobs to agents
+    pub scheduler: Scheduler,
+    pub metrics: Metrics,
+    /// Fleet events for live dashboards
+    pub events: EventBus,
+    /// Metric thresholds checked against each incoming sample
+    pub alerts: Alerts,
+    /// Presigns agent asset uploads; `None` when R2 isn't configured
+    pub r2: Option<R2Uploads>,
+    /// Accept assets of any well-formed content type (`ALLOW_ANY_CONTENT_TYPE`)
+    pub allow_any_content_type: bool,
+    /// Trace-log redacted agent message payloads (`LOG_WS_PAYLOADS`)
+    pub log_ws_payloads: bool,
+    /// Hub-wide limit on registration attempts
+    pub registration_limiter: RegistrationLimiter,
+    /// Agent WebSocket sessions, closed on shutdown
+    pub sessions: SessionTracker,
+    /// Connection acquisitions slower than this are logged with their operation
+    pub db_slow_acquire: Duration,
+    /// When this hub instance started
+    pub started_at: Instant,
+    /// Networks agents may connect from; empty allows any address
+    pub allowed_agent_cidrs: Arc<[IpNet]>,
+    /// Fields identifying a reconnecting agent's existing record
+    pub identity_key: AgentIdentityKey,
+    /// Gates DB-touching message handlers to the pool's capacity (`db_max_connections`)
+    db_permits: Arc<Semaphore>,
 }
 
 impl AppState {
-    pub fn new(db: PgPool) -> Self {
+    pub fn new(
+        db: PgPool,
+        limits: ConnectionLimits,
+        connections: ConnectionRegistry,
+        db_slow_acquire: Duration,
+    ) -> Self {
+        let db_capacity = db.options().get_max_connections() as usize;
         Self {
             db,
-            connections: Arc::new(DashMap::new()),
+            limits,
+            connections: Arc::new(connections),
             tailscale_ip: Arc::new(RwLock::new(None)),
+            pending: PendingCommands::default(),
+            command_queues: CommandQueues::default(),
+            readiness: AgentReadiness::default(),
+            scheduler: Scheduler::new(DEFAULT_JOB_MAX_RETRIES),
+            metrics: Metrics::new(),
+            events: EventBus::new(),
+            alerts: Alerts::default(),
+            r2: None,
+            allow_any_content_type: false,
+            log_ws_payloads: false,
+            registration_limiter: RegistrationLimiter::default(),
+            sessions: SessionTracker::new(),
+            db_slow_acquire,
+            started_at: Instant::now(),
+            allowed_agent_cidrs: Arc::new([]),
+            identity_key: AgentIdentityKey::default(),
+            db_permits: Arc::new(Semaphore::new(db_capacity)),
         }
     }
 
-    /// Register a new agent connection
-    pub fn register_connection(&self, agent_id: Uuid, sender: mpsc::Sender<HubMessage>) {
-        self.connections.insert(agent_id, sender);
+    /// Only accept agent connections from peers within `cidrs`
+    pub fn with_allowed_agent_cidrs(mut self, cidrs: Vec<IpNet>) -> Self {
+        self.allowed_agent_cidrs = cidrs.into();
+        self
     }
 
-    /// Remove an agent connection
-    pub fn remove_connection(&self, agent_id: &Uuid) {
-        self.connections.remove(agent_id);
+    /// Match reconnecting agents to existing records by `key`
+    pub fn with_agent_identity_key(mut self, key: AgentIdentityKey) -> Self {
+        self.identity_key = key;
+        self
     }
 
-    /// Send a message to a specific agent
-    pub async fn send_to_agent(&self, agent_id: &Uuid, message: HubMessage) -> anyhow::Result<()> {
-        if let Some(sender) = self.connections.get(agent_id) {
-            sender
-                .send(message)
-                .await
-                .map_err(|_| anyhow::anyhow!("Failed to send message to agent {}", agent_id))?;
-            Ok(())
-        } else {
-            anyhow::bail!("Agent {} not connected", agent_id)
+    /// Raise alerts when agent metrics cross `thresholds`
+    pub fn with_alert_thresholds(mut self, thresholds: AlertThresholds) -> Self {
+        self.alerts = Alerts::new(thresholds);
+        self
+    }
+
+    /// Issue presigned upload URLs to agents through `r2`
+    pub fn with_r2_uploads(mut self, r2: R2Uploads) -> Self {
+        self.r2 = Some(r2);
+        self
+    }
+
+    /// Accept assets whose content type isn't on the built-in allowlist
+    pub fn with_allow_any_content_type(mut self, allow: bool) -> Self {
+        self.allow_any_content_type = allow;
+        self
+    }
+
+    /// Trace-log each agent message's payload, with secrets redacted
+    pub fn with_log_ws_payloads(mut self, enabled: bool) -> Self {
+        self.log_ws_payloads = enabled;
+        self
+    }
+
+    /// Accept at most `per_sec` registrations per second, in bursts of up to `burst`
+    pub fn with_registration_limit(mut self, per_sec: u32, burst: u32) -> Self {
+        self.registration_limiter = RegistrationLimiter::new(per_sec, burst);
+        self
+    }
+
+    /// Share `pending` with the connection registry, which resolves relayed responses
+    pub fn with_pending_commands(mut self, pending: PendingCommands) -> Self {
+        self.pending = pending;
+        self
+    }
+
+    /// Fail jobs after they have been requeued `max_retries` times
+    pub fn with_job_max_retries(mut self, max_retries: u32) -> Self {
+        self.scheduler = Scheduler::new(max_retries);
+        self
+    }
+
+    /// Whether an agent may connect from `ip`
+    pub fn agent_source_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.allowed_agent_cidrs.is_empty()
+            || self.allowed_agent_cidrs.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Acquire a pooled connection, warning if the wait exceeds `db_slow_acquire`
+    ///
+    /// The warning names `operation` and is logged inside the caller's span (e.g. the
+    /// HTTP request span), so pool starvation can be traced to the code causing it.
+    pub async fn acquire_db(
+        &self,
+        operation: &'static str,
+    ) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+        let start = Instant::now();
+        let result = self.db.acquire().await;
+        let waited = start.elapsed();
+
+        if waited > self.db_slow_acquire {
+            warn!(
+                operation,
+                wait_ms = waited.as_millis() as u64,
+                threshold_ms = self.db_slow_acquire.as_millis() as u64,
+                pool_size = self.db.size(),
+                pool_idle = self.db.num_idle(),
+                acquired = result.is_ok(),
+                "slow database connection acquisition"
+            );
         }
+        result
     }
 
-    /// Get all connected agent IDs
+    /// Wait for a DB permit without shedding, for work that must not be dropped
+    pub async fn wait_db_permit(&self) -> SemaphorePermit<'_> {
+        self.db_permits
+            .acquire()
+            .await
+            .expect("DB permit semaphore is never closed")
+    }
+
+    /// Wait for a DB permit, returning `None` if the work should be shed
+    ///
+    /// Bounding concurrent handlers to the pool size makes excess work queue here
+    /// instead of stampeding the pool and failing on its acquire timeout.
+    pub async fn acquire_db_permit(&self, operation: &str) -> Option<SemaphorePermit<'_>> {
+        if let Ok(permit) = self.db_permits.try_acquire() {
+            return Some(permit);
+        }
+
+        debug!(operation, "database busy, queuing");
+        match tokio::time::timeout(DB_PERMIT_TIMEOUT, self.db_permits.acquire()).await {
+            Ok(Ok(permit)) => Some(permit),
+            Ok(Err(_)) => None,
+            Err(_) => {
+                warn!(
+                    operation,
+                    wait_ms = DB_PERMIT_TIMEOUT.as_millis() as u64,
+                    "database saturated, shedding work"
+                );
+                None
+            }
+        }
+    }
+
+    /// Register a new agent connection for the session `session_id`, returning the
+    /// signal that the hub has dropped it
+    pub async fn register_connection(
+        &self,
+        agent_id: Uuid,
+        session_id: Uuid,
+        sender: mpsc::Sender<HubMessage>,
+    ) -> Arc<Notify> {
+        let close = self
+            .connections
+            .register(agent_id, session_id, sender)
+            .await;
+        self.events.publish(HubEvent::AgentConnected { agent_id });
+        // The new agent may be able to take queued jobs
+        self.scheduler.wake();
+        close
+    }
+
+    /// Remove an agent connection and close its session, returning how long it was
+    /// connected if it was
+    pub async fn remove_connection(&self, agent_id: &Uuid) -> Option<Duration> {
+        let session = self.connections.remove(agent_id).await;
+        if session.is_some() {
+            self.events.publish(HubEvent::AgentDisconnected {
+                agent_id: *agent_id,
+            });
+        }
+        session
+    }
+
+    /// Remove an agent connection if it still belongs to `session_id`
+    pub async fn remove_session(&self, agent_id: &Uuid, session_id: Uuid) -> SessionRemoval {
+        let removal = self.connections.remove_session(agent_id, session_id).await;
+        if let SessionRemoval::Removed(_) = removal {
+            self.events.publish(HubEvent::AgentDisconnected {
+                agent_id: *agent_id,
+            });
+        }
+        removal
+    }
+
+    /// Announce that an agent's status was changed in the database
+    pub fn publish_status(&self, agent_id: Uuid, status: AgentStatus) {
+        self.events
+            .publish(HubEvent::AgentStatusChanged { agent_id, status });
+    }
+
+    /// Send a message to a specific agent, relaying to another hub instance if needed
+    pub async fn send_to_agent(&self, agent_id: &Uuid, message: HubMessage) -> anyhow::Result<()> {
+        self.connections.send(agent_id, message).await
+    }
+
+    /// Get the IDs of agents connected to this instance
     pub fn connected_agents(&self) -> Vec<Uuid> {
-        self.connections.iter().map(|entry| *entry.key()).collect()
+        self.connections.local_agents()
     }
 
-    /// Get the number of connected agents
+    /// Get the number of agents connected to this instance
     pub fn connection_count(&self) -> usize {
-        self.connections.len()
+        self.connections.local_count()
+    }
+
+    /// Time since this hub instance started
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
     }
 
     /// Get the current Tailscale IP address
@@ -4,7 +4,7 @@
 //! functionality to query the node's Tailscale IP address.
 
 use anyhow::{Context, Result, anyhow};
-use podpilot_common::config::Config;
+use podpilot_common::config::{Config, TailscaleConfig};
 use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use std::net::IpAddr;
@@ -107,48 +107,61 @@ pub async fn initialize(config: &Config) -> Result<()> {
         // Skip spawning and skip connection (assume host is already connected)
         // The IP updater task will fetch the IP from the existing daemon
     } else {
-        // Spawn our own daemon with userspace networking
-        let child = spawn_tailscaled_userspace().context("Failed to spawn tailscaled daemon")?;
-
-        // Store the process handle for automatic cleanup on Drop
-        {
-            let mut process = TAILSCALED_PROCESS.write().await;
-            *process = Some(TailscaledHandle::new(child));
-        }
-
-        // Wait for daemon to initialize (socket creation + backend startup)
-        tracing::debug!("Waiting 2 seconds for Tailscale daemon to initialize");
-        sleep(Duration::from_secs(2)).await;
-
-        // Wait for daemon to be ready to accept commands
-        wait_for_daemon_ready()
-            .await
-            .context("Tailscale daemon failed to become ready")?;
+        start_daemon(&config.tailscale).await?;
+    }
 
-        tracing::info!("Tailscale daemon is ready (responsive to commands)");
+    Ok(())
+}
 
-        // Connect to tailnet if OAuth credentials provided
-        if let Some(oauth) = config.tailscale.oauth() {
-            connect_to_tailnet(&oauth.client_id, &oauth.client_secret)
-                .await
-                .context("Failed to connect to Tailscale network with OAuth credentials")?;
+/// Spawn our own daemon with userspace networking and connect it to the tailnet
+///
+/// The handle is only stored once the daemon is ready and connected, replacing any
+/// previous one, so this is also used by the watchdog to restart a crashed daemon.
+/// On failure the new daemon is killed and the previous handle is left in place.
+async fn start_daemon(tailscale: &TailscaleConfig) -> Result<()> {
+    let child = spawn_tailscaled_userspace().context("Failed to spawn tailscaled daemon")?;
+    // Kills the daemon on Drop if any of the steps below fail
+    let handle = TailscaledHandle::new(child);
+
+    // Wait for daemon to initialize (socket creation + backend startup)
+    tracing::debug!("Waiting 2 seconds for Tailscale daemon to initialize");
+    sleep(Duration::from_secs(2)).await;
+
+    // Wait for daemon to be ready to accept commands
+    wait_for_daemon_ready()
+        .await
+        .context("Tailscale daemon failed to become ready")?;
+
+    tracing::info!("Tailscale daemon is ready (responsive to commands)");
+
+    // Connect to tailnet if OAuth credentials provided
+    if let Some(oauth) = tailscale.oauth() {
+        connect_to_tailnet(
+            &oauth.client_id,
+            &oauth.client_secret,
+            &tailscale.advertise_routes,
+            tailscale.accept_routes,
+        )
+        .await
+        .context("Failed to connect to Tailscale network with OAuth credentials")?;
 
-            tracing::info!("Initiated connection to Tailscale network");
+        tracing::info!("Initiated connection to Tailscale network");
 
-            // Wait for full authentication and connection
-            wait_for_connection()
-                .await
-                .context("Tailscale failed to fully authenticate and connect")?;
+        // Wait for full authentication and connection
+        wait_for_connection()
+            .await
+            .context("Tailscale failed to fully authenticate and connect")?;
 
-            tracing::info!("Successfully connected to Tailscale network with OAuth credentials");
-        } else {
-            tracing::warn!(
-                "No OAuth credentials provided (HUB_TAILSCALE_CLIENT_ID/HUB_TAILSCALE_CLIENT_SECRET), \
-                 daemon started but not connected to tailnet"
-            );
-        }
+        tracing::info!("Successfully connected to Tailscale network with OAuth credentials");
+    } else {
+        tracing::warn!(
+            "No OAuth credentials provided (HUB_TAILSCALE_CLIENT_ID/HUB_TAILSCALE_CLIENT_SECRET), \
+             daemon started but not connected to tailnet"
+        );
     }
 
+    *TAILSCALED_PROCESS.write().await = Some(handle);
+
     Ok(())
 }
 
@@ -249,17 +262,25 @@ async fn wait_for_daemon_ready() -> Result<()> {
 
 /// Wait for Tailscale to be fully connected and authenticated
 ///
-/// Polls until BackendState is "Running" and the node has Tailscale IPs assigned.
+/// Polls with increasing intervals (200ms up to 2s, for up to 30s) until BackendState
+/// is "Running" and the node has Tailscale IPs assigned.
 /// This should be called after `tailscale up` to ensure full authentication.
 async fn wait_for_connection() -> Result<()> {
-    let max_attempts = 60;
-    let poll_interval = Duration::from_millis(500);
+    // Poll quickly at first, since an already-authorized node connects in well under
+    // a second, then back off to avoid spawning the CLI constantly on slow tailnets
+    const TIMEOUT: Duration = Duration::from_secs(30);
+    const INITIAL_INTERVAL: Duration = Duration::from_millis(200);
+    const MAX_INTERVAL: Duration = Duration::from_secs(2);
+
     let start_time = std::time::Instant::now();
+    let mut poll_interval = INITIAL_INTERVAL;
     let mut last_backend_state = String::new();
+    let mut attempt = 0u32;
 
     tracing::debug!("Waiting for Tailscale to connect and authenticate");
 
-    for attempt in 1..=max_attempts {
+    loop {
+        attempt += 1;
         match fetch_tailscale_status().await {
             Ok(status) => {
                 last_backend_state = status.backend_state.clone();
@@ -280,35 +301,35 @@ async fn wait_for_connection() -> Result<()> {
 
                 tracing::debug!(
                     attempt,
-                    max_attempts,
                     backend_state = %status.backend_state,
                     has_self = status.self_.is_some(),
+                    next_poll_ms = poll_interval.as_millis() as u64,
                     "Waiting for connection"
                 );
             }
             Err(e) => {
                 tracing::debug!(
                     attempt,
-                    max_attempts,
                     error = %e,
+                    next_poll_ms = poll_interval.as_millis() as u64,
                     "Failed to fetch status while waiting for connection"
                 );
             }
         }
 
-        if attempt < max_attempts {
-            sleep(poll_interval).await;
+        let remaining = TIMEOUT.saturating_sub(start_time.elapsed());
+        if remaining.is_zero() {
+            break;
         }
+        sleep(poll_interval.min(remaining)).await;
+        poll_interval = (poll_interval * 2).min(MAX_INTERVAL);
     }
 
-    let elapsed = start_time.elapsed();
-    let timeout_ms = max_attempts * poll_interval.as_millis() as u32;
-
     Err(anyhow!(
         "Tailscale did not connect after {} attempts ({} ms elapsed, {} ms timeout). Last state: {}",
-        max_attempts,
-        elapsed.as_millis(),
-        timeout_ms,
+        attempt,
+        start_time.elapsed().as_millis(),
+        TIMEOUT.as_millis(),
         last_backend_state
     ))
 }
@@ -340,27 +361,56 @@ fn validate_tags(tags: &str) -> Result<()> {
     Ok(())
 }
 
+/// Validate a subnet route (e.g. `10.0.0.0/24`) to prevent command injection
+///
+/// Routes must be an IP address and a prefix length valid for its family.
+fn validate_route(route: &str) -> Result<()> {
+    let (address, prefix) = route
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Invalid route '{}': expected CIDR notation", route))?;
+    let address: IpAddr = address
+        .parse()
+        .map_err(|_| anyhow!("Invalid route '{}': bad IP address", route))?;
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+    match prefix.parse::<u8>() {
+        Ok(prefix) if prefix <= max_prefix => Ok(()),
+        _ => Err(anyhow!("Invalid route '{}': bad prefix length", route)),
+    }
+}
+
 /// Connect to the Tailscale network using OAuth credentials
 ///
-/// Hostname and tags are hardcoded for Hub deployment.
+/// Hostname and tags are hardcoded for Hub deployment; subnet route flags are
+/// only passed when configured.
 /// The authkey is wrapped in SecretString to prevent accidental logging.
 /// All inputs are validated to prevent command injection attacks.
-async fn connect_to_tailnet(client_id: &SecretString, client_secret: &SecretString) -> Result<()> {
+async fn connect_to_tailnet(
+    client_id: &SecretString,
+    client_secret: &SecretString,
+    advertise_routes: &[String],
+    accept_routes: bool,
+) -> Result<()> {
     const HOSTNAME: &str = "hub-prod";
     const TAGS: &str = "tag:podpilot";
 
     // Validate all inputs (even hardcoded ones, for defense in depth)
     validate_hostname(HOSTNAME).context("Invalid hostname")?;
     validate_tags(TAGS).context("Invalid tags")?;
+    for route in advertise_routes {
+        validate_route(route).context("Invalid TAILSCALE_ADVERTISE_ROUTES")?;
+    }
 
     tracing::debug!(
         hostname = HOSTNAME,
         tags = TAGS,
+        ?advertise_routes,
+        accept_routes,
         "Connecting to Tailscale network"
     );
 
     // Use separate arguments instead of format! to avoid shell injection
-    let output = Command::new("tailscale")
+    let mut command = Command::new("tailscale");
+    command
         .arg("up")
         .arg("--client-id")
         .arg(client_id.expose_secret())
@@ -370,7 +420,14 @@ async fn connect_to_tailnet(client_id: &SecretString, client_secret: &SecretStri
         .arg(HOSTNAME)
         .arg("--advertise-tags")
         .arg(TAGS)
-        .arg("--accept-dns=false")
+        .arg("--accept-dns=false");
+    if !advertise_routes.is_empty() {
+        command.arg(format!("--advertise-routes={}", advertise_routes.join(",")));
+    }
+    if accept_routes {
+        command.arg("--accept-routes");
+    }
+    let output = command
         .output()
         .context("Failed to execute tailscale up command")?;
 
@@ -418,7 +475,33 @@ fn extract_tailscale_ip(status: &TailscaleStatus) -> Result<IpAddr> {
         .ok_or_else(|| anyhow!("No Tailscale IPs found in status response"))
 }
 
+/// Record a Tailscale IP in `hub_network_events` if it differs from the last recorded one
+///
+/// Compared against the database rather than memory, so a restart with an unchanged
+/// IP doesn't add an event.
+async fn record_ip_change(db: &sqlx::PgPool, ip: IpAddr) -> Result<()> {
+    sqlx::query!(
+        r#"
+        WITH latest AS (
+            SELECT ip FROM hub_network_events
+            ORDER BY observed_at DESC, id DESC
+            LIMIT 1
+        )
+        INSERT INTO hub_network_events (previous_ip, ip)
+        SELECT (SELECT ip FROM latest), $1
+        WHERE NOT EXISTS (SELECT 1 FROM latest WHERE latest.ip = $1)
+        "#,
+        ip as _
+    )
+    .execute(db)
+    .await
+    .context("Failed to insert network event")?;
+    Ok(())
+}
+
 /// Background task that periodically fetches and updates the Tailscale IP
+///
+/// Each change is also recorded with [`record_ip_change`].
 pub async fn tailscale_ip_updater_task(
     state: AppState,
     interval: Duration,
@@ -436,8 +519,16 @@ pub async fn tailscale_ip_updater_task(
                 Ok(ip) => {
                     let mut tailscale_ip = state.tailscale_ip.write().await;
                     if *tailscale_ip != Some(ip) {
-                        tracing::info!(%ip, "Tailscale IP updated");
+                        tracing::info!(%ip, previous = ?*tailscale_ip, "Tailscale IP updated");
                         *tailscale_ip = Some(ip);
+                        drop(tailscale_ip);
+
+                        if let Err(e) = record_ip_change(&state.db, ip).await {
+                            tracing::warn!(
+                                error = format!("{:#}", e),
+                                "Failed to record Tailscale IP change"
+                            );
+                        }
                     } else {
                         tracing::trace!(%ip, "Tailscale IP unchanged");
                     }
@@ -463,3 +554,56 @@ pub async fn tailscale_ip_updater_task(
 
     tracing::info!("Tailscale IP updater task stopped");
 }
+
+/// Background task that restarts the spawned tailscaled daemon if it exits
+///
+/// Checks liveness with `try_wait` every `interval`; an exited daemon is respawned
+/// and reconnected to the tailnet. A failed restart is retried on the next check.
+/// Does nothing when using an existing host daemon.
+pub async fn tailscaled_watchdog_task(
+    tailscale: TailscaleConfig,
+    interval: Duration,
+    shutdown: Arc<AtomicBool>,
+) {
+    if TAILSCALED_PROCESS.read().await.is_none() {
+        tracing::debug!("tailscaled not managed by the hub, watchdog disabled");
+        return;
+    }
+
+    tracing::info!(
+        interval_secs = interval.as_secs(),
+        "Starting tailscaled watchdog task"
+    );
+
+    loop {
+        sleep(interval).await;
+
+        if shutdown.load(Ordering::SeqCst) {
+            tracing::info!("tailscaled watchdog task shutting down");
+            break;
+        }
+
+        let exited = match TAILSCALED_PROCESS.write().await.as_mut() {
+            Some(handle) => handle.child.try_wait(),
+            None => break,
+        };
+
+        match exited {
+            Ok(None) => tracing::trace!("tailscaled is running"),
+            Ok(Some(status)) => {
+                tracing::warn!(%status, "tailscaled exited unexpectedly, restarting");
+                match start_daemon(&tailscale).await {
+                    Ok(()) => tracing::info!("tailscaled restarted"),
+                    Err(e) => {
+                        tracing::error!(error = format!("{:#}", e), "Failed to restart tailscaled")
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to check tailscaled status");
+            }
+        }
+    }
+
+    tracing::info!("tailscaled watchdog task stopped");
+}
@@ -0,0 +1,574 @@
+//! Agent management API endpoints.
+
+use axum::extract::{Path, Query, State};
+use axum::http::header::HeaderName;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::{Extension, Json};
+use podpilot_common::config::{MAX_CONFIG_DURATION, Scope};
+use podpilot_common::protocol::Readiness;
+use podpilot_common::rpc::{Command, CommandResponse, ModelSpec};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::commands::{self, CommandError};
+use crate::data::models::{
+    Agent, AgentEvent, AgentStatus, CommandLogEntry, CommandStatus, ProviderType,
+};
+use crate::lifecycle;
+use crate::state::AppState;
+use crate::web::auth::require_scope;
+use crate::web::error::ApiError;
+
+/// Response header carrying the number of agents matching a list's filters
+pub const TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Default and maximum page size for agent listings
+const DEFAULT_AGENT_LIMIT: i64 = 100;
+const MAX_AGENT_LIMIT: i64 = 1000;
+
+/// Default and maximum page size for command history
+const DEFAULT_COMMAND_LIMIT: i64 = 50;
+const MAX_COMMAND_LIMIT: i64 = 500;
+
+/// Default and maximum page size for agent status history
+const DEFAULT_EVENT_LIMIT: i64 = 100;
+const MAX_EVENT_LIMIT: i64 = 1000;
+
+/// Columns agent listings can be sorted by
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentSort {
+    #[default]
+    RegisteredAt,
+    LastSeenAt,
+    CreatedAt,
+    UpdatedAt,
+    Hostname,
+    Status,
+}
+
+impl AgentSort {
+    /// Column to order by; only these fixed names ever reach the SQL
+    fn column(self) -> &'static str {
+        match self {
+            AgentSort::RegisteredAt => "registered_at",
+            AgentSort::LastSeenAt => "last_seen_at",
+            AgentSort::CreatedAt => "created_at",
+            AgentSort::UpdatedAt => "updated_at",
+            AgentSort::Hostname => "hostname",
+            AgentSort::Status => "status",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// An agent as listed by the API, with its live readiness
+#[derive(Debug, Serialize)]
+pub struct AgentListing {
+    #[serde(flatten)]
+    agent: Agent,
+    /// Readiness last reported to this hub instance; `None` when the agent isn't
+    /// connected here
+    readiness: Option<Readiness>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListAgentsQuery {
+    provider: Option<ProviderType>,
+    status: Option<AgentStatus>,
+    #[serde(default)]
+    sort: AgentSort,
+    #[serde(default)]
+    order: SortOrder,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl ListAgentsQuery {
+    /// Append the `WHERE` clause for this query's filters
+    fn push_filters(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        builder.push(" WHERE TRUE");
+        if let Some(provider) = self.provider {
+            builder.push(" AND provider = ").push_bind(provider);
+        }
+        if let Some(status) = self.status {
+            builder.push(" AND status = ").push_bind(status);
+        }
+    }
+}
+
+/// `GET /api/agents` - agents, most recently registered first by default
+///
+/// Filter with `provider` and `status`, order with `sort` and `order`, and page with
+/// `limit` and `offset`. The number of agents matching the filters, regardless of
+/// paging, is returned in the `X-Total-Count` header. Each agent connected to this
+/// instance carries the readiness it last reported.
+pub async fn list_agents(
+    State(state): State<AppState>,
+    Query(query): Query<ListAgentsQuery>,
+) -> Result<(HeaderMap, Json<Vec<AgentListing>>), ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AGENT_LIMIT)
+        .clamp(1, MAX_AGENT_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM agents");
+    query.push_filters(&mut count);
+
+    let mut select = QueryBuilder::new(
+        r#"
+        SELECT id, provider, provider_instance_id, hostname,
+               COALESCE(display_name, hostname) AS display_name, status, tailscale_ip,
+               remote_ip, agent_version, gpu_info, boot_diagnostics, capabilities,
+               registered_at, last_seen_at, terminated_at, previous_agent_id, created_at,
+               updated_at
+        FROM agents
+        "#,
+    );
+    query.push_filters(&mut select);
+    let direction = match query.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+    // Agents that were never seen sort last either way; `id` keeps pages stable
+    select
+        .push(format!(
+            " ORDER BY {} {} NULLS LAST, id",
+            query.sort.column(),
+            direction
+        ))
+        .push(" LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+
+    let mut conn = state.acquire_db("list_agents").await?;
+    let total: i64 = count.build_query_scalar().fetch_one(&mut *conn).await?;
+    let agents = select
+        .build_query_as::<Agent>()
+        .fetch_all(&mut *conn)
+        .await?;
+
+    let agents = agents
+        .into_iter()
+        .map(|agent| AgentListing {
+            readiness: state.readiness.get(&agent.id),
+            agent,
+        })
+        .collect();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(total));
+
+    Ok((headers, Json(agents)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommandHistoryQuery {
+    limit: Option<i64>,
+}
+
+/// `GET /api/agents/{id}/commands` - most recent commands sent to an agent
+pub async fn list_commands(
+    State(state): State<AppState>,
+    Path(agent_id): Path<Uuid>,
+    Query(query): Query<CommandHistoryQuery>,
+) -> Result<Json<Vec<CommandLogEntry>>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_COMMAND_LIMIT)
+        .clamp(1, MAX_COMMAND_LIMIT);
+
+    ensure_agent_exists(&state, agent_id).await?;
+
+    let mut conn = state.acquire_db("list_commands").await?;
+    let entries = sqlx::query_as!(
+        CommandLogEntry,
+        r#"
+        SELECT id, agent_id, correlation_id, command,
+               status AS "status: CommandStatus", response, sent_at, completed_at
+        FROM command_log
+        WHERE agent_id = $1
+        ORDER BY sent_at DESC
+        LIMIT $2
+        "#,
+        agent_id,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Json(entries))
+}
+
+/// `POST /api/agents/{id}/terminate` - mark an agent terminated and close its connection
+///
+/// For reaping agents whose instance died without deregistering. There is no
+/// provider integration yet, so the instance itself is not destroyed. Requires an
+/// admin API key. Terminating an already-terminated agent keeps its original
+/// `terminated_at`.
+pub async fn terminate_agent(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Path(agent_id): Path<Uuid>,
+) -> Result<Json<Agent>, ApiError> {
+    require_scope(scope, Scope::Admin)?;
+
+    // Records and publishes the change like any other; an agent that's already
+    // terminated is left as it was
+    let terminated = lifecycle::transition(
+        &state,
+        agent_id,
+        AgentStatus::Terminated,
+        "manually terminated",
+    )
+    .await?;
+
+    let mut conn = state.acquire_db("terminate_agent").await?;
+    let agent = sqlx::query_as!(
+        Agent,
+        r#"
+        SELECT id, provider AS "provider: ProviderType", provider_instance_id, hostname,
+               COALESCE(display_name, hostname) AS "display_name!",
+               status AS "status: AgentStatus", tailscale_ip AS "tailscale_ip: IpAddr",
+               remote_ip AS "remote_ip: IpAddr",
+               agent_version, gpu_info AS "gpu_info: sqlx::types::Json<serde_json::Value>",
+               boot_diagnostics AS "boot_diagnostics: sqlx::types::Json<serde_json::Value>",
+               capabilities, registered_at, last_seen_at, terminated_at, previous_agent_id,
+               created_at, updated_at
+        FROM agents
+        WHERE id = $1
+        "#,
+        agent_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::not_found(format!("Agent {} not found", agent_id)))?;
+    drop(conn);
+
+    // Ends the agent's session, so it stops sending heartbeat acks and metrics
+    state.remove_connection(&agent_id).await;
+    if terminated {
+        info!(%agent_id, "agent manually terminated");
+    }
+
+    Ok(Json(agent))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgentEventsQuery {
+    limit: Option<i64>,
+}
+
+/// `GET /api/agents/{id}/events` - an agent's status history, newest first
+pub async fn list_events(
+    State(state): State<AppState>,
+    Path(agent_id): Path<Uuid>,
+    Query(query): Query<AgentEventsQuery>,
+) -> Result<Json<Vec<AgentEvent>>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_EVENT_LIMIT)
+        .clamp(1, MAX_EVENT_LIMIT);
+
+    ensure_agent_exists(&state, agent_id).await?;
+
+    let mut conn = state.acquire_db("list_agent_events").await?;
+    let events = sqlx::query_as!(
+        AgentEvent,
+        r#"
+        SELECT id, agent_id, status AS "status: AgentStatus", reason, occurred_at
+        FROM agent_events
+        WHERE agent_id = $1
+        ORDER BY occurred_at DESC, id DESC
+        LIMIT $2
+        "#,
+        agent_id,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Json(events))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExecuteCommandQuery {
+    /// Override for the command's default response timeout
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExecuteCommandResponse {
+    correlation_id: Uuid,
+    response: CommandResponse,
+    duration_ms: u64,
+}
+
+/// `POST /api/agents/{id}/commands` - send a command and wait for its response
+///
+/// Waits for `Command::default_timeout` unless `?timeout_secs=` is given, which may
+/// be at most [`MAX_CONFIG_DURATION`]. Requires an admin API key, since commands
+/// include `Terminate` and `DeleteModel`.
+pub async fn execute_command(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Path(agent_id): Path<Uuid>,
+    Query(query): Query<ExecuteCommandQuery>,
+    Json(command): Json<Command>,
+) -> Result<Json<ExecuteCommandResponse>, ApiError> {
+    require_scope(scope, Scope::Admin)?;
+
+    let timeout = query
+        .timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| command.default_timeout());
+    if timeout > MAX_CONFIG_DURATION {
+        return Err(ApiError::bad_request(format!(
+            "timeout_secs must be at most {}",
+            MAX_CONFIG_DURATION.as_secs()
+        )));
+    }
+    ensure_agent_exists(&state, agent_id).await?;
+
+    let start = Instant::now();
+    let (correlation_id, response) = commands::execute(&state, agent_id, command, timeout).await?;
+
+    Ok(Json(ExecuteCommandResponse {
+        correlation_id,
+        response,
+        duration_ms: start.elapsed().as_millis() as u64,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncModelsRequest {
+    /// Models the agent should end up with; anything else is deleted
+    model_ids: Vec<Uuid>,
+}
+
+/// `POST /api/agents/{id}/models/sync` - make an agent's models match a desired set
+///
+/// Resolves the IDs against `models`, sends a single `SyncModels` command, and then
+/// rewrites the agent's `agent_models` rows from what the agent reports is on disk,
+/// including after a partially failed sync. Requires an admin API key.
+pub async fn sync_models(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Path(agent_id): Path<Uuid>,
+    Json(request): Json<SyncModelsRequest>,
+) -> Result<Json<ExecuteCommandResponse>, ApiError> {
+    require_scope(scope, Scope::Admin)?;
+    ensure_agent_exists(&state, agent_id).await?;
+
+    let desired = resolve_model_specs(&state, &request.model_ids).await?;
+
+    let mut conn = state.acquire_db("load_agent_models").await?;
+    let current: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT model_id FROM agent_models WHERE agent_id = $1",
+        agent_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect();
+    drop(conn);
+
+    let wanted: HashSet<Uuid> = desired.iter().map(|spec| spec.model_id).collect();
+    info!(
+        %agent_id,
+        to_download = wanted.difference(&current).count(),
+        to_delete = current.difference(&wanted).count(),
+        "syncing agent models"
+    );
+
+    let command = Command::SyncModels { desired };
+    let timeout = command.default_timeout();
+    let start = Instant::now();
+    let (correlation_id, response) = commands::execute(&state, agent_id, command, timeout).await?;
+
+    let report = match &response {
+        CommandResponse::Success { data, .. } => data.as_ref(),
+        CommandResponse::Failed { details, .. } => details.as_ref(),
+    };
+    let present = report
+        .and_then(|report| report.get("present"))
+        .and_then(|present| serde_json::from_value::<Vec<Uuid>>(present.clone()).ok());
+    match present {
+        Some(present) => record_agent_models(&state, agent_id, &present).await?,
+        None => warn!(%agent_id, %correlation_id, "model sync response has no model list"),
+    }
+
+    Ok(Json(ExecuteCommandResponse {
+        correlation_id,
+        response,
+        duration_ms: start.elapsed().as_millis() as u64,
+    }))
+}
+
+/// Look up download details for each model, rejecting unknown IDs
+async fn resolve_model_specs(
+    state: &AppState,
+    model_ids: &[Uuid],
+) -> Result<Vec<ModelSpec>, ApiError> {
+    let mut conn = state.acquire_db("resolve_model_specs").await?;
+    let rows = sqlx::query!(
+        "SELECT id, r2_key, hash, file_size FROM models WHERE id = ANY($1)",
+        model_ids
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let found: HashSet<Uuid> = rows.iter().map(|row| row.id).collect();
+    if let Some(missing) = model_ids.iter().find(|id| !found.contains(id)) {
+        return Err(ApiError::bad_request(format!(
+            "Model {} not found",
+            missing
+        )));
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            // Models are stored on the agent under the last segment of their key
+            let filename = row.r2_key.rsplit('/').next().unwrap_or_default();
+            if filename.is_empty() {
+                return Err(ApiError::bad_request(format!(
+                    "Model {} has no filename in its R2 key '{}'",
+                    row.id, row.r2_key
+                )));
+            }
+            Ok(ModelSpec {
+                model_id: row.id,
+                filename: filename.to_string(),
+                r2_key: row.r2_key,
+                sha256_hash: row.hash,
+                file_size: u64::try_from(row.file_size).ok(),
+            })
+        })
+        .collect()
+}
+
+/// Replace an agent's `agent_models` rows with the models it reported having
+async fn record_agent_models(
+    state: &AppState,
+    agent_id: Uuid,
+    present: &[Uuid],
+) -> Result<(), ApiError> {
+    let mut conn = state.acquire_db("record_agent_models").await?;
+    // One statement, so readers never see the agent with none of its models;
+    // IDs no longer in `models` are skipped rather than violating the foreign key
+    sqlx::query!(
+        r#"
+        WITH removed AS (
+            DELETE FROM agent_models
+            WHERE agent_id = $1 AND NOT (model_id = ANY($2))
+        )
+        INSERT INTO agent_models (agent_id, model_id)
+        SELECT $1, id FROM models WHERE id = ANY($2)
+        ON CONFLICT (agent_id, model_id) DO NOTHING
+        "#,
+        agent_id,
+        present
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+impl From<CommandError> for ApiError {
+    fn from(error: CommandError) -> Self {
+        match error {
+            CommandError::Unreachable(_) | CommandError::Unsupported(_) => {
+                ApiError::new(StatusCode::CONFLICT, error.to_string())
+            }
+            CommandError::TimedOut(_) => {
+                ApiError::new(StatusCode::GATEWAY_TIMEOUT, error.to_string())
+            }
+            CommandError::NotReady(_) => {
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, error.to_string())
+            }
+            CommandError::Internal(e) => ApiError::from(e),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PingResponse {
+    correlation_id: Uuid,
+    nonce: u64,
+    round_trip_ms: f64,
+}
+
+/// `POST /api/agents/{id}/ping` - measure command round-trip latency to an agent
+///
+/// Unlike heartbeats, this exercises the full command path (dispatch, agent
+/// command handler, response matching) on demand.
+pub async fn ping(
+    State(state): State<AppState>,
+    Path(agent_id): Path<Uuid>,
+) -> Result<Json<PingResponse>, ApiError> {
+    ensure_agent_exists(&state, agent_id).await?;
+
+    let nonce = commands::ping_nonce();
+    let command = Command::Ping { nonce };
+    let timeout = command.default_timeout();
+
+    let start = Instant::now();
+    let (correlation_id, response) = commands::execute(&state, agent_id, command, timeout).await?;
+    let round_trip = start.elapsed();
+
+    let echoed = match &response {
+        CommandResponse::Success { data, .. } => data
+            .as_ref()
+            .and_then(|data| data.get("nonce"))
+            .and_then(|nonce| nonce.as_u64()),
+        CommandResponse::Failed { error, .. } => {
+            return Err(ApiError::new(
+                StatusCode::BAD_GATEWAY,
+                format!("Agent rejected ping: {}", error),
+            ));
+        }
+    };
+    if echoed != Some(nonce) {
+        return Err(ApiError::new(
+            StatusCode::BAD_GATEWAY,
+            "Agent echoed the wrong ping nonce",
+        ));
+    }
+
+    Ok(Json(PingResponse {
+        correlation_id,
+        nonce,
+        round_trip_ms: round_trip.as_secs_f64() * 1000.0,
+    }))
+}
+
+/// Return 404 unless an agent with this ID has ever been registered
+async fn ensure_agent_exists(state: &AppState, agent_id: Uuid) -> Result<(), ApiError> {
+    let mut conn = state.acquire_db("ensure_agent_exists").await?;
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM agents WHERE id = $1) AS "exists!""#,
+        agent_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if exists {
+        Ok(())
+    } else {
+        Err(ApiError::not_found(format!("Agent {} not found", agent_id)))
+    }
+}
@@ -0,0 +1,119 @@
+//! API key authentication for the REST API.
+//!
+//! Requests under `/api` must carry one of the configured keys in the `X-Api-Key`
+//! header. Several keys may be configured at once so a key can be rotated without
+//! downtime. With no keys configured, debug builds leave the API open for local
+//! development and release builds reject every request.
+//!
+//! Each key carries a [`Scope`], which the middleware attaches to the request's
+//! extensions along with the key's [`ApiKeyId`]. Handlers for destructive endpoints
+//! check the scope with [`require_scope`].
+
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use podpilot_common::config::{ApiKey, Scope};
+use secrecy::ExposeSecret;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+use crate::web::error::ApiError;
+
+/// Request header carrying the API key
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Which configured key authenticated a request, as its position in the key list
+///
+/// Requests let through because no keys are configured carry none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ApiKeyId(pub usize);
+
+/// The set of keys accepted by [`require_api_key`]
+#[derive(Clone)]
+pub struct ApiKeys {
+    keys: Arc<Vec<ApiKey>>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: Vec<ApiKey>) -> Self {
+        Self {
+            keys: Arc::new(keys),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Find a candidate key and its scope, comparing against every key in constant time
+    fn resolve(&self, candidate: &str) -> Option<(ApiKeyId, Scope)> {
+        let mut resolved = None;
+        for (index, key) in self.keys.iter().enumerate() {
+            let matched: bool = key
+                .secret
+                .expose_secret()
+                .as_bytes()
+                .ct_eq(candidate.as_bytes())
+                .into();
+            if matched && resolved.is_none() {
+                resolved = Some((ApiKeyId(index), key.scope));
+            }
+        }
+        resolved
+    }
+}
+
+/// Reject requests without a valid `X-Api-Key` header with 401
+///
+/// On success, the key's [`Scope`] and [`ApiKeyId`] are inserted into the request
+/// extensions.
+pub async fn require_api_key(
+    State(keys): State<ApiKeys>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if keys.is_empty() && cfg!(debug_assertions) {
+        request.extensions_mut().insert(Scope::Admin);
+        return next.run(request).await;
+    }
+
+    let provided = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    let (key_id, scope) = match provided {
+        Some(key) => match keys.resolve(key) {
+            Some(resolved) => resolved,
+            None => {
+                warn!(
+                    path = request.uri().path(),
+                    "rejected request with invalid API key"
+                );
+                return ApiError::new(StatusCode::UNAUTHORIZED, "Invalid API key").into_response();
+            }
+        },
+        None => {
+            return ApiError::new(StatusCode::UNAUTHORIZED, "Missing X-Api-Key header")
+                .into_response();
+        }
+    };
+
+    request.extensions_mut().insert(scope);
+    request.extensions_mut().insert(key_id);
+    next.run(request).await
+}
+
+/// Fail with 403 unless `scope` grants `required`
+pub fn require_scope(scope: Scope, required: Scope) -> Result<(), ApiError> {
+    if scope.permits(required) {
+        Ok(())
+    } else {
+        Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("This endpoint requires the {:?} scope", required),
+        ))
+    }
+}
@@ -0,0 +1,86 @@
+//! Hub diagnostics API endpoints.
+
+use axum::Json;
+use axum::extract::State;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::data::models::NetworkEvent;
+use crate::state::AppState;
+use crate::web::error::ApiError;
+
+/// Number of past IP changes returned by the network endpoint
+const NETWORK_HISTORY_LIMIT: i64 = 10;
+
+#[derive(Debug, Serialize)]
+pub struct NetworkDiagnostics {
+    /// Tailscale IP currently held in memory
+    tailscale_ip: Option<IpAddr>,
+    /// Most recent IP changes, newest first
+    history: Vec<NetworkEvent>,
+}
+
+/// `GET /api/diagnostics/network` - current Tailscale IP and recent changes
+pub async fn network(State(state): State<AppState>) -> Result<Json<NetworkDiagnostics>, ApiError> {
+    let mut conn = state.acquire_db("network_diagnostics").await?;
+    let history = sqlx::query_as!(
+        NetworkEvent,
+        r#"
+        SELECT id, previous_ip AS "previous_ip: IpAddr", ip AS "ip: IpAddr", observed_at
+        FROM hub_network_events
+        ORDER BY observed_at DESC, id DESC
+        LIMIT $1
+        "#,
+        NETWORK_HISTORY_LIMIT
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Json(NetworkDiagnostics {
+        tailscale_ip: state.tailscale_ip().await,
+        history,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandDiagnostics {
+    /// Commands sent and awaiting a response on this instance
+    pending: usize,
+    /// Queued plus in-flight commands per agent, for agents with any
+    queue_depths: HashMap<Uuid, usize>,
+}
+
+/// `GET /api/diagnostics/commands` - command queue depths on this hub instance
+pub async fn commands(State(state): State<AppState>) -> Json<CommandDiagnostics> {
+    Json(CommandDiagnostics {
+        pending: state.pending.len(),
+        queue_depths: state.command_queues.depths(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionDiagnostics {
+    agent_id: Uuid,
+    /// Time since the agent's current WebSocket session was established
+    session_age_secs: u64,
+}
+
+/// `GET /api/diagnostics/connections` - agents connected to this hub instance,
+/// most recently connected first
+///
+/// Agents that keep showing short session ages are reconnecting abnormally often.
+pub async fn connections(State(state): State<AppState>) -> Json<Vec<ConnectionDiagnostics>> {
+    let mut sessions = state.connections.session_ages();
+    sessions.sort_by_key(|(_, age)| *age);
+    Json(
+        sessions
+            .into_iter()
+            .map(|(agent_id, age)| ConnectionDiagnostics {
+                agent_id,
+                session_age_secs: age.as_secs(),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,151 @@
+//! Live fleet event streams for the frontend.
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::{Stream, StreamExt, stream};
+use serde::Deserialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::events::{EventFilter, HubEvent};
+use crate::state::AppState;
+use crate::web::error::ApiError;
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Comma-separated categories to receive (`agent`, `asset`, `alert`); all when omitted
+    types: Option<String>,
+}
+
+/// `GET /api/events/ws` - stream fleet events as JSON text messages
+pub async fn events_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Response, ApiError> {
+    let filter = EventFilter::parse(query.types.as_deref()).map_err(ApiError::bad_request)?;
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, state, filter)))
+}
+
+/// Forward events to a WebSocket subscriber until either side closes
+async fn stream_events(mut socket: WebSocket, state: AppState, filter: EventFilter) {
+    let mut events = state.events.subscribe();
+    debug!(?filter, "event subscriber connected");
+
+    loop {
+        let event = tokio::select! {
+            received = events.recv() => match received {
+                Ok(envelope) => envelope.event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "event subscriber lagged, events skipped");
+                    HubEvent::Lagged { missed }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Subscribers don't send anything; this only watches for the close
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if !filter.allows(&event) {
+            continue;
+        }
+        let json = match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!("Failed to serialize event: {}", e);
+                continue;
+            }
+        };
+        if socket.send(Message::Text(json.into())).await.is_err() {
+            break;
+        }
+    }
+
+    debug!("event subscriber disconnected");
+}
+
+/// `GET /api/events/sse` - stream fleet events as server-sent events
+///
+/// Each event is named after its `type` and carries its sequence number as the
+/// SSE id. Clients reconnecting with `Last-Event-ID` first receive the buffered
+/// events they missed, preceded by a `lagged` event if some are no longer buffered.
+pub async fn events_sse(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let filter = EventFilter::parse(query.types.as_deref()).map_err(ApiError::bad_request)?;
+    let last_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let (catch_up, receiver) = match last_id {
+        Some(last_id) => {
+            let resume = state.events.resume(last_id);
+            debug!(
+                last_id,
+                replayed = resume.replay.len(),
+                missed = resume.missed,
+                "event subscriber resuming"
+            );
+            let mut catch_up = Vec::new();
+            if resume.missed > 0 {
+                catch_up.push(sse_event(
+                    None,
+                    &HubEvent::Lagged {
+                        missed: resume.missed,
+                    },
+                ));
+            }
+            catch_up.extend(
+                resume
+                    .replay
+                    .iter()
+                    .filter(|envelope| filter.allows(&envelope.event))
+                    .map(|envelope| sse_event(Some(envelope.id), &envelope.event)),
+            );
+            (catch_up, resume.receiver)
+        }
+        None => (Vec::new(), state.events.subscribe()),
+    };
+
+    let live = stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(envelope) if filter.allows(&envelope.event) => {
+                    sse_event(Some(envelope.id), &envelope.event)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "event subscriber lagged, events skipped");
+                    sse_event(None, &HubEvent::Lagged { missed })
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((event, (receiver, filter)));
+        }
+    });
+
+    let events = stream::iter(catch_up).chain(live).map(Ok);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Encode an event as SSE, named after its type and tagged with its sequence number
+fn sse_event(id: Option<u64>, event: &HubEvent) -> Event {
+    let mut sse = Event::default().event(event.name());
+    if let Some(id) = id {
+        sse = sse.id(id.to_string());
+    }
+    sse.json_data(event).unwrap_or_else(|e| {
+        warn!("Failed to serialize event: {}", e);
+        Event::default().comment("serialization failed")
+    })
+}
@@ -0,0 +1,93 @@
+//! Fleet-wide summary API endpoint.
+
+use axum::Json;
+use axum::extract::State;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::state::AppState;
+use crate::web::error::ApiError;
+
+#[derive(Debug, Serialize)]
+pub struct FleetSummary {
+    /// Agents per status, including terminated ones
+    by_status: BTreeMap<String, i64>,
+    /// Agents per provider, excluding terminated ones
+    by_provider: BTreeMap<String, i64>,
+    /// Agents connected to this hub instance
+    connected: usize,
+    /// GPU memory across connected agents, in GB
+    gpu_memory_gb: f64,
+    /// Mean of each connected agent's latest GPU utilization sample, if any reported one
+    gpu_utilization_avg: Option<f64>,
+}
+
+/// `GET /api/fleet/summary` - top-line fleet counts and GPU totals
+///
+/// Connection-based figures only cover agents connected to this hub instance.
+pub async fn summary(State(state): State<AppState>) -> Result<Json<FleetSummary>, ApiError> {
+    let connected = state.connected_agents();
+
+    let mut conn = state.acquire_db("fleet_summary").await?;
+    let by_status = sqlx::query!(
+        r#"
+        SELECT status::text AS "status!", COUNT(*) AS "count!"
+        FROM agents
+        GROUP BY status
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| (row.status, row.count))
+    .collect();
+
+    let by_provider = sqlx::query!(
+        r#"
+        SELECT provider::text AS "provider!", COUNT(*) AS "count!"
+        FROM agents
+        WHERE terminated_at IS NULL
+        GROUP BY provider
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| (row.provider, row.count))
+    .collect();
+
+    // gpu_info is untyped JSON, so rows without a numeric memory_gb are skipped
+    // rather than failing the cast
+    let gpu = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(SUM(
+                CASE WHEN jsonb_typeof(gpu_info->'memory_gb') = 'number'
+                     THEN (gpu_info->>'memory_gb')::float8
+                END
+            ), 0) AS "gpu_memory_gb!",
+            (
+                SELECT AVG(latest.gpu_utilization)::float8
+                FROM (
+                    SELECT DISTINCT ON (agent_id) gpu_utilization
+                    FROM agent_metrics
+                    WHERE agent_id = ANY($1)
+                    ORDER BY agent_id, collected_at DESC
+                ) latest
+            ) AS gpu_utilization_avg
+        FROM agents
+        WHERE id = ANY($1)
+        "#,
+        &connected
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Json(FleetSummary {
+        by_status,
+        by_provider,
+        connected: connected.len(),
+        gpu_memory_gb: gpu.gpu_memory_gb,
+        gpu_utilization_avg: gpu.gpu_utilization_avg,
+    }))
+}
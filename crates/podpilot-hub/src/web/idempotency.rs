@@ -0,0 +1,287 @@
+//! `Idempotency-Key` support for mutating API endpoints.
+//!
+//! Clients may send an `Idempotency-Key` header with POST/PUT/PATCH/DELETE requests.
+//! The first response for a key (scoped to the API key that sent it, the method, and
+//! the path) is cached for a short window and replayed for retries, so a retried
+//! request never performs its side effect twice. Server errors and request
+//! timeouts are not cached, allowing a genuine retry. Reusing a key with a different request body is a
+//! client bug and is refused with 422 rather than answered with the first response.
+
+use axum::body::{Body, Bytes, HttpBody, to_bytes};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use rapidhash::v3::rapidhash_v3;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::web::auth::ApiKeyId;
+
+/// Request header carrying the client-chosen idempotency key
+pub const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Response header set on replayed responses
+const REPLAYED_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Longest accepted idempotency key
+const MAX_KEY_LEN: usize = 255;
+
+/// Largest request body accepted alongside an idempotency key, matching axum's default limit
+const MAX_REQUEST_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Largest response body that will be buffered for replay
+const MAX_CACHED_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+/// How often expired keys are swept out of the cache
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A response captured for replay
+#[derive(Clone)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl CachedResponse {
+    fn replay(&self) -> Response {
+        let mut response = (self.status, self.body.clone()).into_response();
+        *response.headers_mut() = self.headers.clone();
+        response
+            .headers_mut()
+            .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+enum SlotState {
+    /// The first request with this key is still being handled
+    InFlight,
+    Completed(CachedResponse),
+}
+
+struct Slot {
+    state: SlotState,
+    /// Hash of the request body the key was first used with
+    fingerprint: u64,
+    created_at: Instant,
+}
+
+impl Slot {
+    fn in_flight(fingerprint: u64) -> Self {
+        Self {
+            state: SlotState::InFlight,
+            fingerprint,
+            created_at: Instant::now(),
+        }
+    }
+}
+
+/// Identifies a request for replay: the API key that sent it, then its method,
+/// path, and idempotency key
+type SlotKey = (Option<ApiKeyId>, String);
+
+/// Short-lived store of processed idempotency keys
+#[derive(Clone)]
+pub struct IdempotencyCache {
+    slots: Arc<DashMap<SlotKey, Slot>>,
+    ttl: Duration,
+    last_eviction: Arc<Mutex<Instant>>,
+}
+
+impl IdempotencyCache {
+    /// Create a cache that remembers keys for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            slots: Arc::new(DashMap::new()),
+            ttl,
+            last_eviction: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    fn is_expired(&self, slot: &Slot) -> bool {
+        slot.created_at.elapsed() >= self.ttl
+    }
+
+    /// Sweep out expired keys, at most once per [`EVICTION_INTERVAL`]
+    ///
+    /// Lookups already ignore expired slots, so this only bounds the cache's size.
+    fn evict_expired(&self) {
+        {
+            let mut last_eviction = self
+                .last_eviction
+                .lock()
+                .expect("idempotency eviction lock poisoned");
+            if last_eviction.elapsed() < EVICTION_INTERVAL {
+                return;
+            }
+            *last_eviction = Instant::now();
+        }
+        self.slots.retain(|_, slot| !self.is_expired(slot));
+    }
+}
+
+/// Removes an `InFlight` slot when dropped, unless [`InFlightGuard::settle`]d first
+///
+/// The handler's future is dropped if the client disconnects or it is cancelled, and
+/// a slot left `InFlight` would answer every retry with 409 until it expires.
+struct InFlightGuard {
+    cache: IdempotencyCache,
+    key: Option<SlotKey>,
+    created_at: Instant,
+}
+
+impl InFlightGuard {
+    /// Hand the slot's key back to the caller, who now settles it
+    fn settle(mut self) -> SlotKey {
+        self.key.take().expect("in-flight guard settled twice")
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            // Only remove our own slot, not one that replaced it after expiring
+            self.cache.slots.remove_if(&key, |_, slot| {
+                matches!(slot.state, SlotState::InFlight) && slot.created_at == self.created_at
+            });
+        }
+    }
+}
+
+fn is_mutating(method: &Method) -> bool {
+    method == Method::POST
+        || method == Method::PUT
+        || method == Method::PATCH
+        || method == Method::DELETE
+}
+
+/// Middleware replaying cached responses for repeated `Idempotency-Key`s
+///
+/// Requests without the header (or non-mutating requests) pass straight through.
+/// A concurrent duplicate of an in-flight request receives 409 Conflict, and a
+/// reused key with a different body receives 422 Unprocessable Entity.
+pub async fn idempotency(
+    State(cache): State<IdempotencyCache>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !is_mutating(request.method()) {
+        return next.run(request).await;
+    }
+
+    let key = match request.headers().get(&IDEMPOTENCY_KEY_HEADER) {
+        None => return next.run(request).await,
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+            _ => {
+                return (StatusCode::BAD_REQUEST, "Invalid Idempotency-Key header").into_response();
+            }
+        },
+    };
+    let key_id = request.extensions().get::<ApiKeyId>().copied();
+    let cache_key = (
+        key_id,
+        format!("{} {} {}", request.method(), request.uri().path(), key),
+    );
+
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_REQUEST_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+    let fingerprint = rapidhash_v3(&body);
+    let request = Request::from_parts(parts, Body::from(body));
+
+    cache.evict_expired();
+    let in_flight = Slot::in_flight(fingerprint);
+    let created_at = in_flight.created_at;
+    let early_response = match cache.slots.entry(cache_key.clone()) {
+        Entry::Occupied(mut slot) if cache.is_expired(slot.get()) => {
+            slot.insert(in_flight);
+            None
+        }
+        Entry::Occupied(slot) if slot.get().fingerprint != fingerprint => Some(
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request body",
+            )
+                .into_response(),
+        ),
+        Entry::Occupied(slot) => match &slot.get().state {
+            SlotState::InFlight => Some(
+                (
+                    StatusCode::CONFLICT,
+                    "A request with this Idempotency-Key is already in progress",
+                )
+                    .into_response(),
+            ),
+            SlotState::Completed(cached) => {
+                debug!(key = %key, "replaying idempotent response");
+                Some(cached.replay())
+            }
+        },
+        Entry::Vacant(slot) => {
+            slot.insert(in_flight);
+            None
+        }
+    };
+    if let Some(response) = early_response {
+        return response;
+    }
+
+    let guard = InFlightGuard {
+        cache: cache.clone(),
+        key: Some(cache_key),
+        created_at,
+    };
+    let (parts, body) = next.run(request).await.into_parts();
+
+    // A 408 comes from the timeout layer, not the handler, which may still have
+    // committed; replaying it would hide the real outcome for the whole TTL
+    if parts.status.is_server_error() || parts.status == StatusCode::REQUEST_TIMEOUT {
+        drop(guard);
+        return Response::from_parts(parts, body);
+    }
+
+    // The handler has already applied its side effect, so a response too large
+    // (or of unknown size) to buffer is passed through uncached rather than failed
+    let cacheable = body
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= MAX_CACHED_BODY_BYTES as u64);
+    if !cacheable {
+        warn!(key = %key, "response too large for idempotency cache, not caching it");
+        drop(guard);
+        return Response::from_parts(parts, body);
+    }
+
+    let body = match to_bytes(body, MAX_CACHED_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => {
+            // The body stream itself failed, so there is nothing left to pass through
+            warn!(key = %key, error = %e, "failed to buffer response for idempotency cache");
+            drop(guard);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    cache.slots.insert(
+        guard.settle(),
+        Slot {
+            state: SlotState::Completed(CachedResponse {
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+            }),
+            fingerprint,
+            created_at: Instant::now(),
+        },
+    );
+
+    Response::from_parts(parts, Body::from(body))
+}
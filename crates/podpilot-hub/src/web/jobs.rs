@@ -0,0 +1,136 @@
+//! Job submission and tracking API endpoints.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use podpilot_common::config::Scope;
+use serde::Deserialize;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::data::models::{Job, JobStatus};
+use crate::state::AppState;
+use crate::web::auth::require_scope;
+use crate::web::error::ApiError;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateJobRequest {
+    model_id: Uuid,
+    /// Passed through to the agent's job runner
+    #[serde(default = "empty_params")]
+    params: serde_json::Value,
+}
+
+fn empty_params() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+/// `POST /api/jobs` - queue a job for the next free agent that can run it
+///
+/// Returns immediately with the queued job; the scheduler assigns it once an agent
+/// with the `jobs` capability is free. Requires an admin API key, since jobs run
+/// commands on agents.
+pub async fn create_job(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Json(request): Json<CreateJobRequest>,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    require_scope(scope, Scope::Admin)?;
+
+    let mut conn = state.acquire_db("create_job").await?;
+    let model_exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM models WHERE id = $1) AS "exists!""#,
+        request.model_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !model_exists {
+        return Err(ApiError::bad_request(format!(
+            "Model {} not found",
+            request.model_id
+        )));
+    }
+
+    let job = sqlx::query_as!(
+        Job,
+        r#"
+        INSERT INTO jobs (model_id, params)
+        VALUES ($1, $2)
+        RETURNING id, model_id, params, status AS "status: JobStatus", assigned_agent_id,
+                  retries, created_at, started_at, finished_at, result
+        "#,
+        request.model_id,
+        request.params
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    drop(conn);
+
+    info!(job_id = %job.id, model_id = %job.model_id, "job queued");
+    state.scheduler.wake();
+
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+/// Default and maximum page size for job listings
+const DEFAULT_JOB_LIMIT: i64 = 100;
+const MAX_JOB_LIMIT: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ListJobsQuery {
+    status: Option<JobStatus>,
+    limit: Option<i64>,
+}
+
+/// `GET /api/jobs` - most recent jobs, optionally only those in one status
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Query(query): Query<ListJobsQuery>,
+) -> Result<Json<Vec<Job>>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_JOB_LIMIT)
+        .clamp(1, MAX_JOB_LIMIT);
+
+    let mut conn = state.acquire_db("list_jobs").await?;
+    let jobs = sqlx::query_as!(
+        Job,
+        r#"
+        SELECT id, model_id, params, status AS "status: JobStatus", assigned_agent_id, retries,
+               created_at, started_at, finished_at, result
+        FROM jobs
+        WHERE $1::job_status IS NULL OR status = $1
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+        query.status as _,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Json(jobs))
+}
+
+/// `GET /api/jobs/{id}` - a single job
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Json<Job>, ApiError> {
+    let mut conn = state.acquire_db("get_job").await?;
+    let job = sqlx::query_as!(
+        Job,
+        r#"
+        SELECT id, model_id, params, status AS "status: JobStatus", assigned_agent_id, retries,
+               created_at, started_at, finished_at, result
+        FROM jobs
+        WHERE id = $1
+        "#,
+        job_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::not_found(format!("Job {} not found", job_id)))?;
+
+    Ok(Json(job))
+}
@@ -1,4 +1,12 @@
+pub mod agents;
 pub mod assets;
+pub mod auth;
+pub mod diagnostics;
+pub mod error;
+pub mod events;
+pub mod fleet;
+pub mod idempotency;
+pub mod jobs;
 pub mod routes;
 
 pub use routes::*;
@@ -2,28 +2,88 @@ use axum::{
     Json, Router,
     body::Body,
     extract::{Request, State},
-    http::{HeaderMap, HeaderValue, StatusCode, Uri},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware,
     response::{Html, IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
 };
 use http::header;
 use std::time::Duration;
 use tower_http::timeout::TimeoutLayer;
 use tower_http::{
     classify::ServerErrorsFailureClass,
-    cors::{Any, CorsLayer},
+    cors::{AllowOrigin, Any, CorsLayer},
     trace::TraceLayer,
 };
 use tracing::{Span, debug, warn};
 
 use crate::{
     state::AppState,
+    web::agents::{self, TOTAL_COUNT_HEADER},
     web::assets::{WebAssets, get_asset_metadata_cached},
+    web::auth::{API_KEY_HEADER, ApiKeys, require_api_key},
+    web::diagnostics,
+    web::events,
+    web::fleet,
+    web::idempotency::{IdempotencyCache, idempotency},
+    web::jobs,
 };
 
 // Import WebSocket handler from ws module
 use crate::ws::agent_websocket_handler;
 
+/// Maximum time to handle a request (except command execution, see `create_router`)
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Response header carrying the hub version on API responses
+const HUB_VERSION_HEADER: header::HeaderName =
+    header::HeaderName::from_static("x-podpilot-hub-version");
+
+/// How long processed `Idempotency-Key`s are remembered
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(60 * 10);
+
+/// Build the CORS layer for browser clients
+///
+/// Configured origins are allowed the methods and headers the frontend uses. With no
+/// origins configured, debug builds allow any origin for local development and release
+/// builds return `None`, so cross-origin requests are denied.
+pub fn cors_layer(allowed_origins: &[String]) -> anyhow::Result<Option<CorsLayer>> {
+    if allowed_origins.is_empty() {
+        if cfg!(debug_assertions) {
+            return Ok(Some(
+                CorsLayer::new()
+                    .allow_origin(Any)
+                    .allow_methods(Any)
+                    .allow_headers(Any)
+                    .expose_headers([HUB_VERSION_HEADER, TOTAL_COUNT_HEADER]),
+            ));
+        }
+        return Ok(None);
+    }
+
+    let origins = allowed_origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin)
+                .map_err(|_| anyhow::anyhow!("'{}' is not a valid CORS origin", origin))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods([Method::GET, Method::POST, Method::DELETE])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                API_KEY_HEADER,
+                header::HeaderName::from_static("idempotency-key"),
+                header::HeaderName::from_static("last-event-id"),
+            ])
+            .expose_headers([HUB_VERSION_HEADER, TOTAL_COUNT_HEADER]),
+    ))
+}
+
 /// Set appropriate caching headers based on asset type
 fn set_caching_headers(response: &mut Response, path: &str, etag: &str) {
     let headers = response.headers_mut();
@@ -85,32 +145,111 @@ async fn health(State(state): State<AppState>) -> impl IntoResponse {
             "database": db_status,
             "tailscale_ip": tailscale_ip,
             "connected_agents": connected_agents,
+            "version": env!("CARGO_PKG_VERSION"),
         })),
     )
 }
 
+/// Hub self-report, mirroring the agent's `/status`
+///
+/// Unlike `/health`, this never queries the database; pool health is read from
+/// the pool's own bookkeeping, so it stays cheap for frequent polling.
+async fn status(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let pool = &state.db;
+    let pool_status = if pool.is_closed() {
+        "closed"
+    } else if pool.num_idle() == 0 && pool.size() >= pool.options().get_max_connections() {
+        "saturated"
+    } else {
+        "ok"
+    };
+
+    Json(serde_json::json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": state.uptime().as_secs(),
+        "connected_agents": state.connection_count(),
+        "tailscale_ip": state.tailscale_ip().await.map(|ip| ip.to_string()),
+        "database_pool": {
+            "status": pool_status,
+            "size": pool.size(),
+            "idle": pool.num_idle(),
+            "max_connections": pool.options().get_max_connections(),
+        },
+    }))
+}
+
+/// Tag API responses with the hub version
+async fn hub_version_header(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        HUB_VERSION_HEADER,
+        HeaderValue::from_static(env!("CARGO_PKG_VERSION")),
+    );
+    response
+}
+
+/// Prometheus scrape endpoint
+async fn metrics(State(state): State<AppState>) -> Response {
+    match state.metrics.render(&state.db).await {
+        Ok(body) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(e) => {
+            warn!(error = format!("{:#}", e), "failed to render metrics");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 /// Creates the web server router
-pub fn create_router(state: AppState) -> Router {
-    let api_router = Router::new().with_state(state.clone());
+///
+/// `cors` comes from [`cors_layer`]; without it, no CORS headers are sent. `/api`
+/// routes require one of `api_keys`, while `/health`, `/metrics`, and `/ws/agent` do not.
+pub fn create_router(state: AppState, cors: Option<CorsLayer>, api_keys: ApiKeys) -> Router {
+    // Routes must be added before a layer for it to apply to them
+    let api_router = Router::new()
+        .route("/agents", get(agents::list_agents))
+        .route("/agents/{id}/commands", get(agents::list_commands))
+        .route("/agents/{id}/events", get(agents::list_events))
+        .route("/agents/{id}/terminate", post(agents::terminate_agent))
+        .route("/diagnostics/network", get(diagnostics::network))
+        .route("/diagnostics/commands", get(diagnostics::commands))
+        .route("/diagnostics/connections", get(diagnostics::connections))
+        .route("/fleet/summary", get(fleet::summary))
+        .route("/jobs", get(jobs::list_jobs).post(jobs::create_job))
+        .route("/jobs/{id}", get(jobs::get_job))
+        .layer(TimeoutLayer::new(REQUEST_TIMEOUT))
+        // Long-lived streams
+        .route("/events/ws", get(events::events_ws))
+        .route("/events/sse", get(events::events_sse))
+        // Waits for the agent's response, bounded by the command's own timeout
+        .route("/agents/{id}/commands", post(agents::execute_command))
+        .route("/agents/{id}/ping", post(agents::ping))
+        .route("/agents/{id}/models/sync", post(agents::sync_models))
+        .layer(middleware::from_fn_with_state(
+            IdempotencyCache::new(IDEMPOTENCY_TTL),
+            idempotency,
+        ))
+        .layer(middleware::from_fn_with_state(api_keys, require_api_key))
+        .layer(middleware::map_response(hub_version_header))
+        .with_state(state.clone());
 
     let mut router = Router::new()
         .route("/health", get(health))
+        .route("/status", get(status))
+        .route("/metrics", get(metrics))
         .route("/ws/agent", get(agent_websocket_handler))
+        .layer(TimeoutLayer::new(REQUEST_TIMEOUT))
         .nest("/api", api_router)
         .with_state(state);
 
-    if cfg!(debug_assertions) {
-        router = router.layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
-    } else {
+    if !cfg!(debug_assertions) {
         router = router.fallback(fallback);
     }
 
-    router.layer((
+    if let Some(cors) = cors {
+        router = router.layer(cors);
+    }
+
+    router.layer(
         TraceLayer::new_for_http()
             .make_span_with(|request: &Request<Body>| {
                 tracing::debug_span!("request", path = request.uri().path())
@@ -153,8 +292,7 @@ pub fn create_router(state: AppState) -> Router {
                     );
                 },
             ),
-        TimeoutLayer::new(Duration::from_secs(10)),
-    ))
+    )
 }
 
 /// Handler that extracts request information for caching
@@ -1,20 +1,50 @@
+use chrono::{DateTime, Utc};
+use podpilot_common::config::format_duration;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::time::{Duration, interval};
 use tracing::{error, info, warn};
+use uuid::Uuid;
 
+use crate::agent_metrics::{MetricsRetention, expire_metrics};
+use crate::commands;
+use crate::data::models::AgentStatus;
+use crate::lifecycle;
 use crate::state::AppState;
 
+/// How long a stale agent has to answer a probe before it's marked as 'error'
+const STALE_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Cleanup task that marks stale agents as 'error' and removes them from the connection registry
-pub async fn cleanup_task(state: AppState, shutdown: Arc<AtomicBool>) {
-    info!("Starting agent cleanup task");
+///
+/// Every `period`, agents that haven't acked a heartbeat within `stale_threshold` are cleaned up.
+/// Every `metrics_retention.interval`, metrics samples past their retention are expired.
+pub async fn cleanup_task(
+    state: AppState,
+    period: Duration,
+    stale_threshold: Duration,
+    metrics_retention: MetricsRetention,
+    shutdown: Arc<AtomicBool>,
+) {
+    info!(
+        interval = format_duration(period),
+        stale_threshold = format_duration(stale_threshold),
+        metrics_retention_days = metrics_retention.days,
+        "Starting agent cleanup task"
+    );
 
-    let mut tick_interval = interval(Duration::from_secs(15));
+    let mut tick_interval = interval(period);
+    let mut metrics_interval = interval(metrics_retention.interval);
 
     loop {
         tokio::select! {
             _ = tick_interval.tick() => {
-                cleanup_stale_agents(&state).await;
+                cleanup_stale_agents(&state, stale_cutoff(Utc::now(), stale_threshold)).await;
+            }
+            _ = metrics_interval.tick() => {
+                if let Err(e) = expire_metrics(&state, &metrics_retention).await {
+                    error!(error = ?e, "Failed to expire agent metrics");
+                }
             }
             _ = tokio::signal::ctrl_c() => {
                 info!("Cleanup task received shutdown signal");
@@ -33,17 +63,30 @@ pub async fn cleanup_task(state: AppState, shutdown: Arc<AtomicBool>) {
     info!("Cleanup task stopped");
 }
 
-/// Find and mark stale agents as 'error', then remove from connection registry
-async fn cleanup_stale_agents(state: &AppState) {
-    // Query for agents that haven't sent a heartbeat in 30+ seconds
+/// Latest `last_seen_at` that still counts as alive at `now`
+pub fn stale_cutoff(now: DateTime<Utc>, stale_threshold: Duration) -> DateTime<Utc> {
+    now - chrono::Duration::from_std(stale_threshold).unwrap_or(chrono::Duration::MAX)
+}
+
+/// Mark active agents last seen before `cutoff` as 'error' and remove them from the
+/// connection registry, returning how many were marked
+///
+/// Missing heartbeat acks don't always mean a dead agent: on a congested link the
+/// acks may just be late. Each stale agent is first sent a ping, and one that
+/// answers within [`STALE_PROBE_TIMEOUT`] is kept and its `last_seen_at` refreshed.
+///
+/// Takes the cutoff rather than reading the clock, so callers control what counts as stale.
+pub async fn cleanup_stale_agents(state: &AppState, cutoff: DateTime<Utc>) -> usize {
     // Only check agents that are in active states (not already error/terminated)
+    // Served by idx_agents_status_last_seen (status, last_seen_at)
     let result = sqlx::query_scalar!(
         r#"
         SELECT id
         FROM agents
         WHERE status IN ('ready', 'running', 'idle')
-          AND last_seen_at < NOW() - INTERVAL '30 seconds'
+          AND last_seen_at < $1
         "#,
+        cutoff
     )
     .fetch_all(&state.db)
     .await;
@@ -52,43 +95,74 @@ async fn cleanup_stale_agents(state: &AppState) {
         Ok(agents) => agents,
         Err(e) => {
             error!("Failed to query stale agents: {}", e);
-            return;
+            return 0;
         }
     };
 
     if stale_agents.is_empty() {
-        return;
+        return 0;
     }
 
     warn!(
-        "Found {} stale agents (no heartbeat for 30+ seconds)",
-        stale_agents.len()
+        "Found {} stale agents (no heartbeat since {}), probing",
+        stale_agents.len(),
+        cutoff
     );
 
-    for agent_id in stale_agents {
-        // Mark agent as error in database
-        if let Err(e) = sqlx::query!(
-            r#"
-            UPDATE agents
-            SET status = 'error'::agent_status,
-                updated_at = NOW()
-            WHERE id = $1
-            "#,
-            agent_id
-        )
-        .execute(&state.db)
-        .await
-        {
-            error!("Failed to mark agent {} as error: {}", agent_id, e);
+    // Probed concurrently, so one unresponsive agent doesn't delay the rest
+    let probes = stale_agents.into_iter().map(|agent_id| async move {
+        let alive = commands::probe(state, agent_id, STALE_PROBE_TIMEOUT).await;
+        (agent_id, alive)
+    });
+    let probed = futures_util::future::join_all(probes).await;
+
+    let mut marked = 0;
+    for (agent_id, alive) in probed {
+        if alive {
+            keep_alive(state, agent_id).await;
             continue;
         }
 
+        // Mark agent as error, unless its status changed meanwhile (e.g. terminated)
+        match lifecycle::transition(state, agent_id, AgentStatus::Error, "missed heartbeats").await
+        {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                error!("Failed to mark agent {} as error: {:#}", agent_id, e);
+                continue;
+            }
+        }
+
         // Remove from connection registry
-        state.remove_connection(&agent_id);
+        state.remove_connection(&agent_id).await;
 
         warn!(
-            "Marked agent {} as error due to missed heartbeats",
+            "Marked agent {} as error: missed heartbeats and an unanswered probe",
             agent_id
         );
+        marked += 1;
+    }
+    marked
+}
+
+/// Refresh a stale agent's `last_seen_at` after it answered a probe
+async fn keep_alive(state: &AppState, agent_id: Uuid) {
+    info!(
+        "Agent {} missed heartbeats but answered a probe, keeping it",
+        agent_id
+    );
+    if let Err(e) = sqlx::query!(
+        r#"
+        UPDATE agents
+        SET last_seen_at = NOW()
+        WHERE id = $1
+        "#,
+        agent_id
+    )
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to refresh agent {} after probe: {}", agent_id, e);
     }
 }
@@ -0,0 +1,95 @@
+//! Closing agent sessions on hub shutdown.
+//!
+//! Upgraded WebSockets outlive axum's graceful shutdown, so sessions are tracked
+//! here instead. Draining asks every session to close, waits up to a deadline,
+//! and aborts whatever is still running.
+
+use podpilot_common::config::format_duration;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+/// Outcome of [`SessionTracker::drain`]
+#[derive(Debug, Clone, Copy)]
+pub struct DrainReport {
+    /// Sessions that closed before the deadline
+    pub drained: usize,
+    /// Sessions still running at the deadline
+    pub aborted: usize,
+}
+
+/// Running agent sessions and the signal asking them to close
+#[derive(Clone)]
+pub struct SessionTracker {
+    shutdown: Arc<watch::Sender<bool>>,
+    sessions: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl SessionTracker {
+    pub fn new() -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            shutdown: Arc::new(shutdown),
+            sessions: Arc::new(Mutex::new(JoinSet::new())),
+        }
+    }
+
+    /// Run a session in the background, tracked for draining
+    pub fn spawn<F>(&self, session: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut sessions = self.sessions.lock().expect("session tracker lock poisoned");
+        // Reap finished sessions so the set only holds live ones
+        while sessions.try_join_next().is_some() {}
+        sessions.spawn(session);
+    }
+
+    /// Receiver that changes to `true` once sessions should close
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Ask every session to close; sessions started afterwards close immediately
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Ask every session to close, then wait up to `deadline` before aborting the rest
+    pub async fn drain(&self, deadline: Duration) -> DrainReport {
+        self.begin_shutdown();
+        let mut sessions =
+            std::mem::take(&mut *self.sessions.lock().expect("session tracker lock poisoned"));
+        let total = sessions.len();
+        info!(
+            sessions = total,
+            deadline = format_duration(deadline),
+            "draining agent sessions"
+        );
+
+        let mut drained = 0;
+        let _ = tokio::time::timeout(deadline, async {
+            while sessions.join_next().await.is_some() {
+                drained += 1;
+            }
+        })
+        .await;
+
+        let aborted = sessions.len();
+        if aborted > 0 {
+            warn!(aborted, "agent sessions did not close in time, aborting");
+            sessions.shutdown().await;
+        }
+
+        DrainReport { drained, aborted }
+    }
+}
+
+impl Default for SessionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
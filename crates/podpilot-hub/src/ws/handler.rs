@@ -1,62 +1,243 @@
-use axum::extract::State;
-use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::response::Response;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code};
+use axum::extract::{ConnectInfo, State};
+use axum::http::header::{SEC_WEBSOCKET_PROTOCOL, USER_AGENT};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use futures_util::{SinkExt, StreamExt};
-use podpilot_common::protocol::{AgentInfo, AgentMessage, AgentRegistration, HubMessage};
+use podpilot_common::config::AgentIdentityKey;
+use podpilot_common::protocol::{
+    AgentInfo, AgentMessage, AgentRegistration, Frame, HubMessage, ProtocolError, ProtocolVersion,
+    Readiness, WireCodec, error_code,
+};
+use podpilot_common::redact::redacted_payload;
+use podpilot_common::retry::retry_with_backoff;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
+use super::rate_limit::{ConnectionRateLimiter, RateDecision};
+use crate::agent_metrics;
+use crate::assets;
+use crate::commands;
+use crate::data::models::AgentStatus;
+use crate::data::retry::{
+    DB_RETRY_ATTEMPTS, DB_RETRY_INITIAL_BACKOFF, DB_RETRY_MAX_BACKOFF, classify, classify_anyhow,
+};
+use crate::lifecycle;
+use crate::registry::SessionRemoval;
+use crate::scheduler;
 use crate::state::AppState;
 
+/// How long to wait for the outbound task to hand back the socket for a close frame,
+/// and then for the agent to answer it
+const CLOSE_HANDOFF_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How recently a terminated agent must have been terminated to count as the
+/// predecessor of a new agent with the same identity
+const PREDECESSOR_WINDOW_DAYS: i32 = 7;
+
+/// How long a disconnected agent's jobs and commands are held for it to reconnect
+///
+/// The agent keeps running its commands across a reconnect, so releasing them right
+/// away would run a job a second time elsewhere.
+const RECONNECT_GRACE: Duration = Duration::from_secs(60);
+
 /// WebSocket upgrade handler for agent connections
+///
+/// Agents that request subprotocols must offer at least one supported
+/// [`ProtocolVersion`], otherwise the upgrade is refused with 426. Agents that
+/// predate versioning send no subprotocol and are treated as v1.
 pub async fn agent_websocket_handler(
     ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
     State(state): State<AppState>,
 ) -> Response {
-    ws.on_upgrade(|socket| handle_agent_socket(socket, state))
+    let user_agent = header_str(&headers, USER_AGENT.as_str());
+    let forwarded_for = header_str(&headers, "x-forwarded-for");
+    info!(
+        %remote_addr,
+        user_agent,
+        forwarded_for,
+        "agent WebSocket upgrade requested"
+    );
+
+    if !state.agent_source_allowed(remote_addr.ip()) {
+        warn!(%remote_addr, "rejecting agent connection from outside the allowed networks");
+        return (StatusCode::FORBIDDEN, "Source address not allowed").into_response();
+    }
+
+    let protocol = match requested_protocol(&headers) {
+        Ok(protocol) => protocol,
+        Err(offered) => {
+            warn!(offered, "rejecting agent with unsupported protocol version");
+            let supported = ProtocolVersion::SUPPORTED
+                .iter()
+                .map(|version| version.subprotocol())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return (
+                StatusCode::UPGRADE_REQUIRED,
+                [(SEC_WEBSOCKET_PROTOCOL, supported.clone())],
+                format!(
+                    "Unsupported protocol version '{}', supported: {}",
+                    offered, supported
+                ),
+            )
+                .into_response();
+        }
+    };
+
+    let max_bytes = state.limits.max_message_bytes;
+    ws.protocols([protocol.subprotocol()])
+        .max_message_size(max_bytes)
+        .max_frame_size(max_bytes)
+        .on_upgrade(move |socket| async move {
+            let sessions = state.sessions.clone();
+            sessions.spawn(handle_agent_socket(socket, state, protocol, remote_addr));
+        })
+}
+
+/// Read a header as a string, ignoring values that aren't valid ASCII
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Resolve the protocol version requested in `Sec-WebSocket-Protocol`
+///
+/// Returns the offered value when none of its versions are supported.
+fn requested_protocol(headers: &HeaderMap) -> Result<ProtocolVersion, String> {
+    let Some(offered) = headers.get(SEC_WEBSOCKET_PROTOCOL) else {
+        return Ok(ProtocolVersion::V1);
+    };
+    let offered = offered.to_str().unwrap_or_default();
+    ProtocolVersion::negotiate(offered).ok_or_else(|| offered.to_string())
+}
+
+/// Per-connection state settled during registration
+#[derive(Debug, Clone)]
+struct Session {
+    agent_id: Uuid,
+    /// Negotiated codec, shared by the inbound loop and the outbound task
+    codec: WireCodec,
+    /// Subprotocol version agreed during the upgrade
+    protocol: ProtocolVersion,
+    /// Peer address of the TCP connection, which may be a proxy rather than the agent
+    remote_addr: SocketAddr,
+    /// Readiness the agent reported in its handshake
+    readiness: Readiness,
+    /// Correlation IDs of commands the agent reported still running
+    in_flight: Vec<Uuid>,
 }
 
 /// Handle a single agent WebSocket connection
-async fn handle_agent_socket(socket: WebSocket, state: AppState) {
-    info!("New WebSocket connection from agent");
+async fn handle_agent_socket(
+    socket: WebSocket,
+    state: AppState,
+    protocol: ProtocolVersion,
+    remote_addr: SocketAddr,
+) {
+    info!(?protocol, %remote_addr, "New WebSocket connection from agent");
 
     let (mut ws_sender, mut ws_receiver) = socket.split();
 
     // Wait for registration message with timeout
-    let agent_id = match wait_for_registration(&mut ws_receiver, &mut ws_sender, &state).await {
-        Ok(id) => {
-            info!("Agent {} registered successfully", id);
-            id
+    let session = match wait_for_registration(
+        &mut ws_receiver,
+        &mut ws_sender,
+        &state,
+        protocol,
+        remote_addr,
+    )
+    .await
+    {
+        Ok(session) => {
+            info!(
+                "Agent {} registered successfully (codec: {:?}, protocol: {:?}, remote: {})",
+                session.agent_id, session.codec, session.protocol, session.remote_addr
+            );
+            record_remote_ip(&state, session.agent_id, session.remote_addr.ip()).await;
+            // The ack is sent, so the agent is registered and connected
+            if let Err(e) = lifecycle::transition(
+                &state,
+                session.agent_id,
+                AgentStatus::Ready,
+                "registration acknowledged",
+            )
+            .await
+            {
+                warn!(agent_id = %session.agent_id, error = %e, "failed to mark agent ready");
+            }
+            session
         }
         Err(e) => {
-            error!("Registration failed: {}", e);
+            // Expected by the hundred during a fleet-wide reconnect
+            if matches!(e, RegistrationError::TryLater(_)) {
+                debug!("Registration deferred: {}", e);
+            } else {
+                error!("Registration failed: {}", e);
+            }
+            // Tell the agent whether retrying can help before closing
+            if let Some(code) = e.code() {
+                let error = HubMessage::Error {
+                    message: e.to_string(),
+                    code: code.to_string(),
+                    correlation_id: None,
+                    retry_after_secs: e.retry_after_secs(),
+                };
+                if let Ok(json) = serde_json::to_string(&error) {
+                    let _ = ws_sender.send(Message::Text(json.into())).await;
+                }
+            }
             let _ = ws_sender.close().await;
             return;
         }
     };
 
-    info!("Agent {} connection established", agent_id);
+    let Session {
+        agent_id,
+        codec,
+        readiness,
+        in_flight,
+        ..
+    } = session;
+    info!(
+        "Agent {} connection established (readiness: {:?})",
+        agent_id, readiness
+    );
 
     // Create channel for sending outbound messages to this agent
     let (outbound_tx, mut outbound_rx) = mpsc::channel::<HubMessage>(32);
 
-    // Register connection in AppState
-    state.register_connection(agent_id, outbound_tx);
+    // Settled before the connection is registered, so no new command is mistaken
+    // for a lost one
+    release_lost_commands(&state, agent_id, &in_flight).await;
+
+    // Known before the connection is, so held commands can't slip through
+    state.readiness.set(agent_id, readiness);
+
+    // Register connection in AppState, under an ID that tells this session apart
+    // from a later one if the agent reconnects before this one is torn down
+    let session_id = Uuid::new_v4();
+    let dropped_by_hub = state
+        .register_connection(agent_id, session_id, outbound_tx)
+        .await;
 
     // Spawn task to handle outbound messages (Hub -> Agent)
     let mut ws_sender_task = ws_sender;
     let outbound_task = tokio::spawn(async move {
         while let Some(message) = outbound_rx.recv().await {
-            let json = match serde_json::to_string(&message) {
-                Ok(j) => j,
+            let frame = match codec.encode(&message) {
+                Ok(f) => f,
                 Err(e) => {
                     error!("Failed to serialize outbound message: {}", e);
                     continue;
                 }
             };
 
-            if let Err(e) = ws_sender_task.send(Message::Text(json.into())).await {
+            if let Err(e) = ws_sender_task.send(to_ws_message(frame)).await {
                 error!("Failed to send message to WebSocket: {}", e);
                 break;
             }
@@ -64,94 +245,599 @@ async fn handle_agent_socket(socket: WebSocket, state: AppState) {
         ws_sender_task
     });
 
-    // Handle inbound messages (Agent -> Hub)
-    while let Some(msg_result) = ws_receiver.next().await {
-        match msg_result {
-            Ok(Message::Close(_)) => {
-                info!("Agent {} closed connection", agent_id);
-                break;
+    // Handle inbound messages (Agent -> Hub) until the agent leaves or the hub shuts down
+    let mut rate_limiter = ConnectionRateLimiter::new(state.limits.messages_per_sec);
+    let mut shutdown = state.sessions.shutdown_signal();
+    let mut close_frame = None;
+    let close_reason = loop {
+        let msg_result = tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break "stream_ended",
+            },
+            _ = shutdown.wait_for(|stopping| *stopping) => {
+                info!("Closing connection to agent {} for hub shutdown", agent_id);
+                close_frame = Some(CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "hub shutting down".into(),
+                });
+                break "hub_shutdown";
             }
-            Ok(Message::Ping(_)) => {
-                // WebSocket library auto-responds to pings
+            // Terminated, marked stale, or replaced by a newer session
+            _ = dropped_by_hub.notified() => {
+                info!("Closing connection to agent {} dropped by the hub", agent_id);
+                close_frame = Some(CloseFrame {
+                    code: close_code::NORMAL,
+                    reason: "session ended by hub".into(),
+                });
+                break "dropped_by_hub";
             }
-            Ok(Message::Text(text)) => {
-                if let Err(e) = handle_agent_message(&state, agent_id, &text).await {
-                    warn!("Error handling message from agent {}: {}", agent_id, e);
-                }
+        };
+
+        let is_data = matches!(msg_result, Ok(Message::Text(_)) | Ok(Message::Binary(_)));
+        if is_data && !allow_message(&state, agent_id, &mut rate_limiter, &mut close_frame).await {
+            if close_frame.is_some() {
+                break "rate_limited";
             }
-            Ok(_) => {}
+            continue;
+        }
+
+        let decoded = match msg_result {
+            Ok(Message::Close(_)) => break "agent_closed",
+            Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => {
+                // WebSocket library auto-responds to pings
+                continue;
+            }
+            Ok(Message::Text(text)) => codec.decode_text::<AgentMessage>(&text),
+            Ok(Message::Binary(bytes)) => codec.decode_binary::<AgentMessage>(&bytes),
             Err(e) => {
                 error!("WebSocket error for agent {}: {}", agent_id, e);
+                let frame = close_frame_for(&e);
+                let reason = if frame.code == close_code::SIZE {
+                    "message_too_large"
+                } else {
+                    "ws_error"
+                };
+                close_frame = Some(frame);
+                break reason;
+            }
+        };
+
+        let result = match decoded {
+            Ok(agent_msg) => handle_agent_message(&state, agent_id, agent_msg).await,
+            Err(e) => Err(ProtocolError::from(e).into()),
+        };
+        if let Err(e) = result {
+            match e.downcast_ref::<ProtocolError>() {
+                // The agent is misbehaving rather than the hub failing, so tell it why
+                Some(violation) => {
+                    warn!("Protocol violation from agent {}: {}", agent_id, violation);
+                    let error = HubMessage::Error {
+                        message: violation.to_string(),
+                        code: violation.code().to_string(),
+                        correlation_id: None,
+                        retry_after_secs: None,
+                    };
+                    let _ = state.send_to_agent(&agent_id, error).await;
+                }
+                None => warn!("Error handling message from agent {}: {:#}", agent_id, e),
+            }
+        }
+    };
+
+    // Cleanup on disconnect
+    let removal = state.remove_session(&agent_id, session_id).await;
+    let session_duration = match removal {
+        SessionRemoval::Removed(duration) => Some(duration),
+        SessionRemoval::Gone | SessionRemoval::Superseded => None,
+    };
+    info!(
+        %agent_id,
+        reason = close_reason,
+        session_duration_secs = session_duration.map(|duration| duration.as_secs()),
+        superseded = removal == SessionRemoval::Superseded,
+        "agent disconnected"
+    );
+
+    // A newer session for this agent owns its jobs, commands, and readiness now
+    if removal != SessionRemoval::Superseded {
+        state.readiness.remove(&agent_id);
+        tokio::spawn(release_after_grace(
+            state.clone(),
+            agent_id,
+            chrono::Utc::now(),
+        ));
+    }
+
+    // Removing the connection dropped the only outbound sender, so the task ends and
+    // returns the socket (after flushing queued messages), which is used to close
+    // cleanly after a protocol error, a policy violation, or the hub dropping it
+    if let Some(frame) = close_frame {
+        let mut outbound_task = outbound_task;
+        match tokio::time::timeout(CLOSE_HANDOFF_TIMEOUT, &mut outbound_task).await {
+            Ok(Ok(mut ws_sender)) => {
+                let _ = ws_sender.send(Message::Close(Some(frame))).await;
+                await_close_reply(&mut ws_receiver).await;
+            }
+            _ => outbound_task.abort(),
+        }
+    } else {
+        outbound_task.abort();
+    }
+}
+
+/// Requeue a disconnected agent's jobs and abandon its commands, unless it
+/// reconnects within [`RECONNECT_GRACE`]
+///
+/// An agent that does come back reports the commands it is still running, and the
+/// rest are released then by [`release_lost_commands`].
+async fn release_after_grace(
+    state: AppState,
+    agent_id: Uuid,
+    disconnected_at: chrono::DateTime<chrono::Utc>,
+) {
+    tokio::time::sleep(RECONNECT_GRACE).await;
+    match reconnected_since(&state, agent_id, disconnected_at).await {
+        Ok(true) => return,
+        Ok(false) => {}
+        Err(e) => warn!(%agent_id, error = %e, "failed to check whether agent reconnected"),
+    }
+
+    // Requeue its jobs before abandoning its commands, so a job's own task finds
+    // the job requeued instead of recording the disconnect as its failure
+    scheduler::requeue_agent_jobs(&state, agent_id, None).await;
+    let abandoned = state.pending.abandon_agent(&agent_id);
+    if abandoned > 0 {
+        info!(
+            %agent_id,
+            abandoned, "agent didn't reconnect, abandoned commands awaiting a response"
+        );
+    }
+}
+
+/// Whether an agent has handshaken since `since`, on this or another hub instance
+async fn reconnected_since(
+    state: &AppState,
+    agent_id: Uuid,
+    since: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<bool> {
+    if state.connections.is_local(&agent_id) {
+        return Ok(true);
+    }
+    let mut conn = state.acquire_db("reconnected_since").await?;
+    let seen = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM agents WHERE id = $1 AND last_seen_at > $2) AS "seen!""#,
+        agent_id,
+        since
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(seen)
+}
+
+/// Give up on commands awaited from a reconnecting agent that it no longer reports
+/// running, requeueing their jobs first as on a disconnect
+///
+/// Only commands awaited on this instance are checked.
+async fn release_lost_commands(state: &AppState, agent_id: Uuid, in_flight: &[Uuid]) {
+    let lost = state.pending.lost(&agent_id, in_flight);
+    if lost.is_empty() {
+        return;
+    }
+
+    let jobs: Vec<Uuid> = lost.iter().filter_map(|(_, job_id)| *job_id).collect();
+    if !jobs.is_empty() {
+        scheduler::requeue_agent_jobs(state, agent_id, Some(&jobs)).await;
+    }
+    let correlation_ids: Vec<Uuid> = lost.iter().map(|(id, _)| *id).collect();
+    let abandoned = state.pending.abandon(&correlation_ids);
+    info!(
+        %agent_id,
+        abandoned, "agent reconnected without commands it was sent, abandoned them"
+    );
+}
+
+/// Apply the connection's rate limit to one inbound message
+///
+/// Returns whether the message should be handled. An agent that has too many
+/// messages dropped within the abuse window is sent a `rate_limited` error and
+/// `close_frame` is set.
+async fn allow_message(
+    state: &AppState,
+    agent_id: Uuid,
+    limiter: &mut ConnectionRateLimiter,
+    close_frame: &mut Option<CloseFrame>,
+) -> bool {
+    match limiter.check() {
+        RateDecision::Allow => true,
+        RateDecision::Drop { first } => {
+            if first {
+                warn!(
+                    "Agent {} exceeded {} messages/sec, dropping messages",
+                    agent_id, state.limits.messages_per_sec
+                );
+            }
+            false
+        }
+        RateDecision::Disconnect => {
+            warn!(
+                "Agent {} kept exceeding its rate limit ({} messages dropped recently), disconnecting",
+                agent_id,
+                limiter.dropped()
+            );
+            let error = HubMessage::Error {
+                message: format!(
+                    "Repeatedly exceeded {} messages/sec",
+                    state.limits.messages_per_sec
+                ),
+                code: error_code::RATE_LIMITED.to_string(),
+                correlation_id: None,
+                retry_after_secs: None,
+            };
+            let _ = state.send_to_agent(&agent_id, error).await;
+            *close_frame = Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: "rate limited".into(),
+            });
+            false
+        }
+    }
+}
+
+/// Choose the close frame sent after an inbound WebSocket error
+///
+/// axum doesn't expose the underlying tungstenite error type, so an oversized
+/// message is recognized by its error text.
+fn close_frame_for(error: &axum::Error) -> CloseFrame {
+    let message = error.to_string();
+    let code = if message.contains("Message too long") || message.contains("Frame too long") {
+        close_code::SIZE
+    } else {
+        close_code::PROTOCOL
+    };
+    CloseFrame {
+        code,
+        reason: message.chars().take(120).collect::<String>().into(),
+    }
+}
+
+/// Wait briefly for the agent to answer a close frame, completing the handshake
+async fn await_close_reply(receiver: &mut futures_util::stream::SplitStream<WebSocket>) {
+    let reply = async {
+        while let Some(Ok(msg)) = receiver.next().await {
+            if matches!(msg, Message::Close(_)) {
                 break;
             }
         }
+    };
+    let _ = tokio::time::timeout(CLOSE_HANDOFF_TIMEOUT, reply).await;
+}
+
+/// Why an agent failed to register
+#[derive(Debug, thiserror::Error)]
+enum RegistrationError {
+    /// The agent sent something other than a valid registration
+    #[error(transparent)]
+    Invalid(#[from] ProtocolError),
+    /// The hub failed to record the registration
+    #[error("{0:#}")]
+    Internal(anyhow::Error),
+    /// The connection timed out or closed before registering
+    #[error("{0:#}")]
+    Disconnected(anyhow::Error),
+    /// Too many agents are registering at once; the agent should retry after this long
+    #[error("Too many agents registering, retry in {0:?}")]
+    TryLater(Duration),
+}
+
+impl RegistrationError {
+    /// Error code to report to the agent, if it's still listening
+    fn code(&self) -> Option<&'static str> {
+        match self {
+            Self::Invalid(_) => Some(error_code::INVALID_REGISTRATION),
+            Self::Internal(_) => Some(error_code::INTERNAL),
+            Self::Disconnected(_) => None,
+            Self::TryLater(_) => Some(error_code::TRY_LATER),
+        }
     }
 
-    // Cleanup on disconnect
-    state.remove_connection(&agent_id);
-    info!("Agent {} disconnected and removed from registry", agent_id);
+    /// Backoff hint to send with the error code
+    fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            Self::TryLater(wait) => Some(whole_secs(*wait)),
+            _ => None,
+        }
+    }
+}
 
-    // Abort outbound task and retrieve sender for cleanup
-    outbound_task.abort();
+/// `duration` rounded up to whole seconds, at least one
+fn whole_secs(duration: Duration) -> u64 {
+    (duration.as_secs() + u64::from(duration.subsec_nanos() > 0)).max(1)
 }
 
 /// Wait for and process the registration message
+///
+/// An agent may first try to [`resume`](AgentMessage::Resume) its previous
+/// registration; if that's rejected, it gets one chance to register in full.
 async fn wait_for_registration(
     receiver: &mut futures_util::stream::SplitStream<WebSocket>,
     sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
     state: &AppState,
-) -> anyhow::Result<Uuid> {
+    protocol: ProtocolVersion,
+    remote_addr: SocketAddr,
+) -> Result<Session, RegistrationError> {
+    let mut agent_msg = receive_handshake_message(receiver).await?;
+
+    // Both resuming and registering hit the database, so turn excess attempts away first
+    state
+        .registration_limiter
+        .check()
+        .map_err(RegistrationError::TryLater)?;
+
+    if let AgentMessage::Resume(resume) = &agent_msg {
+        let session_token = match resume.session_token {
+            Some(token) => resume_agent(state, resume.agent_id, token)
+                .await
+                .map_err(RegistrationError::Internal)?,
+            None => None,
+        };
+        if let Some(session_token) = session_token {
+            info!("Agent {} resumed its registration", resume.agent_id);
+            let codec = WireCodec::negotiate(&resume.supported_codecs);
+            send_handshake_reply(
+                sender,
+                HubMessage::RegisterAck(AgentRegistration {
+                    correlation_id: resume.correlation_id,
+                    agent_id: resume.agent_id,
+                    registered_at: chrono::Utc::now(),
+                    hub_version: env!("CARGO_PKG_VERSION").to_string(),
+                    codec,
+                    session_token: Some(session_token),
+                }),
+            )
+            .await?;
+            return Ok(Session {
+                agent_id: resume.agent_id,
+                codec,
+                protocol,
+                remote_addr,
+                readiness: resume.readiness,
+                in_flight: resume.in_flight.clone(),
+            });
+        }
+
+        debug!(
+            "Rejected resume for agent {}, awaiting full registration",
+            resume.agent_id
+        );
+        send_handshake_reply(
+            sender,
+            HubMessage::Error {
+                message: "Agent cannot be resumed, register instead".to_string(),
+                code: error_code::RESUME_REJECTED.to_string(),
+                correlation_id: Some(resume.correlation_id),
+                retry_after_secs: None,
+            },
+        )
+        .await?;
+        agent_msg = receive_handshake_message(receiver).await?;
+    }
+
+    match agent_msg {
+        AgentMessage::Register(req) => {
+            if req.tailscale_ip != remote_addr.ip() {
+                debug!(
+                    tailscale_ip = %req.tailscale_ip,
+                    %remote_addr,
+                    "agent connected from an address other than its Tailscale IP"
+                );
+            }
+
+            // Create agent record in database, retrying transient DB failures
+            let info = &req;
+            let agent_id = retry_with_backoff(
+                DB_RETRY_ATTEMPTS,
+                DB_RETRY_INITIAL_BACKOFF,
+                DB_RETRY_MAX_BACKOFF,
+                move || async move {
+                    create_agent_record(state, info)
+                        .await
+                        .map_err(classify_anyhow)
+                },
+            )
+            .await
+            .map_err(RegistrationError::Internal)?;
+            lifecycle::record_status(state, agent_id, AgentStatus::Registering, "registered").await;
+
+            // Carried over from the previous connection so dashboards don't go blank
+            // until the first heartbeat ack; not worth failing registration over
+            if let Some(metrics) = &req.metrics {
+                state.alerts.evaluate(&state.events, agent_id, metrics);
+                if let Err(e) =
+                    agent_metrics::record_reconnect_metrics(state, agent_id, metrics).await
+                {
+                    warn!(%agent_id, error = %e, "failed to record registration metrics");
+                }
+            }
+
+            let codec = WireCodec::negotiate(&req.supported_codecs);
+
+            // Without a token the agent registers in full next time, which is fine
+            let session_token = match issue_resume_token(state, agent_id).await {
+                Ok(token) => Some(token),
+                Err(e) => {
+                    warn!(%agent_id, error = %e, "failed to issue resume token");
+                    None
+                }
+            };
+
+            // Send registration acknowledgment
+            send_handshake_reply(
+                sender,
+                HubMessage::RegisterAck(AgentRegistration {
+                    correlation_id: req.correlation_id,
+                    agent_id,
+                    registered_at: chrono::Utc::now(),
+                    hub_version: env!("CARGO_PKG_VERSION").to_string(),
+                    codec,
+                    session_token,
+                }),
+            )
+            .await?;
+
+            Ok(Session {
+                agent_id,
+                codec,
+                protocol,
+                remote_addr,
+                readiness: req.readiness,
+                in_flight: req.in_flight,
+            })
+        }
+        other => Err(ProtocolError::UnexpectedDuringHandshake {
+            received: other.kind(),
+        }
+        .into()),
+    }
+}
+
+/// Wait up to 30s for the next handshake message, which is always JSON
+async fn receive_handshake_message(
+    receiver: &mut futures_util::stream::SplitStream<WebSocket>,
+) -> Result<AgentMessage, RegistrationError> {
     use anyhow::{Context, anyhow};
-    use tokio::time::{Duration, timeout};
+    use tokio::time::timeout;
 
-    // Wait for first message with 30s timeout
     let msg_result = timeout(Duration::from_secs(30), receiver.next())
         .await
-        .context("Timeout waiting for registration")?;
+        .context("Timeout waiting for registration")
+        .map_err(RegistrationError::Disconnected)?;
 
-    let msg = msg_result.ok_or_else(|| anyhow!("Connection closed before registration"))??;
+    let msg = msg_result
+        .ok_or_else(|| anyhow!("Connection closed before registration"))
+        .and_then(|result| result.map_err(anyhow::Error::from))
+        .map_err(RegistrationError::Disconnected)?;
 
-    // Parse the registration message
+    // Registration is always JSON; the negotiated codec applies afterwards
     let text = match msg {
         Message::Text(t) => t,
-        _ => return Err(anyhow!("Expected text message for registration")),
+        _ => return Err(ProtocolError::NonTextHandshake.into()),
     };
 
-    let agent_msg: AgentMessage =
-        serde_json::from_str(&text).context("Failed to parse registration message")?;
+    Ok(WireCodec::Json
+        .decode_text(&text)
+        .map_err(ProtocolError::from)?)
+}
 
-    match agent_msg {
-        AgentMessage::Register(req) => {
-            // Create agent record in database
-            let agent_id = create_agent_record(state, &req).await?;
+/// Send a handshake reply as JSON, before any codec is in effect
+async fn send_handshake_reply(
+    sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
+    reply: HubMessage,
+) -> Result<(), RegistrationError> {
+    use anyhow::Context;
 
-            // Send registration acknowledgment
-            let response = HubMessage::RegisterAck(AgentRegistration {
-                correlation_id: req.correlation_id,
-                agent_id,
-                registered_at: chrono::Utc::now(),
-                hub_version: env!("CARGO_PKG_VERSION").to_string(),
-            });
+    let json = serde_json::to_string(&reply)
+        .context("Failed to serialize registration response")
+        .map_err(RegistrationError::Internal)?;
+    sender
+        .send(Message::Text(json.into()))
+        .await
+        .context("Failed to send registration response")
+        .map_err(RegistrationError::Disconnected)
+}
 
-            let response_json = serde_json::to_string(&response)
-                .context("Failed to serialize registration response")?;
+/// Store the address an agent connected from, alongside its reported Tailscale IP
+///
+/// Best effort: the connection proceeds even if this fails.
+async fn record_remote_ip(state: &AppState, agent_id: Uuid, remote_ip: IpAddr) {
+    let result = async {
+        let mut conn = state.acquire_db("record_remote_ip").await?;
+        sqlx::query!(
+            "UPDATE agents SET remote_ip = $2 WHERE id = $1",
+            agent_id,
+            remote_ip as _
+        )
+        .execute(&mut *conn)
+        .await?;
+        anyhow::Ok(())
+    }
+    .await;
 
-            sender
-                .send(Message::Text(response_json.into()))
-                .await
-                .context("Failed to send registration ack")?;
+    if let Err(e) = result {
+        warn!(%agent_id, error = %e, "failed to record agent remote IP");
+    }
+}
 
-            Ok(agent_id)
-        }
-        AgentMessage::HeartbeatAck(_) => {
-            Err(anyhow!("Unexpected HeartbeatAck during registration"))
-        }
+/// Re-attach an agent that registered earlier, returning a fresh resume token if
+/// it can be resumed
+///
+/// Only agents still in an active state qualify, and only with the token issued at
+/// their latest handshake; one marked errored or terminated must register in full
+/// so its record is refreshed. The token is replaced in the same keyed update, which
+/// is far cheaper than the registration upsert.
+async fn resume_agent(
+    state: &AppState,
+    agent_id: Uuid,
+    session_token: Uuid,
+) -> anyhow::Result<Option<Uuid>> {
+    use anyhow::Context;
+
+    let mut conn = state
+        .acquire_db("resume_agent")
+        .await
+        .context("Failed to acquire database connection")?;
+    let next_token = Uuid::new_v4();
+    let resumed = sqlx::query_scalar!(
+        r#"
+        UPDATE agents
+        SET last_seen_at = NOW(), resume_token = $3
+        WHERE id = $1
+          AND resume_token = $2
+          AND status IN ('registering', 'ready', 'running', 'idle')
+        RETURNING id
+        "#,
+        agent_id,
+        session_token,
+        next_token
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Failed to resume agent")?;
+
+    Ok(resumed.map(|_| next_token))
+}
+
+/// Replace an agent's resume token after a full registration
+async fn issue_resume_token(state: &AppState, agent_id: Uuid) -> anyhow::Result<Uuid> {
+    let token = Uuid::new_v4();
+    let mut conn = state.acquire_db("issue_resume_token").await?;
+    sqlx::query!(
+        "UPDATE agents SET resume_token = $2 WHERE id = $1",
+        agent_id,
+        token
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(token)
+}
+
+/// Wrap an encoded frame in the matching WebSocket message type
+fn to_ws_message(frame: Frame) -> Message {
+    match frame {
+        Frame::Text(text) => Message::Text(text.into()),
+        Frame::Binary(bytes) => Message::Binary(bytes.into()),
     }
 }
 
 /// Handle incoming agent messages
-async fn handle_agent_message(state: &AppState, agent_id: Uuid, text: &str) -> anyhow::Result<()> {
-    let agent_msg: AgentMessage = serde_json::from_str(text)?;
+async fn handle_agent_message(
+    state: &AppState,
+    agent_id: Uuid,
+    agent_msg: AgentMessage,
+) -> anyhow::Result<()> {
+    if state.log_ws_payloads {
+        trace!(%agent_id, payload = %redacted_payload(&agent_msg), "agent message");
+    }
 
     match agent_msg {
         AgentMessage::HeartbeatAck(ack) => {
@@ -160,23 +846,123 @@ async fn handle_agent_message(state: &AppState, agent_id: Uuid, text: &str) -> a
                 agent_id, ack.correlation_id
             );
 
+            // A dropped ack is harmless: the next heartbeat refreshes last_seen_at
+            let Some(_permit) = state.acquire_db_permit("heartbeat_ack").await else {
+                return Ok(());
+            };
+
             // Update last_seen_at in database
-            sqlx::query!(
-                r#"
-                UPDATE agents
-                SET last_seen_at = NOW()
-                WHERE id = $1
-                "#,
-                agent_id
+            retry_with_backoff(
+                DB_RETRY_ATTEMPTS,
+                DB_RETRY_INITIAL_BACKOFF,
+                DB_RETRY_MAX_BACKOFF,
+                move || async move {
+                    let mut conn = state.acquire_db("heartbeat_ack").await.map_err(classify)?;
+                    sqlx::query!(
+                        r#"
+                        UPDATE agents
+                        SET last_seen_at = NOW()
+                        WHERE id = $1
+                        "#,
+                        agent_id
+                    )
+                    .execute(&mut *conn)
+                    .await
+                    .map_err(classify)
+                },
             )
-            .execute(&state.db)
             .await?;
+
+            // Most acks carry no metrics; the agent only attaches changed or stale ones
+            if let Some(metrics) = &ack.metrics {
+                state.alerts.evaluate(&state.events, agent_id, metrics);
+                agent_metrics::record_metrics(state, agent_id, metrics).await?;
+            }
         }
-        AgentMessage::Register(_) => {
-            warn!(
-                "Received unexpected Register message from already-registered agent {}",
-                agent_id
+        AgentMessage::CommandResponse(resp) => {
+            debug!(
+                "Received command response from agent {} (correlation: {})",
+                agent_id, resp.correlation_id
+            );
+
+            // Unlike heartbeat acks, responses are not retried by the agent, so wait
+            // for a permit rather than shedding
+            let _permit = state.wait_db_permit().await;
+            commands::record_response(state, agent_id, resp).await?;
+        }
+        AgentMessage::AssetCreated(asset) => {
+            debug!("Received asset {} from agent {}", asset.r2_key, agent_id);
+
+            if !assets::is_agent_asset_key(agent_id, &asset.r2_key) {
+                warn!(
+                    "Rejected asset {} from agent {}: key outside the agent's upload prefix",
+                    asset.r2_key, agent_id
+                );
+                let error = HubMessage::Error {
+                    message: format!("Asset key '{}' was not issued to this agent", asset.r2_key),
+                    code: error_code::INVALID_UPLOAD.to_string(),
+                    correlation_id: None,
+                    retry_after_secs: None,
+                };
+                state.send_to_agent(&agent_id, error).await?;
+                return Ok(());
+            }
+
+            if !assets::content_type_allowed(&asset.content_type, state.allow_any_content_type) {
+                warn!(
+                    "Rejected asset {} from agent {}: unsupported content type '{}'",
+                    asset.r2_key, agent_id, asset.content_type
+                );
+                let error = HubMessage::Error {
+                    message: format!("Unsupported asset content type '{}'", asset.content_type),
+                    code: error_code::UNSUPPORTED_CONTENT_TYPE.to_string(),
+                    correlation_id: None,
+                    retry_after_secs: None,
+                };
+                state.send_to_agent(&agent_id, error).await?;
+                return Ok(());
+            }
+
+            // Assets aren't re-reported, so wait rather than shed
+            let _permit = state.wait_db_permit().await;
+            assets::record_asset(state, agent_id, &asset).await?;
+        }
+        AgentMessage::RequestUploadUrl(request) => {
+            debug!(
+                "Agent {} requested an upload URL for {} (correlation: {})",
+                agent_id, request.filename, request.correlation_id
             );
+
+            let reply = match assets::issue_upload_url(state, agent_id, &request) {
+                Ok(upload) => HubMessage::UploadUrl(upload),
+                Err(e) => {
+                    warn!("Refused upload URL for agent {}: {}", agent_id, e);
+                    HubMessage::Error {
+                        message: e.to_string(),
+                        code: e.code().to_string(),
+                        correlation_id: Some(request.correlation_id),
+                        retry_after_secs: None,
+                    }
+                }
+            };
+            state.send_to_agent(&agent_id, reply).await?;
+        }
+        AgentMessage::Readiness(update) => {
+            info!(
+                "Agent {} reported readiness {:?}",
+                agent_id, update.readiness
+            );
+            state.readiness.set(agent_id, update.readiness);
+            if update.readiness == Readiness::Ready {
+                // Jobs may have been waiting on this agent
+                state.scheduler.wake();
+            }
+        }
+        handshake @ (AgentMessage::Register(_) | AgentMessage::Resume(_)) => {
+            return Err(ProtocolError::UnexpectedMessage {
+                received: handshake.kind(),
+            }
+            .into());
         }
     }
 
@@ -185,8 +971,20 @@ async fn handle_agent_message(state: &AppState, agent_id: Uuid, text: &str) -> a
 
 /// Create or update agent record in the database
 ///
-/// Checks for an existing agent with the same (tailscale_ip, provider_instance_id).
-/// If found, reuses the existing record and updates its status. Otherwise, creates a new agent.
+/// If the agent sent an `agent_id_hint` for a live agent of the same provider, that
+/// row is reused and takes on the reported identity (see [`reuse_hinted_agent`]).
+///
+/// Otherwise, upserts on the agent identity (tailscale_ip, provider_instance_id): if
+/// a non-terminated agent with the same identity exists, its record is reused and
+/// its status, hostname, GPU info, agent version, and boot diagnostics are refreshed.
+/// Otherwise, a new agent is created. With `AGENT_IDENTITY_KEY` set to match on only
+/// one of the two, [`upsert_by_partial_identity`] is used instead.
+///
+/// This is a single `INSERT ... ON CONFLICT` statement against the partial unique
+/// index `idx_agent_identity`, so concurrent reconnects for the same identity cannot
+/// both pass an existence check and create duplicate rows. Terminated agents are
+/// outside the index, so a reused identity gets a fresh row and the terminated one
+/// is kept as history.
 async fn create_agent_record(state: &AppState, req: &AgentInfo) -> anyhow::Result<Uuid> {
     use crate::data::models::ProviderType as HubProviderType;
     use anyhow::Context;
@@ -201,66 +999,317 @@ async fn create_agent_record(state: &AppState, req: &AgentInfo) -> anyhow::Resul
     let gpu_info_json =
         serde_json::to_value(&req.gpu_info).context("Failed to serialize GPU info")?;
 
-    // Check for existing agent by (tailscale_ip, provider_instance_id)
-    let existing_agent = sqlx::query_scalar!(
+    if let Some(hint) = req.agent_id_hint {
+        if let Some(agent_id) =
+            reuse_hinted_agent(state, hint, provider, req, &gpu_info_json).await?
+        {
+            info!("Reusing hinted agent record: {}", agent_id);
+            return Ok(agent_id);
+        }
+        debug!(%hint, "agent ID hint not reusable, falling back to identity upsert");
+    }
+
+    if state.identity_key != AgentIdentityKey::IpAndInstance {
+        return upsert_by_partial_identity(state, provider, req, &gpu_info_json).await;
+    }
+
+    // The conflict target's WHERE clause must match idx_agent_identity's predicate.
+    // `xmax = 0` only holds for freshly inserted rows, distinguishing insert from update.
+    let mut conn = state
+        .acquire_db("register_agent")
+        .await
+        .context("Failed to acquire database connection")?;
+    let record = sqlx::query!(
         r#"
-        SELECT id FROM agents
-        WHERE tailscale_ip = $1
-          AND provider_instance_id = $2
-          AND terminated_at IS NULL
+        INSERT INTO agents (
+            provider, provider_instance_id, hostname, display_name, status, tailscale_ip,
+            gpu_info, agent_version, boot_diagnostics, capabilities, registered_at, last_seen_at
+        )
+        VALUES ($1, $2, $3, $9, 'registering'::agent_status, $4, $5, $6, $7, $8, NOW(), NOW())
+        ON CONFLICT (tailscale_ip, provider_instance_id)
+            WHERE terminated_at IS NULL
+              AND tailscale_ip IS NOT NULL
+              AND provider_instance_id IS NOT NULL
+        DO UPDATE SET
+            status = 'registering'::agent_status,
+            hostname = EXCLUDED.hostname,
+            display_name = EXCLUDED.display_name,
+            gpu_info = EXCLUDED.gpu_info,
+            agent_version = EXCLUDED.agent_version,
+            boot_diagnostics = EXCLUDED.boot_diagnostics,
+            capabilities = EXCLUDED.capabilities,
+            last_seen_at = NOW(),
+            updated_at = NOW()
+        RETURNING id, (xmax = 0) AS "inserted!"
         "#,
+        provider as _,
+        &req.provider_instance_id,
+        &req.hostname,
         req.tailscale_ip as _,
-        &req.provider_instance_id
+        gpu_info_json,
+        &req.agent_version,
+        req.diagnostics.as_ref(),
+        req.capabilities.as_deref(),
+        req.display_name.as_deref()
     )
-    .fetch_optional(&state.db)
+    .fetch_one(&mut *conn)
     .await
-    .context("Failed to query for existing agent")?;
+    .context("Failed to upsert agent record")?;
 
-    if let Some(agent_id) = existing_agent {
-        // Reuse existing agent - update status, hostname, and timestamp
-        info!("Reusing existing agent record: {}", agent_id);
+    if record.inserted {
+        info!("Created new agent record: {}", record.id);
+        drop(conn);
+        link_terminated_predecessor(state, record.id, req).await;
+    } else {
+        info!("Reusing existing agent record: {}", record.id);
+    }
 
-        sqlx::query!(
-            r#"
-            UPDATE agents
-            SET status = 'registering'::agent_status,
-                hostname = $2,
-                gpu_info = $3,
-                last_seen_at = NOW()
-            WHERE id = $1
-            "#,
-            agent_id,
-            &req.hostname,
-            gpu_info_json
+    Ok(record.id)
+}
+
+/// Reuse the live agent matching one identity field, or create one, returning its ID
+///
+/// Matches on the Tailscale IP for [`AgentIdentityKey::IpOnly`] and on the provider
+/// instance ID otherwise. No unique index covers a single field, so registrations
+/// for the same key are serialized with a transaction-scoped advisory lock instead.
+/// If several live agents match (e.g. after switching modes), the one already
+/// holding the full reported identity wins, then the most recently seen.
+async fn upsert_by_partial_identity(
+    state: &AppState,
+    provider: crate::data::models::ProviderType,
+    req: &AgentInfo,
+    gpu_info_json: &serde_json::Value,
+) -> anyhow::Result<Uuid> {
+    use anyhow::Context;
+
+    let by_ip = state.identity_key == AgentIdentityKey::IpOnly;
+    let lock_key = if by_ip {
+        format!("agent-identity:ip:{}", req.tailscale_ip)
+    } else {
+        format!("agent-identity:instance:{}", req.provider_instance_id)
+    };
+
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .context("Failed to start registration transaction")?;
+    sqlx::query_scalar!(
+        r#"SELECT TRUE AS "locked!" FROM pg_advisory_xact_lock(hashtextextended($1, 0))"#,
+        lock_key
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .context("Failed to lock agent identity")?;
+
+    let reused = sqlx::query_scalar!(
+        r#"
+        UPDATE agents
+        SET status = 'registering'::agent_status,
+            provider = $2,
+            provider_instance_id = $3,
+            hostname = $4,
+            tailscale_ip = $5,
+            gpu_info = $6,
+            agent_version = $7,
+            boot_diagnostics = $8,
+            capabilities = $9,
+            display_name = $10,
+            last_seen_at = NOW(),
+            updated_at = NOW()
+        WHERE id = (
+            SELECT id FROM agents
+            WHERE terminated_at IS NULL
+              AND CASE WHEN $1 THEN tailscale_ip = $5 ELSE provider_instance_id = $3 END
+            ORDER BY (tailscale_ip = $5 AND provider_instance_id = $3) DESC,
+                     last_seen_at DESC NULLS LAST
+            LIMIT 1
         )
-        .execute(&state.db)
+        RETURNING id
+        "#,
+        by_ip,
+        provider as _,
+        &req.provider_instance_id,
+        &req.hostname,
+        req.tailscale_ip as _,
+        gpu_info_json,
+        &req.agent_version,
+        req.diagnostics.as_ref(),
+        req.capabilities.as_deref(),
+        req.display_name.as_deref()
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to reuse agent record")?;
+
+    let (agent_id, inserted) = match reused {
+        Some(agent_id) => {
+            info!("Reusing existing agent record: {}", agent_id);
+            (agent_id, false)
+        }
+        None => {
+            let agent_id = sqlx::query_scalar!(
+                r#"
+                INSERT INTO agents (
+                    provider, provider_instance_id, hostname, display_name, status,
+                    tailscale_ip, gpu_info, agent_version, boot_diagnostics, capabilities,
+                    registered_at, last_seen_at
+                )
+                VALUES ($1, $2, $3, $9, 'registering'::agent_status, $4, $5, $6, $7, $8,
+                        NOW(), NOW())
+                RETURNING id
+                "#,
+                provider as _,
+                &req.provider_instance_id,
+                &req.hostname,
+                req.tailscale_ip as _,
+                gpu_info_json,
+                &req.agent_version,
+                req.diagnostics.as_ref(),
+                req.capabilities.as_deref(),
+                req.display_name.as_deref()
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to create agent record")?;
+            info!("Created new agent record: {}", agent_id);
+            (agent_id, true)
+        }
+    };
+
+    tx.commit()
         .await
-        .context("Failed to update existing agent record")?;
+        .context("Failed to commit registration transaction")?;
+    if inserted {
+        link_terminated_predecessor(state, agent_id, req).await;
+    }
+    Ok(agent_id)
+}
 
-        Ok(agent_id)
-    } else {
-        // Create new agent
-        info!("Creating new agent record");
+/// Point a newly created agent at the most recent agent with the same identity
+/// terminated within [`PREDECESSOR_WINDOW_DAYS`], e.g. when a provider recycles an
+/// instance
+///
+/// Identity follows [`AppState::identity_key`], like the registration upsert. Only
+/// lineage is lost on failure, so errors are logged rather than failing registration.
+async fn link_terminated_predecessor(state: &AppState, agent_id: Uuid, req: &AgentInfo) {
+    let match_ip = state.identity_key != AgentIdentityKey::InstanceOnly;
+    let match_instance = state.identity_key != AgentIdentityKey::IpOnly;
 
-        let agent_id = sqlx::query_scalar!(
+    let result = async {
+        let mut conn = state.acquire_db("link_terminated_predecessor").await?;
+        let previous = sqlx::query_scalar!(
             r#"
-            INSERT INTO agents (
-                provider, provider_instance_id, hostname, status, tailscale_ip, gpu_info,
-                registered_at, last_seen_at
+            UPDATE agents
+            SET previous_agent_id = (
+                SELECT id FROM agents previous
+                WHERE previous.terminated_at IS NOT NULL
+                  AND previous.terminated_at > NOW() - make_interval(days => $2)
+                  AND previous.id <> $1
+                  AND (NOT $3 OR previous.tailscale_ip = $5)
+                  AND (NOT $4 OR previous.provider_instance_id = $6)
+                ORDER BY previous.terminated_at DESC
+                LIMIT 1
             )
-            VALUES ($1, $2, $3, 'registering'::agent_status, $4, $5, NOW(), NOW())
-            RETURNING id
+            WHERE id = $1
+            RETURNING previous_agent_id
             "#,
-            provider as _,
-            &req.provider_instance_id,
-            &req.hostname,
+            agent_id,
+            PREDECESSOR_WINDOW_DAYS,
+            match_ip,
+            match_instance,
             req.tailscale_ip as _,
-            gpu_info_json
+            &req.provider_instance_id
         )
-        .fetch_one(&state.db)
+        .fetch_optional(&mut *conn)
+        .await?;
+        anyhow::Ok(previous.flatten())
+    }
+    .await;
+
+    match result {
+        Ok(Some(previous_agent_id)) => info!(
+            %agent_id,
+            %previous_agent_id,
+            "agent replaces a terminated agent with the same identity"
+        ),
+        Ok(None) => {}
+        Err(e) => warn!(%agent_id, error = %e, "failed to link terminated predecessor"),
+    }
+}
+
+/// Refresh the hinted agent's row with the reported identity, returning its ID
+///
+/// The hint is ignored (returns `None`) if the agent doesn't exist, was terminated,
+/// belongs to another provider, is connected to this instance (so the hint was
+/// copied or guessed), or if another live agent already holds the reported
+/// (tailscale_ip, provider_instance_id), which `idx_agent_identity` would reject.
+/// A concurrent registration can still claim that identity between the check and the
+/// update; the resulting violation is treated the same way.
+async fn reuse_hinted_agent(
+    state: &AppState,
+    hint: Uuid,
+    provider: crate::data::models::ProviderType,
+    req: &AgentInfo,
+    gpu_info_json: &serde_json::Value,
+) -> anyhow::Result<Option<Uuid>> {
+    use anyhow::Context;
+
+    if state.connections.is_local(&hint) {
+        debug!(%hint, "hinted agent is connected, not reusing its record");
+        return Ok(None);
+    }
+
+    let mut conn = state
+        .acquire_db("register_agent")
         .await
-        .context("Failed to create agent record")?;
+        .context("Failed to acquire database connection")?;
+    let result = sqlx::query_scalar!(
+        r#"
+        UPDATE agents
+        SET status = 'registering'::agent_status,
+            provider_instance_id = $3,
+            hostname = $4,
+            tailscale_ip = $5,
+            gpu_info = $6,
+            agent_version = $7,
+            boot_diagnostics = $8,
+            capabilities = $9,
+            display_name = $10,
+            last_seen_at = NOW(),
+            updated_at = NOW()
+        WHERE id = $1
+          AND provider = $2
+          AND terminated_at IS NULL
+          AND NOT EXISTS (
+              SELECT 1 FROM agents other
+              WHERE other.id <> $1
+                AND other.terminated_at IS NULL
+                AND other.tailscale_ip = $5
+                AND other.provider_instance_id = $3
+          )
+        RETURNING id
+        "#,
+        hint,
+        provider as _,
+        &req.provider_instance_id,
+        &req.hostname,
+        req.tailscale_ip as _,
+        gpu_info_json,
+        &req.agent_version,
+        req.diagnostics.as_ref(),
+        req.capabilities.as_deref(),
+        req.display_name.as_deref()
+    )
+    .fetch_optional(&mut *conn)
+    .await;
 
-        Ok(agent_id)
+    match result {
+        Ok(agent_id) => Ok(agent_id),
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("idx_agent_identity") => {
+            debug!(%hint, "reported identity was claimed concurrently, not reusing hint");
+            Ok(None)
+        }
+        Err(e) => Err(e).context("Failed to reuse hinted agent record"),
     }
 }
@@ -1,8 +1,10 @@
 use chrono::Utc;
+use podpilot_common::config::format_duration;
 use podpilot_common::protocol::{HeartbeatMessage, HubMessage};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::task::{JoinError, JoinSet};
 use tokio::time::{Duration, interval};
 use tracing::{debug, error, info};
 use uuid::Uuid;
@@ -10,16 +12,24 @@ use uuid::Uuid;
 use crate::state::AppState;
 
 /// Heartbeat sender task that periodically sends heartbeat pings to all connected agents
-pub async fn heartbeat_sender_task(state: AppState, shutdown: Arc<AtomicBool>) {
-    info!("Starting heartbeat sender task");
+pub async fn heartbeat_sender_task(
+    state: AppState,
+    period: Duration,
+    concurrency: usize,
+    shutdown: Arc<AtomicBool>,
+) {
+    info!(
+        interval = format_duration(period),
+        "Starting heartbeat sender task"
+    );
 
-    let mut tick_interval = interval(Duration::from_secs(10));
+    let mut tick_interval = interval(period);
     let mut sequence_map: HashMap<Uuid, u64> = HashMap::new();
 
     loop {
         tokio::select! {
             _ = tick_interval.tick() => {
-                send_heartbeats(&state, &mut sequence_map).await;
+                send_heartbeats(&state, &mut sequence_map, concurrency).await;
             }
             _ = tokio::signal::ctrl_c() => {
                 info!("Heartbeat sender received shutdown signal");
@@ -38,8 +48,15 @@ pub async fn heartbeat_sender_task(state: AppState, shutdown: Arc<AtomicBool>) {
     info!("Heartbeat sender task stopped");
 }
 
-/// Send heartbeat pings to all connected agents
-async fn send_heartbeats(state: &AppState, sequence_map: &mut HashMap<Uuid, u64>) {
+/// Send heartbeat pings to all connected agents, at most `concurrency` at a time
+///
+/// Sequence numbers are assigned before dispatch, so each agent's heartbeats stay
+/// numbered in order even though sends complete out of order.
+async fn send_heartbeats(
+    state: &AppState,
+    sequence_map: &mut HashMap<Uuid, u64>,
+    concurrency: usize,
+) {
     let connected_agents = state.connected_agents();
 
     if connected_agents.is_empty() {
@@ -49,6 +66,7 @@ async fn send_heartbeats(state: &AppState, sequence_map: &mut HashMap<Uuid, u64>
 
     debug!("Sending heartbeats to {} agents", connected_agents.len());
 
+    let mut sends = JoinSet::new();
     for agent_id in connected_agents {
         // Get or initialize sequence number for this agent
         let sequence = sequence_map.entry(agent_id).or_insert(0);
@@ -60,10 +78,36 @@ async fn send_heartbeats(state: &AppState, sequence_map: &mut HashMap<Uuid, u64>
             sequence: *sequence,
         });
 
-        if let Err(e) = state.send_to_agent(&agent_id, heartbeat).await {
+        if sends.len() >= concurrency.max(1)
+            && let Some(result) = sends.join_next().await
+        {
+            record_send(result, sequence_map);
+        }
+
+        let state = state.clone();
+        sends.spawn(async move {
+            let result = state.send_to_agent(&agent_id, heartbeat).await;
+            (agent_id, result)
+        });
+    }
+
+    while let Some(result) = sends.join_next().await {
+        record_send(result, sequence_map);
+    }
+}
+
+/// Log a failed heartbeat send and forget the agent's sequence
+fn record_send(
+    result: Result<(Uuid, anyhow::Result<()>), JoinError>,
+    sequence_map: &mut HashMap<Uuid, u64>,
+) {
+    match result {
+        Ok((_, Ok(()))) => {}
+        Ok((agent_id, Err(e))) => {
             error!("Failed to send heartbeat to agent {}: {}", agent_id, e);
             // Remove sequence entry for disconnected agents
             sequence_map.remove(&agent_id);
         }
+        Err(e) => error!("Heartbeat send task failed: {}", e),
     }
 }
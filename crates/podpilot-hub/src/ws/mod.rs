@@ -1,7 +1,11 @@
 mod cleanup;
+mod drain;
 mod handler;
 mod heartbeat;
+mod rate_limit;
 
-pub use cleanup::cleanup_task;
+pub use cleanup::{cleanup_stale_agents, cleanup_task, stale_cutoff};
+pub use drain::{DrainReport, SessionTracker};
 pub use handler::agent_websocket_handler;
 pub use heartbeat::heartbeat_sender_task;
+pub use rate_limit::RegistrationLimiter;
@@ -0,0 +1,116 @@
+//! Inbound message and registration rate limiting.
+
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Rolling window over which an agent's dropped messages are counted
+const ABUSE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Seconds' worth of the limit an agent may have dropped within [`ABUSE_WINDOW`]
+/// before being disconnected
+const ABUSE_WINDOW_DROP_SECS: u32 = 5;
+
+/// Outcome of checking one inbound message against the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateDecision {
+    /// Within the limit; handle the message
+    Allow,
+    /// Over the limit; drop the message. `first` is set for the first drop of a burst.
+    Drop { first: bool },
+    /// Too many messages dropped within the abuse window; close the connection
+    Disconnect,
+}
+
+/// Token bucket for a single agent connection
+pub struct ConnectionRateLimiter {
+    limiter: Option<DefaultDirectRateLimiter>,
+    /// When each message dropped within the abuse window arrived, oldest first
+    ///
+    /// Allowed messages don't clear it, so an agent hovering just over the limit
+    /// still accumulates drops.
+    drops: VecDeque<Instant>,
+    /// Drops within the abuse window that get the connection closed
+    max_drops: usize,
+}
+
+impl ConnectionRateLimiter {
+    /// Create a limiter allowing `per_sec` messages per second (0 disables limiting)
+    pub fn new(per_sec: u32) -> Self {
+        Self {
+            limiter: NonZeroU32::new(per_sec)
+                .map(|rate| RateLimiter::direct(Quota::per_second(rate))),
+            drops: VecDeque::new(),
+            max_drops: per_sec.saturating_mul(ABUSE_WINDOW_DROP_SECS) as usize,
+        }
+    }
+
+    /// Messages dropped within the abuse window
+    pub fn dropped(&self) -> usize {
+        self.drops.len()
+    }
+
+    /// Take a token for one inbound message
+    pub fn check(&mut self) -> RateDecision {
+        let Some(limiter) = &self.limiter else {
+            return RateDecision::Allow;
+        };
+
+        if limiter.check().is_ok() {
+            return RateDecision::Allow;
+        }
+
+        let now = Instant::now();
+        while self
+            .drops
+            .front()
+            .is_some_and(|&dropped_at| now.duration_since(dropped_at) >= ABUSE_WINDOW)
+        {
+            self.drops.pop_front();
+        }
+
+        let first = self.drops.is_empty();
+        self.drops.push_back(now);
+        if self.drops.len() > self.max_drops {
+            RateDecision::Disconnect
+        } else {
+            RateDecision::Drop { first }
+        }
+    }
+}
+
+/// Token bucket for registration attempts, shared by every connection to this hub
+///
+/// Registering costs several database round trips, so this keeps a fleet-wide
+/// reconnect (e.g. after a hub restart) from swamping the database.
+#[derive(Clone, Default)]
+pub struct RegistrationLimiter {
+    limiter: Option<Arc<DefaultDirectRateLimiter>>,
+}
+
+impl RegistrationLimiter {
+    /// Allow `per_sec` registrations per second in bursts of up to `burst`
+    /// (0 disables limiting; a zero burst defaults to `per_sec`)
+    pub fn new(per_sec: u32, burst: u32) -> Self {
+        let limiter = NonZeroU32::new(per_sec).map(|rate| {
+            let burst = NonZeroU32::new(burst).unwrap_or(rate);
+            Arc::new(RateLimiter::direct(
+                Quota::per_second(rate).allow_burst(burst),
+            ))
+        });
+        Self { limiter }
+    }
+
+    /// Take a token for one registration, or return how long until one is available
+    pub fn check(&self) -> Result<(), Duration> {
+        let Some(limiter) = &self.limiter else {
+            return Ok(());
+        };
+        limiter
+            .check()
+            .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+    }
+}
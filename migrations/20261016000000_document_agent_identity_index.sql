@@ -0,0 +1,5 @@
+-- Document the agent identity constraint relied on by registration's ON CONFLICT upsert.
+-- The index itself was created in 20251117000000_add_agent_tailscale_ip.sql.
+
+COMMENT ON INDEX idx_agent_identity IS
+    'One active agent per (tailscale_ip, provider_instance_id). Terminated agents are excluded so their history is preserved when an identity is reused; registration upserts against this index.';
@@ -0,0 +1,29 @@
+-- Create command_log table for auditing commands sent to agents
+
+-- Lifecycle of a dispatched command
+CREATE TYPE command_status AS ENUM (
+    'pending',
+    'succeeded',
+    'failed',
+    'timed_out'
+);
+
+CREATE TABLE command_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    agent_id UUID NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    correlation_id UUID NOT NULL UNIQUE,
+    command JSONB NOT NULL,
+    status command_status NOT NULL DEFAULT 'pending',
+    response JSONB,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+
+-- Index for listing an agent's command history, newest first
+CREATE INDEX idx_command_log_agent_sent ON command_log (agent_id, sent_at DESC);
+
+-- Comment on table
+COMMENT ON TABLE command_log IS 'Audit trail of commands dispatched to agents and their results';
+COMMENT ON COLUMN command_log.correlation_id IS 'Correlation ID shared by the command and its response message';
+COMMENT ON COLUMN command_log.response IS 'CommandResponse returned by the agent (or a hub-side failure)';
+COMMENT ON COLUMN command_log.completed_at IS 'When the response arrived or the command was abandoned';
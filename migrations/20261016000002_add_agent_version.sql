@@ -0,0 +1,5 @@
+-- Track the agent binary version reported at registration
+
+ALTER TABLE agents ADD COLUMN IF NOT EXISTS agent_version TEXT;
+
+COMMENT ON COLUMN agents.agent_version IS 'podpilot-agent version reported at the latest registration';
@@ -0,0 +1,5 @@
+-- Store the boot diagnostics an agent reports at registration
+
+ALTER TABLE agents ADD COLUMN IF NOT EXISTS boot_diagnostics JSONB;
+
+COMMENT ON COLUMN agents.boot_diagnostics IS 'Startup conditions (GPU probe, workdir, provider) reported at the latest registration';
@@ -0,0 +1,15 @@
+-- Create hub_network_events table recording the hub's Tailscale IP changes
+
+CREATE TABLE hub_network_events (
+    id BIGSERIAL PRIMARY KEY,
+    previous_ip INET,
+    ip INET NOT NULL,
+    observed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Index for reading the most recent changes
+CREATE INDEX idx_hub_network_events_observed ON hub_network_events (observed_at DESC);
+
+-- Comment on table
+COMMENT ON TABLE hub_network_events IS 'History of the hub''s Tailscale IP, one row per observed change';
+COMMENT ON COLUMN hub_network_events.previous_ip IS 'IP recorded by the preceding event (NULL for the first observation)';
@@ -0,0 +1,17 @@
+-- Create agent_events table, an append-only history of agent status changes
+
+CREATE TABLE agent_events (
+    id BIGSERIAL PRIMARY KEY,
+    agent_id UUID NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    status agent_status NOT NULL,
+    reason TEXT NOT NULL,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Index for reading an agent's recent history
+CREATE INDEX idx_agent_events_agent_occurred ON agent_events (agent_id, occurred_at DESC);
+
+-- Comment on table
+COMMENT ON TABLE agent_events IS 'Agent lifecycle history, one row per status change';
+COMMENT ON COLUMN agent_events.status IS 'Status the agent moved to';
+COMMENT ON COLUMN agent_events.reason IS 'Why the status changed (e.g. registered, missed heartbeats)';
@@ -0,0 +1,24 @@
+-- Create agent_metrics table, holding metrics samples attached to heartbeat acks
+
+CREATE TABLE agent_metrics (
+    id BIGSERIAL PRIMARY KEY,
+    agent_id UUID NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    gpu_memory_used BIGINT NOT NULL,
+    gpu_memory_total BIGINT NOT NULL,
+    gpu_utilization SMALLINT NOT NULL,
+    gpu_temperature SMALLINT,
+    disk_used BIGINT NOT NULL,
+    disk_total BIGINT NOT NULL,
+    memory_used BIGINT NOT NULL,
+    memory_total BIGINT NOT NULL,
+    collected_at TIMESTAMPTZ NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Index for reading an agent's recent samples
+CREATE INDEX idx_agent_metrics_agent_collected ON agent_metrics (agent_id, collected_at DESC);
+
+-- Comment on table
+COMMENT ON TABLE agent_metrics IS 'Agent metrics samples; agents only send a sample when it changed meaningfully or went stale';
+COMMENT ON COLUMN agent_metrics.collected_at IS 'When the agent sampled the metrics';
+COMMENT ON COLUMN agent_metrics.received_at IS 'When the hub recorded the sample';
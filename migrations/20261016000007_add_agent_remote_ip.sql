@@ -0,0 +1,5 @@
+-- Store the peer address an agent's WebSocket connection was observed from
+
+ALTER TABLE agents ADD COLUMN IF NOT EXISTS remote_ip INET;
+
+COMMENT ON COLUMN agents.remote_ip IS 'Source address of the latest connection as seen by the hub, which may differ from tailscale_ip';
@@ -0,0 +1,5 @@
+-- Store the optional features an agent advertises at registration
+
+ALTER TABLE agents ADD COLUMN IF NOT EXISTS capabilities TEXT[];
+
+COMMENT ON COLUMN agents.capabilities IS 'Features advertised at the latest registration (e.g. webui, model_download); NULL for agents that predate advertisement, which are assumed to support everything';
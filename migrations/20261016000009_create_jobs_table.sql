@@ -0,0 +1,35 @@
+-- Create jobs table for work scheduled onto agents
+
+-- Lifecycle of a scheduled job
+CREATE TYPE job_status AS ENUM (
+    'queued',
+    'assigned',
+    'running',
+    'completed',
+    'failed'
+);
+
+CREATE TABLE jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    model_id UUID NOT NULL REFERENCES models(id),
+    params JSONB NOT NULL DEFAULT '{}'::jsonb,
+    status job_status NOT NULL DEFAULT 'queued',
+    assigned_agent_id UUID REFERENCES agents(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+    result JSONB
+);
+
+-- Index for taking queued jobs in FIFO order
+CREATE INDEX idx_jobs_queued ON jobs (created_at) WHERE status = 'queued';
+
+-- Index for finding the jobs an agent is working on
+CREATE INDEX idx_jobs_assigned_agent ON jobs (assigned_agent_id)
+    WHERE status IN ('assigned', 'running');
+
+-- Comment on table
+COMMENT ON TABLE jobs IS 'Work scheduled onto agents, from submission to completion';
+COMMENT ON COLUMN jobs.params IS 'Parameters passed through to the agent''s job runner';
+COMMENT ON COLUMN jobs.assigned_agent_id IS 'Agent the job was last assigned to';
+COMMENT ON COLUMN jobs.result IS 'Job output on success, or the error on failure';
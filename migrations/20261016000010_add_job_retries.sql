@@ -0,0 +1,5 @@
+-- Count how often a job was put back in the queue after losing its agent
+
+ALTER TABLE jobs ADD COLUMN retries INTEGER NOT NULL DEFAULT 0;
+
+COMMENT ON COLUMN jobs.retries IS 'Times the job was requeued after its agent disconnected or its hub was lost';
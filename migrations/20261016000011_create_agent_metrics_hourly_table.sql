@@ -0,0 +1,27 @@
+-- Create agent_metrics_hourly table, holding hourly rollups of expired metrics samples
+
+CREATE TABLE agent_metrics_hourly (
+    agent_id UUID NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    hour TIMESTAMPTZ NOT NULL,
+    samples INTEGER NOT NULL,
+    gpu_utilization_avg DOUBLE PRECISION NOT NULL,
+    gpu_utilization_max SMALLINT NOT NULL,
+    gpu_temperature_max SMALLINT,
+    gpu_memory_used_avg BIGINT NOT NULL,
+    gpu_memory_used_max BIGINT NOT NULL,
+    gpu_memory_total BIGINT NOT NULL,
+    disk_used_max BIGINT NOT NULL,
+    disk_total BIGINT NOT NULL,
+    memory_used_avg BIGINT NOT NULL,
+    memory_used_max BIGINT NOT NULL,
+    memory_total BIGINT NOT NULL,
+    PRIMARY KEY (agent_id, hour)
+);
+
+-- Index for expiring raw samples by age across all agents
+CREATE INDEX idx_agent_metrics_collected ON agent_metrics (collected_at);
+
+-- Comment on table
+COMMENT ON TABLE agent_metrics_hourly IS 'Hourly rollups of agent_metrics samples removed by retention, written only when downsampling is enabled';
+COMMENT ON COLUMN agent_metrics_hourly.hour IS 'Start of the hour the samples were collected in';
+COMMENT ON COLUMN agent_metrics_hourly.samples IS 'Number of raw samples rolled into this row';
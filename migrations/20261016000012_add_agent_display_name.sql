@@ -0,0 +1,5 @@
+-- Operator-chosen name shown in place of the hostname
+
+ALTER TABLE agents ADD COLUMN display_name TEXT;
+
+COMMENT ON COLUMN agents.display_name IS 'Friendly name from the agent''s DISPLAY_NAME, refreshed at each registration; NULL shows the hostname';
@@ -0,0 +1,6 @@
+-- Lineage for recycled instances: a terminated agent's identity registering again
+-- gets a fresh row that points back at the terminated one
+
+ALTER TABLE agents ADD COLUMN previous_agent_id UUID REFERENCES agents(id) ON DELETE SET NULL;
+
+COMMENT ON COLUMN agents.previous_agent_id IS 'Recently terminated agent with the same identity that this one replaced, if any';
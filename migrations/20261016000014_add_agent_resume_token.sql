@@ -0,0 +1,6 @@
+-- Token handed to an agent with each registration acknowledgment; resuming requires
+-- it, so an agent ID alone can't re-attach another agent's registration
+
+ALTER TABLE agents ADD COLUMN resume_token UUID;
+
+COMMENT ON COLUMN agents.resume_token IS 'Token the agent must present to resume its latest session';
@@ -0,0 +1,10 @@
+-- When a job was last claimed for an agent, so jobs orphaned by a dead hub instance
+-- expire from their assignment rather than from submission
+
+ALTER TABLE jobs ADD COLUMN assigned_at TIMESTAMPTZ;
+
+UPDATE jobs SET assigned_at = COALESCE(started_at, created_at)
+WHERE status IN ('assigned', 'running');
+
+COMMENT ON COLUMN jobs.assigned_at IS 'When the job was last assigned to an agent';
+COMMENT ON COLUMN jobs.started_at IS 'When the job was last sent to its agent';
@@ -0,0 +1,10 @@
+-- Keep the command audit trail when an agent is deleted, detaching its rows rather
+-- than deleting them with the agent
+
+ALTER TABLE command_log ALTER COLUMN agent_id DROP NOT NULL;
+
+ALTER TABLE command_log DROP CONSTRAINT command_log_agent_id_fkey;
+ALTER TABLE command_log ADD CONSTRAINT command_log_agent_id_fkey
+    FOREIGN KEY (agent_id) REFERENCES agents(id) ON DELETE SET NULL;
+
+COMMENT ON COLUMN command_log.agent_id IS 'Agent the command was sent to, NULL once the agent is deleted';